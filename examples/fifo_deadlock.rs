@@ -8,7 +8,7 @@ async fn main() {
 
     let mut cnt = 1;
     loop {
-        let std_in = PathBuf::from(fifo_path).join(format!("stdin-fifo"));
+        let std_in = PathBuf::from(fifo_path).join("stdin-fifo");
         mkfifo(&std_in, Mode::S_IRWXU).unwrap_or_default();
         let std_in2 = std_in.clone();
         let std_in3 = std_in.clone();
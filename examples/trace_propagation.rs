@@ -0,0 +1,86 @@
+use sfifo::{Channel, Sfifo, TraceContext};
+use tracing::{info, info_span, Instrument};
+use tracing_subscriber::EnvFilter;
+
+/// Trace context propagation across two processes
+///
+/// Run with:
+/// RUST_LOG=info,sfifo=debug cargo run --example trace_propagation --features tracing
+///
+/// The example re-executes itself as the server process. Every request the
+/// client sends carries its trace id, which the server logs in its own span
+/// and sends back with the reply, so both sides of the pipe can be
+/// correlated.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let fifo_path = "/tmp/trace_propagation_demo";
+    let token = "trace_propagation_token";
+
+    if std::env::args().nth(1).as_deref() == Some("server") {
+        return server(fifo_path, token).await;
+    }
+
+    let _ = std::fs::remove_file(fifo_path);
+    let mut child = tokio::process::Command::new(std::env::current_exe()?)
+        .arg("server")
+        .spawn()?;
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let config = Sfifo::new(fifo_path)
+        .set_propagate_span_context(true)
+        .clone();
+    let mut channel = Channel::connect(&config, token).await?;
+
+    for i in 0..3 {
+        let context = TraceContext::new_root();
+        let span = info_span!("client_request", trace_id = %context.trace_id_hex(), i);
+        let reply = context
+            .scope(
+                async {
+                    info!("sending request");
+                    channel.send(format!("request {}", i).as_bytes()).await?;
+                    channel.recv().await
+                }
+                .instrument(span.clone()),
+            )
+            .await?;
+        let reply_trace = channel.trace_context().map(|c| c.trace_id_hex());
+        span.in_scope(|| info!(?reply_trace, "received {:?}", reply));
+    }
+    drop(channel);
+
+    child.wait().await?;
+    Ok(())
+}
+
+async fn server(fifo_path: &str, token: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Sfifo::new(fifo_path)
+        .set_create(true)
+        .set_cleanup_on_drop(true)
+        .set_propagate_span_context(true)
+        .clone();
+    let mut channel = Channel::accept(&config, token).await?;
+
+    while let Some(message) = channel.recv().await? {
+        let context = channel
+            .trace_context()
+            .unwrap_or_else(TraceContext::new_root);
+        let span = info_span!("server_handle", trace_id = %context.trace_id_hex());
+        // Reply in a child span of the same trace
+        context
+            .child()
+            .scope(
+                async {
+                    info!(pid = std::process::id(), "received {:?}", message);
+                    channel.send(b"done").await
+                }
+                .instrument(span),
+            )
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,21 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use sfifo::{FrameCodec, SfifoError};
+use tokio_util::codec::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = FrameCodec::new().max_frame_size(64 * 1024);
+    let mut src = BytesMut::from(data);
+    loop {
+        match codec.decode_eof(&mut src) {
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(e) => {
+                assert!(SfifoError::from_io(&e).is_some(), "opaque error: {}", e);
+                break;
+            }
+        }
+    }
+});
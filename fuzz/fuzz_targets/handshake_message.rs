@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sfifo::HandshakeMessage;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = HandshakeMessage::from_bytes(data) {
+        // Whatever parses must encode and parse back the same
+        let bytes = message.to_bytes().unwrap();
+        let again = HandshakeMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(again.token, message.token);
+        assert_eq!(again.extensions, message.extensions);
+    }
+    if let Ok(json) = std::str::from_utf8(data) {
+        let _ = HandshakeMessage::from_json(json);
+    }
+});
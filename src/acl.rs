@@ -0,0 +1,103 @@
+use crate::PeerIdentity;
+use std::{os::unix::fs::PermissionsExt, path::Path};
+
+/// Access control for FIFOs exposed to other local users, set on an
+/// `Sfifo` with `set_channel_acl`
+///
+/// The FIFOs created from the configuration, handshake side channels
+/// included, get the group and mode of the ACL. Once authenticated, a
+/// peer is accepted only if its real uid or gid, read from `/proc`, is in
+/// the allowlist; an empty allowlist accepts everyone. A rejected peer, or
+/// one whose identity cannot be gathered, makes the open fail with
+/// `PermissionDenied`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelAcl {
+    group: Option<u32>,
+    mode: Option<u32>,
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl ChannelAcl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give the created FIFOs to group `gid`
+    pub fn group(mut self, gid: u32) -> Self {
+        self.group = Some(gid);
+        self
+    }
+
+    /// Set the permission bits of the created FIFOs, e.g. `0o660`
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode & 0o777);
+        self
+    }
+
+    /// Accept peers running as user `uid`
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.uids.push(uid);
+        self
+    }
+
+    /// Accept peers running with real group `gid`
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.gids.push(gid);
+        self
+    }
+
+    pub fn allows(&self, peer: &PeerIdentity) -> bool {
+        (self.uids.is_empty() && self.gids.is_empty())
+            || self.uids.contains(&peer.uid)
+            || self.gids.contains(&peer.gid)
+    }
+
+    /// Set the group and mode of the FIFO at `path`
+    pub(crate) fn apply(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(gid) = self.group {
+            std::os::unix::fs::chown(path, None, Some(gid))?;
+        }
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sfifo;
+    use std::os::unix::fs::MetadataExt;
+
+    #[tokio::test]
+    async fn test_channel_acl() {
+        let fifo_path = "/tmp/test_channel_acl";
+        let token = "acl_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let own = PeerIdentity::of_process(std::process::id()).unwrap();
+
+        let acl = ChannelAcl::new().group(own.gid).mode(0o660);
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_channel_acl(Some(acl.clone().allow_uid(own.uid)))
+            .clone();
+        let client_config = Sfifo::new(fifo_path)
+            .set_channel_acl(Some(acl.allow_uid(own.uid + 1).allow_gid(own.gid + 1)))
+            .clone();
+
+        // The server accepts the client, which rejects the server
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let error = client_config.open_as_client(token).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(server.await.unwrap().is_ok());
+
+        let metadata = std::fs::metadata(fifo_path).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o660);
+        assert_eq!(metadata.gid(), own.gid);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
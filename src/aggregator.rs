@@ -0,0 +1,224 @@
+use crate::{task::spawn_named, Sfifo};
+use bytes::{Bytes, BytesMut};
+use futures_util::Stream;
+use std::{
+    collections::HashMap,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{io::AsyncReadExt, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+
+// Read buffer size for each source
+const READ_CHUNK: usize = 8192;
+
+type Sources = Arc<Mutex<HashMap<PathBuf, CancellationToken>>>;
+
+/// Fan-in aggregator: reads many FIFOs and yields `(source_path, chunk)`
+/// items from a single `Stream`.
+///
+/// Each source is read by its own task. When all writers of a source close
+/// it, the FIFO is reopened so that new writers are picked up; a source
+/// stops when its FIFO is deleted or it is removed from the aggregator.
+pub struct SfifoAggregator {
+    tx: mpsc::Sender<(PathBuf, Bytes)>,
+    rx: mpsc::Receiver<(PathBuf, Bytes)>,
+    sources: Sources,
+    cancel: CancellationToken,
+}
+
+impl Default for SfifoAggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SfifoAggregator {
+    /// Create an empty aggregator
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel(64);
+        SfifoAggregator {
+            tx,
+            rx,
+            sources: Arc::new(Mutex::new(HashMap::new())),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Start reading the FIFO at `path`, does nothing if already added
+    pub fn add(&self, path: impl AsRef<Path>) {
+        add_source(&self.sources, &self.cancel, &self.tx, path.as_ref());
+    }
+
+    /// Stop reading the FIFO at `path`
+    pub fn remove(&self, path: impl AsRef<Path>) {
+        if let Some(token) = lock(&self.sources).remove(path.as_ref()) {
+            token.cancel();
+        }
+    }
+
+    /// Paths currently being read
+    pub fn sources(&self) -> Vec<PathBuf> {
+        lock(&self.sources).keys().cloned().collect()
+    }
+
+    /// Add every FIFO matching the glob `pattern`, rescanning every
+    /// `interval` to pick up new FIFOs
+    pub fn watch_glob(&self, pattern: &str, interval: Duration) -> std::io::Result<()> {
+        let pattern = glob::Pattern::new(pattern)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let sources = self.sources.clone();
+        let cancel = self.cancel.clone();
+        let tx = self.tx.clone();
+        spawn_named("sfifo::aggregator_glob", async move {
+            loop {
+                if let Ok(paths) = glob::glob(pattern.as_str()) {
+                    for path in paths.flatten() {
+                        let is_fifo = std::fs::metadata(&path)
+                            .map(|m| m.file_type().is_fifo())
+                            .unwrap_or(false);
+                        if is_fifo {
+                            add_source(&sources, &cancel, &tx, &path);
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Receive the next chunk from any source
+    pub async fn recv(&mut self) -> Option<(PathBuf, Bytes)> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for SfifoAggregator {
+    type Item = (PathBuf, Bytes);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for SfifoAggregator {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl std::fmt::Debug for SfifoAggregator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SfifoAggregator")
+            .field("sources", &self.sources())
+            .finish_non_exhaustive()
+    }
+}
+
+fn lock(sources: &Sources) -> std::sync::MutexGuard<'_, HashMap<PathBuf, CancellationToken>> {
+    sources.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn add_source(
+    sources: &Sources,
+    cancel: &CancellationToken,
+    tx: &mpsc::Sender<(PathBuf, Bytes)>,
+    path: &Path,
+) {
+    let token = {
+        let mut sources = lock(sources);
+        if sources.contains_key(path) {
+            return;
+        }
+        let token = cancel.child_token();
+        sources.insert(path.to_path_buf(), token.clone());
+        token
+    };
+    debug!("Aggregator: adding source {:?}", path);
+    let sources = sources.clone();
+    let tx = tx.clone();
+    let path = path.to_path_buf();
+    spawn_named("sfifo::aggregator_source", async move {
+        tokio::select! {
+            res = read_source(&path, &tx) => {
+                if let Err(e) = res {
+                    error!("Aggregator: source {:?} failed: {}", path, e);
+                }
+            }
+            _ = token.cancelled() => {}
+        }
+        lock(&sources).remove(&path);
+        debug!("Aggregator: source {:?} stopped", path);
+    });
+}
+
+async fn read_source(path: &Path, tx: &mpsc::Sender<(PathBuf, Bytes)>) -> std::io::Result<()> {
+    let config = Sfifo::new(path);
+    loop {
+        let mut receiver = config.open_receiver().await?;
+        loop {
+            let mut buf = BytesMut::with_capacity(READ_CHUNK);
+            if receiver.read_buf(&mut buf).await? == 0 {
+                break;
+            }
+            if tx.send((path.to_path_buf(), buf.freeze())).await.is_err() {
+                return Ok(());
+            }
+        }
+        // All writers are gone, wait for new ones unless the FIFO was removed
+        if tokio::fs::metadata(path).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_fifo;
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_aggregator_fan_in() {
+        let dir = "/tmp/test_aggregator";
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        tokio::fs::create_dir_all(dir).await.unwrap();
+        let first = format!("{}/first.fifo", dir);
+        let second = format!("{}/second.fifo", dir);
+        create_fifo(&first).await.unwrap();
+
+        let mut aggregator = SfifoAggregator::new();
+        aggregator
+            .watch_glob(&format!("{}/*.fifo", dir), Duration::from_millis(50))
+            .unwrap();
+
+        let mut sender = Sfifo::new(&first).open_sender().await.unwrap();
+        sender.write_all(b"from first").await.unwrap();
+        let (path, chunk) = aggregator.next().await.unwrap();
+        assert_eq!(path, PathBuf::from(&first));
+        assert_eq!(chunk, "from first");
+
+        // Sources appearing later are picked up by the rescan
+        create_fifo(&second).await.unwrap();
+        let mut sender = Sfifo::new(&second).open_sender().await.unwrap();
+        sender.write_all(b"from second").await.unwrap();
+        let (path, chunk) = aggregator.next().await.unwrap();
+        assert_eq!(path, PathBuf::from(&second));
+        assert_eq!(chunk, "from second");
+        assert_eq!(aggregator.sources().len(), 2);
+
+        aggregator.remove(&first);
+        assert_eq!(aggregator.sources(), vec![PathBuf::from(&second)]);
+
+        let _ = tokio::fs::remove_dir_all(dir).await;
+    }
+}
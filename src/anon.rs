@@ -0,0 +1,103 @@
+use crate::{
+    handshake::MAX_MESSAGE_AGE_SECS, read_handshake_message, task::cancel_after,
+    write_handshake_message, AuthenticatedFifo, HandshakeMessage, HandshakeType, LockedSecret,
+    Sfifo,
+};
+use tokio::net::unix::pipe::{Receiver, Sender};
+use zeroize::Zeroize;
+
+impl Sfifo {
+    /// Creates a connected anonymous pipe, without touching the filesystem.
+    ///
+    /// Both ends are non-blocking and close-on-exec. They can be framed,
+    /// authenticated with `announce_on`/`accept_on`, or handed to a child
+    /// process.
+    pub fn pair() -> std::io::Result<(Sender, Receiver)> {
+        // pipe2(O_CLOEXEC | O_NONBLOCK)
+        tokio::net::unix::pipe::pipe()
+    }
+
+    /// Authenticate the write end of an anonymous pipe by sending a
+    /// handshake request carrying `token`.
+    ///
+    /// A pipe only goes one way, so the reader cannot answer: `peer_info()`
+    /// of the returned FIFO describes the local process.
+    pub async fn announce_on(
+        &self,
+        mut sender: Sender,
+        token: &str,
+    ) -> std::io::Result<AuthenticatedFifo> {
+        let secret = LockedSecret::new(token, self.mlock_secrets)?;
+        let mut request =
+            HandshakeMessage::new(secret.expose().to_string(), HandshakeType::Request)?;
+        let written =
+            tokio::time::timeout(self.timeout, write_handshake_message(&mut sender, &request))
+                .await;
+        request.token.zeroize();
+        written.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "Handshake timeout")
+        })??;
+        Ok(AuthenticatedFifo::new_sender(sender, request, false))
+    }
+
+    /// Authenticate the read end of an anonymous pipe: wait for the
+    /// handshake request sent by `announce_on` and validate its token
+    /// within the configured timeout.
+    pub async fn accept_on(
+        &self,
+        mut receiver: Receiver,
+        token: &str,
+    ) -> std::io::Result<AuthenticatedFifo> {
+        let secret = LockedSecret::new(token, self.mlock_secrets)?;
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let timer = cancel_after(self.timeout, &cancel);
+        let request = read_handshake_message(&mut receiver, &cancel).await;
+        drop(timer);
+        let mut request = request?;
+        if request.message_type != HandshakeType::Request {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Expected handshake request",
+            ));
+        }
+        request.validate(secret.expose(), MAX_MESSAGE_AGE_SECS)?;
+        self.scrub_peer_token(&mut request);
+        Ok(AuthenticatedFifo::new_receiver(receiver, request, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_anonymous_pair() {
+        let (sender, receiver) = Sfifo::pair().unwrap();
+        let config = Sfifo::default().set_timeout(Duration::from_secs(1)).clone();
+
+        let (sender, receiver) = tokio::join!(
+            config.announce_on(sender, "pair_token"),
+            config.accept_on(receiver, "pair_token")
+        );
+        let sender = sender.unwrap();
+        let receiver = receiver.unwrap();
+        assert_eq!(receiver.peer_info().process_id, std::process::id());
+
+        let mut sender = sender.into_framed_sender().unwrap();
+        let mut receiver = receiver.into_framed_receiver().unwrap();
+        sender.send(b"no filesystem").await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "no filesystem");
+
+        // A wrong token is rejected
+        let (sender, receiver) = Sfifo::pair().unwrap();
+        let (_, receiver) = tokio::join!(
+            config.announce_on(sender, "wrong"),
+            config.accept_on(receiver, "pair_token")
+        );
+        assert_eq!(
+            receiver.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{AuthenticatedFifo, PipeEnd, SfifoError};
+use std::os::fd::AsRawFd;
+
+/// Returns the size up to which writes to the pipe are atomic
+///
+/// Writes of at most `PIPE_BUF` bytes are never interleaved with writes
+/// of other writers, larger ones may be split.
+pub fn pipe_buf(fd: &impl AsRawFd) -> std::io::Result<usize> {
+    let limit = unsafe { libc::fpathconf(fd.as_raw_fd(), libc::_PC_PIPE_BUF) };
+    if limit < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(limit as usize)
+}
+
+/// Fail with `SfifoError::MessageTooLarge` if `len` exceeds `limit`
+pub(crate) fn check_atomic(len: usize, limit: usize) -> std::io::Result<()> {
+    if len > limit {
+        return Err(SfifoError::MessageTooLarge { len, limit }.into());
+    }
+    Ok(())
+}
+
+impl AuthenticatedFifo {
+    /// Write `msg` in a single atomic write - only works for Sender
+    ///
+    /// Several processes can share the FIFO with atomic writes without
+    /// their messages interleaving. Messages larger than `PIPE_BUF` are
+    /// refused with `SfifoError::MessageTooLarge`.
+    pub async fn atomic_write(&mut self, msg: &[u8]) -> std::io::Result<()> {
+        let PipeEnd::Sender(sender) = &self.end else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot write to receiver FIFO",
+            ));
+        };
+        check_atomic(msg.len(), pipe_buf(sender)?)?;
+        let n = self.write(msg).await?;
+        if n != msg.len() {
+            // A pipe never splits writes up to PIPE_BUF
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                format!("Atomic write of {} bytes wrote {}", msg.len(), n),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        create_fifo, FramedReceiver, FramedSender, HandshakeMessage, HandshakeType, Sfifo,
+    };
+
+    #[tokio::test]
+    async fn test_atomic_frames_from_several_writers() {
+        let fifo_path = "/tmp/test_atomic_frames";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let limit = pipe_buf(&sender).unwrap();
+        let peer_info = HandshakeMessage::new(String::new(), HandshakeType::Request).unwrap();
+        let mut fifo = AuthenticatedFifo::new_sender(sender, peer_info, false);
+        let error = fifo.atomic_write(&vec![0u8; limit + 1]).await.unwrap_err();
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::MessageTooLarge {
+                len: limit + 1,
+                limit
+            })
+        );
+        drop(fifo);
+
+        let mut writers = Vec::new();
+        for id in 0..4u8 {
+            let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+            let mut framed = FramedSender::new(sender).atomic_frames(true);
+            writers.push(tokio::spawn(async move {
+                let message = vec![id; 3000];
+                for _ in 0..50 {
+                    framed.send(&message).await?;
+                }
+                Ok::<_, std::io::Error>(())
+            }));
+        }
+        let mut counts = [0usize; 4];
+        let mut done = 0;
+        while done < writers.len() * 50 {
+            let message = receiver.recv().await.unwrap().unwrap();
+            assert!(message.iter().all(|&b| b == message[0]));
+            counts[message[0] as usize] += 1;
+            done += 1;
+        }
+        for writer in writers {
+            writer.await.unwrap().unwrap();
+        }
+        assert_eq!(counts, [50; 4]);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_atomic_frames_through_sink() {
+        use futures_util::SinkExt;
+
+        let fifo_path = "/tmp/test_atomic_frames_sink";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let limit = pipe_buf(&sender).unwrap();
+        let mut framed = FramedSender::new(sender).atomic_frames(true);
+        let error = SinkExt::send(&mut framed, bytes::Bytes::from(vec![0u8; limit]))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            SfifoError::from_io(&error),
+            Some(SfifoError::MessageTooLarge { .. })
+        ));
+        drop(framed);
+
+        // Frames fed without flushing are not merged into large writes
+        let mut writers = Vec::new();
+        for id in 0..4u8 {
+            let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+            let mut framed = FramedSender::new(sender).atomic_frames(true);
+            writers.push(tokio::spawn(async move {
+                for _ in 0..50 {
+                    framed.feed(bytes::Bytes::from(vec![id; 3000])).await?;
+                }
+                framed.flush().await
+            }));
+        }
+        let mut counts = [0usize; 4];
+        for _ in 0..writers.len() * 50 {
+            let message = receiver.recv().await.unwrap().unwrap();
+            assert!(message.iter().all(|&b| b == message[0]));
+            counts[message[0] as usize] += 1;
+        }
+        for writer in writers {
+            writer.await.unwrap().unwrap();
+        }
+        assert_eq!(counts, [50; 4]);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_atomic_write() {
+        use tokio::io::AsyncReadExt;
+
+        let fifo_path = "/tmp/test_rate_limited_atomic_write";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let config = Sfifo::new(fifo_path).set_rate_limit(10_000, 100).clone();
+        let mut receiver = config.open_receiver().await.unwrap();
+        let sender = config.open_sender().await.unwrap();
+        let peer_info = HandshakeMessage::new(String::new(), HandshakeType::Request).unwrap();
+        let mut fifo = AuthenticatedFifo::new_sender(sender, peer_info, false).with_config(&config);
+
+        // A message larger than the burst is written whole
+        let message = vec![7u8; 300];
+        fifo.atomic_write(&message).await.unwrap();
+        drop(fifo);
+        let mut received = Vec::new();
+        receiver.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, message);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_atomic_batch_refused_whole() {
+        let fifo_path = "/tmp/test_atomic_batch_refused";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let limit = pipe_buf(&sender).unwrap();
+        let mut framed = FramedSender::new(sender).atomic_frames(true);
+
+        // The frames before the one too large are not sent either
+        let large = vec![0u8; limit];
+        let error = framed
+            .write_batch(&[b"first", b"second", &large])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            SfifoError::from_io(&error),
+            Some(SfifoError::MessageTooLarge { .. })
+        ));
+        framed.send(b"after").await.unwrap();
+        drop(framed);
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"after");
+        assert!(receiver.recv().await.unwrap().is_none());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
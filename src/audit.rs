@@ -0,0 +1,266 @@
+use crate::{wire::push_string, HandshakeMessage, Sfifo};
+use std::{
+    fmt::Write as _,
+    future::Future,
+    io::Write as _,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+tokio::task_local! {
+    // Last peer a handshake of the current task heard from
+    static PEER_SEEN: PeerSeen;
+}
+
+/// How a handshake attempt ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Accepted,
+    Rejected { reason: String },
+}
+
+/// One handshake attempt, passed to the `AuditSink`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub timestamp: SystemTime,
+    /// Path of the FIFO the handshake was for
+    pub path: PathBuf,
+    /// Whether this side was the server
+    pub is_server: bool,
+    /// PID the peer claimed, if it got as far as sending a message
+    pub peer_pid: Option<u32>,
+    pub peer_name: Option<String>,
+    /// Tenant of the client, see `Sfifo::set_token_registry`
+    pub tenant: Option<String>,
+    pub outcome: AuditOutcome,
+}
+
+impl AuditEvent {
+    /// Encode the event as a JSON line, without the newline
+    pub fn to_json(&self) -> String {
+        let millis = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let mut json = String::new();
+        let _ = write!(json, "{{\"timestamp_ms\":{},\"path\":", millis);
+        push_string(&mut json, &self.path.to_string_lossy());
+        json.push_str(",\"side\":");
+        json.push_str(if self.is_server {
+            "\"server\""
+        } else {
+            "\"client\""
+        });
+        json.push_str(",\"peer_pid\":");
+        match self.peer_pid {
+            Some(pid) => {
+                let _ = write!(json, "{}", pid);
+            }
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"peer_name\":");
+        push_optional(&mut json, self.peer_name.as_deref());
+        json.push_str(",\"tenant\":");
+        push_optional(&mut json, self.tenant.as_deref());
+        match &self.outcome {
+            AuditOutcome::Accepted => json.push_str(",\"result\":\"accepted\",\"reason\":null}"),
+            AuditOutcome::Rejected { reason } => {
+                json.push_str(",\"result\":\"rejected\",\"reason\":");
+                push_string(&mut json, reason);
+                json.push('}');
+            }
+        }
+        json
+    }
+}
+
+fn push_optional(json: &mut String, s: Option<&str>) {
+    match s {
+        Some(s) => push_string(json, s),
+        None => json.push_str("null"),
+    }
+}
+
+/// Records handshake attempts, see `Sfifo::set_audit`
+pub trait AuditSink: Send + Sync {
+    fn record(&self, event: &AuditEvent) -> std::io::Result<()>;
+}
+
+/// Appends events as JSON lines to a file created with mode 0600. Each
+/// event is written with a single append, so concurrent writers never
+/// interleave lines
+#[derive(Debug)]
+pub struct FileAuditSink {
+    file: Mutex<std::fs::File>,
+}
+
+impl FileAuditSink {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(FileAuditSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, event: &AuditEvent) -> std::io::Result<()> {
+        let mut line = event.to_json();
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())
+    }
+}
+
+/// Shared handle to an audit sink, set on an `Sfifo`
+///
+/// Every handshake of FIFOs and sockets opened from the configuration is
+/// recorded, whether it succeeded or not. A sink failing to record is
+/// logged and does not fail the handshake.
+#[derive(Clone)]
+pub struct Audit(Arc<dyn AuditSink>);
+
+impl Audit {
+    pub fn new(sink: impl AuditSink + 'static) -> Self {
+        Audit(Arc::new(sink))
+    }
+
+    /// Audit log appended to the JSONL file at `path`
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(FileAuditSink::open(path)?))
+    }
+
+    pub fn record(&self, event: &AuditEvent) -> std::io::Result<()> {
+        self.0.record(event)
+    }
+}
+
+impl std::fmt::Debug for Audit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Audit").finish_non_exhaustive()
+    }
+}
+
+/// Sender of the last handshake message read while running a future with
+/// `watch`, to name the peer of a failed handshake
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeerSeen(Arc<Mutex<Option<(u32, String)>>>);
+
+impl PeerSeen {
+    pub(crate) fn watch<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        PEER_SEEN.scope(self.clone(), future)
+    }
+
+    fn get(&self) -> Option<(u32, String)> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Note the sender of a handshake message read by the current task
+pub(crate) fn note_peer(message: &HandshakeMessage) {
+    let _ = PEER_SEEN.try_with(|seen| {
+        *seen.0.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((message.process_id, message.process_name.clone()));
+    });
+}
+
+impl Sfifo {
+    /// Record a handshake attempt to the audit sink, if any. `result` holds
+    /// the peer of a successful handshake, `seen` names the peer otherwise
+    pub(crate) fn audit_handshake(
+        &self,
+        is_server: bool,
+        result: Result<&HandshakeMessage, &std::io::Error>,
+        seen: &PeerSeen,
+    ) {
+        let Some(audit) = &self.audit else {
+            return;
+        };
+        let (peer, tenant, outcome) = match result {
+            Ok(peer_info) => (
+                Some((peer_info.process_id, peer_info.process_name.clone())),
+                peer_info.tenant.clone(),
+                AuditOutcome::Accepted,
+            ),
+            Err(e) => (
+                seen.get(),
+                None,
+                AuditOutcome::Rejected {
+                    reason: e.to_string(),
+                },
+            ),
+        };
+        let (peer_pid, peer_name) = peer.unzip();
+        let event = AuditEvent {
+            timestamp: SystemTime::now(),
+            path: self.file_path.clone(),
+            is_server,
+            peer_pid,
+            peer_name,
+            tenant,
+            outcome,
+        };
+        if let Err(e) = audit.record(&event) {
+            warn!("Failed to record the handshake to the audit log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let fifo_path = "/tmp/test_audit_log";
+        let log_path = "/tmp/test_audit_log.jsonl";
+        let token = "audit_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(log_path).await;
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_audit(Some(Audit::file(log_path).unwrap()))
+            .clone();
+
+        for client_token in [token, "wrong_token"] {
+            let server_config = config.clone();
+            let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let client =
+                tokio::spawn(
+                    async move { Sfifo::new(fifo_path).open_as_client(client_token).await },
+                );
+            let server = server.await.unwrap();
+            if client_token == token {
+                client.await.unwrap().unwrap();
+            } else {
+                client.abort();
+            }
+            drop(server);
+        }
+
+        let log = std::fs::read_to_string(log_path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let pid = format!("\"peer_pid\":{}", std::process::id());
+        assert!(lines[0].contains("\"path\":\"/tmp/test_audit_log\""));
+        assert!(lines[0].contains("\"side\":\"server\""));
+        assert!(lines[0].contains(&pid));
+        assert!(lines[0].ends_with("\"result\":\"accepted\",\"reason\":null}"));
+        assert!(lines[1].contains(&pid));
+        assert!(lines[1].contains("\"result\":\"rejected\""));
+        assert!(lines[1].contains("Invalid authentication token"));
+        assert!(!log.contains(token));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(log_path).await;
+    }
+}
@@ -0,0 +1,25 @@
+use crate::Sfifo;
+
+/// How authenticated FIFOs opened from a configuration read and write
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Wait for readiness with the tokio reactor, then read or write
+    #[default]
+    Reactor,
+    /// Submit each read or write to an io_uring, linked to a poll of the
+    /// FIFO so the kernel waits for it. Needs the `uring` feature, and
+    /// falls back to the reactor where the kernel refuses io_uring
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    Uring,
+}
+
+impl Sfifo {
+    /// Select how `AuthenticatedFifo::read` and `write` reach the kernel
+    ///
+    /// Framed senders and receivers converted from an authenticated FIFO
+    /// always use the reactor.
+    pub fn set_backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{task::spawn_named, AuthenticatedFifo, FramedSender};
+use std::{
+    os::fd::{AsFd, AsRawFd},
+    time::Duration,
+};
+use tokio::{net::unix::pipe::Receiver, sync::watch};
+
+/// Which side of the watermarks the pipe fill level is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureLevel {
+    /// Below the low watermark, or never went above the high one
+    Low,
+    /// Above the high watermark and not yet drained below the low one
+    High,
+}
+
+/// A sample of the pipe fill level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backpressure {
+    /// Bytes queued in the pipe and not read yet (FIONREAD)
+    pub queued: usize,
+    /// Capacity of the pipe buffer (F_GETPIPE_SZ)
+    pub capacity: usize,
+    pub level: PressureLevel,
+}
+
+type WatermarkCallback = Box<dyn FnMut(Backpressure) + Send>;
+
+/// Samples the fill level of a pipe and reports watermark crossings.
+///
+/// The level switches to `High` once `high` bytes are queued and back to
+/// `Low` when the queue drains to `low` bytes or fewer.
+pub struct BackpressureWatcher {
+    high: usize,
+    low: usize,
+    interval: Duration,
+    on_high: Option<WatermarkCallback>,
+    on_low: Option<WatermarkCallback>,
+}
+
+impl BackpressureWatcher {
+    /// Create a watcher with watermarks expressed in bytes
+    pub fn new(high: usize, low: usize) -> Self {
+        BackpressureWatcher {
+            high,
+            low: low.min(high),
+            interval: Duration::from_millis(100),
+            on_high: None,
+            on_low: None,
+        }
+    }
+
+    /// Set how often the fill level is sampled
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Called when the fill level crosses the high watermark
+    pub fn on_high(mut self, callback: impl FnMut(Backpressure) + Send + 'static) -> Self {
+        self.on_high = Some(Box::new(callback));
+        self
+    }
+
+    /// Called when the fill level drains below the low watermark
+    pub fn on_low(mut self, callback: impl FnMut(Backpressure) + Send + 'static) -> Self {
+        self.on_low = Some(Box::new(callback));
+        self
+    }
+
+    /// Start sampling `fd`, which may be either end of a pipe
+    ///
+    /// The descriptor is duplicated, sampling stops once every clone of the
+    /// returned receiver has been dropped.
+    pub fn spawn(mut self, fd: &impl AsFd) -> std::io::Result<watch::Receiver<Backpressure>> {
+        let fd = fd.as_fd().try_clone_to_owned()?;
+        let (queued, capacity) = pipe_fill_level(&fd)?;
+        let initial = Backpressure {
+            queued,
+            capacity,
+            level: PressureLevel::Low,
+        };
+        let (tx, rx) = watch::channel(initial);
+        spawn_named("sfifo::backpressure", async move {
+            let mut level = PressureLevel::Low;
+            loop {
+                tokio::select! {
+                    _ = tx.closed() => break,
+                    _ = tokio::time::sleep(self.interval) => {}
+                }
+                let Ok((queued, capacity)) = pipe_fill_level(&fd) else {
+                    break;
+                };
+                let previous = level;
+                if queued >= self.high {
+                    level = PressureLevel::High;
+                } else if queued <= self.low {
+                    level = PressureLevel::Low;
+                }
+                let sample = Backpressure {
+                    queued,
+                    capacity,
+                    level,
+                };
+                if level != previous {
+                    let callback = match level {
+                        PressureLevel::High => self.on_high.as_mut(),
+                        PressureLevel::Low => self.on_low.as_mut(),
+                    };
+                    if let Some(callback) = callback {
+                        callback(sample);
+                    }
+                }
+                tx.send_replace(sample);
+            }
+        });
+        Ok(rx)
+    }
+}
+
+/// Returns the bytes queued in a pipe and the pipe capacity.
+///
+/// Outside Linux the capacity cannot be queried, the default pipe size of
+/// the platform is returned instead.
+pub fn pipe_fill_level(fd: &impl AsRawFd) -> std::io::Result<(usize, usize)> {
+    let fd = fd.as_raw_fd();
+    let mut queued: libc::c_int = 0;
+    // FIONREAD works on both ends of a pipe
+    if unsafe { libc::ioctl(fd, libc::FIONREAD, &mut queued) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let capacity = crate::platform::pipe_capacity(&fd)?;
+    Ok((queued as usize, capacity))
+}
+
+/// Read and discard the bytes queued in the pipe of `receiver`, leaving
+/// alone whatever is written after the call. Returns the bytes discarded
+pub(crate) fn discard_queued(receiver: &Receiver) -> std::io::Result<usize> {
+    let (mut queued, _) = pipe_fill_level(receiver)?;
+    let mut discarded = 0;
+    let mut buf = [0u8; 4096];
+    while queued > 0 {
+        let len = queued.min(buf.len());
+        // Read the fd directly, the receiver may not be registered as
+        // readable yet
+        match nix::unistd::read(receiver.as_raw_fd(), &mut buf[..len]) {
+            Ok(0) | Err(nix::errno::Errno::EAGAIN) => break,
+            Ok(n) => {
+                queued -= n;
+                discarded += n;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(discarded)
+}
+
+impl FramedSender {
+    /// Monitor the fill level of the pipe behind this sender
+    pub fn watch_backpressure(
+        &self,
+        watcher: BackpressureWatcher,
+    ) -> std::io::Result<watch::Receiver<Backpressure>> {
+        watcher.spawn(self.get_ref())
+    }
+}
+
+impl AuthenticatedFifo {
+    /// Monitor the fill level of the pipe behind this FIFO
+    pub fn watch_backpressure(
+        &self,
+        watcher: BackpressureWatcher,
+    ) -> std::io::Result<watch::Receiver<Backpressure>> {
+        watcher.spawn(&self.end)
+    }
+}
+
+impl std::fmt::Debug for BackpressureWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackpressureWatcher")
+            .field("high", &self.high)
+            .field("low", &self.low)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, Sfifo};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_backpressure_watermarks() {
+        let fifo_path = "/tmp/test_backpressure";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        let mut sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+
+        let highs = Arc::new(AtomicUsize::new(0));
+        let highs_clone = highs.clone();
+        let mut rx = BackpressureWatcher::new(1000, 100)
+            .interval(Duration::from_millis(10))
+            .on_high(move |_| {
+                highs_clone.fetch_add(1, Ordering::SeqCst);
+            })
+            .spawn(&sender)
+            .unwrap();
+        assert_eq!(rx.borrow().level, PressureLevel::Low);
+
+        sender.write_all(&[0u8; 2000]).await.unwrap();
+        rx.wait_for(|b| b.level == PressureLevel::High)
+            .await
+            .unwrap();
+        assert_eq!(rx.borrow().queued, 2000);
+        assert_eq!(highs.load(Ordering::SeqCst), 1);
+
+        let mut buf = [0u8; 2000];
+        receiver.read_exact(&mut buf).await.unwrap();
+        rx.wait_for(|b| b.level == PressureLevel::Low)
+            .await
+            .unwrap();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,203 @@
+use sfifo::{create_fifo, Sfifo};
+use std::{process::ExitCode, time::Duration};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+const USAGE: &str = "\
+Usage: sfifo <command> <path> [options]
+
+Commands:
+  create <path>                     Create the FIFO
+  send <path> [message...]          Send the message, or stdin if none is given
+  recv <path>                       Copy the FIFO to stdout until the writer closes it
+  tail <path>                       Like recv, but keep reading new writers until
+                                    the FIFO is deleted
+  handshake-test <path> --token T   Perform a handshake and print the peer
+
+Options:
+  --token <token>     Authenticate with the token (send acts as client,
+                      recv/tail as server)
+  --timeout <secs>    Timeout for opening the FIFO (default 3)
+  --server            handshake-test: act as server instead of client
+  -v, --verbose       Log to stderr, RUST_LOG overrides the level";
+
+/// Parsed command line
+struct Args {
+    command: String,
+    path: String,
+    message: Vec<String>,
+    token: Option<String>,
+    timeout: Option<Duration>,
+    server: bool,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let command = args.next().ok_or("Missing command")?;
+        let mut positional = Vec::new();
+        let mut token = None;
+        let mut timeout = None;
+        let mut server = false;
+        let mut verbose = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--token" => token = Some(args.next().ok_or("--token needs a value")?),
+                "--timeout" => {
+                    let secs: f64 = args
+                        .next()
+                        .and_then(|secs| secs.parse().ok())
+                        .ok_or("--timeout needs a number of seconds")?;
+                    timeout = Some(Duration::from_secs_f64(secs));
+                }
+                "--server" => server = true,
+                "-v" | "--verbose" => verbose = true,
+                "-h" | "--help" => return Err(String::new()),
+                _ => positional.push(arg),
+            }
+        }
+        if verbose {
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
+                .init();
+        }
+        if positional.is_empty() {
+            return Err("Missing FIFO path".to_string());
+        }
+        let path = positional.remove(0);
+        Ok(Args {
+            command,
+            path,
+            message: positional,
+            token,
+            timeout,
+            server,
+        })
+    }
+
+    fn config(&self) -> Sfifo {
+        let mut config = Sfifo::new(&self.path);
+        if let Some(timeout) = self.timeout {
+            config.set_timeout(timeout);
+        }
+        config
+    }
+
+    fn require_token(&self) -> std::io::Result<&str> {
+        self.token.as_deref().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "--token is required")
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            if !error.is_empty() {
+                eprintln!("sfifo: {}\n", error);
+            }
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let result = match args.command.as_str() {
+        "create" => create_fifo(&args.path).await,
+        "send" => send(&args).await,
+        "recv" => recv(&args, false).await,
+        "tail" => recv(&args, true).await,
+        "handshake-test" => handshake_test(&args).await,
+        command => {
+            eprintln!("sfifo: Unknown command {}\n\n{}", command, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("sfifo: {}: {}", args.path, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn send(args: &Args) -> std::io::Result<()> {
+    let config = args.config();
+    match &args.token {
+        Some(token) => write_from(args, config.open_as_client(token).await?).await,
+        None => write_from(args, config.open_sender().await?).await,
+    }
+}
+
+/// Write the message given on the command line, or stdin
+async fn write_from(args: &Args, mut fifo: impl AsyncWrite + Unpin) -> std::io::Result<()> {
+    if args.message.is_empty() {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut fifo).await?;
+    } else {
+        let line = args.message.join(" ") + "\n";
+        fifo.write_all(line.as_bytes()).await?;
+    }
+    fifo.flush().await
+}
+
+async fn recv(args: &Args, follow: bool) -> std::io::Result<()> {
+    let mut config = args.config();
+    config.set_wait_for_writer(true);
+    if follow {
+        // Wait for writers until the FIFO is deleted
+        config.set_notify(true);
+    }
+    loop {
+        let result = match &args.token {
+            Some(token) => match config.open_as_server(token).await {
+                Ok(fifo) => read_into_stdout(fifo).await,
+                Err(e) => Err(e),
+            },
+            None => match config.open_receiver().await {
+                Ok(fifo) => read_into_stdout(fifo).await,
+                Err(e) => Err(e),
+            },
+        };
+        match result {
+            // The FIFO was deleted while waiting for a writer
+            Err(_) if follow && !std::path::Path::new(&args.path).exists() => return Ok(()),
+            Err(e) => return Err(e),
+            Ok(()) if !follow => return Ok(()),
+            Ok(()) => {}
+        }
+    }
+}
+
+async fn read_into_stdout(mut fifo: impl AsyncRead + Unpin) -> std::io::Result<()> {
+    let mut stdout = tokio::io::stdout();
+    tokio::io::copy(&mut fifo, &mut stdout).await?;
+    stdout.flush().await
+}
+
+async fn handshake_test(args: &Args) -> std::io::Result<()> {
+    let token = args.require_token()?;
+    // The server keeps the FIFO open until the client attached to it
+    let config = args
+        .config()
+        .set_create(true)
+        .set_wait_for_writer(true)
+        .clone();
+    let started = std::time::Instant::now();
+    let fifo = if args.server {
+        config.open_as_server(token).await?
+    } else {
+        config.open_as_client(token).await?
+    };
+    let peer = fifo.peer_info();
+    println!(
+        "Handshake with {} (pid {}) succeeded in {:?}",
+        peer.process_name,
+        peer.process_id,
+        started.elapsed()
+    );
+    if let Some(identity) = fifo.peer_identity() {
+        println!("Peer uid {}, gid {}", identity.uid, identity.gid);
+        if let Some(exe) = &identity.exe {
+            println!("Peer executable {}", exe.display());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,529 @@
+use crate::{create_fifo, task::spawn_named, ExpiredHandler, FramedSender, Sfifo, SfifoError};
+use bytes::Bytes;
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Bytes of messages kept in memory by default before spilling to disk
+pub const DEFAULT_MEMORY_LIMIT: usize = 1024 * 1024;
+// How often the FIFO is probed for a reader while messages are pending
+const READER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sender that keeps accepting messages while no reader is attached.
+///
+/// Messages are queued in memory up to a limit, then appended to a journal
+/// file, and a background task drains them in order as data frames into
+/// the FIFO whenever a reader has it open. Messages still queued when the
+/// sender is dropped are saved to the journal and sent by the next
+/// `BufferedSender` using it.
+///
+/// Delivery is at least once across restarts: journal records sent before
+/// a crash are sent again. Frames already in the pipe buffer when the
+/// reader exits are lost with it. Messages sent with a deadline are the
+/// exception, they are dropped once it passes.
+pub struct BufferedSender {
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    // Signalled when a message is queued
+    queued: Notify,
+    // Signalled when the queue is empty
+    drained: Notify,
+    expired: Mutex<Option<ExpiredHandler>>,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Drop a message that missed its deadline
+    fn expire(&self, message: Bytes) -> std::io::Result<()> {
+        debug!(
+            "Dropping a message of {} bytes past its deadline",
+            message.len()
+        );
+        let mut expired = self.expired.lock().unwrap_or_else(|e| e.into_inner());
+        match expired.as_mut() {
+            Some(handler) => {
+                handler(message);
+                Ok(())
+            }
+            None => Err(SfifoError::DeadlineExpired { len: message.len() }.into()),
+        }
+    }
+}
+
+impl BufferedSender {
+    /// Start buffering messages for the FIFO at `config.file_path`, spilling
+    /// to the journal at `journal` and resuming any messages left in it
+    pub fn new(config: &Sfifo, journal: impl AsRef<Path>) -> std::io::Result<Self> {
+        let queue = Queue {
+            memory: VecDeque::new(),
+            memory_bytes: 0,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            journal: Journal::open(journal.as_ref())?,
+        };
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(queue),
+            queued: Notify::new(),
+            drained: Notify::new(),
+            expired: Mutex::new(None),
+        });
+        let cancel = CancellationToken::new();
+        spawn_named(
+            "sfifo::buffered_drain",
+            drain(config.clone(), shared.clone(), cancel.clone()),
+        );
+        Ok(BufferedSender { shared, cancel })
+    }
+
+    /// Set how many bytes of messages are kept in memory
+    pub fn with_memory_limit(self, limit: usize) -> Self {
+        self.shared.lock().memory_limit = limit;
+        self
+    }
+
+    /// Set the handler receiving the messages dropped past their deadline
+    pub fn on_expired(self, handler: impl FnMut(Bytes) + Send + 'static) -> Self {
+        *self
+            .shared
+            .expired
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+        self
+    }
+
+    /// Queue a message, never waiting for a reader
+    pub fn send(&self, message: &[u8]) -> std::io::Result<()> {
+        self.shared
+            .lock()
+            .push(Bytes::copy_from_slice(message), None)?;
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+
+    /// Queue a message that is dropped if it cannot be written to the
+    /// FIFO before `deadline`
+    ///
+    /// Such messages are only kept in memory, never in the journal: one
+    /// that does not fit in memory expires right away, and those still
+    /// queued when the sender is dropped expire then. Expired messages go
+    /// to the `on_expired` handler. Without one, a message expiring right
+    /// away fails with `SfifoError::DeadlineExpired`, later ones are
+    /// dropped silently.
+    pub fn send_with_deadline(&self, message: &[u8], deadline: Instant) -> std::io::Result<()> {
+        let message = Bytes::copy_from_slice(message);
+        if deadline <= Instant::now()
+            || !self.shared.lock().push(message.clone(), Some(deadline))?
+        {
+            return self.shared.expire(message);
+        }
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+
+    /// Number of messages not sent yet
+    pub fn pending(&self) -> usize {
+        self.shared.lock().len()
+    }
+
+    /// Wait until every queued message has been written to the FIFO
+    pub async fn flush(&self) {
+        loop {
+            let drained = self.shared.drained.notified();
+            tokio::pin!(drained);
+            drained.as_mut().enable();
+            if self.pending() == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+}
+
+impl Drop for BufferedSender {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        let expired = self.shared.lock().persist().unwrap_or_else(|e| {
+            error!("Failed to save buffered messages to the journal: {}", e);
+            Vec::new()
+        });
+        for message in expired {
+            let _ = self.shared.expire(message);
+        }
+    }
+}
+
+impl std::fmt::Debug for BufferedSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferedSender")
+            .field("pending", &self.pending())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Write queued messages into the FIFO whenever it has a reader
+async fn drain(config: Sfifo, shared: Arc<Shared>, cancel: CancellationToken) {
+    if config.create {
+        if let Err(e) = create_fifo(&config.file_path).await {
+            warn!("Failed to create FIFO {:?}: {}", config.file_path, e);
+        }
+    }
+    let mut sender: Option<FramedSender> = None;
+    loop {
+        let next = shared.lock().front().unwrap_or_else(|e| {
+            error!("Failed to read the message journal: {}", e);
+            None
+        });
+        let Some((message, deadline)) = next else {
+            shared.drained.notify_waiters();
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = shared.queued.notified() => continue,
+            }
+        };
+        if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+            // Let a connected reader see the gap
+            if let Some(framed) = sender.as_mut() {
+                framed.skip_sequence();
+            }
+            expire_front(&shared, message);
+            continue;
+        }
+        let framed = match sender.as_mut() {
+            Some(framed) => framed,
+            None => {
+                // Opening the write end fails with ENXIO while there is no reader
+                let opened =
+                    tokio::net::unix::pipe::OpenOptions::new().open_sender(&config.file_path);
+                match opened {
+                    Ok(pipe) => sender.insert(
+                        FramedSender::new(pipe)
+                            .with_metrics(config.metrics.clone())
+                            .frame_diagnostics(config.frame_diagnostics)
+                            .checksum(config.frame_checksum)
+                            .rate_limit(config.rate_limit),
+                    ),
+                    Err(_) => {
+                        tokio::select! {
+                            _ = cancel.cancelled() => return,
+                            _ = tokio::time::sleep(READER_POLL_INTERVAL) => continue,
+                        }
+                    }
+                }
+            }
+        };
+        let sent = tokio::select! {
+            _ = cancel.cancelled() => return,
+            sent = async {
+                match deadline {
+                    Some(deadline) => framed.send_with_deadline(&message, deadline).await,
+                    None => framed.send(&message).await,
+                }
+            } => sent,
+        };
+        match sent {
+            Ok(()) => {
+                if let Err(e) = shared.lock().pop() {
+                    error!("Failed to update the message journal: {}", e);
+                }
+            }
+            Err(e)
+                if matches!(
+                    SfifoError::from_io(&e),
+                    Some(SfifoError::DeadlineExpired { .. })
+                ) =>
+            {
+                expire_front(&shared, message);
+            }
+            Err(e) => {
+                debug!("Reader of {:?} went away: {}", config.file_path, e);
+                sender = None;
+            }
+        }
+    }
+}
+
+/// Drop the first queued message, which missed its deadline
+fn expire_front(shared: &Shared, message: Bytes) {
+    if let Err(e) = shared.lock().pop() {
+        error!("Failed to update the message journal: {}", e);
+    }
+    let _ = shared.expire(message);
+}
+
+/// Queued messages, the oldest in memory and, once the memory limit was
+/// reached, the newer ones in the journal. Messages in memory may have a
+/// deadline
+struct Queue {
+    memory: VecDeque<(Bytes, Option<Instant>)>,
+    memory_bytes: usize,
+    memory_limit: usize,
+    journal: Journal,
+}
+
+impl Queue {
+    /// Queue a message, `false` if it has a deadline and does not fit in
+    /// memory
+    fn push(&mut self, message: Bytes, deadline: Option<Instant>) -> std::io::Result<bool> {
+        // Once spilled, messages go to the journal until it drains, to
+        // preserve their order
+        if self.journal.is_empty() && self.memory_bytes + message.len() <= self.memory_limit {
+            self.memory_bytes += message.len();
+            self.memory.push_back((message, deadline));
+            return Ok(true);
+        }
+        if deadline.is_some() {
+            return Ok(false);
+        }
+        self.journal.append(&message)?;
+        Ok(true)
+    }
+
+    fn front(&mut self) -> std::io::Result<Option<(Bytes, Option<Instant>)>> {
+        match self.memory.front() {
+            Some(message) => Ok(Some(message.clone())),
+            None => Ok(self.journal.front()?.map(|message| (message, None))),
+        }
+    }
+
+    fn pop(&mut self) -> std::io::Result<()> {
+        match self.memory.pop_front() {
+            Some((message, _)) => {
+                self.memory_bytes -= message.len();
+                Ok(())
+            }
+            None => self.journal.pop(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.memory.len() + self.journal.messages
+    }
+
+    /// Move the messages held in memory to the front of the journal,
+    /// returning those with a deadline, which are dropped instead
+    fn persist(&mut self) -> std::io::Result<Vec<Bytes>> {
+        let mut records = Vec::new();
+        let mut persisted = 0;
+        let mut expired = Vec::new();
+        for (message, deadline) in self.memory.drain(..) {
+            if deadline.is_some() {
+                expired.push(message);
+            } else {
+                encode_record(&message, &mut records)?;
+                persisted += 1;
+            }
+        }
+        self.memory_bytes = 0;
+        if persisted > 0 {
+            self.journal.prepend(records, persisted)?;
+        }
+        Ok(expired)
+    }
+}
+
+/// File of `len: u32 | message` records (little endian), consumed from
+/// `head` and truncated once empty
+struct Journal {
+    path: PathBuf,
+    file: File,
+    head: u64,
+    tail: u64,
+    messages: usize,
+}
+
+impl Journal {
+    /// Open the journal, keeping the complete records of a previous run
+    fn open(path: &Path) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let mut tail = 0;
+        let mut messages = 0;
+        while let Some(header) = data.get(tail..tail + 4) {
+            let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            if data.len() < tail + 4 + len {
+                break;
+            }
+            tail += 4 + len;
+            messages += 1;
+        }
+        // Drop a record torn by a crash
+        file.set_len(tail as u64)?;
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file,
+            head: 0,
+            tail: tail as u64,
+            messages,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.messages == 0
+    }
+
+    fn append(&mut self, message: &[u8]) -> std::io::Result<()> {
+        let mut record = Vec::with_capacity(4 + message.len());
+        encode_record(message, &mut record)?;
+        self.file.seek(SeekFrom::Start(self.tail))?;
+        self.file.write_all(&record)?;
+        self.tail += record.len() as u64;
+        self.messages += 1;
+        Ok(())
+    }
+
+    fn front(&mut self) -> std::io::Result<Option<Bytes>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let len = self.front_len()?;
+        let mut message = vec![0u8; len];
+        self.file.read_exact(&mut message)?;
+        Ok(Some(message.into()))
+    }
+
+    fn pop(&mut self) -> std::io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        self.head += 4 + self.front_len()? as u64;
+        self.messages -= 1;
+        if self.is_empty() {
+            self.file.set_len(0)?;
+            self.head = 0;
+            self.tail = 0;
+        }
+        Ok(())
+    }
+
+    /// Length of the first record, leaving the file positioned on its data
+    fn front_len(&mut self) -> std::io::Result<usize> {
+        let mut header = [0u8; 4];
+        self.file.seek(SeekFrom::Start(self.head))?;
+        self.file.read_exact(&mut header)?;
+        Ok(u32::from_le_bytes(header) as usize)
+    }
+
+    /// Rewrite the journal with `records` in front of the unsent records
+    fn prepend(&mut self, mut records: Vec<u8>, messages: usize) -> std::io::Result<()> {
+        let mut rest = vec![0u8; (self.tail - self.head) as usize];
+        self.file.seek(SeekFrom::Start(self.head))?;
+        self.file.read_exact(&mut rest)?;
+        records.extend_from_slice(&rest);
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&records)?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.head = 0;
+        self.tail = records.len() as u64;
+        self.messages += messages;
+        Ok(())
+    }
+}
+
+fn encode_record(message: &[u8], dst: &mut Vec<u8>) -> std::io::Result<()> {
+    let len = u32::try_from(message.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Message too large"))?;
+    dst.extend_from_slice(&len.to_le_bytes());
+    dst.extend_from_slice(message);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FramedReceiver;
+
+    #[tokio::test]
+    async fn test_buffered_sender_spills_and_drains() {
+        let fifo_path = "/tmp/test_buffered_sender";
+        let journal_path = "/tmp/test_buffered_sender.journal";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(journal_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        // No reader: "one" stays in memory, the rest spills to the journal
+        let sender = BufferedSender::new(&config, journal_path)
+            .unwrap()
+            .with_memory_limit(4);
+        for message in ["one", "two", "three"] {
+            sender.send(message.as_bytes()).unwrap();
+        }
+        assert_eq!(sender.pending(), 3);
+        assert!(std::fs::metadata(journal_path).unwrap().len() > 0);
+
+        // The producer restarts before any consumer showed up
+        drop(sender);
+        let sender = BufferedSender::new(&config, journal_path).unwrap();
+        assert_eq!(sender.pending(), 3);
+        sender.send(b"four").unwrap();
+
+        let mut receiver = FramedReceiver::new(config.open_receiver().await.unwrap());
+        for expected in ["one", "two", "three", "four"] {
+            assert_eq!(receiver.recv().await.unwrap().unwrap(), expected);
+        }
+        sender.flush().await;
+        assert_eq!(sender.pending(), 0);
+        assert_eq!(std::fs::metadata(journal_path).unwrap().len(), 0);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(journal_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_buffered_sender_drops_stale_messages() {
+        let fifo_path = "/tmp/test_buffered_deadline";
+        let journal_path = "/tmp/test_buffered_deadline.journal";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(journal_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        let expired = Arc::new(Mutex::new(Vec::new()));
+        let seen = expired.clone();
+        let sender = BufferedSender::new(&config, journal_path)
+            .unwrap()
+            .with_memory_limit(16)
+            .on_expired(move |message| seen.lock().unwrap().push(message));
+        let soon = Instant::now() + Duration::from_millis(50);
+        sender.send_with_deadline(b"stale", soon).unwrap();
+        sender.send(b"kept").unwrap();
+        // Does not fit in memory, expires right away
+        sender.send_with_deadline(&[0u8; 32], soon).unwrap();
+        assert_eq!(expired.lock().unwrap().len(), 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let mut receiver = FramedReceiver::new(config.open_receiver().await.unwrap());
+        let later = Instant::now() + Duration::from_secs(5);
+        sender.send_with_deadline(b"fresh", later).unwrap();
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "kept");
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "fresh");
+        sender.flush().await;
+        assert_eq!(expired.lock().unwrap()[1], "stale");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(journal_path).await;
+    }
+}
@@ -0,0 +1,241 @@
+use crate::AuthenticatedFifo;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    BufWriter, ReadBuf,
+};
+
+/// Buffer capacity used by `buffered()`
+pub const DEFAULT_BUFFER_CAPACITY: usize = 8 * 1024;
+
+/// Receiver side of an authenticated FIFO reading through a buffer, so
+/// small reads do not each cost a syscall
+#[derive(Debug)]
+pub struct BufferedFifoReader {
+    inner: BufReader<AuthenticatedFifo>,
+}
+
+/// Sender side of an authenticated FIFO collecting small writes in a
+/// buffer, written to the pipe when full or on `flush()`.
+///
+/// Buffered bytes are lost if the writer is dropped without flushing.
+#[derive(Debug)]
+pub struct BufferedFifoWriter {
+    inner: BufWriter<AuthenticatedFifo>,
+}
+
+/// Either buffered adapter, depending on the end of the FIFO
+#[derive(Debug)]
+pub enum BufferedFifo {
+    Reader(BufferedFifoReader),
+    Writer(BufferedFifoWriter),
+}
+
+impl AuthenticatedFifo {
+    /// Wrap the FIFO in a buffered reader or writer of the default capacity
+    pub fn buffered(self) -> BufferedFifo {
+        self.buffered_with_capacity(DEFAULT_BUFFER_CAPACITY)
+    }
+
+    /// Wrap the FIFO in a buffered reader or writer of `capacity` bytes
+    pub fn buffered_with_capacity(self, capacity: usize) -> BufferedFifo {
+        if self.is_receiver() {
+            BufferedFifo::Reader(BufferedFifoReader {
+                inner: BufReader::with_capacity(capacity, self),
+            })
+        } else {
+            BufferedFifo::Writer(BufferedFifoWriter {
+                inner: BufWriter::with_capacity(capacity, self),
+            })
+        }
+    }
+}
+
+impl BufferedFifo {
+    /// The buffered reader - only works for Receiver
+    pub fn into_reader(self) -> std::io::Result<BufferedFifoReader> {
+        match self {
+            BufferedFifo::Reader(reader) => Ok(reader),
+            BufferedFifo::Writer(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot read from sender FIFO",
+            )),
+        }
+    }
+
+    /// The buffered writer - only works for Sender
+    pub fn into_writer(self) -> std::io::Result<BufferedFifoWriter> {
+        match self {
+            BufferedFifo::Writer(writer) => Ok(writer),
+            BufferedFifo::Reader(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot write to receiver FIFO",
+            )),
+        }
+    }
+}
+
+impl BufferedFifoReader {
+    /// Read some bytes, from the buffer when it holds any
+    pub async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf).await
+    }
+
+    /// Read exact number of bytes
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.inner.read_exact(buf).await.map(|_| ())
+    }
+
+    /// Read a line without its `\n` or `\r\n` terminator, `None` at
+    /// end-of-file
+    pub async fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    pub fn get_ref(&self) -> &AuthenticatedFifo {
+        self.inner.get_ref()
+    }
+
+    /// Direct access to the FIFO, bypassing the buffer
+    pub fn get_mut(&mut self) -> &mut AuthenticatedFifo {
+        self.inner.get_mut()
+    }
+
+    /// Get the FIFO back, discarding buffered bytes
+    pub fn into_inner(self) -> AuthenticatedFifo {
+        self.inner.into_inner()
+    }
+}
+
+impl BufferedFifoWriter {
+    /// Append bytes to the buffer, writing it out when full
+    pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.inner.write_all(buf).await
+    }
+
+    /// Append a line (with newline) to the buffer
+    pub async fn write_line(&mut self, s: &str) -> std::io::Result<()> {
+        self.inner.write_all(s.as_bytes()).await?;
+        self.inner.write_all(b"\n").await
+    }
+
+    /// Write the buffered bytes to the pipe
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush().await
+    }
+
+    /// Number of bytes waiting in the buffer
+    pub fn buffered_len(&self) -> usize {
+        self.inner.buffer().len()
+    }
+
+    pub fn get_ref(&self) -> &AuthenticatedFifo {
+        self.inner.get_ref()
+    }
+
+    /// Flush and get the FIFO back
+    pub async fn into_inner(mut self) -> std::io::Result<AuthenticatedFifo> {
+        self.inner.flush().await?;
+        Ok(self.inner.into_inner())
+    }
+}
+
+impl AsyncRead for BufferedFifoReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for BufferedFifoReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.inner).consume(amt)
+    }
+}
+
+impl AsyncWrite for BufferedFifoWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, HandshakeMessage, HandshakeType, Sfifo};
+
+    #[tokio::test]
+    async fn test_buffered_writer_flush() {
+        let fifo_path = "/tmp/test_buffered_fifo";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let peer_info = HandshakeMessage::new(String::new(), HandshakeType::Request).unwrap();
+        let receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        let mut reader = AuthenticatedFifo::new_receiver(receiver, peer_info.clone(), true)
+            .buffered()
+            .into_reader()
+            .unwrap();
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let mut writer = AuthenticatedFifo::new_sender(sender, peer_info, false)
+            .buffered_with_capacity(64)
+            .into_writer()
+            .unwrap();
+
+        for n in 0..3 {
+            writer.write_line(&format!("message {}", n)).await.unwrap();
+        }
+        assert_eq!(writer.buffered_len(), 30);
+        // Nothing reached the pipe yet
+        let mut probe = [0u8; 1];
+        assert_eq!(
+            reader.get_mut().try_read(&mut probe).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+
+        writer.flush().await.unwrap();
+        assert_eq!(writer.buffered_len(), 0);
+        drop(writer);
+        for n in 0..3 {
+            assert_eq!(
+                reader.read_line().await.unwrap().unwrap(),
+                format!("message {}", n)
+            );
+        }
+        assert!(reader.read_line().await.unwrap().is_none());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
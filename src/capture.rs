@@ -0,0 +1,229 @@
+use crate::{Frame, FrameKind, FramedSender};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Start of every capture file, with the format version
+const CAPTURE_MAGIC: &[u8; 6] = b"SFCAP\x01";
+// Timestamp, direction, kind, flags, tag and length of a record
+const RECORD_HEADER_LEN: usize = 17;
+
+/// Whether a captured frame was sent or received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Frame read back from a capture file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedFrame {
+    /// Time since the capture started
+    pub elapsed: Duration,
+    pub direction: Direction,
+    /// The frame as written on or read from the wire, before checksums,
+    /// sequence numbers or trace contexts are removed
+    pub frame: Frame,
+}
+
+/// Records the frames of framed senders and receivers to a file
+///
+/// Clones write to the same file, so both halves of a channel can share
+/// one capture. Recording never fails the traffic, write errors are only
+/// logged. Read the file back with `CaptureReader`.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    inner: Arc<Mutex<CaptureFile>>,
+}
+
+#[derive(Debug)]
+struct CaptureFile {
+    file: File,
+    started: Instant,
+}
+
+impl Capture {
+    /// Start a capture, replacing any file at `path`
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = File::create(path)?;
+        file.write_all(CAPTURE_MAGIC)?;
+        Ok(Capture {
+            inner: Arc::new(Mutex::new(CaptureFile {
+                file,
+                started: Instant::now(),
+            })),
+        })
+    }
+
+    pub(crate) fn record(&self, direction: Direction, frame: &Frame) {
+        let mut capture = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let mut record = BytesMut::with_capacity(RECORD_HEADER_LEN + frame.payload.len());
+        record.put_u64_le(capture.started.elapsed().as_micros() as u64);
+        record.put_u8(direction as u8);
+        record.put_u8(frame.kind as u8);
+        record.put_u8(frame.flags);
+        record.put_u16_le(frame.tag);
+        record.put_u32_le(frame.payload.len() as u32);
+        record.put_slice(&frame.payload);
+        // One write per record, a crash leaves at most one torn record
+        if let Err(e) = capture.file.write_all(&record) {
+            warn!("Failed to record a frame: {}", e);
+        }
+    }
+}
+
+/// Reads the frames of a capture file in order
+#[derive(Debug)]
+pub struct CaptureReader {
+    reader: BufReader<File>,
+}
+
+impl CaptureReader {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; CAPTURE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not an sfifo capture file",
+            ));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    /// Read the next frame, `None` at the end of the capture
+    pub fn next_frame(&mut self) -> std::io::Result<Option<CapturedFrame>> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let elapsed = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let direction = match header[8] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            other => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Unknown capture direction {}", other),
+                ))
+            }
+        };
+        let kind = FrameKind::try_from(header[9])?;
+        let flags = header[10];
+        let tag = u16::from_le_bytes([header[11], header[12]]);
+        let len = u32::from_le_bytes(header[13..17].try_into().unwrap()) as usize;
+        let mut payload = vec![0u8; len];
+        self.reader.read_exact(&mut payload)?;
+        Ok(Some(CapturedFrame {
+            elapsed: Duration::from_micros(elapsed),
+            direction,
+            frame: Frame {
+                kind,
+                flags,
+                tag,
+                payload: Bytes::from(payload),
+            },
+        }))
+    }
+
+    /// Send the captured frames going in `direction` through `sender`,
+    /// returning how many were sent
+    ///
+    /// Frames are sent as captured, use a sender without checksums,
+    /// diagnostics or compression so they are not altered. With
+    /// `realtime`, the original spacing between frames is reproduced.
+    pub async fn replay(
+        mut self,
+        sender: &mut FramedSender,
+        direction: Direction,
+        realtime: bool,
+    ) -> std::io::Result<usize> {
+        let started = tokio::time::Instant::now();
+        let mut sent = 0;
+        while let Some(captured) = self.next_frame()? {
+            if captured.direction != direction {
+                continue;
+            }
+            if realtime {
+                tokio::time::sleep_until(started + captured.elapsed).await;
+            }
+            sender.send_frame(captured.frame).await?;
+            sent += 1;
+        }
+        debug!("Replayed {} captured frames", sent);
+        Ok(sent)
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = std::io::Result<CapturedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, FramedReceiver, Sfifo};
+
+    #[tokio::test]
+    async fn test_capture_and_replay() {
+        let fifo_path = "/tmp/test_capture";
+        let capture_path = "/tmp/test_capture.cap";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let capture = Capture::create(capture_path).unwrap();
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap())
+                .capture(capture.clone());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap())
+            .checksum(true)
+            .capture(capture);
+        sender.send(b"first").await.unwrap();
+        sender.send_user_control(7, b"control").await.unwrap();
+        sender.send(b"second").await.unwrap();
+        for _ in 0..2 {
+            receiver.recv().await.unwrap().unwrap();
+        }
+
+        let captured: Vec<_> = CaptureReader::open(capture_path)
+            .unwrap()
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        assert_eq!(captured.len(), 6);
+        let sent: Vec<_> = captured
+            .iter()
+            .filter(|c| c.direction == Direction::Sent)
+            .collect();
+        assert_eq!(sent[1].frame.kind, FrameKind::UserControl);
+        assert_eq!(sent[1].frame.tag, 7);
+        // Frames are captured as on the wire, checksum included
+        assert_ne!(sent[0].frame.flags, 0);
+        assert!(captured.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+
+        // Feed the received side of the session back into the receiver
+        let mut plain = FramedSender::new(sender.into_inner());
+        let replayed = CaptureReader::open(capture_path)
+            .unwrap()
+            .replay(&mut plain, Direction::Received, false)
+            .await
+            .unwrap();
+        assert_eq!(replayed, 3);
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"first");
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"second");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(capture_path).await;
+    }
+}
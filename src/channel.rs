@@ -0,0 +1,1047 @@
+use crate::{
+    control::{
+        control_frame, is_control, CONTROL_CLOSE, CONTROL_CLOSE_ACK, CONTROL_ROTATE,
+        CONTROL_ROTATE_ACK,
+    },
+    handshake_path,
+    health::{pipe_connected, process_alive},
+    limits, no_checkpoint_store, nonce,
+    rotation::{decode_rotate, is_rotate_ack, rotate_ack_frame, rotate_frame},
+    AtomicMetrics, Checkpoint, Compression, Control, Controls, Frame, FrameKind, FramedReceiver,
+    FramedSender, HandshakeMessage, HealthReport, Metrics, MetricsSnapshot, Reliable, Sfifo,
+    SfifoError, SfifoMetrics, TokenRotation, TraceContext,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{Sink, Stream};
+use std::{
+    collections::VecDeque,
+    os::fd::AsRawFd,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Duplex framed channel between an authenticated client and server.
+///
+/// Client to server traffic flows over the main FIFO path, server to
+/// client traffic over a companion `.rev` FIFO created by the client.
+#[derive(Debug)]
+pub struct Channel {
+    sender: FramedSender,
+    receiver: FramedReceiver,
+    peer_info: HandshakeMessage,
+    is_server: bool,
+    checkpoint: Option<Checkpoint>,
+    stats: Arc<ChannelCounters>,
+    handshake_duration: Duration,
+    reliable: Reliable,
+    // Incarnation of the peer's reliable sender, once it sent a message
+    reliable_peer: Option<u64>,
+    // Data received while waiting for acks, returned first by `recv()`
+    backlog: VecDeque<Bytes>,
+    backlog_bytes: usize,
+    memory_limit: Option<usize>,
+    controls: Option<mpsc::UnboundedSender<Control>>,
+    // Cancelled by a shutdown signal, see `Sfifo::with_signal_shutdown`
+    signal_shutdown: Option<CancellationToken>,
+    // Token shared with the peer, replaced by `rotate_token()`
+    rotation: Option<TokenRotation>,
+}
+
+/// Traffic of a channel, see `Channel::stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// Bytes received, frame headers included
+    pub bytes_in: u64,
+    /// Bytes sent, frame headers included
+    pub bytes_out: u64,
+    /// Frames received, control and heartbeat frames included
+    pub msgs_in: u64,
+    /// Frames sent, control and heartbeat frames included
+    pub msgs_out: u64,
+    /// When bytes were last sent or received, `None` before any traffic
+    pub last_activity: Option<Instant>,
+    /// Times a recreated FIFO was reopened
+    pub reconnects: u64,
+    /// Time from the start of `accept` or `connect` until the channel
+    /// was established, waiting for the peer included
+    pub handshake_duration: Duration,
+}
+
+/// Counters of a channel, fed by the metrics hooks of both halves
+#[derive(Debug, Default)]
+struct ChannelCounters {
+    metrics: AtomicMetrics,
+    last_activity: Mutex<Option<Instant>>,
+}
+
+impl ChannelCounters {
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    }
+}
+
+impl SfifoMetrics for ChannelCounters {
+    fn bytes_read(&self, n: usize) {
+        self.metrics.bytes_read(n);
+        self.touch();
+    }
+    fn bytes_written(&self, n: usize) {
+        self.metrics.bytes_written(n);
+        self.touch();
+    }
+    fn frame_sent(&self) {
+        self.metrics.frame_sent();
+    }
+    fn frame_received(&self) {
+        self.metrics.frame_received();
+    }
+    fn handshake_succeeded(&self) {
+        self.metrics.handshake_succeeded();
+    }
+    fn handshake_failed(&self) {
+        self.metrics.handshake_failed();
+    }
+    fn timeout(&self) {
+        self.metrics.timeout();
+    }
+    fn reconnect(&self) {
+        self.metrics.reconnect();
+    }
+}
+
+/// Latency and throughput measured by `Channel::ping`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PingReport {
+    /// Number of round trips performed
+    pub count: usize,
+    /// Payload size of each ping
+    pub payload_size: usize,
+    /// Total time spent for all round trips
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    /// Payload bytes echoed per second (both directions)
+    pub throughput_bytes_per_sec: f64,
+}
+
+impl PingReport {
+    fn from_samples(mut samples: Vec<Duration>, payload_size: usize) -> Self {
+        samples.sort();
+        let count = samples.len();
+        let total: Duration = samples.iter().sum();
+        let percentile = |p: usize| -> Duration {
+            if count == 0 {
+                return Duration::ZERO;
+            }
+            samples[((count * p).div_ceil(100)).saturating_sub(1).min(count - 1)]
+        };
+        let throughput_bytes_per_sec = if total.is_zero() {
+            0.0
+        } else {
+            (2 * payload_size * count) as f64 / total.as_secs_f64()
+        };
+        PingReport {
+            count,
+            payload_size,
+            total,
+            min: samples.first().copied().unwrap_or_default(),
+            max: samples.last().copied().unwrap_or_default(),
+            mean: if count == 0 {
+                Duration::ZERO
+            } else {
+                total / count as u32
+            },
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            throughput_bytes_per_sec,
+        }
+    }
+}
+
+impl Channel {
+    /// Accept a channel as server: authenticate the client over the main
+    /// FIFO, then attach to the reverse FIFO for replies
+    pub async fn accept(config: &Sfifo, token: &str) -> Result<Channel, std::io::Error> {
+        let started = Instant::now();
+        let receiver = config.open_as_server(token).await?;
+        let peer_info = receiver.peer_info().clone();
+        let receiver = receiver.into_framed_receiver()?;
+
+        let reverse = config
+            .side_channel(handshake_path(&config.file_path, "rev"))
+            .set_timeout(config.timeout)
+            .clone();
+        let sender = FramedSender::new(reverse.open_sender().await?);
+        debug!("Server: Channel established on {:?}", config.file_path);
+        let mut channel = Channel::new(config, sender, receiver, peer_info, true)
+            .with_handshake_duration(started.elapsed());
+        channel.retransmit().await?;
+        Ok(channel)
+    }
+
+    /// Connect a channel as client: authenticate against the server, then
+    /// create and open the reverse FIFO for replies
+    pub async fn connect(config: &Sfifo, token: &str) -> Result<Channel, std::io::Error> {
+        let started = Instant::now();
+        let sender = config.open_as_client(token).await?;
+        let peer_info = sender.peer_info().clone();
+        let sender = sender.into_framed_sender()?;
+
+        let reverse_path = handshake_path(&config.file_path, "rev");
+        let reverse = config.side_channel(&reverse_path).set_create(true).clone();
+        let receiver = FramedReceiver::with_capacity(
+            reverse.open_receiver().await?,
+            config.read_buffer_capacity,
+        );
+        debug!("Client: Channel established on {:?}", config.file_path);
+        let mut channel = Channel::new(config, sender, receiver, peer_info, false)
+            .with_handshake_duration(started.elapsed());
+        channel.retransmit().await?;
+        Ok(channel)
+    }
+
+    fn new(
+        config: &Sfifo,
+        sender: FramedSender,
+        receiver: FramedReceiver,
+        peer_info: HandshakeMessage,
+        is_server: bool,
+    ) -> Channel {
+        // Both halves report to the channel statistics and the configured hooks
+        let stats = Arc::new(ChannelCounters::default());
+        stats.handshake_succeeded();
+        let metrics = Metrics::chain(config.metrics.clone(), Metrics::new(stats.clone()));
+        Channel {
+            sender: sender
+                .with_metrics(Some(metrics.clone()))
+                .propagate_span_context(config.propagate_span_context)
+                .frame_diagnostics(config.frame_diagnostics)
+                .checksum(config.frame_checksum)
+                .rate_limit(config.rate_limit)
+                .max_frame_size(config.frame_size_limit())
+                .compression(Compression::negotiate(config.compression, &peer_info)),
+            receiver: receiver
+                .with_metrics(Some(metrics))
+                .frame_diagnostics(config.frame_diagnostics)
+                .max_frame_size(config.frame_size_limit())
+                .resync_on_corruption(config.frame_resync),
+            peer_info,
+            is_server,
+            checkpoint: config.checkpoint.clone(),
+            stats,
+            handshake_duration: Duration::ZERO,
+            reliable: config.reliable.clone().unwrap_or_default(),
+            reliable_peer: None,
+            backlog: VecDeque::new(),
+            backlog_bytes: 0,
+            memory_limit: config.memory_limit,
+            controls: None,
+            signal_shutdown: config.signal_shutdown_token(),
+            rotation: config.token_rotation.clone(),
+        }
+    }
+
+    fn with_handshake_duration(mut self, duration: Duration) -> Self {
+        self.handshake_duration = duration;
+        self
+    }
+
+    /// Get peer process information
+    pub fn peer_info(&self) -> &HandshakeMessage {
+        &self.peer_info
+    }
+
+    /// Check if this is the server side of the channel
+    pub fn is_server(&self) -> bool {
+        self.is_server
+    }
+
+    /// Sequence announced by the server to resume sending from, if it has
+    /// a checkpoint
+    pub fn resume_from(&self) -> Option<u64> {
+        self.peer_info.resume_from()
+    }
+
+    /// Commit `sequence` as processed to the configured checkpoint store
+    pub fn checkpoint(&self, sequence: u64) -> std::io::Result<()> {
+        match &self.checkpoint {
+            Some(checkpoint) => checkpoint.save(sequence),
+            None => Err(no_checkpoint_store()),
+        }
+    }
+
+    /// Trace context sent by the peer with the last received frame
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        self.receiver.trace_context()
+    }
+
+    /// Skip to the next frame from the peer after a corrupted or torn
+    /// frame, returning the number of bytes skipped
+    pub async fn resync(&mut self) -> std::io::Result<u64> {
+        self.receiver.resync().await
+    }
+
+    /// Counters of the traffic on this channel
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.stats.metrics.snapshot()
+    }
+
+    /// Counters of the traffic on this channel since the previous reset,
+    /// zeroing them
+    pub fn metrics_snapshot_and_reset(&self) -> MetricsSnapshot {
+        self.stats.metrics.snapshot_and_reset()
+    }
+
+    /// Traffic of this channel, counted since it was established or the
+    /// last `metrics_snapshot_and_reset`
+    pub fn stats(&self) -> ChannelStats {
+        let metrics = self.stats.metrics.snapshot();
+        ChannelStats {
+            bytes_in: metrics.bytes_read,
+            bytes_out: metrics.bytes_written,
+            msgs_in: metrics.frames_received,
+            msgs_out: metrics.frames_sent,
+            last_activity: *self
+                .stats
+                .last_activity
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()),
+            reconnects: metrics.reconnects,
+            handshake_duration: self.handshake_duration,
+        }
+    }
+
+    /// Send a data frame to the peer
+    pub async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.sender.send(data).await
+    }
+
+    /// Send a data frame the peer acknowledges, retransmitted after a
+    /// reconnect until it is
+    ///
+    /// Fails with `SfifoError::MemoryLimitExceeded` when the unacknowledged
+    /// messages would exceed the memory limit of the configuration.
+    pub async fn send_reliable(&mut self, data: &[u8]) -> std::io::Result<()> {
+        limits::reserve(self.memory_used(), data.len(), self.memory_limit)?;
+        let frame = self.reliable.enqueue(data);
+        self.sender.send_frame(frame).await
+    }
+
+    /// Acknowledge every reliable message received so far
+    pub async fn ack(&mut self) -> std::io::Result<()> {
+        match self
+            .reliable_peer
+            .and_then(|peer| self.reliable.ack_frame(peer))
+        {
+            Some(frame) => self.sender.send_frame(frame).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Number of reliable messages the peer has not acknowledged yet
+    pub fn unacked(&self) -> usize {
+        self.reliable.unacked()
+    }
+
+    /// Bytes of frames kept in memory: unacknowledged reliable messages
+    /// and data received but not returned yet
+    pub fn memory_used(&self) -> usize {
+        self.reliable.unacked_bytes() + self.backlog_bytes
+    }
+
+    /// Keep data received while waiting for something else for `recv()`
+    fn push_backlog(&mut self, data: Bytes) -> std::io::Result<()> {
+        limits::reserve(self.memory_used(), data.len(), self.memory_limit)?;
+        self.backlog_bytes += data.len();
+        self.backlog.push_back(data);
+        Ok(())
+    }
+
+    fn pop_backlog(&mut self) -> Option<Bytes> {
+        let data = self.backlog.pop_front()?;
+        self.backlog_bytes -= data.len();
+        Some(data)
+    }
+
+    /// Wait until the peer has acknowledged every reliable message
+    ///
+    /// Data received meanwhile is kept for `recv()`.
+    pub async fn wait_acked(&mut self) -> std::io::Result<()> {
+        while self.reliable.unacked() > 0 {
+            let frame = self.receiver.recv_frame().await?.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Peer closed the channel before acknowledging",
+                )
+            })?;
+            if let Some(data) = self.handle_frame(frame).await? {
+                self.push_backlog(data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the reliable messages not acknowledged on a previous channel,
+    /// from the sequence the server resumes at if it has a checkpoint
+    async fn retransmit(&mut self) -> std::io::Result<()> {
+        if let Some(sequence) = self.resume_from() {
+            self.reliable.resume_from(sequence);
+        }
+        let frames = self.reliable.unacked_frames();
+        if !frames.is_empty() {
+            debug!("Retransmitting {} unacknowledged messages", frames.len());
+        }
+        for frame in frames {
+            self.sender.send_frame(frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Close the channel without cutting the peer off mid-message
+    ///
+    /// Waits for the peer to acknowledge the reliable messages sent so far,
+    /// then sends a close frame and waits for the peer to acknowledge it,
+    /// which it does when `recv()` or `wait_acked()` reaches the close
+    /// frame and ends its stream. Data received meanwhile is dropped. Fails
+    /// with `SfifoError::DrainTimeout` if the peer has not acknowledged the
+    /// close by `deadline`.
+    pub async fn shutdown_gracefully(mut self, deadline: Instant) -> std::io::Result<()> {
+        let closed = tokio::time::timeout_at(deadline.into(), async {
+            self.wait_acked().await?;
+            self.sender.send_frame(control_frame(CONTROL_CLOSE)).await?;
+            loop {
+                let frame = self.receiver.recv_frame().await?.ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Peer closed the channel before acknowledging the close",
+                    )
+                })?;
+                if is_control(&frame, CONTROL_CLOSE_ACK) {
+                    return Ok(());
+                }
+                // A peer shutting down as well gets its close acknowledged,
+                // and acknowledges ours on its side
+                let peer_closing = is_control(&frame, CONTROL_CLOSE);
+                self.handle_frame(frame).await?;
+                if peer_closing {
+                    return Ok(());
+                }
+            }
+        })
+        .await;
+        closed.unwrap_or_else(|_| Err(SfifoError::DrainTimeout { unread: None }.into()))
+    }
+
+    /// Switch both sides of the channel to `token` without reconnecting
+    ///
+    /// Announces the token to the client in a control frame and waits for
+    /// its acknowledgment, which the client sends when `recv()` or
+    /// `wait_acked()` reaches the frame and has switched its
+    /// `TokenRotation`. The server switches once acknowledged and keeps
+    /// accepting the previous token in handshakes for the grace window.
+    /// Data received meanwhile is kept for `recv()`. Returns the new epoch,
+    /// or fails with `TimedOut` if not acknowledged by `deadline`, leaving
+    /// the server on the current token. Server side only, with
+    /// `Sfifo::set_token_rotation`.
+    pub async fn rotate_token(&mut self, token: &str, deadline: Instant) -> std::io::Result<u64> {
+        let rotation = match (&self.rotation, self.is_server) {
+            (Some(rotation), true) => rotation.clone(),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Token rotation needs a server channel with a TokenRotation",
+                ))
+            }
+        };
+        let epoch = rotation.epoch() + 1;
+        let acked = tokio::time::timeout_at(deadline.into(), async {
+            self.sender.send_frame(rotate_frame(epoch, token)).await?;
+            loop {
+                let frame = self.receiver.recv_frame().await?.ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Peer closed the channel before acknowledging the token rotation",
+                    )
+                })?;
+                if is_rotate_ack(&frame, epoch) {
+                    return Ok(());
+                }
+                if let Some(data) = self.handle_frame(frame).await? {
+                    self.push_backlog(data)?;
+                }
+            }
+        })
+        .await;
+        acked.unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Peer did not acknowledge the token rotation",
+            ))
+        })?;
+        rotation.rotate(epoch, token.into());
+        info!("Rotated the channel token to epoch {}", epoch);
+        Ok(epoch)
+    }
+
+    /// Switch to the token announced by the server and acknowledge it
+    ///
+    /// Only the server rotates the token, a client announcing one is a
+    /// protocol error.
+    async fn accept_rotation(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        if self.is_server {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Client sent a token rotation frame",
+            ));
+        }
+        let (epoch, token) = decode_rotate(payload)?;
+        let Some(rotation) = &self.rotation else {
+            warn!("Peer rotated the token, but token rotation is not enabled");
+            return Ok(());
+        };
+        if rotation.rotate(epoch, token) {
+            info!("Switched to the token of epoch {}", epoch);
+        }
+        self.sender.send_frame(rotate_ack_frame(epoch)).await
+    }
+
+    /// Send an application-defined control frame to the peer
+    pub async fn send_user_control(&mut self, tag: u16, payload: &[u8]) -> std::io::Result<()> {
+        self.sender.send_user_control(tag, payload).await
+    }
+
+    /// Send an out-of-band control signal to the peer
+    pub async fn send_control(&mut self, control: Control) -> std::io::Result<()> {
+        self.sender.send_frame(control.into_frame()).await
+    }
+
+    /// Stream of the control signals received from the peer, replacing
+    /// any previous one
+    ///
+    /// Signals are picked up while the channel is read with `recv()` or
+    /// `wait_acked()`. User control frames are also passed to the handler
+    /// set with `on_user_control`.
+    pub fn controls(&mut self) -> Controls {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.controls = Some(tx);
+        Controls::new(rx)
+    }
+
+    /// Set the handler invoked for user control frames from the peer
+    pub fn on_user_control(&mut self, handler: impl FnMut(u16, Bytes) + Send + 'static) {
+        self.receiver.on_user_control(handler);
+    }
+
+    /// Receive the next data frame, or `None` once the peer has closed
+    ///
+    /// Pings from the peer are answered while waiting for data. With
+    /// `Sfifo::with_signal_shutdown`, a shutdown signal sends the peer a
+    /// close frame and ends the stream.
+    pub async fn recv(&mut self) -> std::io::Result<Option<Bytes>> {
+        if let Some(data) = self.pop_backlog() {
+            return Ok(Some(data));
+        }
+        loop {
+            let frame = tokio::select! {
+                _ = signalled(self.signal_shutdown.as_ref()) => {
+                    self.close_on_signal().await;
+                    return Ok(None);
+                }
+                frame = self.receiver.recv_frame() => frame?,
+            };
+            let Some(frame) = frame else {
+                return Ok(None);
+            };
+            if let Some(data) = self.handle_frame(frame).await? {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    /// Tell the peer the channel is closing after a shutdown signal, once
+    async fn close_on_signal(&mut self) {
+        if self.signal_shutdown.take().is_none() {
+            return;
+        }
+        debug!("Shutdown signal received, closing the channel");
+        if let Err(e) = self.sender.send_frame(control_frame(CONTROL_CLOSE)).await {
+            debug!("Failed to send the close frame: {}", e);
+        }
+        self.receiver.close();
+    }
+
+    /// Handle a frame from the peer, returning the data to deliver
+    async fn handle_frame(&mut self, frame: Frame) -> std::io::Result<Option<Bytes>> {
+        match frame.kind {
+            FrameKind::Data => return Ok(Some(frame.payload)),
+            FrameKind::Reliable => {
+                let (peer, data) = self.reliable.receive(frame.payload)?;
+                self.reliable_peer = Some(peer);
+                match data {
+                    Some(data) => {
+                        if self.reliable.auto_ack() {
+                            self.ack().await?;
+                        }
+                        return Ok(Some(data));
+                    }
+                    None => {
+                        if let Some(ack) = self.reliable.repeat_ack_frame(peer) {
+                            self.sender.send_frame(ack).await?;
+                        }
+                    }
+                }
+            }
+            FrameKind::Ack => self.reliable.acknowledge(frame.payload)?,
+            FrameKind::Control if frame.tag == CONTROL_CLOSE => {
+                self.sender
+                    .send_frame(control_frame(CONTROL_CLOSE_ACK))
+                    .await?
+            }
+            FrameKind::Control if frame.tag == CONTROL_CLOSE_ACK => {}
+            FrameKind::Control if frame.tag == CONTROL_ROTATE => {
+                self.accept_rotation(&frame.payload).await?
+            }
+            FrameKind::Control if frame.tag == CONTROL_ROTATE_ACK => {}
+            FrameKind::Control => self.forward_control(frame)?,
+            FrameKind::UserControl => {
+                if self.controls.is_some() {
+                    self.forward_control(frame.clone())?;
+                }
+                self.receiver.handle_user_control(frame);
+            }
+            FrameKind::Ping => self.pong(frame.payload).await?,
+            FrameKind::Pong => {}
+        }
+        Ok(None)
+    }
+
+    /// Pass a control frame to the `controls()` stream, if any
+    fn forward_control(&mut self, frame: Frame) -> std::io::Result<()> {
+        if let Some(controls) = &self.controls {
+            if controls.send(Control::from_frame(frame)?).is_err() {
+                self.controls = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the channel into its sending and receiving halves
+    pub fn split(self) -> (FramedSender, FramedReceiver) {
+        (self.sender, self.receiver)
+    }
+
+    async fn pong(&mut self, payload: Bytes) -> std::io::Result<()> {
+        self.sender
+            .send_frame(Frame::new(FrameKind::Pong, payload))
+            .await
+    }
+
+    /// Answer pings from the peer until it closes the channel
+    ///
+    /// Data and user control frames received meanwhile are discarded.
+    pub async fn echo_server(&mut self) -> std::io::Result<()> {
+        while let Some(frame) = self.receiver.recv_frame().await? {
+            if frame.kind == FrameKind::Ping {
+                self.pong(frame.payload).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the peer is alive, and with a `ping_deadline` that it
+    /// answers a ping in time
+    ///
+    /// Data and control frames received while waiting for the pong are
+    /// handled as by `recv()`, the data being returned by the next calls.
+    pub async fn probe(
+        &mut self,
+        ping_deadline: Option<Duration>,
+    ) -> std::io::Result<HealthReport> {
+        let started = Instant::now();
+        let peer_pid = self.peer_info.process_id;
+        let mut report = HealthReport {
+            peer_pid,
+            peer_alive: process_alive(peer_pid, None),
+            pipe_connected: pipe_connected(self.sender.get_ref().as_raw_fd(), true)
+                && pipe_connected(self.receiver.as_raw_fd(), false),
+            round_trip: None,
+            pinged: false,
+            elapsed: Duration::ZERO,
+        };
+        if let Some(deadline) = ping_deadline.filter(|_| report.pipe_connected) {
+            let payload = Bytes::copy_from_slice(&nonce::new_nonce()?);
+            self.sender
+                .send_frame(Frame::new(FrameKind::Ping, payload.clone()))
+                .await?;
+            report.pinged = true;
+            let pong = tokio::time::timeout(deadline, async {
+                while let Some(frame) = self.receiver.recv_frame().await? {
+                    if frame.kind == FrameKind::Pong && frame.payload == payload {
+                        return Ok(true);
+                    }
+                    if let Some(data) = self.handle_frame(frame).await? {
+                        self.push_backlog(data)?;
+                    }
+                }
+                Ok::<_, std::io::Error>(false)
+            })
+            .await;
+            if let Ok(Ok(true)) = pong {
+                report.round_trip = Some(started.elapsed());
+            }
+        }
+        report.elapsed = started.elapsed();
+        Ok(report)
+    }
+
+    /// Measure round-trip latency against a peer running `echo_server()`
+    ///
+    /// Sends `n` pings of `size` bytes one after another. Frames other
+    /// than the matching pong are discarded, so this should be run on an
+    /// otherwise idle channel.
+    pub async fn ping(&mut self, n: usize, size: usize) -> std::io::Result<PingReport> {
+        let size = size.max(8);
+        let mut samples = Vec::with_capacity(n);
+        for seq in 0..n as u64 {
+            let mut payload = BytesMut::with_capacity(size);
+            payload.put_u64_le(seq);
+            payload.resize(size, 0xa5);
+            let payload = payload.freeze();
+
+            let start = Instant::now();
+            self.sender
+                .send_frame(Frame::new(FrameKind::Ping, payload.clone()))
+                .await?;
+            loop {
+                let frame = self.receiver.recv_frame().await?.ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Peer closed the channel during ping",
+                    )
+                })?;
+                if frame.kind == FrameKind::Pong && frame.payload == payload {
+                    break;
+                }
+            }
+            samples.push(start.elapsed());
+        }
+        Ok(PingReport::from_samples(samples, size))
+    }
+}
+
+/// Wait for `token` to be cancelled, forever without one
+async fn signalled(token: Option<&CancellationToken>) {
+    match token {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Yields incoming data frames
+///
+/// Unlike `recv()`, pings are not answered and reliable messages are not
+/// acknowledged when the channel is consumed as a stream.
+impl Stream for Channel {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(data) = self.pop_backlog() {
+            return Poll::Ready(Some(Ok(data)));
+        }
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+/// Sends each item as a data frame
+impl Sink<Bytes> for Channel {
+    type Error = std::io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.sender).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> std::io::Result<()> {
+        Pin::new(&mut self.sender).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.sender).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.sender).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::FRAME_HEADER_LEN;
+    use futures_util::{SinkExt, StreamExt};
+
+    #[test]
+    fn test_ping_report_percentiles() {
+        let samples = (1..=100).map(Duration::from_millis).collect();
+        let report = PingReport::from_samples(samples, 10);
+        assert_eq!(report.count, 100);
+        assert_eq!(report.min, Duration::from_millis(1));
+        assert_eq!(report.max, Duration::from_millis(100));
+        assert_eq!(report.p50, Duration::from_millis(50));
+        assert_eq!(report.p90, Duration::from_millis(90));
+        assert_eq!(report.p99, Duration::from_millis(99));
+    }
+
+    #[tokio::test]
+    async fn test_channel_ping_echo() {
+        let fifo_path = "/tmp/test_channel_ping";
+        let token = "ping_token";
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&server_config, token).await?;
+            channel.echo_server().await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        assert!(!channel.is_server());
+        let report = channel.ping(10, 64).await.unwrap();
+        assert_eq!(report.count, 10);
+        assert_eq!(report.payload_size, 64);
+        assert!(report.min <= report.p50 && report.p50 <= report.max);
+        let stats = channel.stats();
+        assert_eq!((stats.msgs_out, stats.msgs_in), (10, 10));
+        assert_eq!(stats.bytes_out, stats.bytes_in);
+        assert!(stats.bytes_out >= 10 * 64);
+        assert!(stats.last_activity.is_some());
+        assert!(stats.handshake_duration > Duration::ZERO);
+        drop(channel);
+
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_channel_forward_echo() {
+        let fifo_path = "/tmp/test_channel_forward";
+        let token = "forward_token";
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+
+        // Echo everything back by forwarding the stream half into the sink half
+        let server = tokio::spawn(async move {
+            let channel = Channel::accept(&server_config, token).await?;
+            let (sink, stream) = channel.split();
+            stream.forward(sink).await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        channel.send(b"hello").await.unwrap();
+        channel.send(b"world").await.unwrap();
+        assert_eq!(channel.next().await.unwrap().unwrap(), "hello");
+        assert_eq!(channel.next().await.unwrap().unwrap(), "world");
+
+        let stats = channel.metrics_snapshot_and_reset();
+        assert_eq!(stats.frames_sent, 2);
+        assert_eq!(stats.frames_received, 2);
+        assert_eq!(stats.bytes_written, 2 * (FRAME_HEADER_LEN as u64 + 5));
+        assert_eq!(stats.handshakes_succeeded, 1);
+        assert_eq!(channel.metrics_snapshot(), MetricsSnapshot::default());
+        channel.close().await.unwrap();
+        drop(channel);
+
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_channel_reliable_retransmit() {
+        let fifo_path = "/tmp/test_channel_reliable";
+        let token = "reliable_token";
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .clone();
+        let client_config = Sfifo::new(fifo_path)
+            .set_reliable(Some(Reliable::new()))
+            .clone();
+
+        // The first server receives without acknowledging, then exits
+        let config = server_config
+            .clone()
+            .set_reliable(Some(Reliable::new().with_auto_ack(false)))
+            .clone();
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&config, token).await?;
+            Ok::<_, std::io::Error>((channel.recv().await?, channel.recv().await?))
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        channel.send_reliable(b"one").await.unwrap();
+        channel.send_reliable(b"two").await.unwrap();
+        let (first, second) = server.await.unwrap().unwrap();
+        assert_eq!(first.unwrap(), "one");
+        assert_eq!(second.unwrap(), "two");
+        assert_eq!(channel.unacked(), 2);
+        drop(channel);
+
+        // A restarted server gets both again and acknowledges them
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&server_config, token).await?;
+            let received = (channel.recv().await?, channel.recv().await?);
+            assert!(channel.recv().await?.is_none());
+            Ok::<_, std::io::Error>(received)
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        channel.wait_acked().await.unwrap();
+        assert_eq!(channel.unacked(), 0);
+        drop(channel);
+
+        let (first, second) = server.await.unwrap().unwrap();
+        assert_eq!(first.unwrap(), "one");
+        assert_eq!(second.unwrap(), "two");
+    }
+
+    #[tokio::test]
+    async fn test_channel_reliable_resume_from() {
+        let fifo_path = "/tmp/test_channel_resume_from";
+        let checkpoint_path = "/tmp/test_channel_resume_from.seq";
+        let token = "resume_from_token";
+        let checkpoint = crate::Checkpoint::file(checkpoint_path);
+        let server_reliable = Reliable::new();
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .set_checkpoint(Some(checkpoint.clone()))
+            .set_reliable(Some(server_reliable.clone()))
+            .clone();
+
+        // The server processed the first message before the previous
+        // channel broke, only the rest is sent again
+        checkpoint.save(1).unwrap();
+        let reliable = Reliable::new();
+        for data in ["one", "two", "three"] {
+            reliable.enqueue(data.as_bytes());
+        }
+        let config = server_config.clone();
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&config, token).await?;
+            Ok::<_, std::io::Error>((channel.recv().await?, channel.recv().await?))
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_config = Sfifo::new(fifo_path)
+            .set_reliable(Some(reliable.clone()))
+            .clone();
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        channel.wait_acked().await.unwrap();
+        drop(channel);
+        let (first, second) = server.await.unwrap().unwrap();
+        assert_eq!(first.unwrap(), "two");
+        assert_eq!(second.unwrap(), "three");
+
+        // A sender that lost its state continues after the checkpoint
+        checkpoint.save(5).unwrap();
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&server_config, token).await?;
+            channel.recv().await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_config = Sfifo::new(fifo_path)
+            .set_reliable(Some(Reliable::new()))
+            .clone();
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        channel.send_reliable(b"six").await.unwrap();
+        channel.wait_acked().await.unwrap();
+        assert_eq!(server.await.unwrap().unwrap().unwrap(), "six");
+        assert_eq!(server_reliable.delivered(), 6);
+
+        let _ = tokio::fs::remove_file(checkpoint_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_channel_memory_limit() {
+        let fifo_path = "/tmp/test_channel_memory_limit";
+        let token = "memory_limit_token";
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .clone();
+        let client_config = Sfifo::new(fifo_path)
+            .set_reliable(Some(Reliable::new()))
+            .set_memory_limit(4)
+            .clone();
+
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&server_config, token).await?;
+            channel.recv().await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        channel.send_reliable(b"one").await.unwrap();
+        let error = channel.send_reliable(b"two").await.unwrap_err();
+        assert_eq!(
+            crate::SfifoError::from_io(&error),
+            Some(&crate::SfifoError::MemoryLimitExceeded {
+                requested: 3,
+                used: 3,
+                limit: 4
+            })
+        );
+        assert_eq!(channel.memory_used(), 3);
+        assert_eq!(server.await.unwrap().unwrap().unwrap(), "one");
+    }
+
+    #[tokio::test]
+    async fn test_channel_shutdown_gracefully() {
+        let fifo_path = "/tmp/test_channel_shutdown";
+        let token = "shutdown_token";
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&server_config, token).await?;
+            let mut received = Vec::new();
+            while let Some(data) = channel.recv().await? {
+                received.push(data);
+            }
+            Ok::<_, std::io::Error>(received)
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        channel.send(b"first").await.unwrap();
+        channel.send_reliable(b"second").await.unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        channel.shutdown_gracefully(deadline).await.unwrap();
+
+        let received = server.await.unwrap().unwrap();
+        assert_eq!(received, vec![&b"first"[..], &b"second"[..]]);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{extension::EXT_RESUME_FROM, Extension, HandshakeMessage};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Persists the last sequence number a consumer has fully processed
+pub trait CheckpointStore: Send + Sync {
+    /// Load the last committed sequence, `None` if nothing was committed yet
+    fn load(&self) -> std::io::Result<Option<u64>>;
+    /// Commit `sequence` as processed
+    fn save(&self, sequence: u64) -> std::io::Result<()>;
+}
+
+/// Stores the checkpoint as decimal text in a file, replaced atomically
+#[derive(Debug, Clone)]
+pub struct FileCheckpoint {
+    path: PathBuf,
+}
+
+impl FileCheckpoint {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileCheckpoint {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl CheckpointStore for FileCheckpoint {
+    fn load(&self) -> std::io::Result<Option<u64>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(content) => content
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self, sequence: u64) -> std::io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, sequence.to_string())?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+type LoadFn = Box<dyn Fn() -> std::io::Result<Option<u64>> + Send + Sync>;
+type SaveFn = Box<dyn Fn(u64) -> std::io::Result<()> + Send + Sync>;
+
+/// Delegates checkpoint storage to user callbacks
+pub struct CallbackCheckpoint {
+    load: LoadFn,
+    save: SaveFn,
+}
+
+impl CallbackCheckpoint {
+    pub fn new(
+        load: impl Fn() -> std::io::Result<Option<u64>> + Send + Sync + 'static,
+        save: impl Fn(u64) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        CallbackCheckpoint {
+            load: Box::new(load),
+            save: Box::new(save),
+        }
+    }
+}
+
+impl CheckpointStore for CallbackCheckpoint {
+    fn load(&self) -> std::io::Result<Option<u64>> {
+        (self.load)()
+    }
+
+    fn save(&self, sequence: u64) -> std::io::Result<()> {
+        (self.save)(sequence)
+    }
+}
+
+/// Shared handle to a checkpoint store, set on an `Sfifo` receiver
+///
+/// During the handshake the server loads the checkpoint and tells the
+/// client to resume from the sequence following it.
+#[derive(Clone)]
+pub struct Checkpoint(Arc<dyn CheckpointStore>);
+
+impl Checkpoint {
+    pub fn new(store: impl CheckpointStore + 'static) -> Self {
+        Checkpoint(Arc::new(store))
+    }
+
+    /// Checkpoint kept in a file
+    pub fn file(path: impl AsRef<Path>) -> Self {
+        Self::new(FileCheckpoint::new(path))
+    }
+
+    /// Load the last committed sequence
+    pub fn load(&self) -> std::io::Result<Option<u64>> {
+        self.0.load()
+    }
+
+    /// Commit `sequence` as processed
+    pub fn save(&self, sequence: u64) -> std::io::Result<()> {
+        self.0.save(sequence)
+    }
+
+    /// Sequence the sender should resume from, the one after the checkpoint
+    pub(crate) fn resume_extension(&self) -> std::io::Result<Option<Extension>> {
+        let Some(sequence) = self.load()? else {
+            return Ok(None);
+        };
+        let resume_from = sequence.checked_add(1).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Checkpoint is at the last sequence number",
+            )
+        })?;
+        Ok(Some(Extension::new(
+            EXT_RESUME_FROM,
+            resume_from.to_le_bytes(),
+        )))
+    }
+}
+
+impl std::fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Checkpoint").finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for CallbackCheckpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackCheckpoint").finish_non_exhaustive()
+    }
+}
+
+impl HandshakeMessage {
+    /// First sequence the peer has not processed yet, sent by a server
+    /// with a checkpoint store
+    pub fn resume_from(&self) -> Option<u64> {
+        let value = self.extension(EXT_RESUME_FROM)?;
+        Some(u64::from_le_bytes(value.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_checkpoint() {
+        let path = "/tmp/test_checkpoint_file";
+        let _ = std::fs::remove_file(path);
+        let checkpoint = Checkpoint::file(path);
+        assert_eq!(checkpoint.load().unwrap(), None);
+        assert!(checkpoint.resume_extension().unwrap().is_none());
+
+        checkpoint.save(41).unwrap();
+        assert_eq!(Checkpoint::file(path).load().unwrap(), Some(41));
+
+        let extension = checkpoint.resume_extension().unwrap().unwrap();
+        let message = HandshakeMessage::new(String::new(), crate::HandshakeType::Response)
+            .unwrap()
+            .with_extension(extension);
+        assert_eq!(message.resume_from(), Some(42));
+
+        checkpoint.save(u64::MAX).unwrap();
+        let error = checkpoint.resume_extension().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
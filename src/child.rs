@@ -0,0 +1,164 @@
+use crate::Sfifo;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use tokio::{
+    net::unix::pipe::{Receiver, Sender},
+    process::{Child, Command},
+};
+
+/// Descriptor a pipe end is attached to in the child process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildTarget {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// Any other descriptor number, e.g. 3 for `--sync-fd=3`
+    Fd(RawFd),
+}
+
+impl ChildTarget {
+    fn fd(self) -> RawFd {
+        match self {
+            ChildTarget::Stdin => libc::STDIN_FILENO,
+            ChildTarget::Stdout => libc::STDOUT_FILENO,
+            ChildTarget::Stderr => libc::STDERR_FILENO,
+            ChildTarget::Fd(fd) => fd,
+        }
+    }
+}
+
+/// Wires pipe or FIFO ends into a child process.
+///
+/// Each `to_child`/`from_child` call keeps the child side of a pipe and
+/// returns the parent side. `spawn` installs the child sides at their
+/// target descriptors (clearing close-on-exec only there), then closes
+/// them in the parent so that end-of-file is seen once the child exits.
+#[derive(Debug, Default)]
+pub struct ChildFifo {
+    ends: Vec<(OwnedFd, ChildTarget)>,
+}
+
+impl ChildFifo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Anonymous pipe the child reads from at `target`
+    pub fn to_child(&mut self, target: ChildTarget) -> std::io::Result<Sender> {
+        let (sender, receiver) = Sfifo::pair()?;
+        self.ends.push((receiver.into_blocking_fd()?, target));
+        Ok(sender)
+    }
+
+    /// Anonymous pipe the child writes to at `target`
+    pub fn from_child(&mut self, target: ChildTarget) -> std::io::Result<Receiver> {
+        let (sender, receiver) = Sfifo::pair()?;
+        self.ends.push((sender.into_blocking_fd()?, target));
+        Ok(receiver)
+    }
+
+    /// Named FIFO at `config.file_path` the child reads from at `target`
+    pub async fn fifo_to_child(
+        &mut self,
+        config: &Sfifo,
+        target: ChildTarget,
+    ) -> std::io::Result<Sender> {
+        // Opening the read end first lets the write end open without waiting
+        let receiver = config.open_receiver().await?;
+        let sender = config.reopening().open_sender().await?;
+        self.ends.push((receiver.into_blocking_fd()?, target));
+        Ok(sender)
+    }
+
+    /// Named FIFO at `config.file_path` the child writes to at `target`
+    pub async fn fifo_from_child(
+        &mut self,
+        config: &Sfifo,
+        target: ChildTarget,
+    ) -> std::io::Result<Receiver> {
+        let receiver = config.open_receiver().await?;
+        let sender = config.reopening().open_sender().await?;
+        self.ends.push((sender.into_blocking_fd()?, target));
+        Ok(receiver)
+    }
+
+    /// Spawn `command` with the child ends installed
+    pub fn spawn(self, command: &mut Command) -> std::io::Result<Child> {
+        let mappings: Vec<(RawFd, RawFd)> = self
+            .ends
+            .iter()
+            .map(|(fd, target)| (fd.as_raw_fd(), target.fd()))
+            .collect();
+        // Only async-signal-safe calls are allowed between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                for &(fd, target) in &mappings {
+                    if fd == target {
+                        let flags = libc::fcntl(fd, libc::F_GETFD);
+                        if flags < 0
+                            || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0
+                        {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    } else if libc::dup2(fd, target) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn();
+        // Dropping `self` closes the child ends in the parent
+        drop(self);
+        child
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_child_pipes() {
+        let mut wiring = ChildFifo::new();
+        let mut stdin = wiring.to_child(ChildTarget::Stdin).unwrap();
+        let mut stdout = wiring.from_child(ChildTarget::Stdout).unwrap();
+        let mut extra = wiring.from_child(ChildTarget::Fd(5)).unwrap();
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "cat; echo extra >&5"]);
+        let mut child = wiring.spawn(&mut command).unwrap();
+
+        stdin.write_all(b"through stdin").await.unwrap();
+        drop(stdin);
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "through stdin");
+        let mut output = String::new();
+        extra.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "extra\n");
+
+        assert!(child.wait().await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn test_child_named_fifo() {
+        let fifo_path = "/tmp/test_child_named_fifo";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        let mut wiring = ChildFifo::new();
+        let mut output = wiring
+            .fifo_from_child(&config, ChildTarget::Stdout)
+            .await
+            .unwrap();
+        let mut child = wiring.spawn(Command::new("echo").arg("from fifo")).unwrap();
+
+        let mut buf = String::new();
+        output.read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "from fifo\n");
+        assert!(child.wait().await.unwrap().success());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
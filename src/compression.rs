@@ -0,0 +1,175 @@
+use crate::{extension::EXT_COMPRESSION, Extension, HandshakeMessage};
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Frame flag: the payload is compressed, see `Compression`
+pub const FLAG_COMPRESSED: u8 = 0x08;
+/// Payloads smaller than this are sent uncompressed by default
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+// Algorithm id and uncompressed length in front of compressed payloads
+const HEADER_LEN: usize = 5;
+
+/// Compression algorithm for frame payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CompressionAlgorithm {
+    /// LZ4 block format, requires the `lz4` feature
+    Lz4 = 1,
+}
+
+impl CompressionAlgorithm {
+    /// Whether support for the algorithm was compiled in
+    pub fn is_available(self) -> bool {
+        match self {
+            CompressionAlgorithm::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(CompressionAlgorithm::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// Per-message compression of framed data and user control payloads.
+///
+/// Both peers advertise the algorithm in the handshake and it is only
+/// used when the other side supports it, frames are sent raw otherwise.
+/// Payloads below `threshold`, or that would not shrink, are sent raw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    pub threshold: usize,
+}
+
+impl Compression {
+    /// LZ4 with the default threshold
+    pub fn lz4() -> Self {
+        Compression {
+            algorithm: CompressionAlgorithm::Lz4,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+
+    /// Set the payload size below which messages are sent raw
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Handshake extension advertising the algorithm, if it is available
+    pub(crate) fn offer(config: Option<Compression>) -> Option<Extension> {
+        let compression = config.filter(|c| c.algorithm.is_available())?;
+        Some(Extension::new(
+            EXT_COMPRESSION,
+            vec![compression.algorithm as u8],
+        ))
+    }
+
+    /// The configured compression, if the peer advertised its algorithm
+    pub(crate) fn negotiate(
+        config: Option<Compression>,
+        peer: &HandshakeMessage,
+    ) -> Option<Compression> {
+        let compression = config.filter(|c| c.algorithm.is_available())?;
+        peer.compression_algorithms()
+            .contains(&compression.algorithm)
+            .then_some(compression)
+    }
+
+    /// Compress `payload` if it is large enough and shrinks
+    pub(crate) fn compress(&self, payload: &[u8]) -> Option<Bytes> {
+        if payload.len() < self.threshold || payload.len() > u32::MAX as usize {
+            return None;
+        }
+        let compressed = compress_block(self.algorithm, payload)?;
+        if HEADER_LEN + compressed.len() >= payload.len() {
+            return None;
+        }
+        let mut buf = BytesMut::with_capacity(HEADER_LEN + compressed.len());
+        buf.put_u8(self.algorithm as u8);
+        buf.put_u32_le(payload.len() as u32);
+        buf.put_slice(&compressed);
+        Some(buf.freeze())
+    }
+}
+
+#[cfg_attr(not(feature = "lz4"), allow(unused_variables))]
+fn compress_block(algorithm: CompressionAlgorithm, payload: &[u8]) -> Option<Vec<u8>> {
+    match algorithm {
+        #[cfg(feature = "lz4")]
+        CompressionAlgorithm::Lz4 => Some(lz4_flex::block::compress(payload)),
+        #[cfg(not(feature = "lz4"))]
+        CompressionAlgorithm::Lz4 => None,
+    }
+}
+
+/// Restore a compressed payload, refusing to inflate beyond `max_size`
+pub(crate) fn decompress(payload: &[u8], max_size: usize) -> std::io::Result<Bytes> {
+    let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+    if payload.len() < HEADER_LEN {
+        return Err(invalid("Truncated compressed frame"));
+    }
+    let algorithm = CompressionAlgorithm::from_id(payload[0])
+        .ok_or_else(|| invalid("Unknown frame compression algorithm"))?;
+    let len = u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]) as usize;
+    if len > max_size {
+        return Err(invalid("Decompressed frame too large"));
+    }
+    match algorithm {
+        #[cfg(feature = "lz4")]
+        CompressionAlgorithm::Lz4 => {
+            let data = lz4_flex::block::decompress(&payload[HEADER_LEN..], len)
+                .map_err(|e| invalid(&format!("Invalid lz4 frame: {}", e)))?;
+            if data.len() != len {
+                return Err(invalid("Decompressed frame length mismatch"));
+            }
+            Ok(data.into())
+        }
+        #[cfg(not(feature = "lz4"))]
+        CompressionAlgorithm::Lz4 => Err(invalid(
+            "Frame compressed with lz4, but the lz4 feature is disabled",
+        )),
+    }
+}
+
+impl HandshakeMessage {
+    /// Compression algorithms advertised by the peer
+    pub fn compression_algorithms(&self) -> Vec<CompressionAlgorithm> {
+        self.extension(EXT_COMPRESSION)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|&id| CompressionAlgorithm::from_id(id))
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "lz4"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_threshold_and_round_trip() {
+        let compression = Compression::lz4().with_threshold(64);
+        assert!(compression.compress(b"short").is_none());
+
+        let payload = "{\"level\":\"info\",\"msg\":\"pipe\"}\n".repeat(64);
+        let compressed = compression.compress(payload.as_bytes()).unwrap();
+        assert!(compressed.len() < payload.len());
+        assert_eq!(
+            decompress(&compressed, payload.len()).unwrap(),
+            payload.as_bytes()
+        );
+        assert!(decompress(&compressed, payload.len() - 1).is_err());
+
+        let peer = HandshakeMessage::new(String::new(), crate::HandshakeType::Response).unwrap();
+        assert_eq!(Compression::negotiate(Some(compression), &peer), None);
+        let peer = peer.with_extension(Compression::offer(Some(compression)).unwrap());
+        assert_eq!(
+            Compression::negotiate(Some(compression), &peer),
+            Some(compression)
+        );
+    }
+}
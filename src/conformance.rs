@@ -0,0 +1,390 @@
+use crate::{
+    Channel, Control, Frame, FrameKind, FramedReceiver, FramedSender, HandshakeMessage, Sfifo,
+};
+use std::{fmt, future::Future, process::Stdio, time::Duration};
+
+// Time allowed for each check
+const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+// Payload of the large frame check, several times PIPE_BUF
+const LARGE_PAYLOAD_LEN: usize = 16 * 1024;
+
+/// Protocol feature exercised by `Conformance`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConformanceFeature {
+    /// Three-way handshake on the `.c2s`/`.s2c` side channels
+    Handshake,
+    /// Data frames are echoed back unchanged, including empty ones
+    Framing,
+    /// Frames larger than `PIPE_BUF`, arriving in several reads
+    LargeFrames,
+    /// Frames carrying a CRC32 trailer are verified and accepted
+    Checksum,
+    /// Pings are answered with a pong carrying the same payload
+    Heartbeat,
+    /// Control frames are skipped without disturbing the data stream
+    Control,
+    /// The peer closes the reverse FIFO once the main FIFO is closed
+    Close,
+}
+
+impl ConformanceFeature {
+    /// Features every peer must support, the others are optional
+    pub fn is_required(&self) -> bool {
+        !matches!(
+            self,
+            ConformanceFeature::Checksum | ConformanceFeature::Control
+        )
+    }
+}
+
+/// Outcome of one check, `Err` holding the reason of a failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceCheck {
+    pub feature: ConformanceFeature,
+    pub result: Result<(), String>,
+}
+
+/// Features supported by a peer, as found by `Conformance::run`
+#[derive(Debug, Clone)]
+pub struct ConformanceReport {
+    /// Handshake message of the peer, if the handshake succeeded
+    pub peer: Option<HandshakeMessage>,
+    pub checks: Vec<ConformanceCheck>,
+}
+
+impl ConformanceReport {
+    /// Whether the peer passed the check of `feature`
+    pub fn supports(&self, feature: ConformanceFeature) -> bool {
+        self.checks
+            .iter()
+            .any(|check| check.feature == feature && check.result.is_ok())
+    }
+
+    /// Whether the peer supports every required feature
+    pub fn is_conformant(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.result.is_ok() || !check.feature.is_required())
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(peer) = &self.peer {
+            writeln!(f, "Peer {} (pid {})", peer.process_name, peer.process_id)?;
+        }
+        for check in &self.checks {
+            let required = if check.feature.is_required() {
+                "required"
+            } else {
+                "optional"
+            };
+            match &check.result {
+                Ok(()) => writeln!(f, "  {:?} ({}): ok", check.feature, required)?,
+                Err(reason) => writeln!(
+                    f,
+                    "  {:?} ({}): FAILED, {}",
+                    check.feature, required, reason
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Harness checking a peer implementation of the sfifo protocol, see
+/// `PROTOCOL.md`.
+///
+/// The harness connects as a `Channel` client to the peer under test,
+/// which must accept as a `Channel` server and then echo every data frame
+/// back as a data frame, answer pings, skip control frames, and close the
+/// reverse FIFO once the main FIFO reaches end-of-file.
+#[derive(Debug, Clone)]
+pub struct Conformance {
+    config: Sfifo,
+    token: String,
+    timeout: Duration,
+}
+
+impl Conformance {
+    /// Check the peer serving `config`, authenticating with `token`
+    pub fn new(config: &Sfifo, token: &str) -> Self {
+        Conformance {
+            config: config.clone(),
+            token: token.to_string(),
+            timeout: DEFAULT_CHECK_TIMEOUT,
+        }
+    }
+
+    /// Time allowed for each check, 3 seconds by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run every check against the peer
+    ///
+    /// Checks stop at the first failure of a required feature, as the
+    /// connection cannot be trusted afterwards. Features not reached are
+    /// reported as failed.
+    pub async fn run(&self) -> ConformanceReport {
+        let mut report = ConformanceReport {
+            peer: None,
+            checks: Vec::new(),
+        };
+        let channel = self
+            .check(Channel::connect(&self.config, &self.token))
+            .await;
+        let channel = match channel {
+            Ok(channel) => channel,
+            Err(reason) => {
+                report.record(ConformanceFeature::Handshake, Err(reason));
+                report.skip_remaining();
+                return report;
+            }
+        };
+        report.peer = Some(channel.peer_info().clone());
+        report.record(ConformanceFeature::Handshake, Ok(()));
+
+        let (mut sender, mut receiver) = channel.split();
+        for feature in [
+            ConformanceFeature::Framing,
+            ConformanceFeature::LargeFrames,
+            ConformanceFeature::Checksum,
+            ConformanceFeature::Heartbeat,
+            ConformanceFeature::Control,
+        ] {
+            let result;
+            (sender, result) = self.run_check(feature, sender, &mut receiver).await;
+            let failed = result.is_err() && feature.is_required();
+            report.record(feature, result);
+            if failed {
+                report.skip_remaining();
+                return report;
+            }
+        }
+
+        // Close the main FIFO and wait for the peer to close the reverse one
+        drop(sender);
+        let result = self
+            .check(async {
+                loop {
+                    if receiver.recv_frame().await?.is_none() {
+                        return Ok(());
+                    }
+                }
+            })
+            .await;
+        report.record(ConformanceFeature::Close, result);
+        report
+    }
+
+    /// Spawn the peer with `command` and run the checks against it
+    ///
+    /// The command gets the FIFO path and the token in the `SFIFO_PATH`
+    /// and `SFIFO_TOKEN` environment variables, and is killed once the
+    /// checks are done.
+    pub async fn run_with_peer(
+        &self,
+        command: &mut tokio::process::Command,
+    ) -> std::io::Result<ConformanceReport> {
+        let mut peer = command
+            .env("SFIFO_PATH", &self.config.file_path)
+            .env("SFIFO_TOKEN", &self.token)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let report = self.run().await;
+        let _ = peer.kill().await;
+        Ok(report)
+    }
+
+    async fn run_check(
+        &self,
+        feature: ConformanceFeature,
+        mut sender: FramedSender,
+        receiver: &mut FramedReceiver,
+    ) -> (FramedSender, Result<(), String>) {
+        let result = match feature {
+            ConformanceFeature::Framing => {
+                self.check(async {
+                    for payload in [&b"conformance"[..], b"", &[0u8, 0xff, b'\n', 0x53]] {
+                        sender.send(payload).await?;
+                        expect_echo(receiver, payload).await?;
+                    }
+                    Ok(())
+                })
+                .await
+            }
+            ConformanceFeature::LargeFrames => {
+                self.check(async {
+                    let payload: Vec<u8> = (0..LARGE_PAYLOAD_LEN).map(|i| i as u8).collect();
+                    sender.send(&payload).await?;
+                    expect_echo(receiver, &payload).await
+                })
+                .await
+            }
+            ConformanceFeature::Checksum => {
+                sender = sender.checksum(true);
+                let result = self
+                    .check(async {
+                        sender.send(b"checksummed").await?;
+                        expect_echo(receiver, b"checksummed").await
+                    })
+                    .await;
+                sender = sender.checksum(false);
+                result
+            }
+            ConformanceFeature::Heartbeat => {
+                self.check(async {
+                    let payload = b"conformance ping";
+                    sender
+                        .send_frame(Frame::new(FrameKind::Ping, &payload[..]))
+                        .await?;
+                    expect(receiver, |frame| {
+                        frame.kind == FrameKind::Pong && frame.payload[..] == payload[..]
+                    })
+                    .await
+                })
+                .await
+            }
+            ConformanceFeature::Control => {
+                self.check(async {
+                    sender.send_frame(Control::Flush.into_frame()).await?;
+                    sender.send_user_control(0x7fff, b"ignored").await?;
+                    sender.send(b"after control").await?;
+                    expect_echo(receiver, b"after control").await
+                })
+                .await
+            }
+            ConformanceFeature::Handshake | ConformanceFeature::Close => Ok(()),
+        };
+        (sender, result)
+    }
+
+    /// Run a check with the timeout, describing its error
+    async fn check<T>(&self, check: impl Future<Output = std::io::Result<T>>) -> Result<T, String> {
+        match tokio::time::timeout(self.timeout, check).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no answer within {:?}", self.timeout)),
+        }
+    }
+}
+
+impl ConformanceReport {
+    fn record(&mut self, feature: ConformanceFeature, result: Result<(), String>) {
+        self.checks.push(ConformanceCheck { feature, result });
+    }
+
+    /// Fail the features not checked yet
+    fn skip_remaining(&mut self) {
+        for feature in [
+            ConformanceFeature::Handshake,
+            ConformanceFeature::Framing,
+            ConformanceFeature::LargeFrames,
+            ConformanceFeature::Checksum,
+            ConformanceFeature::Heartbeat,
+            ConformanceFeature::Control,
+            ConformanceFeature::Close,
+        ] {
+            if !self.checks.iter().any(|check| check.feature == feature) {
+                self.record(feature, Err("not checked".to_string()));
+            }
+        }
+    }
+}
+
+/// Read frames until one matches, skipping the others
+async fn expect(
+    receiver: &mut FramedReceiver,
+    mut matches: impl FnMut(&Frame) -> bool,
+) -> std::io::Result<()> {
+    loop {
+        let frame = receiver.recv_frame().await?.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Peer closed the channel")
+        })?;
+        if matches(&frame) {
+            return Ok(());
+        }
+        debug!("Conformance: skipping {:?} frame", frame.kind);
+    }
+}
+
+/// Read frames until the echo of `payload`, failing on any other data
+async fn expect_echo(receiver: &mut FramedReceiver, payload: &[u8]) -> std::io::Result<()> {
+    let mut mismatch = None;
+    expect(receiver, |frame| {
+        if frame.kind != FrameKind::Data {
+            return false;
+        }
+        if frame.payload[..] != payload[..] {
+            mismatch = Some(frame.payload.len());
+        }
+        true
+    })
+    .await?;
+    match mismatch {
+        Some(len) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Echoed {} bytes differ from the {} bytes sent",
+                len,
+                payload.len()
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reference peer: echoes data, answers pings unless told not to
+    async fn echo_peer(config: Sfifo, token: &str, answer_pings: bool) -> std::io::Result<()> {
+        let (mut sender, mut receiver) = Channel::accept(&config, token).await?.split();
+        while let Some(frame) = receiver.recv_frame().await? {
+            match frame.kind {
+                FrameKind::Data => sender.send(&frame.payload).await?,
+                FrameKind::Ping if answer_pings => {
+                    sender
+                        .send_frame(Frame::new(FrameKind::Pong, frame.payload))
+                        .await?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conformance_against_reference_peer() {
+        let fifo_path = "/tmp/test_conformance";
+        let token = "conformance_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        let peer = tokio::spawn(echo_peer(config.clone(), token, true));
+        let report = Conformance::new(&config, token).run().await;
+        assert!(report.is_conformant(), "{}", report);
+        assert!(report.checks.iter().all(|check| check.result.is_ok()));
+        assert_eq!(report.checks.len(), 7);
+        peer.await.unwrap().unwrap();
+
+        // A peer ignoring pings fails the heartbeat check
+        let peer = tokio::spawn(echo_peer(config.clone(), token, false));
+        let report = Conformance::new(&config, token)
+            .timeout(Duration::from_millis(300))
+            .run()
+            .await;
+        assert!(report.supports(ConformanceFeature::Framing));
+        assert!(!report.supports(ConformanceFeature::Heartbeat));
+        assert!(!report.supports(ConformanceFeature::Close));
+        assert!(!report.is_conformant());
+        drop(report);
+        peer.abort();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
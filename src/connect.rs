@@ -0,0 +1,97 @@
+use crate::{AuthenticatedFifo, Sfifo};
+use futures_util::StreamExt;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Handshakes run at once by `Sfifo::connect_all`
+pub const DEFAULT_CONNECT_CONCURRENCY: usize = 16;
+
+impl Sfifo {
+    /// Connect as client to every FIFO of `paths` with `token`, running up
+    /// to `DEFAULT_CONNECT_CONCURRENCY` handshakes at once
+    ///
+    /// Each path is opened with the options of this configuration. The
+    /// result of every path is returned, a failed handshake does not stop
+    /// the others.
+    pub async fn connect_all(
+        &self,
+        paths: impl IntoIterator<Item = impl AsRef<Path>>,
+        token: &str,
+    ) -> HashMap<PathBuf, std::io::Result<AuthenticatedFifo>> {
+        self.connect_all_bounded(paths, token, DEFAULT_CONNECT_CONCURRENCY)
+            .await
+    }
+
+    /// `connect_all` with at most `concurrency` handshakes at once
+    pub async fn connect_all_bounded(
+        &self,
+        paths: impl IntoIterator<Item = impl AsRef<Path>>,
+        token: &str,
+        concurrency: usize,
+    ) -> HashMap<PathBuf, std::io::Result<AuthenticatedFifo>> {
+        let connections = paths.into_iter().map(|path| {
+            let config = self.at_path(path);
+            async move {
+                let result = config.open_as_client(token).await;
+                if let Err(e) = &result {
+                    warn!("Failed to connect to {:?}: {}", config.file_path, e);
+                }
+                (config.file_path, result)
+            }
+        });
+        futures_util::stream::iter(connections)
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_connect_all() {
+        let token = "connect_all_token";
+        let paths: Vec<_> = (0..4)
+            .map(|i| PathBuf::from(format!("/tmp/test_connect_all_{}", i)))
+            .collect();
+        for path in &paths {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        // The last path has no server, and a handshake channel that is
+        // not a FIFO: its handshake fails once the open times out
+        tokio::fs::write(paths[3].with_extension("c2s"), b"")
+            .await
+            .unwrap();
+        let servers: Vec<_> = paths[..3]
+            .iter()
+            .map(|path| {
+                let config = Sfifo::new(path).set_create(true).clone();
+                tokio::spawn(async move { config.open_as_server(token).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let results = Sfifo::new("/unused")
+            .connect_all_bounded(&paths, token, 2)
+            .await;
+        assert_eq!(results.len(), 4);
+        for path in &paths[..3] {
+            assert!(results[path].is_ok());
+        }
+        assert!(results[&paths[3]].is_err());
+        for server in servers {
+            server.await.unwrap().unwrap();
+        }
+
+        for path in &paths {
+            let _ = tokio::fs::remove_file(path).await;
+            let _ = tokio::fs::remove_file(path.with_extension("c2s")).await;
+            let _ = tokio::fs::remove_file(path.with_extension("s2c")).await;
+        }
+    }
+}
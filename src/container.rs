@@ -0,0 +1,394 @@
+use crate::{dir::file_name, dir::make_fifo, pipe::open_held_receiver, Sfifo};
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf},
+    net::unix::pipe::{Receiver, Sender},
+};
+
+/// Mode of the FIFOs created without a channel ACL
+const DEFAULT_STDIO_MODE: u32 = 0o600;
+
+/// Control message for the process attached to a `ContainerStdio`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdioControl {
+    /// Resize the terminal of the container
+    Resize { width: u16, height: u16 },
+    /// Close the stdin of the container
+    CloseStdin,
+}
+
+impl StdioControl {
+    // One line per message, atomic on the FIFO and readable with `cat`
+    fn encode(self) -> String {
+        match self {
+            StdioControl::Resize { width, height } => format!("resize {} {}\n", width, height),
+            StdioControl::CloseStdin => "close-stdin\n".to_string(),
+        }
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let control = match words.next()? {
+            "resize" => StdioControl::Resize {
+                width: words.next()?.parse().ok()?,
+                height: words.next()?.parse().ok()?,
+            },
+            "close-stdin" => StdioControl::CloseStdin,
+            _ => return None,
+        };
+        words.next().is_none().then_some(control)
+    }
+}
+
+/// FIFOs attaching the stdio of a container, named after its id
+///
+/// In `dir`, the container `id` gets `<id>-stdin`, `<id>-stdout`,
+/// `<id>-stderr` and `<id>-control`, the paths handed to the runtime.
+/// With a terminal, stderr is merged into stdout and has no FIFO. The
+/// side creating the FIFOs owns them and removes them on drop; the other
+/// side uses `ContainerStdio::new` with the same directory and id. Opens
+/// use the timeout and retry policy of the configuration given.
+#[derive(Debug)]
+pub struct ContainerStdio {
+    dir: PathBuf,
+    id: String,
+    terminal: bool,
+    config: Sfifo,
+    // FIFOs created by this side, removed on drop
+    created: Vec<PathBuf>,
+}
+
+impl ContainerStdio {
+    /// Refer to the FIFOs of container `id` in `dir`, without creating them
+    pub fn new(dir: impl AsRef<Path>, id: &str, config: &Sfifo) -> std::io::Result<Self> {
+        file_name(Path::new(id))?;
+        Ok(ContainerStdio {
+            dir: dir.as_ref().to_path_buf(),
+            id: id.to_string(),
+            terminal: false,
+            config: config.clone(),
+            created: Vec::new(),
+        })
+    }
+
+    /// Whether the container runs with a terminal, which has no stderr
+    pub fn terminal(mut self, terminal: bool) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// Create the FIFOs, which must not exist yet, and own them
+    ///
+    /// They get the group and mode of the channel ACL of the
+    /// configuration, or mode 0600 without one. FIFOs created before an
+    /// error are removed.
+    pub fn create(mut self) -> std::io::Result<Self> {
+        for path in self.paths() {
+            make_fifo(None, &path, true)?;
+            // Dropping `self` on error removes the FIFOs created so far
+            self.created.push(path.clone());
+            match &self.config.channel_acl {
+                Some(acl) => acl.apply(&path)?,
+                None => std::fs::set_permissions(
+                    &path,
+                    std::fs::Permissions::from_mode(DEFAULT_STDIO_MODE),
+                )?,
+            }
+        }
+        debug!("Created stdio FIFOs of container {}", self.id);
+        Ok(self)
+    }
+
+    fn path(&self, stream: &str) -> PathBuf {
+        self.dir.join(format!("{}-{}", self.id, stream))
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.stdin_path(), self.stdout_path()];
+        paths.extend(self.stderr_path());
+        paths.push(self.control_path());
+        paths
+    }
+
+    pub fn stdin_path(&self) -> PathBuf {
+        self.path("stdin")
+    }
+
+    pub fn stdout_path(&self) -> PathBuf {
+        self.path("stdout")
+    }
+
+    /// Path of the stderr FIFO, `None` with a terminal
+    pub fn stderr_path(&self) -> Option<PathBuf> {
+        (!self.terminal).then(|| self.path("stderr"))
+    }
+
+    pub fn control_path(&self) -> PathBuf {
+        self.path("control")
+    }
+
+    /// Open stdin for writing, waiting for the container to open it
+    pub async fn stdin(&self) -> std::io::Result<StdinWriter> {
+        let sender = self.config.at_path(self.stdin_path()).open_sender().await?;
+        Ok(StdinWriter { sender })
+    }
+
+    /// Open stdout for reading, waiting for the container to open it
+    pub async fn stdout(&self) -> std::io::Result<StdoutReader> {
+        let receiver = self.open_output(self.stdout_path()).await?;
+        Ok(StdoutReader { receiver })
+    }
+
+    /// Open stderr for reading, waiting for the container to open it
+    pub async fn stderr(&self) -> std::io::Result<StderrReader> {
+        let Some(path) = self.stderr_path() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "a container with a terminal has no stderr",
+            ));
+        };
+        let receiver = self.open_output(path).await?;
+        Ok(StderrReader { receiver })
+    }
+
+    async fn open_output(&self, path: PathBuf) -> std::io::Result<Receiver> {
+        self.config
+            .at_path(path)
+            .set_wait_for_writer(true)
+            .open_receiver()
+            .await
+    }
+
+    /// Open the control FIFO for sending, waiting for the runtime side to
+    /// listen
+    pub async fn control_sender(&self) -> std::io::Result<StdioControlSender> {
+        let sender = self
+            .config
+            .at_path(self.control_path())
+            .open_sender()
+            .await?;
+        Ok(StdioControlSender { sender })
+    }
+
+    /// Listen on the control FIFO, on the runtime side
+    ///
+    /// The FIFO is opened for reading and writing, so the listener never
+    /// sees end-of-file while senders come and go.
+    pub fn control_receiver(&self) -> std::io::Result<StdioControlReceiver> {
+        let (receiver, hold) = open_held_receiver(&self.control_path())?;
+        Ok(StdioControlReceiver {
+            lines: BufReader::new(receiver),
+            _hold: hold,
+        })
+    }
+}
+
+impl Drop for ContainerStdio {
+    fn drop(&mut self) {
+        for path in &self.created {
+            if std::fs::remove_file(path).is_ok() {
+                debug!("Removed FIFO {:?}", path);
+            }
+        }
+    }
+}
+
+/// Write end of the stdin of a container, dropping it closes stdin
+#[derive(Debug)]
+pub struct StdinWriter {
+    sender: Sender,
+}
+
+impl StdinWriter {
+    /// Get the underlying pipe sender
+    pub fn into_inner(self) -> Sender {
+        self.sender
+    }
+}
+
+impl AsyncWrite for StdinWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().sender).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().sender).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().sender).poll_shutdown(cx)
+    }
+}
+
+/// Read end of the stdout of a container
+#[derive(Debug)]
+pub struct StdoutReader {
+    receiver: Receiver,
+}
+
+impl StdoutReader {
+    /// Get the underlying pipe receiver
+    pub fn into_inner(self) -> Receiver {
+        self.receiver
+    }
+}
+
+impl AsyncRead for StdoutReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().receiver).poll_read(cx, buf)
+    }
+}
+
+/// Read end of the stderr of a container
+#[derive(Debug)]
+pub struct StderrReader {
+    receiver: Receiver,
+}
+
+impl StderrReader {
+    /// Get the underlying pipe receiver
+    pub fn into_inner(self) -> Receiver {
+        self.receiver
+    }
+}
+
+impl AsyncRead for StderrReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().receiver).poll_read(cx, buf)
+    }
+}
+
+/// Sends control messages to the runtime side of a container
+#[derive(Debug)]
+pub struct StdioControlSender {
+    sender: Sender,
+}
+
+impl StdioControlSender {
+    pub async fn send(&mut self, control: StdioControl) -> std::io::Result<()> {
+        self.sender.write_all(control.encode().as_bytes()).await
+    }
+
+    pub async fn resize(&mut self, width: u16, height: u16) -> std::io::Result<()> {
+        self.send(StdioControl::Resize { width, height }).await
+    }
+
+    pub async fn close_stdin(&mut self) -> std::io::Result<()> {
+        self.send(StdioControl::CloseStdin).await
+    }
+}
+
+/// Receives the control messages sent to a container
+#[derive(Debug)]
+pub struct StdioControlReceiver {
+    lines: BufReader<Receiver>,
+    // Write end keeping the FIFO open where it cannot be opened read-write
+    _hold: Option<Sender>,
+}
+
+impl StdioControlReceiver {
+    /// Wait for the next control message, skipping unknown ones
+    pub async fn recv(&mut self) -> std::io::Result<StdioControl> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.lines.read_line(&mut line).await? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            match StdioControl::decode(&line) {
+                Some(control) => return Ok(control),
+                None => warn!("Ignoring unknown stdio control {:?}", line.trim_end()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_container_stdio() {
+        let dir = "/tmp/test_container_stdio";
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        tokio::fs::create_dir(dir).await.unwrap();
+        let config = Sfifo::new(dir);
+
+        let stdio = ContainerStdio::new(dir, "ctr1", &config)
+            .unwrap()
+            .create()
+            .unwrap();
+        let stdin_path = stdio.stdin_path();
+        let metadata = std::fs::metadata(&stdin_path).unwrap();
+        assert!(metadata.file_type().is_fifo());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert!(ContainerStdio::new(dir, "ctr1", &config)
+            .unwrap()
+            .create()
+            .is_err());
+        assert!(ContainerStdio::new(dir, "../ctr1", &config).is_err());
+
+        // The container side, here a shell
+        let mut child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!(
+                "cat < {0}-stdin > {0}-stdout; echo oops > {0}-stderr",
+                Path::new(dir).join("ctr1").display()
+            ))
+            .spawn()
+            .unwrap();
+        let mut stdin = stdio.stdin().await.unwrap();
+        let mut stdout = stdio.stdout().await.unwrap();
+        stdin.write_all(b"hello").await.unwrap();
+        drop(stdin);
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "hello");
+        let mut stderr = stdio.stderr().await.unwrap();
+        let mut output = String::new();
+        stderr.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "oops\n");
+        child.wait().await.unwrap();
+
+        let runtime = ContainerStdio::new(dir, "ctr1", &config).unwrap();
+        let mut listener = runtime.control_receiver().unwrap();
+        let mut control = stdio.control_sender().await.unwrap();
+        control.resize(120, 40).await.unwrap();
+        control.sender.write_all(b"bogus\n").await.unwrap();
+        control.close_stdin().await.unwrap();
+        assert_eq!(
+            listener.recv().await.unwrap(),
+            StdioControl::Resize {
+                width: 120,
+                height: 40
+            }
+        );
+        assert_eq!(listener.recv().await.unwrap(), StdioControl::CloseStdin);
+
+        // Only the creating side removes the FIFOs
+        drop(runtime);
+        assert!(stdin_path.exists());
+        drop(stdio);
+        assert!(!stdin_path.exists());
+        let _ = tokio::fs::remove_dir_all(dir).await;
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{Frame, FrameKind};
+use bytes::Bytes;
+use futures_util::Stream;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+// Tags of control frames
+const CONTROL_PAUSE: u16 = 1;
+const CONTROL_RESUME: u16 = 2;
+const CONTROL_FLUSH: u16 = 3;
+const CONTROL_RENAME: u16 = 4;
+// Markers of a transaction, consumed by the receiver
+pub(crate) const CONTROL_BEGIN: u16 = 5;
+pub(crate) const CONTROL_COMMIT: u16 = 6;
+// Snapshot request, or snapshot when it has a payload
+pub(crate) const CONTROL_SNAPSHOT: u16 = 7;
+// End of the stream sent by a graceful shutdown, and its acknowledgment
+pub(crate) const CONTROL_CLOSE: u16 = 8;
+pub(crate) const CONTROL_CLOSE_ACK: u16 = 9;
+// New token announced by the server, and its acknowledgment
+pub(crate) const CONTROL_ROTATE: u16 = 10;
+pub(crate) const CONTROL_ROTATE_ACK: u16 = 11;
+
+/// Control frame with `tag` and no payload
+pub(crate) fn control_frame(tag: u16) -> Frame {
+    Frame {
+        tag,
+        ..Frame::new(FrameKind::Control, Bytes::new())
+    }
+}
+
+/// Whether `frame` is the control frame with `tag`
+pub(crate) fn is_control(frame: &Frame, tag: u16) -> bool {
+    frame.kind == FrameKind::Control && frame.tag == tag
+}
+
+/// Out-of-band signal between the peers of a `Channel`, kept apart from
+/// the data stream. The library only carries them, acting on them is up
+/// to the application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    /// Ask the peer to stop sending data
+    Pause,
+    /// Let the peer send data again after a `Pause`
+    Resume,
+    /// Ask the peer to flush what it has buffered
+    Flush,
+    /// Tell the peer the stream was renamed
+    Rename(String),
+    /// Application-defined signal, sent as a user control frame
+    User(u16, Bytes),
+}
+
+impl Control {
+    pub(crate) fn into_frame(self) -> Frame {
+        let (tag, payload) = match self {
+            Control::Pause => (CONTROL_PAUSE, Bytes::new()),
+            Control::Resume => (CONTROL_RESUME, Bytes::new()),
+            Control::Flush => (CONTROL_FLUSH, Bytes::new()),
+            Control::Rename(name) => (CONTROL_RENAME, Bytes::from(name)),
+            Control::User(tag, payload) => return Frame::user_control(tag, payload),
+        };
+        Frame {
+            tag,
+            ..Frame::new(FrameKind::Control, payload)
+        }
+    }
+
+    /// Decode a control or user control frame
+    pub(crate) fn from_frame(frame: Frame) -> std::io::Result<Control> {
+        if frame.kind == FrameKind::UserControl {
+            return Ok(Control::User(frame.tag, frame.payload));
+        }
+        match frame.tag {
+            CONTROL_PAUSE => Ok(Control::Pause),
+            CONTROL_RESUME => Ok(Control::Resume),
+            CONTROL_FLUSH => Ok(Control::Flush),
+            CONTROL_RENAME => String::from_utf8(frame.payload.to_vec())
+                .map(Control::Rename)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            tag => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unknown control signal {}", tag),
+            )),
+        }
+    }
+}
+
+/// Control signals received from the peer, see `Channel::controls`
+#[derive(Debug)]
+pub struct Controls {
+    rx: mpsc::UnboundedReceiver<Control>,
+}
+
+impl Controls {
+    pub(crate) fn new(rx: mpsc::UnboundedReceiver<Control>) -> Self {
+        Controls { rx }
+    }
+
+    /// Next control signal, `None` once the channel is gone
+    pub async fn recv(&mut self) -> Option<Control> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for Controls {
+    type Item = Control;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Control>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Channel, Sfifo};
+    use futures_util::StreamExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_channel_controls() {
+        let fifo_path = "/tmp/test_channel_controls";
+        let token = "control_token";
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+        let signals = vec![
+            Control::Pause,
+            Control::Resume,
+            Control::Flush,
+            Control::Rename("logs.1".to_string()),
+            Control::User(7, Bytes::from_static(b"rotate")),
+        ];
+
+        let sent = signals.clone();
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&server_config, token).await?;
+            channel.send(b"before").await?;
+            for control in sent {
+                channel.send_control(control).await?;
+            }
+            channel.send(b"after").await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut channel = Channel::connect(&client_config, token).await.unwrap();
+        let controls = channel.controls();
+        assert_eq!(channel.recv().await.unwrap().unwrap(), "before");
+        assert_eq!(channel.recv().await.unwrap().unwrap(), "after");
+        server.await.unwrap().unwrap();
+        drop(channel);
+
+        assert_eq!(controls.collect::<Vec<_>>().await, signals);
+    }
+}
@@ -0,0 +1,238 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::SfifoError;
+
+/// Frame flag: the payload starts with a writer id and sequence number
+pub const FLAG_SEQUENCED: u8 = 0x02;
+/// Encoded size of the sequence header: writer id and sequence number
+pub const SEQUENCE_HEADER_LEN: usize = 16;
+/// Bytes included in the dump of a corrupted frame stream
+pub(crate) const DUMP_LEN: usize = 64;
+
+/// Diagnostic checks on the frame stream, to track down corruption caused
+/// by several writers sharing a FIFO
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDiagnostics {
+    #[default]
+    Off,
+    /// Senders stamp frames with a writer id and sequence number, receivers
+    /// check that sequences are strictly increasing per writer and report
+    /// gaps in them
+    Check,
+    /// Like `Check`, and errors about a corrupted stream carry a dump of
+    /// the bytes at the offending offset
+    CheckAndDump,
+}
+
+impl FrameDiagnostics {
+    pub fn is_enabled(&self) -> bool {
+        *self != FrameDiagnostics::Off
+    }
+}
+
+/// Sequence state of a sender
+#[derive(Debug)]
+pub(crate) struct SequenceStamp {
+    writer: u64,
+    next: u64,
+}
+
+impl SequenceStamp {
+    /// Writer ids combine the pid with a per-process counter
+    pub(crate) fn new() -> Self {
+        static INSTANCE: AtomicU32 = AtomicU32::new(0);
+        let instance = INSTANCE.fetch_add(1, Ordering::Relaxed);
+        SequenceStamp {
+            writer: (std::process::id() as u64) << 32 | instance as u64,
+            next: 0,
+        }
+    }
+
+    /// Prefix `payload` with the writer id and the next sequence number
+    pub(crate) fn stamp(&mut self, payload: &[u8]) -> Bytes {
+        let mut buf = BytesMut::with_capacity(SEQUENCE_HEADER_LEN + payload.len());
+        buf.put_u64_le(self.writer);
+        buf.put_u64_le(self.next);
+        buf.put_slice(payload);
+        self.next += 1;
+        buf.freeze()
+    }
+
+    /// Use up a sequence number for a message that will never be sent, so
+    /// the receiver sees the gap
+    pub(crate) fn skip(&mut self) {
+        self.next += 1;
+    }
+}
+
+/// Last sequence seen from each writer
+#[derive(Debug, Default)]
+pub(crate) struct SequenceTracker {
+    last: HashMap<u64, u64>,
+    // Gap found in front of the last stripped frame
+    gap: Option<SfifoError>,
+}
+
+impl SequenceTracker {
+    /// Split the sequence header off `payload` and, if `check` is set,
+    /// verify it against the previous frame of the same writer
+    pub(crate) fn strip(
+        &mut self,
+        payload: &mut Bytes,
+        check: bool,
+        offset: u64,
+    ) -> std::io::Result<()> {
+        if payload.len() < SEQUENCE_HEADER_LEN {
+            return Err(SfifoError::FrameCorrupted {
+                offset,
+                reason: "Truncated frame sequence header".to_string(),
+                dump: None,
+            }
+            .into());
+        }
+        let writer = payload.get_u64_le();
+        let received = payload.get_u64_le();
+        if !check {
+            return Ok(());
+        }
+        if let Some(&previous) = self.last.get(&writer) {
+            if received <= previous {
+                return Err(SfifoError::SequenceViolation {
+                    writer,
+                    previous,
+                    received,
+                    offset,
+                }
+                .into());
+            }
+            if received > previous + 1 {
+                self.gap = Some(SfifoError::GapDetected {
+                    writer,
+                    expected: previous + 1,
+                    got: received,
+                });
+            }
+        }
+        self.last.insert(writer, received);
+        Ok(())
+    }
+
+    /// Gap found in front of the last stripped frame, if any
+    pub(crate) fn take_gap(&mut self) -> Option<SfifoError> {
+        self.gap.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Frame, FrameCodec};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn stamped_stream(stamp: &mut SequenceStamp, count: usize, size: usize) -> Vec<u8> {
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        for i in 0..count {
+            let mut frame = Frame::data(stamp.stamp(&vec![i as u8; size]));
+            frame.flags |= FLAG_SEQUENCED;
+            codec.encode(frame, &mut buf).unwrap();
+        }
+        buf.to_vec()
+    }
+
+    #[test]
+    fn test_interleaving_fuzz() {
+        // Splice the streams of two writers at pseudo-random points, the way
+        // writes larger than PIPE_BUF from two processes can interleave
+        let mut seed: u64 = 0x5eed;
+        for _ in 0..200 {
+            let first = stamped_stream(&mut SequenceStamp::new(), 4, 300);
+            let second = stamped_stream(&mut SequenceStamp::new(), 4, 300);
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let cut = 1 + (seed >> 33) as usize % (first.len() - 1);
+            let mut spliced = BytesMut::from(&first[..cut]);
+            spliced.extend_from_slice(&second);
+            spliced.extend_from_slice(&first[cut..]);
+            let total = spliced.len() as u64;
+
+            let mut codec = FrameCodec::new().dump_on_corruption(true);
+            let mut tracker = SequenceTracker::default();
+            let mut decoded = 0;
+            let error = loop {
+                let frame = match codec.decode_eof(&mut spliced) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break None,
+                    Err(e) => break Some(e),
+                };
+                let mut payload = frame.payload;
+                if let Err(e) = tracker.strip(&mut payload, true, codec.frame_offset()) {
+                    break Some(e);
+                }
+                decoded += 1;
+            };
+            let cut_at_frame_boundary = cut.is_multiple_of(first.len() / 4);
+            match error.as_ref().and_then(SfifoError::from_io) {
+                Some(SfifoError::FrameCorrupted { offset, dump, .. }) => {
+                    assert!(*offset <= total);
+                    assert!(dump.is_some());
+                }
+                Some(SfifoError::SequenceViolation { offset, .. }) => assert!(*offset < total),
+                // A length read from the middle of a payload
+                Some(SfifoError::FrameTooLarge { len, .. }) => assert!(*len as u64 > total),
+                other => {
+                    // Whole frames interleaved cleanly, nothing is lost
+                    assert!(cut_at_frame_boundary, "undetected corruption: {:?}", other);
+                    assert_eq!(decoded, 8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequence_violation() {
+        let mut stamp = SequenceStamp::new();
+        let mut tracker = SequenceTracker::default();
+        let mut first = stamp.stamp(b"a");
+        let mut second = stamp.stamp(b"b");
+        tracker.strip(&mut second, true, 0).unwrap();
+        let error = tracker.strip(&mut first, true, 42).unwrap_err();
+        assert!(matches!(
+            SfifoError::from_io(&error),
+            Some(SfifoError::SequenceViolation {
+                previous: 1,
+                received: 0,
+                offset: 42,
+                ..
+            })
+        ));
+        assert_eq!(first, "a");
+    }
+
+    #[test]
+    fn test_gap_detected() {
+        let mut stamp = SequenceStamp::new();
+        let mut tracker = SequenceTracker::default();
+        let mut first = stamp.stamp(b"a");
+        stamp.skip();
+        stamp.skip();
+        let mut fourth = stamp.stamp(b"d");
+        tracker.strip(&mut first, true, 0).unwrap();
+        assert!(tracker.take_gap().is_none());
+        tracker.strip(&mut fourth, true, 27).unwrap();
+        assert_eq!(fourth, "d");
+        assert_eq!(
+            tracker.take_gap(),
+            Some(SfifoError::GapDetected {
+                writer: stamp.writer,
+                expected: 1,
+                got: 3,
+            })
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{with_timeout, Sfifo};
+use nix::{
+    errno::Errno,
+    fcntl::{AtFlags, OFlag},
+    sys::stat::{fstatat, Mode, SFlag},
+    unistd::{unlinkat, UnlinkatFlags},
+};
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+use nix::unistd::mkfifoat;
+use std::{
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::{Component, Path, PathBuf},
+};
+use tokio::net::unix::pipe::{Receiver, Sender};
+
+/// `mkfifoat(2)`, which nix leaves out on Apple targets although the
+/// system has it since macOS 13
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn mkfifoat(dir: Option<RawFd>, path: &Path, mode: Mode) -> nix::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| Errno::EINVAL)?;
+    let dir = dir.unwrap_or(libc::AT_FDCWD);
+    let ret = unsafe { libc::mkfifoat(dir, path.as_ptr(), mode.bits()) };
+    Errno::result(ret).map(drop)
+}
+
+/// Creates a FIFO at `path`, relative to `dir` if given, without any
+/// check-then-create race
+///
+/// An existing FIFO is accepted unless `exclusive` is set. A symbolic link
+/// is never followed: it is refused like any file that is not a FIFO.
+pub(crate) fn make_fifo(dir: Option<RawFd>, path: &Path, exclusive: bool) -> std::io::Result<()> {
+    let errno = match mkfifoat(dir, path, Mode::S_IRWXU) {
+        Ok(()) => return Ok(()),
+        Err(Errno::EEXIST) => Errno::EEXIST,
+        Err(e) => return Err(e.into()),
+    };
+    let what = match fstatat(dir, path, AtFlags::AT_SYMLINK_NOFOLLOW) {
+        Ok(stat) => match SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT {
+            SFlag::S_IFIFO if !exclusive => return Ok(()),
+            SFlag::S_IFIFO => "a FIFO",
+            SFlag::S_IFLNK => "a symbolic link, which is not followed",
+            _ => "a file that is not a FIFO",
+        },
+        // Removed since, report the original conflict
+        Err(_) => return Err(errno.into()),
+    };
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        format!("{:?} is already {}", path, what),
+    ))
+}
+
+// Access mode of directory descriptors, which are only used to resolve
+// names: O_PATH needs no read permission, O_SEARCH only search permission
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+const DIR_ACCESS: OFlag = OFlag::O_PATH;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "netbsd"))]
+const DIR_ACCESS: OFlag = OFlag::O_SEARCH;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "netbsd"
+)))]
+const DIR_ACCESS: OFlag = OFlag::O_RDONLY;
+
+/// FIFOs of one directory, reached through a descriptor of the directory
+///
+/// Every operation takes a plain file name and resolves it relative to the
+/// directory opened once, so renaming or replacing the directory path, or
+/// planting a symbolic link in it, cannot redirect it: symbolic links are
+/// never followed. Sandboxed services that only hold a pre-opened
+/// directory descriptor use it with `SfifoDir::from_fd`. Opens wait and
+/// retry as configured by the `Sfifo` given on construction.
+#[derive(Debug)]
+pub struct SfifoDir {
+    dir: OwnedFd,
+    path: Option<PathBuf>,
+    config: Sfifo,
+}
+
+impl SfifoDir {
+    /// Open the directory at `dir`, which must not be a symbolic link,
+    /// using the timeout and retry policy of `config`
+    pub fn open(dir: impl AsRef<Path>, config: &Sfifo) -> std::io::Result<Self> {
+        let path = dir.as_ref().to_path_buf();
+        let fd = nix::fcntl::open(
+            &path,
+            DIR_ACCESS | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        Ok(SfifoDir {
+            // Safety: the descriptor was just opened and is owned by nobody else
+            dir: unsafe { OwnedFd::from_raw_fd(fd) },
+            path: Some(path),
+            config: config.clone(),
+        })
+    }
+
+    /// Use the directory descriptor `dir`, which may be an `O_PATH` one,
+    /// with the timeout and retry policy of `config`
+    pub fn from_fd(dir: OwnedFd, config: &Sfifo) -> std::io::Result<Self> {
+        let stat = nix::sys::stat::fstat(dir.as_raw_fd())?;
+        if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFDIR {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Descriptor does not refer to a directory",
+            ));
+        }
+        Ok(SfifoDir {
+            dir,
+            path: None,
+            config: config.clone(),
+        })
+    }
+
+    /// Path the directory was opened at, `None` if made from a descriptor
+    pub fn dir_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Create the FIFO `name`, accepting an existing FIFO
+    pub fn create(&self, name: impl AsRef<Path>) -> std::io::Result<()> {
+        make_fifo(Some(self.dir.as_raw_fd()), file_name(name.as_ref())?, false)
+    }
+
+    /// Create the FIFO `name`, failing with `AlreadyExists` if anything is
+    /// already there
+    pub fn create_exclusive(&self, name: impl AsRef<Path>) -> std::io::Result<()> {
+        make_fifo(Some(self.dir.as_raw_fd()), file_name(name.as_ref())?, true)
+    }
+
+    /// Open the write end of the FIFO `name`, waiting for a reader
+    pub async fn open_sender(&self, name: impl AsRef<Path>) -> std::io::Result<Sender> {
+        let name = file_name(name.as_ref())?;
+        let file_op = |_| async {
+            let mut attempt = 0;
+            loop {
+                match self.open_at(name, OFlag::O_WRONLY) {
+                    Ok(fd) => return Sender::from_owned_fd(fd),
+                    // No reader yet
+                    Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                        attempt += 1;
+                        self.config.retry_policy.retry(attempt, e).await?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        with_timeout(file_op, self.config.timeout).await
+    }
+
+    /// Open the read end of the FIFO `name`
+    pub async fn open_receiver(&self, name: impl AsRef<Path>) -> std::io::Result<Receiver> {
+        let name = file_name(name.as_ref())?;
+        Receiver::from_owned_fd(self.open_at(name, OFlag::O_RDONLY)?)
+    }
+
+    /// Remove the FIFO `name`
+    pub fn remove(&self, name: impl AsRef<Path>) -> std::io::Result<()> {
+        let name = file_name(name.as_ref())?;
+        Ok(unlinkat(
+            Some(self.dir.as_raw_fd()),
+            name,
+            UnlinkatFlags::NoRemoveDir,
+        )?)
+    }
+
+    fn open_at(&self, name: &Path, access: OFlag) -> std::io::Result<OwnedFd> {
+        let fd = nix::fcntl::openat(
+            Some(self.dir.as_raw_fd()),
+            name,
+            access | OFlag::O_NONBLOCK | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        // Safety: the descriptor was just opened and is owned by nobody else
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+impl AsFd for SfifoDir {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.dir.as_fd()
+    }
+}
+
+/// Check that `name` names an entry of the directory itself
+pub(crate) fn file_name(name: &Path) -> std::io::Result<&Path> {
+    let mut components = name.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(name),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{:?} is not a plain file name", name),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_sfifo_dir() {
+        let dir = "/tmp/test_sfifo_dir";
+        let _ = tokio::fs::remove_dir_all(dir).await;
+        tokio::fs::create_dir(dir).await.unwrap();
+
+        let fifos = SfifoDir::open(dir, &Sfifo::new(dir)).unwrap();
+        fifos.create("data").unwrap();
+        fifos.create("data").unwrap();
+        assert!(fifos.create_exclusive("data").is_err());
+        let mut receiver = fifos.open_receiver("data").await.unwrap();
+        let mut sender = fifos.open_sender("data").await.unwrap();
+        sender.write_all(b"at").await.unwrap();
+        let mut buf = [0u8; 2];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"at");
+
+        // Symbolic links are neither followed nor replaced
+        std::os::unix::fs::symlink("data", format!("{}/link", dir)).unwrap();
+        let error = fifos.open_receiver("link").await.unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ELOOP));
+        let error = fifos.create("link").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            crate::create_fifo(format!("{}/link", dir))
+                .await
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::AlreadyExists
+        );
+        assert!(fifos.open_receiver("../test_sfifo_dir/data").await.is_err());
+
+        fifos.remove("data").unwrap();
+        assert!(fifos.open_receiver("data").await.is_err());
+
+        // A pre-opened descriptor works the same, a file is refused
+        let fd: OwnedFd = std::fs::File::open(dir).unwrap().into();
+        let pre_opened = SfifoDir::from_fd(fd, &Sfifo::new(dir)).unwrap();
+        assert_eq!(pre_opened.dir_path(), None);
+        pre_opened.create("again").unwrap();
+        assert!(Path::new(dir).join("again").exists());
+        tokio::fs::write(format!("{}/file", dir), b"")
+            .await
+            .unwrap();
+        let fd: OwnedFd = std::fs::File::open(format!("{}/file", dir)).unwrap().into();
+        assert!(SfifoDir::from_fd(fd, &Sfifo::new(dir)).is_err());
+        let _ = tokio::fs::remove_dir_all(dir).await;
+    }
+}
@@ -0,0 +1,82 @@
+use crate::Sfifo;
+use std::path::Path;
+use tokio::net::unix::pipe::{Receiver, Sender};
+
+impl Sfifo {
+    /// Open the sending end of this FIFO and the receiving end of the FIFO
+    /// at `incoming` together, as one side of a duplex pair
+    ///
+    /// Both sides may open their pair in any order without deadlocking:
+    /// the receiving end is opened first, which never waits, then the
+    /// sending end waits for the peer's receiving end while, with
+    /// `wait_for_writer`, the receiving end waits for the peer's sending
+    /// end. `incoming` is opened with the options of this configuration.
+    pub async fn open_duplex(
+        &self,
+        incoming: impl AsRef<Path>,
+    ) -> std::io::Result<(Sender, Receiver)> {
+        open_duplex(self, &self.at_path(incoming)).await
+    }
+}
+
+/// Open the sending end of `outgoing` and the receiving end of `incoming`
+/// concurrently, see `Sfifo::open_duplex`
+pub(crate) async fn open_duplex(
+    outgoing: &Sfifo,
+    incoming: &Sfifo,
+) -> std::io::Result<(Sender, Receiver)> {
+    // The read end opens at once and lets the peer's write end open
+    let receiver = incoming
+        .clone()
+        .set_wait_for_writer(false)
+        .open_receiver()
+        .await?;
+    let writer_attached = async {
+        if incoming.wait_for_writer {
+            incoming.wait_for_peer(&receiver).await
+        } else {
+            Ok(())
+        }
+    };
+    let (sender, ()) = tokio::try_join!(outgoing.open_sender(), writer_attached)?;
+    Ok((sender, receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_open_duplex() {
+        let a_to_b = "/tmp/test_open_duplex_ab";
+        let b_to_a = "/tmp/test_open_duplex_ba";
+        for path in [a_to_b, b_to_a] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+
+        // Both sides wait for a writer, which would deadlock if each opened
+        // one direction after the other with blocking opens
+        let side = |outgoing: &'static str, incoming: &'static str| {
+            let config = Sfifo::new(outgoing)
+                .set_create(true)
+                .set_wait_for_writer(true)
+                .clone();
+            tokio::spawn(async move {
+                let (mut sender, mut receiver) = config.open_duplex(incoming).await?;
+                sender.write_all(outgoing.as_bytes()).await?;
+                let mut buf = vec![0u8; incoming.len()];
+                receiver.read_exact(&mut buf).await?;
+                Ok::<_, std::io::Error>(String::from_utf8(buf).unwrap())
+            })
+        };
+        let a = side(a_to_b, b_to_a);
+        let b = side(b_to_a, a_to_b);
+        assert_eq!(a.await.unwrap().unwrap(), b_to_a);
+        assert_eq!(b.await.unwrap().unwrap(), a_to_b);
+
+        for path in [a_to_b, b_to_a] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use crate::StallReport;
+use std::{fmt, path::PathBuf, time::Duration};
+
+/// Errors specific to sfifo.
+///
+/// They are returned wrapped in a `std::io::Error` so every API keeps its
+/// `std::io::Result` signature; use `SfifoError::from_io` to inspect them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SfifoError {
+    /// Another reader has the FIFO open, data would be split between readers
+    MultipleReaders { pids: Vec<u32> },
+    /// The frame stream could not be decoded at `offset`, typically because
+    /// two writers interleaved frames larger than `PIPE_BUF`. `dump` holds
+    /// the bytes at the offset when frame diagnostics dump them
+    FrameCorrupted {
+        offset: u64,
+        reason: String,
+        dump: Option<Vec<u8>>,
+    },
+    /// Frame diagnostics saw a sequence number from `writer` that is not
+    /// greater than the previous one, in the frame starting at `offset`
+    SequenceViolation {
+        writer: u64,
+        previous: u64,
+        received: u64,
+        offset: u64,
+    },
+    /// The checksum of the frame starting at `offset` does not match its
+    /// content, e.g. because its writer died mid-frame
+    ChecksumMismatch {
+        offset: u64,
+        expected: u32,
+        actual: u32,
+    },
+    /// The frame stream was corrupted at `offset`, `skipped` bytes were
+    /// discarded to reach the next frame
+    Resynchronized {
+        offset: u64,
+        skipped: u64,
+        reason: String,
+    },
+    /// A message of `len` bytes was refused by an atomic write because it
+    /// exceeds the `limit` (`PIPE_BUF`) up to which pipe writes are atomic
+    MessageTooLarge { len: usize, limit: usize },
+    /// A message of `len` bytes could not be written before its deadline
+    /// and was dropped
+    DeadlineExpired { len: usize },
+    /// A state update with sequence `received` arrived when `expected`
+    /// was due, so the state of a `SnapshotSubscriber` cannot be trusted
+    SequenceGap { expected: u64, received: u64 },
+    /// Frame diagnostics saw sequence `got` from `writer` while `expected`
+    /// was due: the frames in between were lost, e.g. dropped past their
+    /// deadline. The frame carrying `got` is delivered next
+    GapDetected {
+        writer: u64,
+        expected: u64,
+        got: u64,
+    },
+    /// `open_sender()` gave up after `attempts` attempts over `elapsed`,
+    /// because its timeout expired or, with `notify`, the FIFO was
+    /// deleted. `last_os_error` is the errno of the last attempt: `ENXIO`
+    /// while no reader has the FIFO open, `ENOENT`, `EACCES`...
+    OpenAborted {
+        attempts: u32,
+        elapsed: Duration,
+        last_os_error: Option<i32>,
+        deleted: bool,
+    },
+    /// The connection saw no traffic for `idle` and was closed, see
+    /// `AuthenticatedFifo::set_idle_timeout`
+    IdleTimeout { idle: Duration },
+    /// An open with stall diagnostics timed out, `report` tells what
+    /// `/proc` showed about the FIFO at that point
+    Stalled { report: StallReport },
+    /// `path` no longer refers to the FIFO instance with inode `opened`
+    /// the handle was opened on: it was deleted (`current` is `None`) or
+    /// recreated, so the peer can no longer be reached through the handle
+    StaleChannel {
+        path: PathBuf,
+        opened: Option<u64>,
+        current: Option<u64>,
+    },
+    /// A frame or handshake message of `len` bytes exceeds the `limit`
+    /// set with `Sfifo::set_max_frame_size`, or `MAX_HANDSHAKE_SIZE`
+    FrameTooLarge { len: usize, limit: usize },
+    /// Keeping `requested` more bytes of frames in memory, on top of the
+    /// `used` bytes already kept, would exceed the `limit` set with
+    /// `Sfifo::set_memory_limit`
+    MemoryLimitExceeded {
+        requested: usize,
+        used: usize,
+        limit: usize,
+    },
+    /// The `what` being parsed, such as a handshake message, ended before
+    /// all of its fields
+    Truncated { what: &'static str },
+    /// A length field of the `what` being parsed runs past the `available`
+    /// bytes
+    LengthOverflow {
+        what: &'static str,
+        available: usize,
+    },
+    /// The `what` being parsed holds `value`, which is not one of its
+    /// variants
+    InvalidDiscriminant { what: &'static str, value: u64 },
+    /// A graceful shutdown reached its deadline with `unread` bytes still
+    /// in the pipe, or, when `None`, before the peer acknowledged the close
+    DrainTimeout { unread: Option<usize> },
+}
+
+impl SfifoError {
+    /// The `std::io::ErrorKind` used when converting into `std::io::Error`
+    pub fn kind(&self) -> std::io::ErrorKind {
+        match self {
+            SfifoError::MultipleReaders { .. } => std::io::ErrorKind::ResourceBusy,
+            SfifoError::FrameCorrupted { .. }
+            | SfifoError::SequenceViolation { .. }
+            | SfifoError::ChecksumMismatch { .. }
+            | SfifoError::Resynchronized { .. }
+            | SfifoError::SequenceGap { .. }
+            | SfifoError::GapDetected { .. } => std::io::ErrorKind::InvalidData,
+            SfifoError::MessageTooLarge { .. } => std::io::ErrorKind::InvalidInput,
+            SfifoError::DeadlineExpired { .. } => std::io::ErrorKind::TimedOut,
+            SfifoError::OpenAborted { deleted: true, .. } => std::io::ErrorKind::Other,
+            SfifoError::OpenAborted { .. }
+            | SfifoError::IdleTimeout { .. }
+            | SfifoError::Stalled { .. } => std::io::ErrorKind::TimedOut,
+            SfifoError::StaleChannel { .. } => std::io::ErrorKind::NotConnected,
+            SfifoError::FrameTooLarge { .. } => std::io::ErrorKind::InvalidData,
+            SfifoError::MemoryLimitExceeded { .. } => std::io::ErrorKind::OutOfMemory,
+            SfifoError::Truncated { .. }
+            | SfifoError::LengthOverflow { .. }
+            | SfifoError::InvalidDiscriminant { .. } => std::io::ErrorKind::InvalidData,
+            SfifoError::DrainTimeout { .. } => std::io::ErrorKind::TimedOut,
+        }
+    }
+
+    /// Get the sfifo error carried by an I/O error, if any
+    pub fn from_io(error: &std::io::Error) -> Option<&SfifoError> {
+        error.get_ref()?.downcast_ref::<SfifoError>()
+    }
+}
+
+impl fmt::Display for SfifoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SfifoError::MultipleReaders { pids } => {
+                write!(f, "FIFO has other readers (pids {:?})", pids)
+            }
+            SfifoError::FrameCorrupted {
+                offset,
+                reason,
+                dump,
+            } => {
+                write!(f, "{} at stream offset {}", reason, offset)?;
+                if let Some(dump) = dump {
+                    write!(f, ", bytes:")?;
+                    for byte in dump {
+                        write!(f, " {:02x}", byte)?;
+                    }
+                }
+                Ok(())
+            }
+            SfifoError::SequenceViolation {
+                writer,
+                previous,
+                received,
+                offset,
+            } => write!(
+                f,
+                "Frame sequence {} from writer {:#x} follows {} at stream offset {}, \
+                 frames were reordered or interleaved",
+                received, writer, previous, offset
+            ),
+            SfifoError::ChecksumMismatch {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Frame checksum mismatch at stream offset {} (expected {:#010x}, got {:#010x})",
+                offset, expected, actual
+            ),
+            SfifoError::Resynchronized {
+                offset,
+                skipped,
+                reason,
+            } => write!(
+                f,
+                "{} at stream offset {}, skipped {} bytes to the next frame",
+                reason, offset, skipped
+            ),
+            SfifoError::MessageTooLarge { len, limit } => write!(
+                f,
+                "Message of {} bytes exceeds PIPE_BUF ({} bytes), its write would not be atomic",
+                len, limit
+            ),
+            SfifoError::DeadlineExpired { len } => {
+                write!(f, "Message of {} bytes dropped past its deadline", len)
+            }
+            SfifoError::SequenceGap { expected, received } => write!(
+                f,
+                "State update {} received while {} was expected, request a new snapshot",
+                received, expected
+            ),
+            SfifoError::GapDetected {
+                writer,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Missing {} frames from writer {:#x}: expected sequence {}, got {}",
+                got - expected,
+                writer,
+                expected,
+                got
+            ),
+            SfifoError::OpenAborted {
+                attempts,
+                elapsed,
+                last_os_error,
+                deleted,
+            } => {
+                let reason = if *deleted {
+                    "File deleted"
+                } else {
+                    "File operation timed out"
+                };
+                write!(
+                    f,
+                    "{} after {} open attempts in {:?}",
+                    reason, attempts, elapsed
+                )?;
+                if let Some(errno) = last_os_error {
+                    write!(
+                        f,
+                        ", last error: {}",
+                        std::io::Error::from_raw_os_error(*errno)
+                    )?;
+                }
+                Ok(())
+            }
+            SfifoError::IdleTimeout { idle } => {
+                write!(f, "No traffic for {:?}, connection closed", idle)
+            }
+            SfifoError::Stalled { report } => report.fmt(f),
+            SfifoError::StaleChannel { path, current, .. } => match current {
+                Some(_) => write!(f, "FIFO {:?} was recreated, reopen it", path),
+                None => write!(f, "FIFO {:?} was deleted", path),
+            },
+            SfifoError::FrameTooLarge { len, limit } => write!(
+                f,
+                "Frame of {} bytes exceeds the maximum frame size of {} bytes",
+                len, limit
+            ),
+            SfifoError::MemoryLimitExceeded {
+                requested,
+                used,
+                limit,
+            } => write!(
+                f,
+                "Keeping {} more bytes of frames in memory exceeds the limit of {} bytes ({} used)",
+                requested, limit, used
+            ),
+            SfifoError::Truncated { what } => write!(f, "Truncated {}", what),
+            SfifoError::LengthOverflow { what, available } => write!(
+                f,
+                "Length in {} exceeds the {} bytes available",
+                what, available
+            ),
+            SfifoError::InvalidDiscriminant { what, value } => {
+                write!(f, "Invalid {} {}", what, value)
+            }
+            SfifoError::DrainTimeout {
+                unread: Some(unread),
+            } => write!(
+                f,
+                "Shutdown deadline passed with {} bytes left unread by the peer",
+                unread
+            ),
+            SfifoError::DrainTimeout { unread: None } => {
+                write!(
+                    f,
+                    "Shutdown deadline passed before the peer acknowledged the close"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SfifoError {}
+
+impl From<SfifoError> for std::io::Error {
+    fn from(error: SfifoError) -> Self {
+        std::io::Error::new(error.kind(), error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sfifo_error_round_trip() {
+        let error: std::io::Error = SfifoError::MultipleReaders { pids: vec![1] }.into();
+        assert_eq!(error.kind(), std::io::ErrorKind::ResourceBusy);
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::MultipleReaders { pids: vec![1] })
+        );
+        assert!(SfifoError::from_io(&std::io::Error::other("other")).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::SfifoError;
+use bytes::{Buf, BufMut};
+
+/// Sequence the sender should resume from, as a little endian `u64`
+pub const EXT_RESUME_FROM: u16 = 1;
+/// Compression algorithms the sender supports, one id byte each
+pub const EXT_COMPRESSION: u16 = 2;
+/// Random nonce chosen by the sender of the message
+pub const EXT_NONCE: u16 = 3;
+/// Nonce of the peer's previous message, proving the message is fresh
+pub const EXT_NONCE_ECHO: u16 = 4;
+/// Session token issued by the server, or presented by a resuming client
+pub const EXT_SESSION: u16 = 5;
+/// Offer to exchange kernel credentials in a request, path of the Unix
+/// socket to connect to for it in a response
+pub const EXT_KERNEL_CRED: u16 = 6;
+/// HMAC-SHA256 proving the sender holds its token, computed over the
+/// nonces of the handshake instead of sending the token
+pub const EXT_PROOF: u16 = 7;
+
+/// Extension types understood by this version of the crate
+pub const KNOWN_EXTENSIONS: &[u16] = &[
+    EXT_RESUME_FROM,
+    EXT_COMPRESSION,
+    EXT_NONCE,
+    EXT_NONCE_ECHO,
+    EXT_SESSION,
+    EXT_KERNEL_CRED,
+    EXT_PROOF,
+];
+
+/// Optional handshake field, encoded as type-length-value after the fixed
+/// part of the handshake message.
+///
+/// Peers ignore extension types they do not know, so new fields can be
+/// added without breaking older versions of the crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub kind: u16,
+    pub value: Vec<u8>,
+}
+
+impl Extension {
+    /// Create an extension
+    pub fn new(kind: u16, value: impl Into<Vec<u8>>) -> Self {
+        Extension {
+            kind,
+            value: value.into(),
+        }
+    }
+
+    /// Check whether this version of the crate understands the extension
+    pub fn is_known(&self) -> bool {
+        KNOWN_EXTENSIONS.contains(&self.kind)
+    }
+}
+
+/// Append extensions as `kind: u16 | len: u16 | value` entries (little endian)
+pub(crate) fn encode_extensions(
+    extensions: &[Extension],
+    dst: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    for extension in extensions {
+        let len = u16::try_from(extension.value.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Extension too large")
+        })?;
+        dst.put_u16_le(extension.kind);
+        dst.put_u16_le(len);
+        dst.put_slice(&extension.value);
+    }
+    Ok(())
+}
+
+/// Parse the extension section, which runs until the end of `src`
+pub(crate) fn decode_extensions(mut src: &[u8]) -> std::io::Result<Vec<Extension>> {
+    const WHAT: &str = "handshake extension";
+    let mut extensions = Vec::new();
+    while src.has_remaining() {
+        if src.remaining() < 4 {
+            return Err(SfifoError::Truncated { what: WHAT }.into());
+        }
+        let kind = src.get_u16_le();
+        let len = src.get_u16_le() as usize;
+        if src.remaining() < len {
+            return Err(SfifoError::LengthOverflow {
+                what: WHAT,
+                available: src.remaining(),
+            }
+            .into());
+        }
+        extensions.push(Extension::new(kind, &src[..len]));
+        src.advance(len);
+    }
+    Ok(extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_round_trip() {
+        let extensions = vec![
+            Extension::new(0x7001, b"abc".to_vec()),
+            Extension::new(2, vec![]),
+        ];
+        let mut buf = Vec::new();
+        encode_extensions(&extensions, &mut buf).unwrap();
+        assert_eq!(decode_extensions(&buf).unwrap(), extensions);
+
+        buf.pop();
+        assert!(decode_extensions(&buf).is_err());
+    }
+}
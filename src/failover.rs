@@ -0,0 +1,186 @@
+use crate::{fifo_inode, FramedSender, Sfifo};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// How often the paths preferred over the active one are probed
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sends frames to the first healthy FIFO of an ordered list of paths
+///
+/// A path is healthy when it has a reader. Once the reader of the active
+/// path goes away or the path is deleted or recreated, the message is sent
+/// to the next healthy path instead. While a fallback path is active, the
+/// preferred paths are probed every `probe_interval` and the sender moves
+/// back to the first one that has a reader again.
+#[derive(Debug)]
+pub struct FailoverSender {
+    config: Sfifo,
+    paths: Vec<PathBuf>,
+    active: Option<Active>,
+    probe_interval: Duration,
+    last_probe: Instant,
+}
+
+#[derive(Debug)]
+struct Active {
+    index: usize,
+    inode: Option<u64>,
+    sender: FramedSender,
+}
+
+impl FailoverSender {
+    /// Send to `paths`, in order of preference, with the framing options
+    /// of `config`
+    pub fn new(config: &Sfifo, paths: impl IntoIterator<Item = impl AsRef<Path>>) -> Self {
+        FailoverSender {
+            config: config.clone(),
+            paths: paths
+                .into_iter()
+                .map(|p| p.as_ref().to_path_buf())
+                .collect(),
+            active: None,
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            last_probe: Instant::now(),
+        }
+    }
+
+    /// Set how often the preferred paths are probed while failed over
+    pub fn probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// Path frames are currently sent to
+    pub fn active_path(&self) -> Option<&Path> {
+        self.active
+            .as_ref()
+            .map(|active| self.paths[active.index].as_path())
+    }
+
+    /// Send a data frame to the first healthy path
+    ///
+    /// Fails with `NotConnected` if no path has a reader.
+    pub async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.probe_preferred();
+        loop {
+            let active = match self.active.take() {
+                Some(active) if fifo_inode(&self.paths[active.index]) == active.inode => active,
+                Some(active) => {
+                    warn!("FIFO {:?} was removed", self.paths[active.index]);
+                    self.connect_from(active.index + 1)?
+                }
+                None => self.connect_from(0)?,
+            };
+            let active = self.active.insert(active);
+            match active.sender.send(data).await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                    let index = active.index;
+                    self.active = None;
+                    warn!("Reader of {:?} went away, failing over", self.paths[index]);
+                    self.active = Some(self.connect_from(index + 1)?);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Move back to a preferred path that has a reader again
+    fn probe_preferred(&mut self) {
+        let Some(index) = self.active.as_ref().map(|active| active.index) else {
+            return;
+        };
+        if index == 0 || self.last_probe.elapsed() < self.probe_interval {
+            return;
+        }
+        self.last_probe = Instant::now();
+        if let Some(active) = (0..index).find_map(|i| self.open(i)) {
+            info!("Failing back to {:?}", self.paths[active.index]);
+            self.active = Some(active);
+        }
+    }
+
+    /// Open the first path from `start` that has a reader
+    fn connect_from(&mut self, start: usize) -> std::io::Result<Active> {
+        let active = (start..self.paths.len())
+            .find_map(|i| self.open(i))
+            .or_else(|| (0..start.min(self.paths.len())).find_map(|i| self.open(i)))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "No FIFO path has a reader",
+                )
+            })?;
+        self.last_probe = Instant::now();
+        debug!("Sending to {:?}", self.paths[active.index]);
+        Ok(active)
+    }
+
+    fn open(&self, index: usize) -> Option<Active> {
+        let path = &self.paths[index];
+        // Opening the write end fails with ENXIO while there is no reader
+        let pipe = tokio::net::unix::pipe::OpenOptions::new()
+            .open_sender(path)
+            .ok()?;
+        let sender = FramedSender::new(pipe)
+            .with_metrics(self.config.metrics.clone())
+            .frame_diagnostics(self.config.frame_diagnostics)
+            .checksum(self.config.frame_checksum)
+            .rate_limit(self.config.rate_limit);
+        Some(Active {
+            index,
+            inode: fifo_inode(path),
+            sender,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, FramedReceiver};
+
+    #[tokio::test]
+    async fn test_failover_and_back() {
+        let primary = "/tmp/test_failover_primary";
+        let secondary = "/tmp/test_failover_secondary";
+        for path in [primary, secondary] {
+            let _ = tokio::fs::remove_file(path).await;
+            create_fifo(path).await.unwrap();
+        }
+        let mut sender = FailoverSender::new(&Sfifo::new(primary), [primary, secondary])
+            .probe_interval(Duration::from_millis(50));
+        assert_eq!(
+            sender.send(b"nobody").await.unwrap_err().kind(),
+            std::io::ErrorKind::NotConnected
+        );
+
+        let receiver = |path| async move {
+            FramedReceiver::new(Sfifo::new(path).open_receiver().await.unwrap())
+        };
+        let mut first = receiver(primary).await;
+        let mut second = receiver(secondary).await;
+        sender.send(b"one").await.unwrap();
+        assert_eq!(sender.active_path(), Some(Path::new(primary)));
+        assert_eq!(&first.recv().await.unwrap().unwrap()[..], b"one");
+
+        // The primary consumer goes away
+        drop(first);
+        sender.send(b"two").await.unwrap();
+        assert_eq!(sender.active_path(), Some(Path::new(secondary)));
+        assert_eq!(&second.recv().await.unwrap().unwrap()[..], b"two");
+
+        // It comes back and is picked up at the next probe
+        let mut first = receiver(primary).await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        sender.send(b"three").await.unwrap();
+        assert_eq!(sender.active_path(), Some(Path::new(primary)));
+        assert_eq!(&first.recv().await.unwrap().unwrap()[..], b"three");
+
+        for path in [primary, secondary] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+}
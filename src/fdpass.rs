@@ -0,0 +1,216 @@
+use crate::Sfifo;
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags, UnixAddr};
+use std::{
+    io::{IoSlice, IoSliceMut},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+    time::Duration,
+};
+use tokio::{
+    io::Interest,
+    net::{
+        unix::pipe::{Receiver, Sender},
+        UnixListener, UnixStream,
+    },
+};
+
+// Byte sent along with the descriptor, stream sockets need a payload
+const FD_MARKER: u8 = b'F';
+
+impl Sfifo {
+    /// Open the FIFO for reading and hand its write end to the process
+    /// waiting in `receive_fd_from` on `socket_path`.
+    ///
+    /// Only the socket path has to be reachable by both processes, which
+    /// lets FIFOs cross mount namespaces.
+    pub async fn send_fd_to(&self, socket_path: impl AsRef<Path>) -> std::io::Result<Receiver> {
+        let receiver = self.open_receiver().await?;
+        let sender = self.reopening().open_sender().await?;
+        let stream = connect_with_timeout(socket_path.as_ref(), self.timeout).await?;
+        let flags = no_sigpipe(&stream)?;
+        stream
+            .async_io(Interest::WRITABLE, || {
+                let fds = [sender.as_raw_fd()];
+                sendmsg::<UnixAddr>(
+                    stream.as_raw_fd(),
+                    &[IoSlice::new(&[FD_MARKER])],
+                    &[ControlMessage::ScmRights(&fds)],
+                    flags,
+                    None,
+                )
+                .map_err(std::io::Error::from)
+            })
+            .await?;
+        debug!(
+            "Sent FIFO {:?} over {:?}",
+            self.file_path,
+            socket_path.as_ref()
+        );
+        Ok(receiver)
+    }
+
+    /// Listen on `socket_path` for a FIFO write end sent with `send_fd_to`
+    /// and wrap it as a `Sender`.
+    pub async fn receive_fd_from(&self, socket_path: impl AsRef<Path>) -> std::io::Result<Sender> {
+        let socket_path = socket_path.as_ref();
+        let _ = std::fs::remove_file(socket_path);
+        let listener = UnixListener::bind(socket_path)?;
+        let accepted = tokio::time::timeout(self.timeout, listener.accept()).await;
+        let _ = std::fs::remove_file(socket_path);
+        let (stream, _) = accepted.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "No FIFO descriptor received")
+        })??;
+        let fd = tokio::time::timeout(self.timeout, receive_fd(&stream))
+            .await
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "No FIFO descriptor received")
+            })??;
+        Sender::from_owned_fd(fd)
+    }
+}
+
+async fn connect_with_timeout(path: &Path, timeout: Duration) -> std::io::Result<UnixStream> {
+    tokio::time::timeout(timeout, async {
+        loop {
+            match UnixStream::connect(path).await {
+                Ok(stream) => return stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    })
+    .await
+    .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "FD socket not available"))
+}
+
+/// Flags to send on `stream` with, so a vanished peer fails the send with
+/// `EPIPE` instead of raising SIGPIPE
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn no_sigpipe(_stream: &UnixStream) -> std::io::Result<MsgFlags> {
+    Ok(MsgFlags::MSG_NOSIGNAL)
+}
+
+/// Flags to send on `stream` with, which is set to not raise SIGPIPE for a
+/// vanished peer since `MSG_NOSIGNAL` is not available
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd"
+))]
+fn no_sigpipe(stream: &UnixStream) -> std::io::Result<MsgFlags> {
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NOSIGPIPE,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(MsgFlags::empty())
+}
+
+/// Flags to send on `stream` with, this platform cannot suppress SIGPIPE
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd"
+)))]
+fn no_sigpipe(_stream: &UnixStream) -> std::io::Result<MsgFlags> {
+    Ok(MsgFlags::empty())
+}
+
+// Received descriptors are marked close-on-exec atomically where the
+// platform supports it, and right after receiving them otherwise
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECV_FLAGS: MsgFlags = MsgFlags::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: MsgFlags = MsgFlags::empty();
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_cloexec(_fd: &OwnedFd) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_cloexec(fd: &OwnedFd) -> std::io::Result<()> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+    fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    Ok(())
+}
+
+async fn receive_fd(stream: &UnixStream) -> std::io::Result<OwnedFd> {
+    stream
+        .async_io(Interest::READABLE, || {
+            let mut marker = [0u8; 1];
+            let mut iov = [IoSliceMut::new(&mut marker)];
+            let mut space = nix::cmsg_space!([std::os::fd::RawFd; 1]);
+            let msg = recvmsg::<UnixAddr>(
+                stream.as_raw_fd(),
+                &mut iov,
+                Some(&mut space),
+                RECV_FLAGS,
+            )
+            .map_err(std::io::Error::from)?;
+            for cmsg in msg.cmsgs().map_err(std::io::Error::from)? {
+                if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                    let mut fds = fds.into_iter();
+                    if let Some(fd) = fds.next() {
+                        // Close any extra descriptor we did not ask for
+                        for extra in fds {
+                            drop(unsafe { OwnedFd::from_raw_fd(extra) });
+                        }
+                        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                        set_cloexec(&fd)?;
+                        return Ok(fd);
+                    }
+                }
+            }
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Message carried no file descriptor",
+            ))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_fd_passing() {
+        let fifo_path = "/tmp/test_fd_passing_fifo";
+        let socket_path = "/tmp/test_fd_passing.sock";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let client = tokio::spawn(async move {
+            // The client never sees the FIFO path
+            let mut sender = Sfifo::new("unused").receive_fd_from(socket_path).await?;
+            sender.write_all(b"passed").await
+        });
+
+        let mut receiver = Sfifo::new(fifo_path)
+            .set_create(true)
+            .clone()
+            .send_fd_to(socket_path)
+            .await
+            .unwrap();
+        client.await.unwrap().unwrap();
+        let mut buf = [0u8; 6];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"passed");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
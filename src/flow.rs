@@ -0,0 +1,168 @@
+use crate::FramedReceiver;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+use tokio::sync::watch;
+
+/// Pauses and resumes a `FramedReceiver`, from any task
+///
+/// While paused the receiver stops reading from the FIFO, so once the
+/// pipe fills up the writer blocks instead of data piling up in memory.
+#[derive(Debug, Clone)]
+pub struct PauseHandle(Arc<watch::Sender<bool>>);
+
+impl PauseHandle {
+    /// Stop reading; pending `recv()` calls wait until resumed
+    pub fn pause(&self) {
+        self.set(true);
+    }
+
+    /// Read again after a `pause()`
+    pub fn resume(&self) {
+        self.set(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until the receiver is paused
+    pub async fn paused(&self) {
+        let _ = self.0.subscribe().wait_for(|paused| *paused).await;
+    }
+
+    /// Wait until the receiver is resumed
+    pub async fn resumed(&self) {
+        let _ = self.0.subscribe().wait_for(|paused| !*paused).await;
+    }
+
+    fn set(&self, paused: bool) {
+        self.0.send_if_modified(|current| {
+            if *current == paused {
+                return false;
+            }
+            debug!("Receiver {}", if paused { "paused" } else { "resumed" });
+            *current = paused;
+            true
+        });
+    }
+}
+
+impl Default for PauseHandle {
+    fn default() -> Self {
+        PauseHandle(Arc::new(watch::Sender::new(false)))
+    }
+}
+
+/// Pause state of a receiver, polled before each read
+#[derive(Default)]
+pub(crate) struct Pause {
+    handle: PauseHandle,
+    resumed: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl Pause {
+    pub(crate) fn handle(&self) -> &PauseHandle {
+        &self.handle
+    }
+
+    /// Ready once the receiver is not paused
+    pub(crate) fn poll_resumed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if !self.handle.is_paused() {
+                self.resumed = None;
+                return Poll::Ready(());
+            }
+            let handle = self.handle.clone();
+            let resumed = self
+                .resumed
+                .get_or_insert_with(|| Box::pin(async move { handle.resumed().await }));
+            ready!(resumed.as_mut().poll(cx));
+            self.resumed = None;
+        }
+    }
+}
+
+impl std::fmt::Debug for Pause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Pause")
+            .field(&self.handle.is_paused())
+            .finish()
+    }
+}
+
+impl FramedReceiver {
+    /// Stop reading from the FIFO until `resume()`, letting the pipe
+    /// apply backpressure to the writer
+    ///
+    /// Frames already read stay buffered and are delivered once resumed.
+    /// Use `pause_handle()` to pause from another task.
+    pub fn pause(&self) {
+        self.pause_handle().pause();
+    }
+
+    /// Read from the FIFO again after a `pause()`
+    pub fn resume(&self) {
+        self.pause_handle().resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause_handle().is_paused()
+    }
+
+    /// Handle pausing and resuming this receiver, and awaiting either
+    pub fn pause_handle(&self) -> &PauseHandle {
+        self.pause.handle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_fifo, FramedReceiver, FramedSender, Sfifo};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_paused_receiver_applies_backpressure() {
+        let fifo_path = "/tmp/test_pause_receiver";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap());
+        let handle = receiver.pause_handle().clone();
+        receiver.pause();
+        assert!(handle.is_paused());
+
+        // Nothing is read while paused, so the writer ends up blocked
+        let payload = vec![7u8; 16 * 1024];
+        let mut blocked = false;
+        for _ in 0..16 {
+            if tokio::time::timeout(Duration::from_millis(100), sender.send(&payload))
+                .await
+                .is_err()
+            {
+                blocked = true;
+                break;
+            }
+        }
+        assert!(blocked);
+        let pending = tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await;
+        assert!(pending.is_err());
+
+        let resumer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            handle.resume();
+            handle.resumed().await;
+        });
+        let data = receiver.recv().await.unwrap().unwrap();
+        assert_eq!(data.len(), payload.len());
+        assert!(!receiver.is_paused());
+        resumer.await.unwrap();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,1624 @@
+use crate::{
+    atomic::{check_atomic, pipe_buf},
+    capture::{Capture, Direction},
+    compression::{self, FLAG_COMPRESSED},
+    control,
+    diagnostics::{SequenceStamp, SequenceTracker, DUMP_LEN, FLAG_SEQUENCED},
+    flow::Pause,
+    metrics,
+    ratelimit::TokenBucket,
+    readbuf::DEFAULT_READ_BUFFER_CAPACITY,
+    reliable::decode_reliable,
+    state::StateGuard,
+    trace_context::{FLAG_TRACE_CONTEXT, TRACE_CONTEXT_LEN},
+    transaction::Transactions,
+    ChannelState, Compression, FrameDiagnostics, Metrics, OwnedFifo, RateLimit, SfifoError,
+    TraceContext,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use std::{
+    os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Instant,
+};
+use tokio::net::unix::pipe::{Receiver, Sender};
+use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
+
+/// Marker at the start of every frame ("SF")
+pub const FRAME_MAGIC: u16 = 0x5346;
+/// Size of the fixed frame header: magic, kind, flags, tag and length
+pub const FRAME_HEADER_LEN: usize = 10;
+/// Largest payload accepted by the decoder
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+/// Frame flag: the payload ends with a CRC32 of the kind, tag and payload
+pub const FLAG_CHECKSUM: u8 = 0x04;
+/// Size of the checksum trailer
+pub const CHECKSUM_LEN: usize = 4;
+
+/// Type of a frame on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// Application payload, delivered in order by `recv()`. The tag is the
+    /// lane of a `Multiplexer`
+    Data = 0,
+    /// Application-defined control message identified by its tag
+    UserControl = 1,
+    /// Liveness/latency probe, answered with a `Pong` carrying the same payload
+    Ping = 2,
+    /// Answer to a `Ping`
+    Pong = 3,
+    /// Application payload behind a sequence number, see `Reliable`
+    Reliable = 4,
+    /// Cumulative acknowledgment of reliable frames
+    Ack = 5,
+    /// Out-of-band signal identified by its tag, see `Control`
+    Control = 6,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = std::io::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FrameKind::Data),
+            1 => Ok(FrameKind::UserControl),
+            2 => Ok(FrameKind::Ping),
+            3 => Ok(FrameKind::Pong),
+            4 => Ok(FrameKind::Reliable),
+            5 => Ok(FrameKind::Ack),
+            6 => Ok(FrameKind::Control),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Unknown frame kind {}", value),
+            )),
+        }
+    }
+}
+
+/// A single frame of the sfifo wire format
+///
+/// Layout (little endian):
+/// `magic: u16 | kind: u8 | flags: u8 | tag: u16 | len: u32 | payload`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub flags: u8,
+    pub tag: u16,
+    pub payload: Bytes,
+}
+
+impl Frame {
+    /// Create a data frame
+    pub fn data(payload: impl Into<Bytes>) -> Self {
+        Self::new(FrameKind::Data, payload)
+    }
+
+    /// Create a frame of the given kind without tag or flags
+    pub fn new(kind: FrameKind, payload: impl Into<Bytes>) -> Self {
+        Frame {
+            kind,
+            flags: 0,
+            tag: 0,
+            payload: payload.into(),
+        }
+    }
+
+    /// Create a user control frame with an application-defined tag
+    pub fn user_control(tag: u16, payload: impl Into<Bytes>) -> Self {
+        Frame {
+            kind: FrameKind::UserControl,
+            flags: 0,
+            tag,
+            payload: payload.into(),
+        }
+    }
+}
+
+/// Codec encoding and decoding sfifo frames
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+    // Stream offset of the next frame to decode
+    offset: u64,
+    // Stream offset of the last decoded frame
+    frame_offset: u64,
+    dump_on_corruption: bool,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            offset: 0,
+            frame_offset: 0,
+            dump_on_corruption: false,
+        }
+    }
+}
+
+impl FrameCodec {
+    /// Create a codec with the default limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse frames with a payload larger than `bytes`, on both encoding
+    /// and decoding
+    pub fn max_frame_size(mut self, bytes: usize) -> Self {
+        self.max_frame_size = bytes;
+        self
+    }
+
+    /// Include the bytes at the offending offset in corruption errors
+    pub fn dump_on_corruption(mut self, enabled: bool) -> Self {
+        self.dump_on_corruption = enabled;
+        self
+    }
+
+    /// Offset in the byte stream of the last decoded frame
+    pub fn frame_offset(&self) -> u64 {
+        self.frame_offset
+    }
+
+    /// Position of the first plausible frame header in `src`, or `Err`
+    /// with the number of leading bytes that cannot start one
+    fn sync_point(&self, src: &[u8]) -> Result<usize, usize> {
+        let mut pos = 0;
+        while src.len() - pos >= FRAME_HEADER_LEN {
+            if self.plausible_header(&src[pos..]) {
+                return Ok(pos);
+            }
+            pos += 1;
+        }
+        Err(pos)
+    }
+
+    /// Whether `src` starts with a header the decoder would accept
+    fn plausible_header(&self, src: &[u8]) -> bool {
+        let len = u32::from_le_bytes([src[6], src[7], src[8], src[9]]) as usize;
+        u16::from_le_bytes([src[0], src[1]]) == FRAME_MAGIC
+            && FrameKind::try_from(src[2]).is_ok()
+            && len <= self.max_frame_size
+    }
+
+    fn corrupted(&self, src: &BytesMut, reason: impl Into<String>) -> std::io::Error {
+        SfifoError::FrameCorrupted {
+            offset: self.offset,
+            reason: reason.into(),
+            dump: self
+                .dump_on_corruption
+                .then(|| src[..src.len().min(DUMP_LEN)].to_vec()),
+        }
+        .into()
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, std::io::Error> {
+        if src.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let magic = u16::from_le_bytes([src[0], src[1]]);
+        if magic != FRAME_MAGIC {
+            return Err(self.corrupted(src, "Invalid frame magic"));
+        }
+        let len = u32::from_le_bytes([src[6], src[7], src[8], src[9]]) as usize;
+        if len > self.max_frame_size {
+            return Err(SfifoError::FrameTooLarge {
+                len,
+                limit: self.max_frame_size,
+            }
+            .into());
+        }
+        if src.len() < FRAME_HEADER_LEN + len {
+            src.reserve(FRAME_HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+        let kind = FrameKind::try_from(src[2]).map_err(|e| self.corrupted(src, e.to_string()))?;
+        let flags = src[3];
+        let tag = u16::from_le_bytes([src[4], src[5]]);
+        src.advance(FRAME_HEADER_LEN);
+        let payload = src.split_to(len).freeze();
+        self.frame_offset = self.offset;
+        self.offset += (FRAME_HEADER_LEN + len) as u64;
+        Ok(Some(Frame {
+            kind,
+            flags,
+            tag,
+            payload,
+        }))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, std::io::Error> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            None => Err(self.corrupted(src, "Stream ended inside a frame")),
+        }
+    }
+}
+
+/// Scan in progress for the next frame header
+#[derive(Debug)]
+struct Resync {
+    offset: u64,
+    skipped: u64,
+    reason: String,
+}
+
+impl Resync {
+    fn finish(self) -> std::io::Error {
+        SfifoError::Resynchronized {
+            offset: self.offset,
+            skipped: self.skipped,
+            reason: self.reason,
+        }
+        .into()
+    }
+}
+
+/// Decoder of a `FramedReceiver`
+///
+/// Decoding errors are yielded as items rather than errors, which would
+/// end the underlying `FramedRead` for good, so the stream can continue
+/// after a resynchronization.
+#[derive(Debug, Default)]
+struct ReceiveCodec {
+    codec: FrameCodec,
+    resync_on_corruption: bool,
+    resync: Option<Resync>,
+}
+
+impl ReceiveCodec {
+    /// Start scanning for the next frame header
+    fn start_resync(&mut self, reason: impl Into<String>) {
+        if self.resync.is_none() {
+            self.resync = Some(Resync {
+                offset: self.codec.offset,
+                skipped: 0,
+                reason: reason.into(),
+            });
+        }
+    }
+
+    /// Drop `n` bytes from the front of `src` while resynchronizing
+    fn skip(&mut self, src: &mut BytesMut, n: usize) {
+        src.advance(n);
+        self.codec.offset += n as u64;
+        if let Some(resync) = self.resync.as_mut() {
+            resync.skipped += n as u64;
+        }
+    }
+
+    /// Handle a decoding error, scanning past it when enabled
+    fn corrupted(&mut self, error: std::io::Error) -> Option<std::io::Error> {
+        if !self.resync_on_corruption {
+            return Some(error);
+        }
+        match SfifoError::from_io(&error) {
+            Some(SfifoError::FrameCorrupted { reason, .. }) => self.start_resync(reason.clone()),
+            // Most likely a corrupted length rather than a huge frame
+            Some(too_large @ SfifoError::FrameTooLarge { .. }) => {
+                self.start_resync(too_large.to_string())
+            }
+            _ => return Some(error),
+        }
+        None
+    }
+}
+
+impl Decoder for ReceiveCodec {
+    type Item = std::io::Result<Frame>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Self::Item>> {
+        loop {
+            if self.resync.is_some() {
+                match self.codec.sync_point(src) {
+                    Ok(pos) => {
+                        self.skip(src, pos);
+                        let resync = self.resync.take().map(Resync::finish);
+                        return Ok(resync.map(Err));
+                    }
+                    Err(pos) => {
+                        self.skip(src, pos);
+                        return Ok(None);
+                    }
+                }
+            }
+            match self.codec.decode(src) {
+                Ok(frame) => return Ok(frame.map(Ok)),
+                Err(e) => {
+                    if let Some(e) = self.corrupted(e) {
+                        return Ok(Some(Err(e)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Self::Item>> {
+        if let Some(item) = self.decode(src)? {
+            return Ok(Some(item));
+        }
+        if src.is_empty() && self.resync.is_none() {
+            return Ok(None);
+        }
+        if self.resync.is_none() {
+            let error = self.codec.corrupted(src, "Stream ended inside a frame");
+            if let Some(e) = self.corrupted(error) {
+                return Ok(Some(Err(e)));
+            }
+        }
+        // Nothing left to synchronize on
+        self.skip(src, src.len());
+        Ok(self.resync.take().map(|resync| Err(resync.finish())))
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), std::io::Error> {
+        if frame.payload.len() > self.max_frame_size {
+            return Err(SfifoError::FrameTooLarge {
+                len: frame.payload.len(),
+                limit: self.max_frame_size,
+            }
+            .into());
+        }
+        dst.reserve(FRAME_HEADER_LEN + frame.payload.len());
+        dst.put_u16_le(FRAME_MAGIC);
+        dst.put_u8(frame.kind as u8);
+        dst.put_u8(frame.flags);
+        dst.put_u16_le(frame.tag);
+        dst.put_u32_le(frame.payload.len() as u32);
+        dst.put_slice(&frame.payload);
+        Ok(())
+    }
+}
+
+/// Callback invoked for every user control frame received
+pub type UserControlHandler = Box<dyn FnMut(u16, Bytes) + Send>;
+
+/// Callback invoked with every message dropped because it missed its
+/// deadline
+pub type ExpiredHandler = Box<dyn FnMut(Bytes) + Send>;
+
+/// Sending half of a framed FIFO
+pub struct FramedSender {
+    inner: FramedWrite<Sender, FrameCodec>,
+    guard: Option<OwnedFifo>,
+    // Reports the connection as closed once dropped
+    state: Option<StateGuard>,
+    metrics: Option<Metrics>,
+    propagate_span_context: bool,
+    sequence: Option<SequenceStamp>,
+    checksum: bool,
+    compression: Option<Compression>,
+    // PIPE_BUF when frames must be written atomically
+    atomic_limit: Option<usize>,
+    expired: Option<ExpiredHandler>,
+    rate_limit: Option<TokenBucket>,
+    capture: Option<Capture>,
+}
+
+impl FramedSender {
+    /// Wrap a pipe sender with the sfifo frame format
+    pub fn new(sender: Sender) -> Self {
+        FramedSender {
+            inner: FramedWrite::new(sender, FrameCodec::new()),
+            guard: None,
+            state: None,
+            metrics: None,
+            propagate_span_context: false,
+            sequence: None,
+            checksum: false,
+            compression: None,
+            atomic_limit: None,
+            expired: None,
+            rate_limit: None,
+            capture: None,
+        }
+    }
+
+    /// Keep the FIFO files alive for as long as this sender exists
+    pub(crate) fn with_guard(mut self, guard: Option<OwnedFifo>) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    /// Keep the state of the authenticated FIFO this sender came from
+    pub(crate) fn with_state(mut self, state: StateGuard) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Report sent frames to the given metrics hooks
+    pub(crate) fn with_metrics(mut self, metrics: Option<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Prefix data and user control frames sent inside a
+    /// `TraceContext::scope` with that trace context
+    pub fn propagate_span_context(mut self, enabled: bool) -> Self {
+        self.propagate_span_context = enabled;
+        self
+    }
+
+    /// Stamp every frame with a writer id and sequence number so the
+    /// receiver can detect interleaved writers
+    pub fn frame_diagnostics(mut self, diagnostics: FrameDiagnostics) -> Self {
+        self.sequence = diagnostics.is_enabled().then(SequenceStamp::new);
+        self
+    }
+
+    /// Append a CRC32 to every frame so the receiver detects corrupted
+    /// or torn frames
+    pub fn checksum(mut self, enabled: bool) -> Self {
+        self.checksum = enabled;
+        self
+    }
+
+    /// Compress large data and user control payloads
+    ///
+    /// Receivers decompress flagged frames whatever their own settings,
+    /// provided support for the algorithm was compiled in.
+    pub fn compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Refuse frames that cannot be written atomically
+    ///
+    /// Frames whose encoded size exceeds `PIPE_BUF` fail with
+    /// `SfifoError::MessageTooLarge`, so the frames of several writers
+    /// sharing the FIFO never interleave.
+    pub fn atomic_frames(mut self, enabled: bool) -> Self {
+        self.atomic_limit = enabled.then(|| pipe_buf(self.get_ref()).unwrap_or(libc::PIPE_BUF));
+        self
+    }
+
+    /// Refuse to send frames with a payload larger than `bytes`, see
+    /// `Sfifo::set_max_frame_size`
+    pub fn max_frame_size(mut self, bytes: usize) -> Self {
+        let codec = self.inner.encoder_mut();
+        *codec = std::mem::take(codec).max_frame_size(bytes);
+        self
+    }
+
+    /// Record the frames sent to `capture`, as written on the wire
+    pub fn capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Throttle the frames sent, see `Sfifo::set_rate_limit`
+    pub fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.rate_limit = limit.map(TokenBucket::new);
+        self
+    }
+
+    /// Add the headers and trailer enabled on this sender
+    fn prepare(&mut self, frame: Frame) -> Frame {
+        let frame = self.compress(frame);
+        let mut frame = self.attach_context(frame);
+        if let Some(sequence) = self.sequence.as_mut() {
+            frame.payload = sequence.stamp(&frame.payload);
+            frame.flags |= FLAG_SEQUENCED;
+        }
+        if self.checksum {
+            let crc = frame_checksum(&frame);
+            let mut payload = BytesMut::with_capacity(frame.payload.len() + CHECKSUM_LEN);
+            payload.put_slice(&frame.payload);
+            payload.put_u32_le(crc);
+            frame.payload = payload.freeze();
+            frame.flags |= FLAG_CHECKSUM;
+        }
+        frame
+    }
+
+    /// Compress the payload, if enabled and worthwhile
+    fn compress(&self, frame: Frame) -> Frame {
+        let Some(compression) = self.compression else {
+            return frame;
+        };
+        if !matches!(
+            frame.kind,
+            FrameKind::Data | FrameKind::UserControl | FrameKind::Reliable
+        ) {
+            return frame;
+        }
+        match compression.compress(&frame.payload) {
+            Some(payload) => Frame {
+                flags: frame.flags | FLAG_COMPRESSED,
+                payload,
+                ..frame
+            },
+            None => frame,
+        }
+    }
+
+    /// Attach the current trace context to the frame, if enabled
+    fn attach_context(&self, frame: Frame) -> Frame {
+        if !self.propagate_span_context
+            || !matches!(
+                frame.kind,
+                FrameKind::Data | FrameKind::UserControl | FrameKind::Reliable
+            )
+        {
+            return frame;
+        }
+        let Some(context) = TraceContext::current() else {
+            return frame;
+        };
+        let mut payload = BytesMut::with_capacity(TRACE_CONTEXT_LEN + frame.payload.len());
+        context.encode(&mut payload);
+        payload.put_slice(&frame.payload);
+        Frame {
+            flags: frame.flags | FLAG_TRACE_CONTEXT,
+            payload: payload.freeze(),
+            ..frame
+        }
+    }
+
+    /// Send a data frame
+    pub async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.send_frame(Frame::data(Bytes::copy_from_slice(data)))
+            .await
+    }
+
+    /// Send a data frame, unless the pipe cannot take it before `deadline`
+    ///
+    /// The deadline covers waiting for earlier frames to be flushed and
+    /// for room in the pipe. Once the frame is being written it is written
+    /// completely, so the frame stream stays intact. A message missing its
+    /// deadline is passed to the `on_expired` handler, or fails with
+    /// `SfifoError::DeadlineExpired` if there is none.
+    pub async fn send_with_deadline(
+        &mut self,
+        data: &[u8],
+        deadline: Instant,
+    ) -> std::io::Result<()> {
+        let ready = tokio::time::timeout_at(deadline.into(), async {
+            self.inner.flush().await?;
+            self.inner.get_ref().writable().await
+        })
+        .await;
+        match ready {
+            Ok(ready) => {
+                ready?;
+                self.send(data).await
+            }
+            Err(_) => self.expire(Bytes::copy_from_slice(data)),
+        }
+    }
+
+    /// Set the handler receiving the messages that missed their deadline,
+    /// which are then dropped without error
+    pub fn on_expired(&mut self, handler: impl FnMut(Bytes) + Send + 'static) {
+        self.expired = Some(Box::new(handler));
+    }
+
+    /// Use up the sequence number of a message that will not be sent, so
+    /// a receiver checking sequences sees the gap
+    pub(crate) fn skip_sequence(&mut self) {
+        if let Some(sequence) = self.sequence.as_mut() {
+            sequence.skip();
+        }
+    }
+
+    /// Drop a message that missed its deadline
+    pub(crate) fn expire(&mut self, message: Bytes) -> std::io::Result<()> {
+        self.skip_sequence();
+        debug!(
+            "Dropping a message of {} bytes past its deadline",
+            message.len()
+        );
+        match self.expired.as_mut() {
+            Some(handler) => {
+                handler(message);
+                Ok(())
+            }
+            None => Err(SfifoError::DeadlineExpired { len: message.len() }.into()),
+        }
+    }
+
+    /// Send an application-defined control frame
+    ///
+    /// User control frames are handed to the receiver's user control
+    /// handler as soon as they are decoded, they never show up in `recv()`.
+    pub async fn send_user_control(&mut self, tag: u16, payload: &[u8]) -> std::io::Result<()> {
+        self.send_frame(Frame::user_control(tag, Bytes::copy_from_slice(payload)))
+            .await
+    }
+
+    /// Send a raw frame
+    pub async fn send_frame(&mut self, frame: Frame) -> std::io::Result<()> {
+        let frame = self.prepare(frame);
+        let len = FRAME_HEADER_LEN + frame.payload.len();
+        if let Some(limit) = self.atomic_limit {
+            check_atomic(len, limit)?;
+        }
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            bucket.acquire(len).await;
+        }
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Sent, &frame);
+        }
+        self.inner.send(frame).await?;
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            bucket.consume(len);
+        }
+        record_frame_sent(self.metrics.as_ref(), len);
+        Ok(())
+    }
+
+    /// Send data frames, coalescing them into as few writes as possible
+    pub async fn write_batch(&mut self, messages: &[&[u8]]) -> std::io::Result<()> {
+        let frames = messages
+            .iter()
+            .map(|data| Frame::data(Bytes::copy_from_slice(data)))
+            .collect();
+        self.send_batch(frames).await
+    }
+
+    /// Send raw frames, coalescing them into as few writes as possible
+    ///
+    /// Consecutive frames are written together as long as they fit in
+    /// `PIPE_BUF` bytes, so every write stays atomic towards other writers
+    /// of the FIFO. A larger frame is written on its own.
+    pub async fn send_batch(&mut self, frames: Vec<Frame>) -> std::io::Result<()> {
+        let limit = match self.atomic_limit {
+            Some(limit) => limit,
+            None => pipe_buf(self.get_ref())?,
+        };
+        let frames: Vec<Frame> = frames.into_iter().map(|f| self.prepare(f)).collect();
+        // Refuse the batch before any of it is sent
+        if self.atomic_limit.is_some() {
+            for frame in &frames {
+                check_atomic(FRAME_HEADER_LEN + frame.payload.len(), limit)?;
+            }
+        }
+        let mut pending = Vec::new();
+        for frame in frames {
+            let len = FRAME_HEADER_LEN + frame.payload.len();
+            if pending.iter().sum::<usize>() + len > limit {
+                self.flush_batch(&mut pending).await?;
+            }
+            if let Some(bucket) = self.rate_limit.as_mut() {
+                bucket.acquire(len).await;
+                bucket.consume(len);
+            }
+            if let Some(capture) = &self.capture {
+                capture.record(Direction::Sent, &frame);
+            }
+            self.inner.feed(frame).await?;
+            pending.push(len);
+        }
+        self.flush_batch(&mut pending).await
+    }
+
+    /// Send frames that must not be interleaved with the frames of other
+    /// writers: in a single write with `atomic_frames`, refusing them with
+    /// `SfifoError::MessageTooLarge` if they exceed `PIPE_BUF` together,
+    /// like `send_batch()` otherwise
+    pub(crate) async fn send_together(&mut self, frames: Vec<Frame>) -> std::io::Result<()> {
+        let Some(limit) = self.atomic_limit else {
+            return self.send_batch(frames).await;
+        };
+        let frames: Vec<Frame> = frames.into_iter().map(|f| self.prepare(f)).collect();
+        let mut lens: Vec<usize> = frames
+            .iter()
+            .map(|frame| FRAME_HEADER_LEN + frame.payload.len())
+            .collect();
+        check_atomic(lens.iter().sum(), limit)?;
+        for (frame, &len) in frames.into_iter().zip(&lens) {
+            if let Some(bucket) = self.rate_limit.as_mut() {
+                bucket.acquire(len).await;
+                bucket.consume(len);
+            }
+            if let Some(capture) = &self.capture {
+                capture.record(Direction::Sent, &frame);
+            }
+            self.inner.feed(frame).await?;
+        }
+        self.flush_batch(&mut lens).await
+    }
+
+    /// Write the frames fed so far, whose encoded lengths are `pending`
+    async fn flush_batch(&mut self, pending: &mut Vec<usize>) -> std::io::Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.inner.flush().await?;
+        for len in pending.drain(..) {
+            record_frame_sent(self.metrics.as_ref(), len);
+        }
+        Ok(())
+    }
+
+    /// Get a reference to the underlying pipe sender
+    pub fn get_ref(&self) -> &Sender {
+        self.inner.get_ref()
+    }
+
+    /// Get the underlying pipe sender
+    pub fn into_inner(self) -> Sender {
+        self.inner.into_inner()
+    }
+}
+
+/// Receiving half of a framed FIFO
+pub struct FramedReceiver {
+    inner: FramedRead<Receiver, ReceiveCodec>,
+    user_control: Option<UserControlHandler>,
+    guard: Option<OwnedFifo>,
+    // Reports the connection as closed once dropped
+    state: Option<StateGuard>,
+    metrics: Option<Metrics>,
+    trace_context: Option<TraceContext>,
+    diagnostics: FrameDiagnostics,
+    sequences: SequenceTracker,
+    // Payload of the last frame if it failed its checksum
+    torn: Option<Bytes>,
+    // Set after a decoding error until `resync()`
+    failed: bool,
+    // Set once the writer sent its close frame
+    closed: bool,
+    pub(crate) pause: Pause,
+    transactions: Transactions,
+    // Frame following a sequence gap, delivered after the gap is reported
+    after_gap: Option<Frame>,
+    capture: Option<Capture>,
+}
+
+impl FramedReceiver {
+    /// Wrap a pipe receiver with the sfifo frame format
+    pub fn new(receiver: Receiver) -> Self {
+        Self::with_capacity(receiver, None)
+    }
+
+    /// Wrap a pipe receiver, reading through a buffer of `capacity` bytes
+    /// or `DEFAULT_READ_BUFFER_CAPACITY`
+    ///
+    /// Received payloads are views into that buffer, its memory is reused
+    /// once they are dropped.
+    pub fn with_capacity(receiver: Receiver, capacity: Option<usize>) -> Self {
+        let capacity = capacity.unwrap_or(DEFAULT_READ_BUFFER_CAPACITY);
+        FramedReceiver {
+            inner: FramedRead::with_capacity(receiver, ReceiveCodec::default(), capacity),
+            user_control: None,
+            guard: None,
+            state: None,
+            metrics: None,
+            trace_context: None,
+            diagnostics: FrameDiagnostics::Off,
+            sequences: SequenceTracker::default(),
+            torn: None,
+            failed: false,
+            closed: false,
+            pause: Pause::default(),
+            transactions: Transactions::default(),
+            after_gap: None,
+            capture: None,
+        }
+    }
+
+    /// Keep the FIFO files alive for as long as this receiver exists
+    pub(crate) fn with_guard(mut self, guard: Option<OwnedFifo>) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    /// Keep the state of the authenticated FIFO this receiver came from
+    pub(crate) fn with_state(mut self, state: StateGuard) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Report received frames to the given metrics hooks
+    pub(crate) fn with_metrics(mut self, metrics: Option<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Set the handler invoked for user control frames
+    ///
+    /// Without a handler, user control frames are discarded by `recv()`.
+    pub fn on_user_control(&mut self, handler: impl FnMut(u16, Bytes) + Send + 'static) {
+        self.user_control = Some(Box::new(handler));
+    }
+
+    /// Receive the next data frame, or `None` once the writer has closed
+    ///
+    /// Ping and pong frames are discarded, there is no way to answer them
+    /// from a receiver alone. Reliable frames are delivered without being
+    /// acknowledged.
+    pub async fn recv(&mut self) -> std::io::Result<Option<Bytes>> {
+        while let Some(frame) = self.recv_frame().await? {
+            match frame.kind {
+                FrameKind::Data => return Ok(Some(frame.payload)),
+                FrameKind::Reliable => return Ok(Some(decode_reliable(frame.payload)?.2)),
+                FrameKind::UserControl => self.handle_user_control(frame),
+                FrameKind::Ping | FrameKind::Pong | FrameKind::Ack | FrameKind::Control => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Dispatch a user control frame to the handler, if any
+    pub(crate) fn handle_user_control(&mut self, frame: Frame) {
+        if let Some(handler) = self.user_control.as_mut() {
+            handler(frame.tag, frame.payload);
+        }
+    }
+
+    /// Check that frame sequence numbers are strictly increasing per
+    /// writer, failing with `SfifoError::SequenceViolation` otherwise
+    ///
+    /// Lost frames, such as messages the writer dropped past their
+    /// deadline, are reported as `SfifoError::GapDetected` before the
+    /// frame following them is delivered.
+    pub fn frame_diagnostics(mut self, diagnostics: FrameDiagnostics) -> Self {
+        self.diagnostics = diagnostics;
+        let dump = diagnostics == FrameDiagnostics::CheckAndDump;
+        let decoder = self.inner.decoder_mut();
+        decoder.codec = std::mem::take(&mut decoder.codec).dump_on_corruption(dump);
+        self
+    }
+
+    /// Refuse frames with a payload larger than `bytes`, see
+    /// `Sfifo::set_max_frame_size`
+    pub fn max_frame_size(mut self, bytes: usize) -> Self {
+        let decoder = self.inner.decoder_mut();
+        decoder.codec = std::mem::take(&mut decoder.codec).max_frame_size(bytes);
+        self
+    }
+
+    /// Skip to the next frame after corrupted bytes instead of failing
+    ///
+    /// The error for the corrupted frame is replaced by
+    /// `SfifoError::Resynchronized`, reported once the next frame header
+    /// is found, and receiving continues from that frame.
+    pub fn resync_on_corruption(mut self, enabled: bool) -> Self {
+        self.inner.decoder_mut().resync_on_corruption = enabled;
+        self
+    }
+
+    /// Record the frames received to `capture`, as read from the wire
+    pub fn capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Skip to the next frame after a corrupted or torn frame
+    ///
+    /// Scans forward for the frame magic followed by a plausible header,
+    /// reading from the FIFO as needed, and returns the number of bytes
+    /// skipped. The payload of a frame that failed its checksum is scanned
+    /// too, since a writer dying mid-frame makes the frame swallow the
+    /// start of the next one.
+    pub async fn resync(&mut self) -> std::io::Result<u64> {
+        self.failed = false;
+        self.start_resync("Resynchronization requested");
+        match self.inner.next().await {
+            Some(Ok(Err(e))) => match SfifoError::from_io(&e) {
+                Some(SfifoError::Resynchronized { skipped, .. }) => Ok(*skipped),
+                _ => Err(e),
+            },
+            Some(Ok(Ok(_))) => unreachable!("frames are decoded once resynchronized"),
+            Some(Err(e)) => Err(e),
+            None => Ok(self
+                .inner
+                .decoder_mut()
+                .resync
+                .take()
+                .map_or(0, |resync| resync.skipped)),
+        }
+    }
+
+    /// Start scanning for the next frame header, from within the torn
+    /// frame if the last frame failed its checksum
+    fn start_resync(&mut self, reason: &str) {
+        let torn = self.torn.take();
+        let decoder = self.inner.decoder_mut();
+        if decoder.resync.is_some() {
+            return;
+        }
+        decoder.start_resync(reason);
+        let Some(torn) = torn else {
+            return;
+        };
+        let offset = decoder.codec.frame_offset;
+        decoder.codec.offset = offset + FRAME_HEADER_LEN as u64;
+        if let Some(resync) = decoder.resync.as_mut() {
+            resync.offset = offset;
+            resync.skipped = FRAME_HEADER_LEN as u64;
+        }
+        let buffer = self.inner.read_buffer_mut();
+        let mut restored = BytesMut::with_capacity(torn.len() + buffer.len());
+        restored.put_slice(&torn);
+        restored.put_slice(buffer);
+        *buffer = restored;
+    }
+
+    /// Trace context sent with the last received frame, if any
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        self.trace_context
+    }
+
+    /// Receive the next raw frame of any kind
+    ///
+    /// A trace context sent with the frame is removed from the payload
+    /// and made available through `trace_context()`.
+    pub async fn recv_frame(&mut self) -> std::io::Result<Option<Frame>> {
+        std::future::poll_fn(|cx| self.poll_frame(cx))
+            .await
+            .transpose()
+    }
+
+    /// End the stream as if the writer had sent its close frame
+    pub(crate) fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Poll the next raw frame, resynchronizing on corruption when enabled
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<std::io::Result<Frame>>> {
+        loop {
+            if self.failed || self.closed {
+                return Poll::Ready(None);
+            }
+            ready!(self.pause.poll_resumed(cx));
+            if let Some(frame) = self.transactions.pop() {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            let received = match self.after_gap.take() {
+                Some(frame) => Ok(frame),
+                None => match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                    Some(Ok(Ok(frame))) => self.received(frame),
+                    Some(Ok(Err(e))) | Some(Err(e)) => {
+                        self.transactions.abort("Frame decoding failed");
+                        // Like a `FramedRead` error, end the stream until resynchronized
+                        self.failed = !matches!(
+                            SfifoError::from_io(&e),
+                            Some(SfifoError::Resynchronized { .. })
+                        );
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    None => {
+                        self.transactions.abort("Writer closed the FIFO");
+                        if let Some(state) = &self.state {
+                            state.set(ChannelState::Closed);
+                        }
+                        return Poll::Ready(None);
+                    }
+                },
+            };
+            let error = match received {
+                Ok(frame) => match self.transactions.accept(frame) {
+                    Some(frame) => {
+                        if control::is_control(&frame, control::CONTROL_CLOSE) {
+                            // The writer shut down gracefully, nothing follows
+                            self.closed = true;
+                            if let Some(state) = &self.state {
+                                state.set(ChannelState::Closed);
+                            }
+                        }
+                        return Poll::Ready(Some(Ok(frame)));
+                    }
+                    None => continue,
+                },
+                Err(e) => e,
+            };
+            let mismatch = matches!(
+                SfifoError::from_io(&error),
+                Some(SfifoError::ChecksumMismatch { .. })
+            );
+            self.transactions.abort("Invalid frame");
+            if !(mismatch && self.inner.decoder().resync_on_corruption) {
+                return Poll::Ready(Some(Err(error)));
+            }
+            self.start_resync("Frame checksum mismatch");
+        }
+    }
+
+    /// Account for a decoded frame and extract its trace context
+    fn received(&mut self, mut frame: Frame) -> std::io::Result<Frame> {
+        record_frame_received(self.metrics.as_ref(), &frame);
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Received, &frame);
+        }
+        let offset = self.inner.decoder().codec.frame_offset();
+        self.torn = None;
+        if frame.flags & FLAG_CHECKSUM != 0 {
+            let payload = frame.payload.clone();
+            if let Err(e) = verify_checksum(&mut frame, offset) {
+                self.torn = Some(payload);
+                return Err(e);
+            }
+            frame.flags &= !FLAG_CHECKSUM;
+        }
+        if frame.flags & FLAG_SEQUENCED != 0 {
+            let check = self.diagnostics.is_enabled();
+            self.sequences.strip(&mut frame.payload, check, offset)?;
+            frame.flags &= !FLAG_SEQUENCED;
+        }
+        self.trace_context = None;
+        if frame.flags & FLAG_TRACE_CONTEXT != 0 {
+            self.trace_context = Some(TraceContext::decode(&mut frame.payload)?);
+            frame.flags &= !FLAG_TRACE_CONTEXT;
+        }
+        if frame.flags & FLAG_COMPRESSED != 0 {
+            let max_size = self.inner.decoder().codec.max_frame_size;
+            frame.payload = compression::decompress(&frame.payload, max_size)?;
+            frame.flags &= !FLAG_COMPRESSED;
+        }
+        if let Some(gap) = self.sequences.take_gap() {
+            self.after_gap = Some(frame);
+            return Err(gap.into());
+        }
+        Ok(frame)
+    }
+
+    /// Get the underlying pipe receiver
+    pub fn into_inner(self) -> Receiver {
+        self.inner.into_inner()
+    }
+}
+
+/// Sends each item as a data frame
+///
+/// With `atomic_frames`, each frame is written on its own before the next
+/// one is accepted, so that buffered frames never add up to a write larger
+/// than `PIPE_BUF`.
+impl Sink<Bytes> for FramedSender {
+    type Error = std::io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        // Items are only known once sent: wait for earlier items to be paid
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            ready!(bucket.poll_acquire(cx, 0));
+        }
+        if self.atomic_limit.is_some() {
+            ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        }
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Bytes) -> std::io::Result<()> {
+        let frame = self.prepare(Frame::data(item));
+        let len = FRAME_HEADER_LEN + frame.payload.len();
+        if let Some(limit) = self.atomic_limit {
+            check_atomic(len, limit)?;
+        }
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            bucket.consume(len);
+        }
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Sent, &frame);
+        }
+        Pin::new(&mut self.inner).start_send(frame)?;
+        record_frame_sent(self.metrics.as_ref(), len);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Yields the payload of each data frame, with the same semantics as `recv()`
+impl Stream for FramedReceiver {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let frame = match ready!(self.poll_frame(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            };
+            match frame.kind {
+                FrameKind::Data => return Poll::Ready(Some(Ok(frame.payload))),
+                FrameKind::Reliable => {
+                    return Poll::Ready(Some(
+                        decode_reliable(frame.payload).map(|(_, _, data)| data),
+                    ))
+                }
+                FrameKind::UserControl => self.handle_user_control(frame),
+                FrameKind::Ping | FrameKind::Pong | FrameKind::Ack | FrameKind::Control => {}
+            }
+        }
+    }
+}
+
+/// CRC32 of the kind, tag and payload of a frame
+fn frame_checksum(frame: &Frame) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[frame.kind as u8]);
+    hasher.update(&frame.tag.to_le_bytes());
+    hasher.update(&frame.payload);
+    hasher.finalize()
+}
+
+/// Check and remove the checksum trailer of a frame
+fn verify_checksum(frame: &mut Frame, offset: u64) -> std::io::Result<()> {
+    if frame.payload.len() < CHECKSUM_LEN {
+        return Err(SfifoError::FrameCorrupted {
+            offset,
+            reason: "Truncated frame checksum".to_string(),
+            dump: None,
+        }
+        .into());
+    }
+    let trailer = frame.payload.split_off(frame.payload.len() - CHECKSUM_LEN);
+    let expected = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let actual = frame_checksum(frame);
+    if expected != actual {
+        return Err(SfifoError::ChecksumMismatch {
+            offset,
+            expected,
+            actual,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn record_frame_sent(metrics: Option<&Metrics>, len: usize) {
+    metrics::emit(metrics, |m| {
+        m.frame_sent();
+        m.bytes_written(len);
+    });
+}
+
+fn record_frame_received(metrics: Option<&Metrics>, frame: &Frame) {
+    metrics::emit(metrics, |m| {
+        m.frame_received();
+        m.bytes_read(FRAME_HEADER_LEN + frame.payload.len());
+    });
+}
+
+impl AsFd for FramedSender {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.get_ref().as_fd()
+    }
+}
+
+impl AsRawFd for FramedSender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+}
+
+impl AsFd for FramedReceiver {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.get_ref().as_fd()
+    }
+}
+
+impl AsRawFd for FramedReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+}
+
+impl std::fmt::Debug for FramedSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FramedSender")
+            .field("guard", &self.guard)
+            .field("propagate_span_context", &self.propagate_span_context)
+            .field("expired", &self.expired.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for FramedReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FramedReceiver")
+            .field("user_control", &self.user_control.is_some())
+            .field("guard", &self.guard)
+            .field("trace_context", &self.trace_context)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, Sfifo};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_frame_codec_round_trip() {
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(Frame::data("hello"), &mut buf).unwrap();
+        codec
+            .encode(Frame::user_control(7, "ctl"), &mut buf)
+            .unwrap();
+
+        // A partial frame is not decoded
+        let mut partial = BytesMut::from(&buf[..FRAME_HEADER_LEN + 2]);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Frame::data("hello")));
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::user_control(7, "ctl"))
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_frame_codec_rejects_bad_magic() {
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::from(&[0u8; FRAME_HEADER_LEN][..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_frame_codec_max_frame_size() {
+        let mut codec = FrameCodec::new().max_frame_size(4);
+        let mut buf = BytesMut::new();
+        let error = codec.encode(Frame::data("hello"), &mut buf).unwrap_err();
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::FrameTooLarge { len: 5, limit: 4 })
+        );
+
+        FrameCodec::new()
+            .encode(Frame::data("hello"), &mut buf)
+            .unwrap();
+        let error = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::FrameTooLarge { len: 5, limit: 4 })
+        );
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_frame_codec_round_trip(
+            frames in proptest::collection::vec(
+                (0u8..7, proptest::num::u8::ANY, proptest::num::u16::ANY,
+                 proptest::collection::vec(proptest::num::u8::ANY, 0..64)),
+                0..8,
+            ),
+            split in proptest::num::usize::ANY,
+        ) {
+            let frames: Vec<Frame> = frames
+                .into_iter()
+                .map(|(kind, flags, tag, payload)| Frame {
+                    kind: FrameKind::try_from(kind).unwrap(),
+                    flags,
+                    tag,
+                    payload: payload.into(),
+                })
+                .collect();
+            let mut codec = FrameCodec::new();
+            let mut encoded = BytesMut::new();
+            for frame in &frames {
+                codec.encode(frame.clone(), &mut encoded).unwrap();
+            }
+
+            // Decoding is the same whatever the reads the stream arrives in
+            let rest = encoded.split_off(split % (encoded.len() + 1));
+            let mut decoded = Vec::new();
+            while let Some(frame) = codec.decode(&mut encoded).unwrap() {
+                decoded.push(frame);
+            }
+            encoded.unsplit(rest);
+            while let Some(frame) = codec.decode_eof(&mut encoded).unwrap() {
+                decoded.push(frame);
+            }
+            proptest::prop_assert_eq!(decoded, frames);
+        }
+
+        #[test]
+        fn prop_frame_codec_rejects_garbage(
+            bytes in proptest::collection::vec(proptest::num::u8::ANY, 0..256),
+        ) {
+            let mut codec = FrameCodec::new().max_frame_size(1024);
+            let mut src = BytesMut::from(&bytes[..]);
+            loop {
+                match codec.decode_eof(&mut src) {
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(e) => {
+                        proptest::prop_assert!(SfifoError::from_io(&e).is_some());
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_framed_stream_and_sink() {
+        let fifo_path = "/tmp/test_framed_stream_sink";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let receiver = FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap());
+
+        let mut items = futures_util::stream::iter(["a", "b", "c"].map(|s| Ok(Bytes::from(s))));
+        sender.send_all(&mut items).await.unwrap();
+        drop(sender);
+
+        let received: Vec<Bytes> = receiver.map(|item| item.unwrap()).collect().await;
+        assert_eq!(received, vec!["a", "b", "c"]);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_write_batch() {
+        let fifo_path = "/tmp/test_framed_write_batch";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap());
+
+        // Crosses PIPE_BUF twice: before and after the large message
+        let small = vec![b'x'; 1000];
+        let large = vec![b'y'; 5000];
+        let batch: Vec<&[u8]> = vec![&small, &small, &small, &small, &large, b"last"];
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(data) = receiver.recv().await.unwrap() {
+                received.push(data);
+            }
+            received
+        });
+        sender.write_batch(&batch).await.unwrap();
+        drop(sender);
+
+        let received = reader.await.unwrap();
+        assert_eq!(received, batch);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_send_with_deadline() {
+        let fifo_path = "/tmp/test_framed_deadline";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let _receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap());
+        sender
+            .send_with_deadline(b"in time", Instant::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+        // Nobody reads: fill the pipe
+        while sender.get_ref().try_write(&[0u8; 4096]).is_ok() {}
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let error = sender
+            .send_with_deadline(b"stale", deadline)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::DeadlineExpired { len: 5 })
+        );
+
+        let expired = Arc::new(Mutex::new(Vec::new()));
+        let seen = expired.clone();
+        sender.on_expired(move |message| seen.lock().unwrap().push(message));
+        let deadline = Instant::now() + Duration::from_millis(50);
+        sender.send_with_deadline(b"stale", deadline).await.unwrap();
+        assert_eq!(*expired.lock().unwrap(), vec![Bytes::from_static(b"stale")]);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_gap_after_expired_message() {
+        let fifo_path = "/tmp/test_framed_gap";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap())
+                .frame_diagnostics(FrameDiagnostics::Check);
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap())
+            .frame_diagnostics(FrameDiagnostics::Check);
+        sender.on_expired(|_| {});
+        sender.send(b"first").await.unwrap();
+        sender.expire(Bytes::from_static(b"lost")).unwrap();
+        sender.send(b"third").await.unwrap();
+
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"first");
+        let error = receiver.recv().await.unwrap_err();
+        assert!(matches!(
+            SfifoError::from_io(&error),
+            Some(SfifoError::GapDetected {
+                expected: 1,
+                got: 2,
+                ..
+            })
+        ));
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"third");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_user_control_frames() {
+        let fifo_path = "/tmp/test_user_control_fifo";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        let mut framed_receiver = FramedReceiver::new(receiver);
+        let controls = Arc::new(Mutex::new(Vec::new()));
+        let controls_clone = controls.clone();
+        framed_receiver.on_user_control(move |tag, payload| {
+            controls_clone.lock().unwrap().push((tag, payload));
+        });
+
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let mut framed_sender = FramedSender::new(sender);
+        framed_sender.send(b"one").await.unwrap();
+        framed_sender
+            .send_user_control(42, b"signal")
+            .await
+            .unwrap();
+        framed_sender.send(b"two").await.unwrap();
+        drop(framed_sender);
+
+        assert_eq!(framed_receiver.recv().await.unwrap().unwrap(), "one");
+        assert_eq!(framed_receiver.recv().await.unwrap().unwrap(), "two");
+        assert!(framed_receiver.recv().await.unwrap().is_none());
+        assert_eq!(
+            controls.lock().unwrap().as_slice(),
+            &[(42, Bytes::from_static(b"signal"))]
+        );
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_trace_context_propagation() {
+        let fifo_path = "/tmp/test_frame_trace_context";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap())
+            .propagate_span_context(true);
+
+        let context = TraceContext::new_root();
+        context.scope(sender.send(b"traced")).await.unwrap();
+        sender.send(b"untraced").await.unwrap();
+
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "traced");
+        assert_eq!(receiver.trace_context(), Some(context));
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "untraced");
+        assert_eq!(receiver.trace_context(), None);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_checksum_detects_corrupted_frame() {
+        let fifo_path = "/tmp/test_frame_checksum";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut raw = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let mut sender =
+            FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap()).checksum(true);
+
+        sender.send(b"intact").await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "intact");
+
+        // Same frame with a payload byte flipped on the way
+        let mut frame = sender.prepare(Frame::data("intact"));
+        let mut payload = BytesMut::from(&frame.payload[..]);
+        payload[0] ^= 0xff;
+        frame.payload = payload.freeze();
+        let mut buf = BytesMut::new();
+        FrameCodec::new().encode(frame, &mut buf).unwrap();
+        raw.write_all(&buf).await.unwrap();
+
+        let error = receiver.recv().await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            SfifoError::from_io(&error),
+            Some(SfifoError::ChecksumMismatch { offset, .. }) if *offset == buf.len() as u64
+        ));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_resync_after_torn_frame() {
+        let fifo_path = "/tmp/test_frame_resync_torn";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut raw = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let mut sender =
+            FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap()).checksum(true);
+
+        // A writer dies three bytes into a 14 byte payload
+        let mut torn = BytesMut::new();
+        torn.put_u16_le(FRAME_MAGIC);
+        torn.put_u8(FrameKind::Data as u8);
+        torn.put_u8(FLAG_CHECKSUM);
+        torn.put_u16_le(0);
+        torn.put_u32_le(14);
+        torn.put_slice(b"abc");
+        raw.write_all(&torn).await.unwrap();
+        sender.send(b"after").await.unwrap();
+
+        let error = receiver.recv().await.unwrap_err();
+        assert!(matches!(
+            SfifoError::from_io(&error),
+            Some(SfifoError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(receiver.resync().await.unwrap(), torn.len() as u64);
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "after");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_resync_on_corruption() {
+        let fifo_path = "/tmp/test_frame_resync_auto";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap())
+                .resync_on_corruption(true);
+        let mut raw = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap());
+
+        sender.send(b"first").await.unwrap();
+        raw.write_all(b"garbage").await.unwrap();
+        sender.send(b"second").await.unwrap();
+
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "first");
+        let error = receiver.recv().await.unwrap_err();
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::Resynchronized {
+                offset: (FRAME_HEADER_LEN + 5) as u64,
+                skipped: 7,
+                reason: "Invalid frame magic".to_string(),
+            })
+        );
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "second");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{pipe::wrong_direction, FifoTransport, Sfifo};
+use std::{
+    os::fd::{AsFd, BorrowedFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::unix::pipe::{Receiver, Sender},
+};
+
+/// How `Sfifo::open_with` opens the FIFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Read end, a blocking descriptor if `blocking` is set
+    Reader { blocking: bool },
+    /// Write end, a blocking descriptor if `blocking` is set
+    Writer { blocking: bool },
+    /// Both ends in one non-blocking descriptor, see `Sfifo::open_rdwr`
+    ReadWrite,
+}
+
+/// FIFO opened by `Sfifo::open_with`
+#[derive(Debug)]
+pub enum FifoHandle {
+    /// Non-blocking read end
+    Receiver(Receiver),
+    /// Non-blocking write end
+    Sender(Sender),
+    /// Blocking end, or both ends with `OpenMode::ReadWrite`
+    File(tokio::fs::File),
+}
+
+impl FifoHandle {
+    /// Get the handle as a file, as returned by the legacy `open()`
+    pub fn into_file(self) -> std::io::Result<tokio::fs::File> {
+        let fd = match self {
+            FifoHandle::Receiver(receiver) => receiver.into_nonblocking_fd()?,
+            FifoHandle::Sender(sender) => sender.into_nonblocking_fd()?,
+            FifoHandle::File(file) => return Ok(file),
+        };
+        Ok(tokio::fs::File::from_std(std::fs::File::from(fd)))
+    }
+}
+
+impl Sfifo {
+    /// Open the FIFO in `mode`, creating it first if `create` is set
+    pub async fn open_with(&self, mode: OpenMode) -> std::io::Result<FifoHandle> {
+        let blocking_file = |fd| tokio::fs::File::from_std(std::fs::File::from(fd));
+        Ok(match mode {
+            OpenMode::Reader { blocking: false } => {
+                FifoHandle::Receiver(self.open_receiver().await?)
+            }
+            OpenMode::Writer { blocking: false } => FifoHandle::Sender(self.open_sender().await?),
+            OpenMode::Reader { blocking: true } => FifoHandle::File(blocking_file(
+                self.open_receiver().await?.into_blocking_fd()?,
+            )),
+            OpenMode::Writer { blocking: true } => {
+                FifoHandle::File(blocking_file(self.open_sender().await?.into_blocking_fd()?))
+            }
+            OpenMode::ReadWrite => FifoHandle::File(self.open_rdwr().await?),
+        })
+    }
+
+    /// Mode matching the `read`, `write` and `blocking` flags, for `open()`
+    pub(crate) fn legacy_open_mode(&self) -> std::io::Result<OpenMode> {
+        match (self.read, self.write) {
+            (true, true) if self.read_write_unchecked => Ok(OpenMode::ReadWrite),
+            (true, true) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "For safety, read and write cannot be true at the same time \
+                 unless read_write_unchecked is set",
+            )),
+            (true, false) => Ok(OpenMode::Reader {
+                blocking: self.blocking,
+            }),
+            (false, _) => Ok(OpenMode::Writer {
+                blocking: self.blocking,
+            }),
+        }
+    }
+}
+
+impl AsFd for FifoHandle {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            FifoHandle::Receiver(inner) => inner.as_fd(),
+            FifoHandle::Sender(inner) => inner.as_fd(),
+            FifoHandle::File(inner) => inner.as_fd(),
+        }
+    }
+}
+
+impl AsyncRead for FifoHandle {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            FifoHandle::Receiver(inner) => Pin::new(inner).poll_read(cx, buf),
+            FifoHandle::File(inner) => Pin::new(inner).poll_read(cx, buf),
+            FifoHandle::Sender(_) => {
+                Poll::Ready(Err(wrong_direction("Cannot read from sender FIFO")))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for FifoHandle {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            FifoHandle::Sender(inner) => Pin::new(inner).poll_write(cx, buf),
+            FifoHandle::File(inner) => Pin::new(inner).poll_write(cx, buf),
+            FifoHandle::Receiver(_) => {
+                Poll::Ready(Err(wrong_direction("Cannot write to receiver FIFO")))
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            FifoHandle::Sender(inner) => Pin::new(inner).poll_flush(cx),
+            FifoHandle::File(inner) => Pin::new(inner).poll_flush(cx),
+            FifoHandle::Receiver(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            FifoHandle::Sender(inner) => Pin::new(inner).poll_shutdown(cx),
+            FifoHandle::File(inner) => Pin::new(inner).poll_shutdown(cx),
+            FifoHandle::Receiver(_) => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl FifoTransport for FifoHandle {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_open_with_modes() {
+        let fifo_path = "/tmp/test_open_with";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        let mut receiver = config
+            .open_with(OpenMode::Reader { blocking: false })
+            .await
+            .unwrap();
+        assert!(matches!(receiver, FifoHandle::Receiver(_)));
+        let mut sender = config
+            .open_with(OpenMode::Writer { blocking: true })
+            .await
+            .unwrap();
+        assert!(matches!(sender, FifoHandle::File(_)));
+        sender.write_all(b"mode").await.unwrap();
+        sender.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"mode");
+        assert_eq!(
+            receiver.write_all(b"x").await.unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+
+        // Both flags only map to a mode when explicitly allowed
+        let mut legacy = config.clone();
+        legacy.set_read(true).set_write(true);
+        assert!(legacy.legacy_open_mode().is_err());
+        legacy.set_read_write_unchecked(true);
+        assert_eq!(legacy.legacy_open_mode().unwrap(), OpenMode::ReadWrite);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
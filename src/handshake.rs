@@ -0,0 +1,569 @@
+use crate::{
+    handshake_path, nonce, peercred::CredSocket, proof::Prover, read_handshake_message,
+    write_handshake_message, ChannelState, Compression, HandshakeMessage, HandshakePhase,
+    HandshakeType, NonceCache, SessionStore, Sfifo, WireFormat,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroize;
+
+// Largest age of a handshake message
+pub(crate) const MAX_MESSAGE_AGE_SECS: u64 = 30;
+
+/// Where the messages of a handshake travel
+pub(crate) trait HandshakeIo {
+    /// Read the next message of the peer
+    async fn recv(&mut self, cancel: &CancellationToken) -> std::io::Result<HandshakeMessage>;
+    /// Send a message to the peer
+    async fn send(&mut self, message: &HandshakeMessage) -> std::io::Result<()>;
+}
+
+/// A reader and a writer set up by the caller, such as the halves of a
+/// socket
+impl<R, W> HandshakeIo for (R, W)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    async fn recv(&mut self, cancel: &CancellationToken) -> std::io::Result<HandshakeMessage> {
+        read_handshake_message(&mut self.0, cancel).await
+    }
+
+    async fn send(&mut self, message: &HandshakeMessage) -> std::io::Result<()> {
+        write_handshake_message(&mut self.1, message).await
+    }
+}
+
+/// Side-channel FIFOs of an `Sfifo`: requests and acknowledgments go over
+/// `PATH.c2s`, responses over `PATH.s2c`, each opened for one message
+pub(crate) struct SideChannels<'a> {
+    config: &'a Sfifo,
+    is_server: bool,
+}
+
+impl<'a> SideChannels<'a> {
+    pub(crate) fn new(config: &'a Sfifo, is_server: bool) -> Self {
+        SideChannels { config, is_server }
+    }
+
+    fn open(&self, extension: &str) -> Sfifo {
+        let mut sfifo = self
+            .config
+            .side_channel(handshake_path(&self.config.file_path, extension));
+        sfifo.set_create(true);
+        sfifo
+    }
+}
+
+impl HandshakeIo for SideChannels<'_> {
+    async fn recv(&mut self, cancel: &CancellationToken) -> std::io::Result<HandshakeMessage> {
+        let extension = if self.is_server { "c2s" } else { "s2c" };
+        let mut receiver = self.open(extension).open_receiver().await?;
+        read_handshake_message(&mut receiver, cancel).await
+    }
+
+    async fn send(&mut self, message: &HandshakeMessage) -> std::io::Result<()> {
+        let extension = if self.is_server { "s2c" } else { "c2s" };
+        let mut sender = self.open(extension).open_sender().await?;
+        write_handshake_message(&mut sender, message).await
+    }
+}
+
+/// What a handshake checks and adds beyond the bare exchange. The
+/// defaults are those of `server_handshake` and `client_handshake`
+pub(crate) trait HandshakeHooks {
+    /// Wire format of the messages sent by the client
+    fn wire_format(&self) -> WireFormat {
+        WireFormat::default()
+    }
+
+    /// Cache rejecting replayed nonces
+    fn nonce_cache(&self) -> Option<&NonceCache> {
+        None
+    }
+
+    fn report(&self, _phase: HandshakePhase) {}
+
+    /// Check the token of a client request
+    fn authenticate(
+        &self,
+        request: &mut HandshakeMessage,
+        expected_token: &str,
+    ) -> std::io::Result<()> {
+        request.validate(expected_token, MAX_MESSAGE_AGE_SECS)
+    }
+
+    /// Token the server presents to the client of `request`, and expects
+    /// in its acknowledgment
+    fn client_token<'a>(&self, _request: &'a HandshakeMessage, token: &'a str) -> &'a str {
+        token
+    }
+
+    /// Response to a request resuming `session`, and whether it was
+    /// accepted
+    fn session_response(
+        &mut self,
+        _request: &HandshakeMessage,
+        _session: &str,
+    ) -> std::io::Result<(HandshakeMessage, bool)> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Session resumption needs an Sfifo server",
+        ))
+    }
+
+    fn extend_request(&self, request: HandshakeMessage) -> HandshakeMessage {
+        request
+    }
+
+    fn extend_response(
+        &mut self,
+        _request: &HandshakeMessage,
+        response: HandshakeMessage,
+    ) -> std::io::Result<HandshakeMessage> {
+        Ok(response)
+    }
+
+    /// Run once the response is sent, before the acknowledgment is read
+    async fn responded(
+        &mut self,
+        _request: &mut HandshakeMessage,
+        _cancel: &CancellationToken,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Check a response whose token and nonce are valid
+    async fn verify_response(&self, _response: &mut HandshakeMessage) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Run once the client acknowledged the response
+    fn acknowledged(&mut self) {}
+}
+
+/// Hooks of the bare exchange
+struct Bare;
+
+impl HandshakeHooks for Bare {}
+
+/// Route of the handshake of an `Sfifo`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Route {
+    SideChannels,
+    Inband,
+    Socket,
+}
+
+/// Hooks of the handshakes of an `Sfifo`: token registries and rotation,
+/// replay protection, progress reports, and depending on the route
+/// extensions, sessions and the kernel credential exchange
+pub(crate) struct SfifoHooks<'a> {
+    config: &'a Sfifo,
+    route: Route,
+    cred_socket: Option<CredSocket>,
+    // Issued in the response, redeemable once acknowledged
+    session: Option<String>,
+}
+
+impl<'a> SfifoHooks<'a> {
+    fn new(config: &'a Sfifo, route: Route) -> Self {
+        SfifoHooks {
+            config,
+            route,
+            cred_socket: None,
+            session: None,
+        }
+    }
+
+    /// Handshake over the side-channel FIFOs
+    pub(crate) fn side_channels(config: &'a Sfifo) -> Self {
+        Self::new(config, Route::SideChannels)
+    }
+
+    /// In-band handshake over the FIFO itself
+    pub(crate) fn inband(config: &'a Sfifo) -> Self {
+        Self::new(config, Route::Inband)
+    }
+
+    /// Handshake over a Unix socket, whose peer credentials come with the
+    /// connection
+    pub(crate) fn socket(config: &'a Sfifo) -> Self {
+        Self::new(config, Route::Socket)
+    }
+}
+
+impl HandshakeHooks for SfifoHooks<'_> {
+    fn wire_format(&self) -> WireFormat {
+        self.config.wire_format
+    }
+
+    fn nonce_cache(&self) -> Option<&NonceCache> {
+        self.config.nonce_cache.as_ref()
+    }
+
+    fn report(&self, phase: HandshakePhase) {
+        match phase {
+            HandshakePhase::WaitingForPeer => self.config.state.set(ChannelState::WaitingPeer),
+            HandshakePhase::SentRequest | HandshakePhase::ReceivedRequest => {
+                self.config.state.set(ChannelState::Handshaking)
+            }
+            _ => {}
+        }
+        self.config.report(phase);
+    }
+
+    fn authenticate(
+        &self,
+        request: &mut HandshakeMessage,
+        expected_token: &str,
+    ) -> std::io::Result<()> {
+        self.config.authenticate_client(request, expected_token)
+    }
+
+    fn client_token<'a>(&self, request: &'a HandshakeMessage, token: &'a str) -> &'a str {
+        self.config.client_token(request, token)
+    }
+
+    fn session_response(
+        &mut self,
+        request: &HandshakeMessage,
+        session: &str,
+    ) -> std::io::Result<(HandshakeMessage, bool)> {
+        if self.route != Route::SideChannels {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Session resumption needs the side-channel handshake",
+            ));
+        }
+        self.config.session_response(request, session)
+    }
+
+    fn extend_request(&self, mut request: HandshakeMessage) -> HandshakeMessage {
+        if self.route == Route::Socket {
+            return request;
+        }
+        if let Some(extension) = Compression::offer(self.config.compression) {
+            request = request.with_extension(extension);
+        }
+        self.config.offer_kernel_cred(request)
+    }
+
+    fn extend_response(
+        &mut self,
+        request: &HandshakeMessage,
+        mut response: HandshakeMessage,
+    ) -> std::io::Result<HandshakeMessage> {
+        if self.route == Route::Socket {
+            return Ok(response);
+        }
+        if self.route == Route::SideChannels && self.config.sessions.is_some() {
+            let session = SessionStore::issue()?;
+            response = response.with_session_token(&session);
+            self.session = Some(session);
+        }
+        response = self.config.with_server_extensions(response)?;
+        self.cred_socket = self.config.bind_cred_socket(request)?;
+        if let Some(socket) = &self.cred_socket {
+            response = response.with_extension(socket.extension());
+        }
+        Ok(response)
+    }
+
+    async fn responded(
+        &mut self,
+        request: &mut HandshakeMessage,
+        cancel: &CancellationToken,
+    ) -> std::io::Result<()> {
+        if let Some(socket) = self.cred_socket.take() {
+            request.kernel_cred = Some(socket.accept(request.process_id, cancel).await?);
+        }
+        Ok(())
+    }
+
+    async fn verify_response(&self, response: &mut HandshakeMessage) -> std::io::Result<()> {
+        if self.route == Route::Socket {
+            return Ok(());
+        }
+        self.config.verify_server_cred(response).await
+    }
+
+    fn acknowledged(&mut self) {
+        if let (Some(sessions), Some(session)) = (&self.config.sessions, self.session.take()) {
+            sessions.insert(session);
+        }
+    }
+}
+
+/// Server side of the request, response and acknowledgment exchange,
+/// shared by every transport. Returns the request of the client
+pub(crate) async fn server_exchange(
+    io: &mut impl HandshakeIo,
+    hooks: &mut impl HandshakeHooks,
+    token: &str,
+    expected_token: &str,
+    cancel: &CancellationToken,
+) -> std::io::Result<HandshakeMessage> {
+    hooks.report(HandshakePhase::WaitingForPeer);
+    let mut request = io.recv(cancel).await?;
+    hooks.report(HandshakePhase::ReceivedRequest);
+    debug!("Server: Received client handshake request {:?}", request);
+    expect_type(&request, HandshakeType::Request)?;
+
+    if let Some(session) = request.session_token() {
+        let (response, accepted) = hooks.session_response(&request, session)?;
+        io.send(&response).await?;
+        if !accepted {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Invalid or expired session token",
+            ));
+        }
+        return Ok(request);
+    }
+    hooks.authenticate(&mut request, expected_token)?;
+    let client_nonce = request.fresh_nonce(hooks.nonce_cache())?;
+
+    debug!("Server: Sending handshake response");
+    let server_nonce = nonce::new_nonce()?;
+    let mut response = HandshakeMessage::new(String::new(), HandshakeType::Response)?
+        .with_nonce(&server_nonce)
+        .with_nonce_echo(&client_nonce)
+        .with_proof(
+            hooks.client_token(&request, token),
+            Prover::Server,
+            &client_nonce,
+            &server_nonce,
+        )
+        .with_wire_format(request.wire_format);
+    response = hooks.extend_response(&request, response)?;
+    io.send(&response).await?;
+    hooks.report(HandshakePhase::SentResponse);
+    hooks.responded(&mut request, cancel).await?;
+
+    debug!("Server: Waiting for client acknowledgment");
+    let ack = io.recv(cancel).await?;
+    expect_type(&ack, HandshakeType::Ack)?;
+    ack.check_timestamp(MAX_MESSAGE_AGE_SECS)?;
+    ack.check_nonce_echo(&server_nonce)?;
+    ack.check_proof(
+        hooks.client_token(&request, expected_token),
+        Prover::Client,
+        &server_nonce,
+        &client_nonce,
+    )?;
+    hooks.acknowledged();
+    debug!(
+        "Server: Handshake completed with client PID {}",
+        request.process_id
+    );
+    Ok(request)
+}
+
+/// Client side of the exchange, the counterpart of `server_exchange`.
+/// Returns the response of the server
+pub(crate) async fn client_exchange(
+    io: &mut impl HandshakeIo,
+    hooks: &mut impl HandshakeHooks,
+    token: &str,
+    expected_token: &str,
+    cancel: &CancellationToken,
+) -> std::io::Result<HandshakeMessage> {
+    debug!("client: Sending handshake request");
+    hooks.report(HandshakePhase::WaitingForPeer);
+    let client_nonce = nonce::new_nonce()?;
+    let mut request = HandshakeMessage::new(token.to_string(), HandshakeType::Request)?
+        .with_nonce(&client_nonce)
+        .with_wire_format(hooks.wire_format());
+    request = hooks.extend_request(request);
+    let written = io.send(&request).await;
+    request.token.zeroize();
+    written?;
+    hooks.report(HandshakePhase::SentRequest);
+
+    hooks.report(HandshakePhase::AwaitingResponse);
+    let mut response = io.recv(cancel).await?;
+    debug!("client: Received server response {:?}", response);
+    expect_type(&response, HandshakeType::Response)?;
+    response.check_timestamp(MAX_MESSAGE_AGE_SECS)?;
+    response.check_nonce_echo(&client_nonce)?;
+    let server_nonce = response.fresh_nonce(hooks.nonce_cache())?;
+    response.check_proof(expected_token, Prover::Server, &client_nonce, &server_nonce)?;
+    // The server proved it holds the token without sending it
+    response.token = expected_token.to_string();
+    hooks.verify_response(&mut response).await?;
+
+    debug!("client: Sending acknowledgment");
+    let ack = HandshakeMessage::new(String::new(), HandshakeType::Ack)?
+        .with_nonce_echo(&server_nonce)
+        .with_proof(token, Prover::Client, &server_nonce, &client_nonce)
+        .with_wire_format(hooks.wire_format());
+    io.send(&ack).await?;
+    debug!(
+        "Client: Handshake completed with server PID {}",
+        response.process_id
+    );
+    Ok(response)
+}
+
+/// Authenticate a client over a transport set up by the caller, such as a
+/// socket or a pair of pipes
+///
+/// Runs the same request, response and acknowledgment exchange as
+/// `Sfifo::open_as_server`, answering in the wire format of the request.
+/// Returns the request of the client. There is no timeout, wrap the call
+/// in `tokio::time::timeout` if needed. Session resumption and extension
+/// negotiation are left to `Sfifo`.
+pub async fn server_handshake<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    token: &str,
+    expected_token: &str,
+) -> std::io::Result<HandshakeMessage>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let cancel = CancellationToken::new();
+    server_exchange(
+        &mut (reader, writer),
+        &mut Bare,
+        token,
+        expected_token,
+        &cancel,
+    )
+    .await
+}
+
+/// Authenticate to a server over a transport set up by the caller, the
+/// counterpart of `server_handshake`
+///
+/// Messages are sent in bincode. Returns the response of the server.
+pub async fn client_handshake<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    token: &str,
+    expected_token: &str,
+) -> std::io::Result<HandshakeMessage>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let cancel = CancellationToken::new();
+    client_exchange(
+        &mut (reader, writer),
+        &mut Bare,
+        token,
+        expected_token,
+        &cancel,
+    )
+    .await
+}
+
+pub(crate) fn expect_type(
+    message: &HandshakeMessage,
+    expected: HandshakeType,
+) -> std::io::Result<()> {
+    if message.message_type == expected {
+        return Ok(());
+    }
+    let name = match expected {
+        HandshakeType::Request => "request",
+        HandshakeType::Response => "response",
+        HandshakeType::Ack => "acknowledgment",
+    };
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Expected handshake {}", name),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Extension, EXT_PROOF};
+    use tokio::{io::duplex, net::UnixStream};
+
+    #[tokio::test]
+    async fn test_handshake_over_caller_transports() {
+        // A socket pair, one stream per side
+        let (client, server) = UnixStream::pair().unwrap();
+        let (mut client_reader, mut client_writer) = client.into_split();
+        let (mut server_reader, mut server_writer) = server.into_split();
+        let (request, response) = tokio::join!(
+            server_handshake(&mut server_reader, &mut server_writer, "server", "client"),
+            client_handshake(&mut client_reader, &mut client_writer, "client", "server"),
+        );
+        assert_eq!(request.unwrap().process_id, std::process::id());
+        assert_eq!(response.unwrap().token, "server");
+
+        // In-memory pipes, with a client holding the wrong token
+        let (client, server) = duplex(4096);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client);
+        let (server_reader, server_writer) = tokio::io::split(server);
+        let (server, client) = tokio::join!(
+            async move {
+                let (mut reader, mut writer) = (server_reader, server_writer);
+                server_handshake(&mut reader, &mut writer, "secret", "secret").await
+            },
+            client_handshake(&mut client_reader, &mut client_writer, "wrong", "secret"),
+        );
+        assert_eq!(
+            server.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        assert!(client.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_server_secret_never_sent() {
+        // A client holding the client token only
+        let (client, server) = duplex(4096);
+        let (mut client_reader, mut client_writer) = tokio::io::split(client);
+        let (mut server_reader, mut server_writer) = tokio::io::split(server);
+        let server = tokio::spawn(async move {
+            server_handshake(
+                &mut server_reader,
+                &mut server_writer,
+                "server_secret",
+                "client_token",
+            )
+            .await
+        });
+        let cancel = CancellationToken::new();
+        let client_nonce = nonce::new_nonce().unwrap();
+        let request = HandshakeMessage::new("client_token".to_string(), HandshakeType::Request)
+            .unwrap()
+            .with_nonce(&client_nonce);
+        write_handshake_message(&mut client_writer, &request)
+            .await
+            .unwrap();
+        let response = read_handshake_message(&mut client_reader, &cancel)
+            .await
+            .unwrap();
+        assert!(response.token.is_empty());
+        let server_nonce = response.nonce().unwrap();
+        response
+            .check_proof(
+                "server_secret",
+                Prover::Server,
+                &client_nonce,
+                &server_nonce,
+            )
+            .unwrap();
+
+        // Echoing the server's proof back does not authenticate the client
+        let ack = HandshakeMessage::new(String::new(), HandshakeType::Ack)
+            .unwrap()
+            .with_nonce_echo(&server_nonce)
+            .with_extension(Extension::new(
+                EXT_PROOF,
+                response.extension(EXT_PROOF).unwrap(),
+            ));
+        write_handshake_message(&mut client_writer, &ack)
+            .await
+            .unwrap();
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{AuthenticatedFifo, PeerIdentity, PipeEnd};
+use std::{
+    os::fd::{AsRawFd, RawFd},
+    time::{Duration, Instant},
+};
+
+/// Liveness of a peer, returned by `AuthenticatedFifo::probe()` and
+/// `Channel::probe()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Process id announced by the peer during the handshake
+    pub peer_pid: u32,
+    /// The peer process exists, and still runs the same executable when
+    /// its identity was gathered
+    pub peer_alive: bool,
+    /// The peer still holds its end of the FIFO open
+    pub pipe_connected: bool,
+    /// Round trip of the ping, `None` if no ping was sent or no pong came
+    /// back before the deadline
+    pub round_trip: Option<Duration>,
+    /// Whether a ping was sent
+    pub pinged: bool,
+    /// Time spent probing
+    pub elapsed: Duration,
+}
+
+impl HealthReport {
+    /// Whether every check of the probe passed
+    pub fn is_healthy(&self) -> bool {
+        self.peer_alive && self.pipe_connected && (!self.pinged || self.round_trip.is_some())
+    }
+}
+
+impl AuthenticatedFifo {
+    /// Check that the peer process is alive and still has the FIFO open
+    ///
+    /// Only looks at the process table and the pipe, nothing is sent to
+    /// the peer, so this is cheap enough for frequent liveness checks. A
+    /// `Channel` can also ping the peer, see `Channel::probe()`.
+    pub fn probe(&self) -> HealthReport {
+        let started = Instant::now();
+        let peer_pid = self.peer_info.process_id;
+        HealthReport {
+            peer_pid,
+            peer_alive: process_alive(peer_pid, self.peer_identity.as_ref()),
+            pipe_connected: pipe_connected(
+                self.end.as_raw_fd(),
+                matches!(self.end, PipeEnd::Sender(_)),
+            ),
+            round_trip: None,
+            pinged: false,
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+/// Check that `pid` exists, and runs the executable of `identity`
+pub(crate) fn process_alive(pid: u32, identity: Option<&PeerIdentity>) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process exists, EPERM means it does but
+    // belongs to another user
+    let exists = unsafe { libc::kill(pid, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    if !exists {
+        return false;
+    }
+    // A different executable means the pid was reused
+    match (
+        identity.and_then(|i| i.exe.as_ref()),
+        std::fs::read_link(format!("/proc/{}/exe", pid)),
+    ) {
+        (Some(expected), Ok(exe)) => *expected == exe,
+        _ => true,
+    }
+}
+
+/// Check that the other end of the pipe behind `fd` is still open
+pub(crate) fn pipe_connected(fd: RawFd, is_sender: bool) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: if is_sender {
+            libc::POLLOUT
+        } else {
+            libc::POLLIN
+        },
+        revents: 0,
+    };
+    // A sender gets POLLERR once the readers are gone, a receiver POLLHUP
+    // once the writers are
+    let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+    ready >= 0 && pollfd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sfifo;
+
+    #[tokio::test]
+    async fn test_probe_detects_closed_peer() {
+        let fifo_path = "/tmp/test_probe";
+        let token = "probe_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client = Sfifo::new(fifo_path).open_as_client(token).await.unwrap();
+        let server = server.await.unwrap().unwrap();
+
+        let report = client.probe();
+        assert_eq!(report.peer_pid, std::process::id());
+        assert!(report.is_healthy(), "{:?}", report);
+        assert!(server.probe().is_healthy());
+
+        drop(server);
+        let report = client.probe();
+        assert!(report.peer_alive);
+        assert!(!report.pipe_connected);
+        assert!(!report.is_healthy());
+        assert!(!process_alive(u32::MAX / 2, None));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_channel_probe_pings_peer() {
+        let fifo_path = "/tmp/test_channel_probe";
+        let token = "probe_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+        let server = tokio::spawn({
+            let config = config.clone();
+            async move {
+                let mut channel = crate::Channel::accept(&config, token).await?;
+                channel.send(b"before pong").await?;
+                channel.echo_server().await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut client = crate::Channel::connect(&config, token).await.unwrap();
+
+        let report = client.probe(Some(Duration::from_secs(1))).await.unwrap();
+        assert!(report.pinged);
+        assert!(report.round_trip.is_some());
+        assert!(report.is_healthy());
+        // Data received while waiting for the pong is kept
+        assert_eq!(&client.recv().await.unwrap().unwrap()[..], b"before pong");
+
+        drop(client);
+        server.await.unwrap().unwrap();
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
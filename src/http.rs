@@ -0,0 +1,224 @@
+use crate::{Channel, Sfifo};
+use bytes::{Buf, Bytes};
+use futures_util::{ready, Sink, Stream};
+use hyper::{rt::ReadBufCursor, Uri};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Largest data frame sent by one write, well under the frame size limit
+const MAX_WRITE: usize = 64 * 1024;
+
+/// Byte stream over a duplex `Channel`, for HTTP/1 with hyper
+///
+/// Each write is sent as one data frame and frames are read back to back,
+/// so any protocol expecting a connected socket can run on it. It
+/// implements both the tokio and the hyper IO traits.
+#[derive(Debug)]
+pub struct ChannelIo {
+    channel: Channel,
+    // Rest of the last frame received
+    pending: Bytes,
+}
+
+impl ChannelIo {
+    pub fn new(channel: Channel) -> Self {
+        ChannelIo {
+            channel,
+            pending: Bytes::new(),
+        }
+    }
+
+    /// Get the underlying channel
+    pub fn into_inner(self) -> Channel {
+        self.channel
+    }
+
+    /// Ready with up to `max` bytes, empty at the end of the stream
+    fn poll_chunk(&mut self, cx: &mut Context<'_>, max: usize) -> Poll<std::io::Result<Bytes>> {
+        while self.pending.is_empty() {
+            match ready!(Pin::new(&mut self.channel).poll_next(cx)) {
+                Some(data) => self.pending = data?,
+                None => return Poll::Ready(Ok(Bytes::new())),
+            }
+        }
+        let n = max.min(self.pending.len());
+        Poll::Ready(Ok(self.pending.split_to(n)))
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let mut channel = Pin::new(&mut self.channel);
+        ready!(channel.as_mut().poll_ready(cx))?;
+        let n = buf.len().min(MAX_WRITE);
+        channel.start_send(Bytes::copy_from_slice(&buf[..n]))?;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl From<Channel> for ChannelIo {
+    fn from(channel: Channel) -> Self {
+        ChannelIo::new(channel)
+    }
+}
+
+impl AsyncRead for ChannelIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let chunk = ready!(self.get_mut().poll_chunk(cx, buf.remaining()))?;
+        buf.put_slice(&chunk);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ChannelIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.get_mut().poll_send(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().channel).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().channel).poll_close(cx)
+    }
+}
+
+impl hyper::rt::Read for ChannelIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: ReadBufCursor<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let chunk = ready!(self.get_mut().poll_chunk(cx, buf.remaining()))?;
+        buf.put_slice(chunk.chunk());
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl hyper::rt::Write for ChannelIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.get_mut().poll_send(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_shutdown(self, cx)
+    }
+}
+
+/// Tower service connecting a `Channel` for each call, to plug a FIFO
+/// where HTTP clients expect a connector
+///
+/// The URI is ignored: every connection goes to the FIFO path of the
+/// configuration, authenticated with the token. The server side accepts
+/// with `Channel::accept` and serves `ChannelIo::new(channel)`, for
+/// example with `hyper::server::conn::http1::Builder::serve_connection`.
+#[derive(Debug, Clone)]
+pub struct FifoConnector {
+    config: Sfifo,
+    token: String,
+}
+
+impl FifoConnector {
+    pub fn new(config: &Sfifo, token: &str) -> Self {
+        FifoConnector {
+            config: config.clone(),
+            token: token.to_string(),
+        }
+    }
+
+    /// Connect a channel, outside of a tower stack
+    pub async fn connect(&self) -> std::io::Result<ChannelIo> {
+        Channel::connect(&self.config, &self.token)
+            .await
+            .map(ChannelIo::new)
+    }
+}
+
+impl tower_service::Service<Uri> for FifoConnector {
+    type Response = ChannelIo;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = std::io::Result<ChannelIo>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let connector = self.clone();
+        Box::pin(async move { connector.connect().await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{service::service_fn, Request, Response};
+    use std::{convert::Infallible, time::Duration};
+    use tower_service::Service;
+
+    #[tokio::test]
+    async fn test_http_over_fifo() {
+        let fifo_path = "/tmp/test_http_over_fifo";
+        let token = "http_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let server = tokio::spawn(async move {
+            let channel = Channel::accept(&server_config, token).await.unwrap();
+            let service = service_fn(|request: Request<hyper::body::Incoming>| async move {
+                let body = request.into_body().collect().await.unwrap().to_bytes();
+                let reply = format!("echo {}", String::from_utf8_lossy(&body));
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(reply))))
+            });
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(ChannelIo::new(channel), service)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut connector = FifoConnector::new(&Sfifo::new(fifo_path), token);
+        let io = connector
+            .call(Uri::from_static("http://localhost/"))
+            .await
+            .unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(io).await.unwrap();
+        let connection = tokio::spawn(connection);
+
+        for i in 0..2 {
+            let request = Request::post("/")
+                .header("host", "localhost")
+                .body(Full::new(Bytes::from(format!("request {}", i))))
+                .unwrap();
+            let response = sender.send_request(request).await.unwrap();
+            assert!(response.status().is_success());
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, format!("echo request {}", i).as_bytes());
+        }
+
+        drop(sender);
+        connection.await.unwrap().unwrap();
+        server.await.unwrap().unwrap();
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(format!("{}.rev", fifo_path)).await;
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{AuthenticatedFifo, HandshakeMessage, HandshakePhase, Sfifo};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Identity of the peer process, gathered locally from `/proc/<pid>`
+/// after the handshake rather than taken from what the peer reported.
+///
+/// Only the process id comes from the handshake: a peer holding the token
+/// can still claim another process id, the identity then describes that
+/// process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerIdentity {
+    pub pid: u32,
+    /// Real user id
+    pub uid: u32,
+    /// Real group id
+    pub gid: u32,
+    /// Unified (v2) cgroup path, `None` if not readable
+    pub cgroup: Option<String>,
+    /// Target of `/proc/<pid>/exe`, `None` if not readable
+    pub exe: Option<PathBuf>,
+    /// SELinux context or AppArmor profile from `/proc/<pid>/attr/current`,
+    /// `None` without a security module or if not readable
+    pub security_label: Option<String>,
+}
+
+impl PeerIdentity {
+    /// Gather the identity of process `pid`
+    pub fn of_process(pid: u32) -> std::io::Result<Self> {
+        let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+        let status = std::fs::read_to_string(proc_dir.join("status"))?;
+        let id_field = |name: &str| -> std::io::Result<u32> {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|ids| ids.split_whitespace().next())
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("No {} in /proc/{}/status", name, pid),
+                    )
+                })
+        };
+        let cgroup = std::fs::read_to_string(proc_dir.join("cgroup"))
+            .ok()
+            .and_then(|content| {
+                content
+                    .lines()
+                    .find_map(|line| line.strip_prefix("0::").map(str::to_string))
+            });
+        Ok(PeerIdentity {
+            pid,
+            uid: id_field("Uid:")?,
+            gid: id_field("Gid:")?,
+            cgroup,
+            exe: std::fs::read_link(proc_dir.join("exe")).ok(),
+            security_label: security_label(&proc_dir),
+        })
+    }
+}
+
+// Label of the process, with the trailing NUL or newline removed
+fn security_label(proc_dir: &Path) -> Option<String> {
+    let label = std::fs::read_to_string(proc_dir.join("attr/current")).ok()?;
+    let label = label.trim_end_matches(['\0', '\n']);
+    (!label.is_empty()).then(|| label.to_string())
+}
+
+type PolicyFn = dyn Fn(&PeerIdentity) -> bool + Send + Sync;
+
+/// Decides whether a peer may connect, set on an `Sfifo` with
+/// `set_peer_policy`
+///
+/// The policy runs on both sides once the handshake succeeded; a rejected
+/// peer makes the open fail with `PermissionDenied`, as does a peer whose
+/// identity cannot be gathered.
+#[derive(Clone)]
+pub struct PeerPolicy(Arc<PolicyFn>);
+
+impl PeerPolicy {
+    pub fn new(policy: impl Fn(&PeerIdentity) -> bool + Send + Sync + 'static) -> Self {
+        PeerPolicy(Arc::new(policy))
+    }
+
+    /// Accept only peers running the executable at `exe`
+    pub fn executable(exe: impl Into<PathBuf>) -> Self {
+        let exe = exe.into();
+        Self::new(move |peer| peer.exe.as_ref() == Some(&exe))
+    }
+
+    /// Accept only peers running as user `uid`
+    pub fn uid(uid: u32) -> Self {
+        Self::new(move |peer| peer.uid == uid)
+    }
+
+    /// Accept only peers whose security label satisfies `accept`, e.g.
+    /// `|label| label.starts_with("system_u:system_r:container_runtime_t")`
+    ///
+    /// Peers without a label are rejected.
+    pub fn security_label(accept: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        Self::new(move |peer| peer.security_label.as_deref().is_some_and(&accept))
+    }
+
+    pub fn allows(&self, peer: &PeerIdentity) -> bool {
+        (self.0)(peer)
+    }
+}
+
+impl std::fmt::Debug for PeerPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PeerPolicy").finish_non_exhaustive()
+    }
+}
+
+impl Sfifo {
+    /// Gather the identity of the authenticated peer and apply the channel
+    /// ACL and the peer policy to it
+    pub(crate) fn verify_peer(
+        &self,
+        peer_info: HandshakeMessage,
+    ) -> std::io::Result<(HandshakeMessage, Option<PeerIdentity>)> {
+        let identity = PeerIdentity::of_process(peer_info.process_id);
+        if self.peer_policy.is_none() && self.channel_acl.is_none() {
+            self.report(HandshakePhase::Validated);
+            return Ok((peer_info, identity.ok()));
+        }
+        let denied =
+            |reason: String| std::io::Error::new(std::io::ErrorKind::PermissionDenied, reason);
+        let identity = identity.map_err(|e| {
+            denied(format!(
+                "Cannot identify peer PID {}: {}",
+                peer_info.process_id, e
+            ))
+        })?;
+        if let Some(acl) = self
+            .channel_acl
+            .as_ref()
+            .filter(|acl| !acl.allows(&identity))
+        {
+            warn!("Peer {:?} rejected by ACL {:?}", identity, acl);
+            return Err(denied(format!(
+                "Peer PID {} (uid {}, gid {}) not allowed by the channel ACL",
+                identity.pid, identity.uid, identity.gid
+            )));
+        }
+        if self
+            .peer_policy
+            .as_ref()
+            .is_some_and(|policy| !policy.allows(&identity))
+        {
+            warn!("Peer {:?} rejected by policy", identity);
+            return Err(denied(format!(
+                "Peer PID {} rejected by policy",
+                identity.pid
+            )));
+        }
+        self.report(HandshakePhase::Validated);
+        Ok((peer_info, Some(identity)))
+    }
+}
+
+impl AuthenticatedFifo {
+    /// Identity of the peer process gathered after the handshake, `None`
+    /// if `/proc` could not be read
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.peer_identity.as_ref()
+    }
+
+    pub(crate) fn with_peer_identity(mut self, identity: Option<PeerIdentity>) -> Self {
+        self.peer_identity = identity;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_peer_policy() {
+        let own = PeerIdentity::of_process(std::process::id()).unwrap();
+        assert_eq!(own.exe, std::env::current_exe().ok());
+
+        let fifo_path = "/tmp/test_peer_policy";
+        let token = "policy_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_peer_policy(Some(PeerPolicy::uid(own.uid)))
+            .clone();
+        let client_config = Sfifo::new(fifo_path)
+            .set_peer_policy(Some(PeerPolicy::executable("/usr/bin/containerd")))
+            .clone();
+
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let error = client_config.open_as_client(token).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+
+        let server = server.await.unwrap().unwrap();
+        assert_eq!(server.peer_identity(), Some(&own));
+
+        // Label policies only accept labelled peers
+        let label = own.security_label.clone();
+        let policy = PeerPolicy::security_label(move |l| Some(l) == label.as_deref());
+        assert_eq!(policy.allows(&own), own.security_label.is_some());
+        let unlabelled = PeerIdentity {
+            security_label: None,
+            ..own
+        };
+        assert!(!policy.allows(&unlabelled));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{AuthenticatedFifo, ChannelState, SfifoError};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+
+/// Deadline pushed back by every read or write, see
+/// `AuthenticatedFifo::set_idle_timeout`
+#[derive(Debug)]
+pub(crate) struct IdleTimer {
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+    expired: bool,
+}
+
+impl IdleTimer {
+    fn new(timeout: Duration) -> Self {
+        IdleTimer {
+            timeout,
+            sleep: Box::pin(tokio::time::sleep(timeout)),
+            expired: false,
+        }
+    }
+
+    /// Record traffic, restarting the timeout
+    pub(crate) fn touch(&mut self) {
+        if !self.expired {
+            self.sleep.as_mut().reset(Instant::now() + self.timeout);
+        }
+    }
+
+    /// Ready with the error to return once the timeout expired, and on
+    /// every call after that
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Error> {
+        if !self.expired {
+            if self.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            warn!("No traffic for {:?}, closing the connection", self.timeout);
+            self.expired = true;
+        }
+        Poll::Ready(SfifoError::IdleTimeout { idle: self.timeout }.into())
+    }
+
+    pub(crate) async fn expired(&mut self) -> std::io::Error {
+        std::future::poll_fn(|cx| self.poll_expired(cx)).await
+    }
+}
+
+impl AuthenticatedFifo {
+    /// Close the connection after `timeout` without traffic
+    ///
+    /// Any bytes read or written, heartbeats included, restart the
+    /// timeout. Once it expires, reads and writes fail with
+    /// `SfifoError::IdleTimeout` (kind `TimedOut`) and the state becomes
+    /// `Closed`; drop the FIFO to release it. The timeout does not carry
+    /// over to framed senders and receivers made from this FIFO.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle = Some(IdleTimer::new(timeout));
+        self
+    }
+
+    /// Timeout set by `set_idle_timeout`
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle.as_ref().map(|idle| idle.timeout)
+    }
+
+    /// Ready once the idle timeout expired, marking the connection closed;
+    /// always pending without timeout
+    pub(crate) fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Error> {
+        let Some(idle) = self.idle.as_mut() else {
+            return Poll::Pending;
+        };
+        let error = ready!(idle.poll_expired(cx));
+        self.state.set(ChannelState::Closed);
+        Poll::Ready(error)
+    }
+
+    pub(crate) fn touch_idle(&mut self) {
+        if let Some(idle) = self.idle.as_mut() {
+            idle.touch();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sfifo;
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let fifo_path = "/tmp/test_idle_timeout";
+        let token = "idle_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut client = Sfifo::new(fifo_path).open_as_client(token).await.unwrap();
+        let mut server = server.await.unwrap().unwrap();
+        server.set_idle_timeout(Duration::from_millis(200));
+
+        // Traffic keeps the connection open past the timeout
+        let mut buf = [0u8; 4];
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            client.write_all(b"beat").await.unwrap();
+            server.read_exact(&mut buf).await.unwrap();
+        }
+
+        let error = server.read(&mut buf).await.unwrap_err();
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::IdleTimeout {
+                idle: Duration::from_millis(200)
+            })
+        );
+        assert_eq!(server.state(), ChannelState::Closed);
+        // Late traffic does not revive it
+        client.write_all(b"late").await.unwrap();
+        assert!(server.read(&mut buf).await.is_err());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
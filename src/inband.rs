@@ -0,0 +1,193 @@
+use crate::{
+    duplex,
+    handshake::{self, HandshakeIo, SfifoHooks},
+    handshake_path, read_handshake_message, write_handshake_message, HandshakeMessage, Sfifo,
+};
+use std::path::{Path, PathBuf};
+use tokio::net::unix::pipe::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+
+/// Removes the reverse handshake FIFO when the handshake ends, whether it
+/// succeeded or not
+struct ReverseFifo(PathBuf);
+
+impl ReverseFifo {
+    async fn create(file_path: &Path) -> std::io::Result<Self> {
+        let path = handshake_path(file_path, "hs");
+        crate::create_fifo(&path).await?;
+        Ok(ReverseFifo(path))
+    }
+
+    fn config(&self, owner: &Sfifo) -> Sfifo {
+        owner.side_channel(&self.0)
+    }
+}
+
+impl Drop for ReverseFifo {
+    fn drop(&mut self) {
+        if std::fs::remove_file(&self.0).is_ok() {
+            debug!("Removed reverse handshake FIFO {:?}", self.0);
+        }
+    }
+}
+
+/// Server end of an in-band handshake: messages of the client arrive on
+/// the FIFO, the response goes through the reverse FIFO
+struct InbandServer<'a> {
+    config: &'a Sfifo,
+    receiver: Receiver,
+    reverse: ReverseFifo,
+}
+
+impl HandshakeIo for InbandServer<'_> {
+    async fn recv(&mut self, cancel: &CancellationToken) -> std::io::Result<HandshakeMessage> {
+        read_handshake_message(&mut self.receiver, cancel).await
+    }
+
+    async fn send(&mut self, message: &HandshakeMessage) -> std::io::Result<()> {
+        let mut sender = self
+            .reverse
+            .config(self.config)
+            .set_timeout(self.config.timeout)
+            .open_sender()
+            .await?;
+        write_handshake_message(&mut sender, message).await
+    }
+}
+
+/// Client end of an in-band handshake, opened with the first message. The
+/// reverse FIFO goes away once the response is read
+struct InbandClient<'a> {
+    config: &'a Sfifo,
+    sender: Option<Sender>,
+    reverse: Option<(ReverseFifo, Receiver)>,
+}
+
+impl InbandClient<'_> {
+    fn unopened() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::NotConnected,
+            "In-band handshake FIFO is not open",
+        )
+    }
+}
+
+impl HandshakeIo for InbandClient<'_> {
+    async fn recv(&mut self, cancel: &CancellationToken) -> std::io::Result<HandshakeMessage> {
+        let (_reverse, mut receiver) = self.reverse.take().ok_or_else(Self::unopened)?;
+        read_handshake_message(&mut receiver, cancel).await
+    }
+
+    async fn send(&mut self, message: &HandshakeMessage) -> std::io::Result<()> {
+        if self.sender.is_none() {
+            // The server creates it before opening the FIFO, creating it
+            // here only makes sure it goes away if the server died since.
+            // Its read end is open before the server can answer
+            let reverse = ReverseFifo::create(&self.config.file_path).await?;
+            let (sender, receiver) =
+                duplex::open_duplex(self.config, &reverse.config(self.config)).await?;
+            self.sender = Some(sender);
+            self.reverse = Some((reverse, receiver));
+        }
+        let sender = self.sender.as_mut().ok_or_else(Self::unopened)?;
+        write_handshake_message(sender, message).await
+    }
+}
+
+impl Sfifo {
+    /// In-band handshake as server: the request and acknowledgment arrive
+    /// on the FIFO itself, the response goes through a reverse FIFO that
+    /// only exists during the handshake. Returns the FIFO receiver, with
+    /// nothing read past the acknowledgment
+    pub(crate) async fn perform_inband_server_handshake(
+        &self,
+        token: &str,
+        expected_token: &str,
+        cancel_token: &CancellationToken,
+    ) -> std::io::Result<(HandshakeMessage, Receiver)> {
+        let reverse = ReverseFifo::create(&self.file_path).await?;
+        let mut io = InbandServer {
+            config: self,
+            receiver: self.open_receiver().await?,
+            reverse,
+        };
+        debug!("Server: Waiting for in-band handshake request");
+        let client_request = handshake::server_exchange(
+            &mut io,
+            &mut SfifoHooks::inband(self),
+            token,
+            expected_token,
+            cancel_token,
+        )
+        .await?;
+        Ok((client_request, io.receiver))
+    }
+
+    /// In-band handshake as client, counterpart of
+    /// `perform_inband_server_handshake`. Returns the FIFO sender
+    pub(crate) async fn perform_inband_client_handshake(
+        &self,
+        token: &str,
+        expected_token: &str,
+        cancel_token: &CancellationToken,
+    ) -> std::io::Result<(HandshakeMessage, Sender)> {
+        let mut io = InbandClient {
+            config: self,
+            sender: None,
+            reverse: None,
+        };
+        let server_response = handshake::client_exchange(
+            &mut io,
+            &mut SfifoHooks::inband(self),
+            token,
+            expected_token,
+            cancel_token,
+        )
+        .await?;
+        let sender = io.sender.ok_or_else(InbandClient::unopened)?;
+        Ok((server_response, sender))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_inband_handshake() {
+        let fifo_path = "/tmp/test_inband_handshake";
+        let token = "inband_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let reverse_path = handshake_path(fifo_path, "hs");
+        let side_channels = ["c2s", "s2c", "hs"].map(|ext| handshake_path(fifo_path, ext));
+
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_inband_handshake(true)
+            .clone();
+        let server_config = config.clone();
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut client = config.open_as_client(token).await.unwrap();
+        client.write_all(b"in band").await.unwrap();
+
+        let mut server = server.await.unwrap().unwrap();
+        let mut buf = [0u8; 7];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"in band");
+        assert!(side_channels.iter().all(|path| !path.exists()));
+
+        // A rejected client leaves no reverse FIFO behind either
+        let server_config = config.clone();
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client = tokio::spawn(async move { config.open_as_client("wrong").await });
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(!reverse_path.exists());
+        client.abort();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -1,16 +1,198 @@
+use audit::PeerSeen;
+use bytes::BytesMut;
 use getset::{Getters, Setters};
-use log::{debug, error, info};
-use nix::{sys::stat::Mode, unistd::mkfifo};
+use handshake::{SfifoHooks, SideChannels};
+use ratelimit::TokenBucket;
 use serde::{Deserialize, Serialize};
+use state::{StateGuard, StateWatch};
 use std::{
+    os::{fd::AsRawFd, unix::fs::MetadataExt},
     path::{Path, PathBuf},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::net::unix::pipe::{Receiver, Sender};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::unix::pipe::{Receiver, Sender},
+    sync::watch,
+};
+use zeroize::Zeroize;
+
+#[macro_use]
+mod trace;
+
+mod acl;
+mod aggregator;
+mod anon;
+mod atomic;
+mod audit;
+mod backend;
+mod backpressure;
+mod buffered;
+mod bufio;
+mod capture;
+mod channel;
+mod checkpoint;
+mod child;
+mod compression;
+mod conformance;
+mod connect;
+mod container;
+mod control;
+mod diagnostics;
+mod dir;
+mod duplex;
+mod error;
+mod extension;
+mod failover;
+mod fdpass;
+mod flow;
+mod frame;
+mod handle;
+mod handshake;
+mod health;
+#[cfg(feature = "hyper")]
+mod http;
+mod identity;
+mod idle;
+mod inband;
+mod limits;
+mod lines;
+mod logpump;
+mod memory;
+mod metrics;
+mod mux;
+mod nonce;
+mod outcome;
+mod owned;
+mod peercred;
+mod pipe;
+mod platform;
+mod presence;
+mod procfs;
+mod producer;
+mod progress;
+mod proof;
+mod ratelimit;
+mod raw;
+mod readbuf;
+mod reliable;
+mod retry;
+mod rotation;
+mod secret;
+mod session;
+mod shutdown;
+mod signal;
+mod snapshot;
+mod socket;
+mod stall;
+mod state;
+mod supervisor;
+mod tail;
+mod task;
+mod tee;
+#[cfg(feature = "test-util")]
+mod testing;
+mod tokens;
+mod trace_context;
+mod transaction;
+mod typed;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+mod watcher;
+mod wire;
+mod zerocopy;
+
+pub use acl::ChannelAcl;
+pub use aggregator::SfifoAggregator;
+pub use atomic::pipe_buf;
+pub use audit::{Audit, AuditEvent, AuditOutcome, AuditSink, FileAuditSink};
+pub use backend::Backend;
+pub use backpressure::{pipe_fill_level, Backpressure, BackpressureWatcher, PressureLevel};
+pub use buffered::{BufferedSender, DEFAULT_MEMORY_LIMIT};
+pub use bufio::{BufferedFifo, BufferedFifoReader, BufferedFifoWriter, DEFAULT_BUFFER_CAPACITY};
+pub use capture::{Capture, CaptureReader, CapturedFrame, Direction};
+pub use channel::{Channel, ChannelStats, PingReport};
+pub use checkpoint::{CallbackCheckpoint, Checkpoint, CheckpointStore, FileCheckpoint};
+pub use child::{ChildFifo, ChildTarget};
+pub use compression::{
+    Compression, CompressionAlgorithm, DEFAULT_COMPRESSION_THRESHOLD, FLAG_COMPRESSED,
+};
+pub use conformance::{Conformance, ConformanceCheck, ConformanceFeature, ConformanceReport};
+pub use connect::DEFAULT_CONNECT_CONCURRENCY;
+pub use container::{
+    ContainerStdio, StderrReader, StdinWriter, StdioControl, StdioControlReceiver,
+    StdioControlSender, StdoutReader,
+};
+pub use control::{Control, Controls};
+pub use diagnostics::{FrameDiagnostics, FLAG_SEQUENCED, SEQUENCE_HEADER_LEN};
+pub use dir::SfifoDir;
+pub use error::SfifoError;
+pub use extension::{
+    Extension, EXT_COMPRESSION, EXT_KERNEL_CRED, EXT_NONCE, EXT_NONCE_ECHO, EXT_PROOF,
+    EXT_RESUME_FROM, EXT_SESSION, KNOWN_EXTENSIONS,
+};
+pub use failover::{FailoverSender, DEFAULT_PROBE_INTERVAL};
+pub use flow::PauseHandle;
+pub use frame::{
+    ExpiredHandler, Frame, FrameCodec, FrameKind, FramedReceiver, FramedSender, UserControlHandler,
+    CHECKSUM_LEN, DEFAULT_MAX_FRAME_SIZE, FLAG_CHECKSUM,
+};
+pub use handle::{FifoHandle, OpenMode};
+pub use handshake::{client_handshake, server_handshake};
+pub use health::HealthReport;
+#[cfg(feature = "hyper")]
+pub use http::{ChannelIo, FifoConnector};
+pub use identity::{PeerIdentity, PeerPolicy};
+pub use logpump::{FifoLogPump, FsyncPolicy, LogPumpHandle, LogPumpStats};
+pub use memory::{FifoTransport, MemoryFifo};
+pub use metrics::{
+    clear_global_metrics, set_global_metrics, AtomicMetrics, Metrics, MetricsSnapshot, SfifoMetrics,
+};
+pub use mux::{LaneReceiver, LaneSender, Multiplexer};
+pub use nonce::{NonceCache, NONCE_LEN};
+pub use outcome::ReadOutcome;
+pub use owned::OwnedFifo;
+pub use peercred::KernelPeerCred;
+pub use pipe::PipeEnd;
+pub use presence::{Presence, WriterEvent};
+pub use procfs::{fifo_openers, FifoOpener};
+pub use producer::{SfifoConsumer, SfifoProducer, WriterId, WriterStream};
+pub use progress::HandshakePhase;
+pub use ratelimit::RateLimit;
+pub use raw::{detect_protocol, handshake_pending, PeerProtocol, RawFifo};
+pub use readbuf::DEFAULT_READ_BUFFER_CAPACITY;
+pub use reliable::{Reliable, RELIABLE_SEQUENCE_LEN};
+pub use retry::{Backoff, RetryAttempt, RetryHook, RetryPolicy, DEFAULT_RETRY_DELAY};
+pub use rotation::TokenRotation;
+pub use secret::{constant_time_eq, Credentials, LockedSecret, MlockMode, SecretString};
+pub use session::{SessionStore, DEFAULT_SESSION_TTL};
+pub use signal::shutdown_signal;
+pub use snapshot::{SnapshotPublisher, SnapshotSource, SnapshotSubscriber, SyncEvent};
+pub use socket::{AuthenticatedSocket, Connection, Transport};
+pub use stall::StallReport;
+pub use state::ChannelState;
+pub use supervisor::{SfifoSupervisor, SupervisorHandle, SupervisorStatus};
+pub use tail::FifoTailer;
+pub use tee::TeeFifo;
+#[cfg(feature = "test-util")]
+pub use testing::ScopedFifo;
+pub use tokens::{CallbackTokens, FileTokens, StaticTokens, TokenRegistry, TokenStore};
+pub use trace_context::{TraceContext, FLAG_TRACE_CONTEXT, TRACE_CONTEXT_LEN};
+pub use transaction::Transaction;
+#[cfg(feature = "json")]
+pub use typed::JsonCodec;
+#[cfg(feature = "postcard")]
+pub use typed::PostcardCodec;
+pub use typed::{BincodeCodec, FifoCodec, TypedReceiver, TypedSender};
+pub use watcher::{FifoEvent, FifoWatcher};
+pub use wire::WireFormat;
+pub use zerocopy::BorrowedPayload;
 // Define a constant for the default timeout duration
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
 // Define a constant for handshake timeout
 const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest binary handshake message accepted, checked before reading it
+pub const MAX_HANDSHAKE_SIZE: usize = 4096;
 
 // Handshake message structure for process authentication
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -20,6 +202,20 @@ pub struct HandshakeMessage {
     pub token: String,
     pub timestamp: u64,
     pub message_type: HandshakeType,
+    /// Optional TLV fields, encoded after the fixed part of the message
+    #[serde(skip)]
+    pub extensions: Vec<Extension>,
+    /// Encoding the message was received in, or is written in
+    #[serde(skip)]
+    pub wire_format: WireFormat,
+    /// Credentials of the sender checked with the kernel, see
+    /// `Sfifo::set_kernel_peer_cred`
+    #[serde(skip)]
+    pub kernel_cred: Option<KernelPeerCred>,
+    /// Tenant the server resolved the client's token to, see
+    /// `Sfifo::set_token_registry`
+    #[serde(skip)]
+    pub tenant: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -31,69 +227,141 @@ pub enum HandshakeType {
 
 // Authenticated FIFO wrapper that ensures both ends are verified
 #[derive(Debug)]
-pub enum AuthenticatedFifo {
-    Sender {
-        inner: Sender,
-        peer_info: HandshakeMessage,
-        is_server: bool,
-    },
-    Receiver {
-        inner: Receiver,
-        peer_info: HandshakeMessage,
-        is_server: bool,
-    },
+pub struct AuthenticatedFifo {
+    end: PipeEnd,
+    peer_info: HandshakeMessage,
+    is_server: bool,
+    // Configuration the FIFO was opened with, used to reopen the path
+    config: Option<Sfifo>,
+    // Credentials of the handshake, kept with keepalive to authenticate
+    // again when the path is reopened
+    credentials: Option<Credentials>,
+    // Inode of the FIFO path at open time
+    inode: Option<u64>,
+    // Removes the FIFO files when dropped, if cleanup on drop was requested
+    guard: Option<OwnedFifo>,
+    peer_identity: Option<PeerIdentity>,
+    // Bytes read past the last line returned by `read_line`, and the
+    // buffer `read_bytes` reads through
+    line_buf: BytesMut,
+    // Reports the connection as closed once dropped
+    state: StateGuard,
+    rate_limit: Option<TokenBucket>,
+    idle: Option<idle::IdleTimer>,
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    uring: Option<uring::UringIo>,
 }
 
 impl AuthenticatedFifo {
     /// Get peer process information
     pub fn peer_info(&self) -> &HandshakeMessage {
-        match self {
-            AuthenticatedFifo::Sender { peer_info, .. } => peer_info,
-            AuthenticatedFifo::Receiver { peer_info, .. } => peer_info,
-        }
+        &self.peer_info
     }
 
     /// Check if this is the server side of the connection
     pub fn is_server(&self) -> bool {
-        match self {
-            AuthenticatedFifo::Sender { is_server, .. } => *is_server,
-            AuthenticatedFifo::Receiver { is_server, .. } => *is_server,
-        }
+        self.is_server
     }
 
     /// Create a new sender-based AuthenticatedFifo
     pub fn new_sender(sender: Sender, peer_info: HandshakeMessage, is_server: bool) -> Self {
-        AuthenticatedFifo::Sender {
-            inner: sender,
+        AuthenticatedFifo {
+            end: PipeEnd::Sender(sender),
             peer_info,
             is_server,
+            config: None,
+            credentials: None,
+            inode: None,
+            guard: None,
+            peer_identity: None,
+            line_buf: BytesMut::new(),
+            state: StateGuard::new(StateWatch::default()),
+            rate_limit: None,
+            idle: None,
+            #[cfg(all(feature = "uring", target_os = "linux"))]
+            uring: None,
         }
     }
 
     /// Create a new receiver-based AuthenticatedFifo
     pub fn new_receiver(receiver: Receiver, peer_info: HandshakeMessage, is_server: bool) -> Self {
-        AuthenticatedFifo::Receiver {
-            inner: receiver,
+        AuthenticatedFifo {
+            end: PipeEnd::Receiver(receiver),
             peer_info,
             is_server,
+            config: None,
+            credentials: None,
+            inode: None,
+            guard: None,
+            peer_identity: None,
+            line_buf: BytesMut::new(),
+            state: StateGuard::new(StateWatch::default()),
+            rate_limit: None,
+            idle: None,
+            #[cfg(all(feature = "uring", target_os = "linux"))]
+            uring: None,
+        }
+    }
+
+    /// Metrics hooks of the configuration the FIFO was opened with
+    pub(crate) fn metrics(&self) -> Option<&Metrics> {
+        self.config.as_ref().and_then(|c| c.metrics.as_ref())
+    }
+
+    /// Remember the configuration and the current FIFO instance so the
+    /// path can be reopened later
+    fn with_config(mut self, config: &Sfifo) -> Self {
+        if config.create && config.cleanup_on_drop {
+            self.guard =
+                Some(OwnedFifo::adopt(&config.file_path).removed_on_signal(config.track_created()));
+        }
+        self.inode = fifo_inode(&config.file_path);
+        self.config = Some(config.clone());
+        self.state = StateGuard::new(config.state.clone());
+        self.rate_limit = config.rate_limit.map(TokenBucket::new);
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        if config.backend == Backend::Uring {
+            self.uring = uring::UringIo::new()
+                .map_err(|e| warn!("io_uring unavailable, using the reactor: {}", e))
+                .ok();
+        }
+        self
+    }
+
+    /// Keep the credentials of the handshake if the path may be reopened
+    fn with_credentials(mut self, credentials: &Credentials) -> Self {
+        if self.config.as_ref().is_some_and(|c| c.keepalive) {
+            self.credentials = Some(credentials.clone());
         }
+        self
+    }
+
+    /// Current state of the connection
+    pub fn state(&self) -> ChannelState {
+        self.state.get()
+    }
+
+    /// Watch the state of the connection, shared with the `Sfifo` it was
+    /// opened from
+    pub fn watch_state(&self) -> watch::Receiver<ChannelState> {
+        self.state.subscribe()
     }
 
     /// Check if this is a sender
     pub fn is_sender(&self) -> bool {
-        matches!(self, AuthenticatedFifo::Sender { .. })
+        matches!(self.end, PipeEnd::Sender(_))
     }
 
     /// Check if this is a receiver
     pub fn is_receiver(&self) -> bool {
-        matches!(self, AuthenticatedFifo::Receiver { .. })
+        matches!(self.end, PipeEnd::Receiver(_))
     }
 
     /// Try to read data (non-blocking) - only works for Receiver
     pub fn try_read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match self {
-            AuthenticatedFifo::Receiver { inner, .. } => inner.try_read(buf),
-            AuthenticatedFifo::Sender { .. } => Err(std::io::Error::new(
+        match &self.end {
+            PipeEnd::Receiver(inner) => inner.try_read(buf),
+            PipeEnd::Sender(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Cannot read from sender FIFO",
             )),
@@ -102,9 +370,9 @@ impl AuthenticatedFifo {
 
     /// Try to write data (non-blocking) - only works for Sender
     pub fn try_write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self {
-            AuthenticatedFifo::Sender { inner, .. } => inner.try_write(buf),
-            AuthenticatedFifo::Receiver { .. } => Err(std::io::Error::new(
+        match &self.end {
+            PipeEnd::Sender(inner) => inner.try_write(buf),
+            PipeEnd::Receiver(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Cannot write to receiver FIFO",
             )),
@@ -113,9 +381,9 @@ impl AuthenticatedFifo {
 
     /// Wait for readiness - only works for appropriate variant
     pub async fn readable(&self) -> std::io::Result<()> {
-        match self {
-            AuthenticatedFifo::Receiver { inner, .. } => inner.readable().await,
-            AuthenticatedFifo::Sender { .. } => Err(std::io::Error::new(
+        match &self.end {
+            PipeEnd::Receiver(inner) => inner.readable().await,
+            PipeEnd::Sender(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Cannot wait for readable on sender FIFO",
             )),
@@ -124,9 +392,9 @@ impl AuthenticatedFifo {
 
     /// Wait for writable - only works for Sender
     pub async fn writable(&self) -> std::io::Result<()> {
-        match self {
-            AuthenticatedFifo::Sender { inner, .. } => inner.writable().await,
-            AuthenticatedFifo::Receiver { .. } => Err(std::io::Error::new(
+        match &self.end {
+            PipeEnd::Sender(inner) => inner.writable().await,
+            PipeEnd::Receiver(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Cannot wait for writable on receiver FIFO",
             )),
@@ -134,17 +402,82 @@ impl AuthenticatedFifo {
     }
 
     /// Read some bytes from the FIFO (async) - only works for Receiver
+    ///
+    /// With keepalive enabled, an end-of-file caused by the FIFO being
+    /// deleted and recreated reopens the path and keeps reading instead
+    /// of returning 0.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(bytes = tracing::field::Empty))
+    )]
     pub async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match self {
-            AuthenticatedFifo::Receiver { .. } => loop {
-                self.readable().await?;
-                match self.try_read(buf) {
-                    Ok(n) => return Ok(n),
+        if !self.line_buf.is_empty() {
+            return Ok(self.take_buffered(buf));
+        }
+        self.read_pipe(buf).await
+    }
+
+    /// `read` without the bytes buffered by `read_line`
+    async fn read_pipe(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let n = self.read_once(buf).await?;
+            if n == 0 && !buf.is_empty() {
+                if self.reopen_if_recreated().await? {
+                    continue;
+                }
+                self.state.set(ChannelState::Closed);
+                if let Some(error) = self.replaced_error() {
+                    return Err(error);
+                }
+            }
+            record_span!("bytes", n);
+            metrics::emit(self.metrics(), |m| m.bytes_read(n));
+            return Ok(n);
+        }
+    }
+
+    async fn read_once(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        if let (Some(uring), PipeEnd::Receiver(inner)) = (self.uring.as_mut(), &self.end) {
+            let read = uring.read(inner.as_raw_fd(), buf);
+            let n = match self.idle.as_mut() {
+                Some(idle) => tokio::select! {
+                    biased;
+                    error = idle.expired() => {
+                        self.state.set(ChannelState::Closed);
+                        return Err(error);
+                    }
+                    n = read => n?,
+                },
+                None => read.await?,
+            };
+            self.touch_idle();
+            return Ok(n);
+        }
+        match &self.end {
+            PipeEnd::Receiver(inner) => loop {
+                match self.idle.as_mut() {
+                    // An expired timeout wins over data arriving late
+                    Some(idle) => tokio::select! {
+                        biased;
+                        error = idle.expired() => {
+                            self.state.set(ChannelState::Closed);
+                            return Err(error);
+                        }
+                        ready = inner.readable() => ready?,
+                    },
+                    None => inner.readable().await?,
+                }
+                match inner.try_read(buf) {
+                    Ok(n) => {
+                        self.touch_idle();
+                        return Ok(n);
+                    }
                     Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
                     Err(e) => return Err(e),
                 }
             },
-            AuthenticatedFifo::Sender { .. } => Err(std::io::Error::new(
+            PipeEnd::Sender(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Cannot read from sender FIFO",
             )),
@@ -169,17 +502,80 @@ impl AuthenticatedFifo {
     }
 
     /// Write some bytes to the FIFO (async) - only works for Sender
+    ///
+    /// With keepalive enabled, a broken pipe caused by the FIFO being
+    /// deleted and recreated reopens the path and retries the write.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(bytes = tracing::field::Empty))
+    )]
     pub async fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self {
-            AuthenticatedFifo::Sender { .. } => loop {
-                self.writable().await?;
-                match self.try_write(buf) {
-                    Ok(n) => return Ok(n),
+        if let Some(bucket) = self.rate_limit.as_mut() {
+            bucket.acquire(buf.len()).await;
+        }
+        loop {
+            match self.write_once(buf).await {
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                    if self.reopen_if_recreated().await? {
+                        continue;
+                    }
+                    self.state.set(ChannelState::Closed);
+                    return Err(self.replaced_error().unwrap_or(e));
+                }
+                Ok(n) => {
+                    if let Some(bucket) = self.rate_limit.as_mut() {
+                        bucket.consume(n);
+                    }
+                    record_span!("bytes", n);
+                    metrics::emit(self.metrics(), |m| m.bytes_written(n));
+                    return Ok(n);
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn write_once(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        if let (Some(uring), PipeEnd::Sender(inner)) = (self.uring.as_mut(), &self.end) {
+            let write = uring.write(inner.as_raw_fd(), buf);
+            let n = match self.idle.as_mut() {
+                Some(idle) => tokio::select! {
+                    biased;
+                    error = idle.expired() => {
+                        self.state.set(ChannelState::Closed);
+                        return Err(error);
+                    }
+                    n = write => n?,
+                },
+                None => write.await?,
+            };
+            self.touch_idle();
+            return Ok(n);
+        }
+        match &self.end {
+            PipeEnd::Sender(inner) => loop {
+                match self.idle.as_mut() {
+                    Some(idle) => tokio::select! {
+                        biased;
+                        error = idle.expired() => {
+                            self.state.set(ChannelState::Closed);
+                            return Err(error);
+                        }
+                        ready = inner.writable() => ready?,
+                    },
+                    None => inner.writable().await?,
+                }
+                match inner.try_write(buf) {
+                    Ok(n) => {
+                        self.touch_idle();
+                        return Ok(n);
+                    }
                     Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
                     Err(e) => return Err(e),
                 }
             },
-            AuthenticatedFifo::Receiver { .. } => Err(std::io::Error::new(
+            PipeEnd::Receiver(_) => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Cannot write to receiver FIFO",
             )),
@@ -206,6 +602,189 @@ impl AuthenticatedFifo {
         self.write_all(s.as_bytes()).await?;
         self.write_all(b"\n").await
     }
+
+    /// Handshake extensions sent by the peer that this version of the
+    /// crate does not understand
+    pub fn peer_unknown_extensions(&self) -> Vec<&Extension> {
+        self.peer_info.unknown_extensions()
+    }
+
+    /// Sequence announced by the server to resume sending from, if it has
+    /// a checkpoint
+    pub fn resume_from(&self) -> Option<u64> {
+        self.peer_info.resume_from()
+    }
+
+    /// Commit `sequence` as processed to the configured checkpoint store
+    pub fn checkpoint(&self, sequence: u64) -> std::io::Result<()> {
+        match self.config.as_ref().and_then(|c| c.checkpoint.as_ref()) {
+            Some(checkpoint) => checkpoint.save(sequence),
+            None => Err(no_checkpoint_store()),
+        }
+    }
+
+    /// Compression negotiated with the peer during the handshake
+    pub fn compression(&self) -> Option<Compression> {
+        let config = self.config.as_ref().and_then(|c| c.compression);
+        Compression::negotiate(config, &self.peer_info)
+    }
+
+    /// Convert into a framed sender - only works for Sender
+    pub fn into_framed_sender(self) -> std::io::Result<FramedSender> {
+        let compression = self.compression();
+        let config = self.config.unwrap_or_default();
+        match self.end {
+            PipeEnd::Sender(inner) => Ok(FramedSender::new(inner)
+                .max_frame_size(config.frame_size_limit())
+                .with_guard(self.guard)
+                .with_state(self.state)
+                .with_metrics(config.metrics)
+                .propagate_span_context(config.propagate_span_context)
+                .frame_diagnostics(config.frame_diagnostics)
+                .checksum(config.frame_checksum)
+                .atomic_frames(config.atomic_frames)
+                .rate_limit(config.rate_limit)
+                .compression(compression)),
+            PipeEnd::Receiver(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot frame a receiver FIFO as sender",
+            )),
+        }
+    }
+
+    /// Convert into a framed receiver - only works for Receiver
+    pub fn into_framed_receiver(self) -> std::io::Result<FramedReceiver> {
+        let config = self.config.unwrap_or_default();
+        match self.end {
+            PipeEnd::Receiver(inner) => Ok(FramedReceiver::with_capacity(
+                inner,
+                config.read_buffer_capacity,
+            )
+            .max_frame_size(config.frame_size_limit())
+            .with_guard(self.guard)
+            .with_state(self.state)
+            .with_metrics(config.metrics)
+            .frame_diagnostics(config.frame_diagnostics)
+            .resync_on_corruption(config.frame_resync)),
+            PipeEnd::Sender(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot frame a sender FIFO as receiver",
+            )),
+        }
+    }
+
+    /// Check whether the FIFO path no longer refers to the FIFO this end
+    /// was opened on (deleted, or deleted and recreated)
+    pub fn is_recreated(&self) -> bool {
+        match &self.config {
+            Some(config) => fifo_inode(&config.file_path) != self.inode,
+            None => false,
+        }
+    }
+
+    /// Check that the FIFO path still refers to the FIFO this end was
+    /// opened on, failing with `SfifoError::StaleChannel` once it was
+    /// deleted or recreated, e.g. by a restarted peer
+    pub fn verify_same_instance(&self) -> std::io::Result<()> {
+        match self.stale_channel() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    fn stale_channel(&self) -> Option<SfifoError> {
+        let config = self.config.as_ref()?;
+        let current = fifo_inode(&config.file_path);
+        (current != self.inode).then(|| SfifoError::StaleChannel {
+            path: config.file_path.clone(),
+            opened: self.inode,
+            current,
+        })
+    }
+
+    /// `SfifoError::StaleChannel` if the path now holds another FIFO, which
+    /// turns the end-of-file or broken pipe of the dead one into an error
+    /// when keepalive does not reopen it. A path that was only deleted is
+    /// a regular close
+    fn replaced_error(&self) -> Option<std::io::Error> {
+        self.stale_channel()
+            .filter(|e| {
+                matches!(
+                    e,
+                    SfifoError::StaleChannel {
+                        current: Some(_),
+                        ..
+                    }
+                )
+            })
+            .map(Into::into)
+    }
+
+    /// Reopen the FIFO path, authenticating the peer again
+    ///
+    /// This is meant to resume a session after the FIFO file has been
+    /// recreated. A client holding a session token resumes the session,
+    /// otherwise both sides run the handshake again with the credentials
+    /// the FIFO was opened with, which are only kept with keepalive.
+    /// `peer_info()` then describes the peer of the new handshake.
+    pub async fn reopen(&mut self) -> std::io::Result<()> {
+        let Some(config) = self.config.as_ref() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "FIFO was not opened from an Sfifo configuration",
+            ));
+        };
+        // Handshake with a state of its own, this FIFO reports degraded
+        // until it completes. The path is removed on drop by this FIFO
+        let mut config = config.reopening().at_path(&config.file_path);
+        config.cleanup_on_drop = false;
+        let reconnecting = self.state.connecting(ChannelState::Degraded);
+        let session = match self.end {
+            PipeEnd::Sender(_) => self.peer_info.session_token(),
+            PipeEnd::Receiver(_) => None,
+        };
+        let fresh = match (session, &self.credentials) {
+            (Some(session), _) => config.resume_session(session).await?,
+            (None, Some(credentials)) if self.is_server => {
+                config.open_as_server_with_credentials(credentials).await?
+            }
+            (None, Some(credentials)) => {
+                config.open_as_client_with_credentials(credentials).await?
+            }
+            (None, None) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "No credentials to authenticate the reopened FIFO with",
+                ))
+            }
+        };
+        let AuthenticatedFifo {
+            end,
+            peer_info,
+            inode,
+            peer_identity,
+            ..
+        } = fresh;
+        self.end = end;
+        self.peer_info = peer_info;
+        self.inode = inode;
+        self.peer_identity = peer_identity;
+        reconnecting.connected();
+        metrics::emit(config.metrics.as_ref(), |m| m.reconnect());
+        info!("Reopened recreated FIFO {:?}", config.file_path);
+        Ok(())
+    }
+
+    /// Reopen the FIFO if keepalive is enabled and the path was recreated
+    async fn reopen_if_recreated(&mut self) -> std::io::Result<bool> {
+        let keepalive = self.config.as_ref().is_some_and(|c| c.keepalive);
+        if !keepalive || !self.is_recreated() {
+            return Ok(false);
+        }
+        debug!("FIFO was recreated, reopening");
+        self.reopen().await?;
+        Ok(true)
+    }
 }
 
 impl HandshakeMessage {
@@ -215,7 +794,7 @@ impl HandshakeMessage {
         let process_name = get_process_name()?;
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+            .map_err(std::io::Error::other)?
             .as_secs();
 
         Ok(HandshakeMessage {
@@ -224,35 +803,97 @@ impl HandshakeMessage {
             token,
             timestamp,
             message_type,
+            extensions: Vec::new(),
+            wire_format: WireFormat::default(),
+            kernel_cred: None,
+            tenant: None,
         })
     }
 
+    /// Add an extension to the message
+    pub fn with_extension(mut self, extension: Extension) -> Self {
+        self.extensions.push(extension);
+        self
+    }
+
+    /// Write the message in the given wire format
+    pub fn with_wire_format(mut self, wire_format: WireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+
+    /// Get the value of the first extension of the given type
+    pub fn extension(&self, kind: u16) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|e| e.kind == kind)
+            .map(|e| e.value.as_slice())
+    }
+
+    /// Extensions sent by the peer that this version does not understand
+    pub fn unknown_extensions(&self) -> Vec<&Extension> {
+        self.extensions.iter().filter(|e| !e.is_known()).collect()
+    }
+
     /// Serialize the handshake message to bytes
+    ///
+    /// The fixed fields are bincode encoded and followed by the TLV
+    /// extension section, which older peers ignore as trailing bytes.
     pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
-        bincode::serialize(self)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        let mut bytes = bincode::serialize(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        extension::encode_extensions(&self.extensions, &mut bytes)?;
+        Ok(bytes)
     }
 
     /// Deserialize bytes to handshake message
+    ///
+    /// Malformed input fails with `SfifoError::Truncated`,
+    /// `LengthOverflow` or `InvalidDiscriminant`, length fields are
+    /// checked against the input before anything is allocated.
     pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
-        bincode::deserialize(bytes)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        use bincode::Options;
+        let mut rest = bytes;
+        // Same encoding as `bincode::serialize`. Past the limit a length
+        // field is rejected before allocating, a short field read within
+        // it hits the end of the input instead
+        let fixed: FixedFields = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(bytes.len() as u64 + 8)
+            .deserialize_from(&mut rest)
+            .map_err(|e| malformed_handshake(*e, bytes.len()))?;
+        Ok(HandshakeMessage {
+            process_id: fixed.process_id,
+            process_name: fixed.process_name,
+            token: fixed.token,
+            timestamp: fixed.timestamp,
+            message_type: HandshakeType::try_from(fixed.message_type)?,
+            extensions: extension::decode_extensions(rest)?,
+            wire_format: WireFormat::default(),
+            kernel_cred: None,
+            tenant: None,
+        })
     }
 
     /// Validate the handshake message
     pub fn validate(&self, expected_token: &str, max_age_secs: u64) -> std::io::Result<()> {
-        // Validate token
-        if self.token != expected_token {
+        // Validate token, in constant time
+        if !constant_time_eq(self.token.as_bytes(), expected_token.as_bytes()) {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::PermissionDenied,
                 "Invalid authentication token",
             ));
         }
 
-        // Validate timestamp to prevent replay attacks
+        self.check_timestamp(max_age_secs)
+    }
+
+    /// Reject messages older than `max_age_secs` to prevent replay attacks
+    pub(crate) fn check_timestamp(&self, max_age_secs: u64) -> std::io::Result<()> {
         let current_time = SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+            .map_err(std::io::Error::other)?
             .as_secs();
 
         if current_time.saturating_sub(self.timestamp) > max_age_secs {
@@ -267,7 +908,7 @@ impl HandshakeMessage {
 }
 
 // Define the Sfifo struct with getters and setters for its fields
-#[derive(Default, Clone, Getters, Setters)]
+#[derive(Debug, Default, Clone, Getters, Setters)]
 pub struct Sfifo {
     #[getset(get = "pub")]
     pub file_path: PathBuf,
@@ -277,12 +918,161 @@ pub struct Sfifo {
     pub notify: bool,
     #[getset(get = "pub", set = "pub")]
     pub create: bool,
+    /// Create the FIFO on open, failing with `AlreadyExists` if the path
+    /// is already taken
+    #[getset(get = "pub", set = "pub")]
+    pub create_exclusive: bool,
     #[getset(get = "pub", set = "pub")]
     pub write: bool,
     #[getset(get = "pub", set = "pub")]
     pub read: bool,
     #[getset(get = "pub", set = "pub")]
     pub blocking: bool,
+    /// Transparently reopen authenticated FIFOs when the path is deleted
+    /// and recreated while the session is alive, authenticating the peer
+    /// again. Without it, they fail with `SfifoError::StaleChannel` once
+    /// the old FIFO is closed
+    #[getset(get = "pub", set = "pub")]
+    pub keepalive: bool,
+    /// Unlink created FIFOs (including the `.c2s`/`.s2c` handshake pair)
+    /// when the authenticated FIFO is dropped
+    #[getset(get = "pub", set = "pub")]
+    pub cleanup_on_drop: bool,
+    /// Make the authenticated server fail with `SfifoError::MultipleReaders`
+    /// if any other reader has the FIFO open
+    #[getset(get = "pub", set = "pub")]
+    pub single_reader: bool,
+    /// Lock the memory holding the token during handshakes. With locking
+    /// enabled, the peer token is also scrubbed from `peer_info()`
+    #[getset(get = "pub", set = "pub")]
+    pub mlock_secrets: MlockMode,
+    /// Where the receiving side persists processed sequences. The server
+    /// announces the resume point to the client during the handshake
+    #[getset(get = "pub", set = "pub")]
+    pub checkpoint: Option<Checkpoint>,
+    /// Counters hooks for FIFOs opened from this configuration, reported
+    /// in addition to the global hooks
+    #[getset(get = "pub", set = "pub")]
+    pub metrics: Option<Metrics>,
+    /// Attach the current `TraceContext` to frames sent by framed senders
+    /// opened from this configuration
+    #[getset(get = "pub", set = "pub")]
+    pub propagate_span_context: bool,
+    /// Let `open()` open the FIFO `O_RDWR` when both read and write are
+    /// set, see `open_rdwr()`. Linux only
+    #[getset(get = "pub", set = "pub")]
+    pub read_write_unchecked: bool,
+    /// Stamp frames with sequence numbers and check them on receipt, to
+    /// diagnose several writers corrupting a FIFO
+    #[getset(get = "pub", set = "pub")]
+    pub frame_diagnostics: FrameDiagnostics,
+    /// Append a CRC32 to frames sent by framed senders opened from this
+    /// configuration, receivers verify it whenever present
+    #[getset(get = "pub", set = "pub")]
+    pub frame_checksum: bool,
+    /// Make framed senders opened from this configuration refuse frames
+    /// larger than `PIPE_BUF`, so several writers can share the FIFO
+    #[getset(get = "pub", set = "pub")]
+    pub atomic_frames: bool,
+    /// Framed receivers skip corrupted bytes up to the next frame and
+    /// report `SfifoError::Resynchronized` instead of failing
+    #[getset(get = "pub", set = "pub")]
+    pub frame_resync: bool,
+    /// Compress large framed messages. The algorithm is advertised during
+    /// the handshake and only used when the peer supports it
+    #[getset(get = "pub", set = "pub")]
+    pub compression: Option<Compression>,
+    /// Reject handshakes whose nonce was already seen, on top of the
+    /// nonce echo that binds each handshake message to the previous one
+    #[getset(get = "pub", set = "pub")]
+    pub nonce_cache: Option<NonceCache>,
+    /// Issue session tokens to clients after full handshakes, letting them
+    /// reconnect with `resume_session`
+    #[getset(get = "pub", set = "pub")]
+    pub sessions: Option<SessionStore>,
+    /// Delivery state of `Channel::send_reliable()` messages, kept across
+    /// reconnects so unacknowledged messages are retransmitted
+    #[getset(get = "pub", set = "pub")]
+    pub reliable: Option<Reliable>,
+    /// Let `open_receiver()` wait until a writer has the FIFO open, with
+    /// the same timeout and notify handling as `open_sender()`
+    #[getset(get = "pub", set = "pub")]
+    pub wait_for_writer: bool,
+    /// Let `open_receiver()` discard the bytes already queued in the FIFO,
+    /// left over from a previous session, before returning
+    #[getset(get = "pub", set = "pub")]
+    pub drain_on_open: bool,
+    /// Authenticate over the FIFO itself plus a reverse FIFO that only
+    /// exists during the handshake, instead of the `.c2s`/`.s2c` side
+    /// channels. Both sides must enable it
+    #[getset(get = "pub", set = "pub")]
+    pub inband_handshake: bool,
+    /// Encoding of the handshake messages sent by clients. Servers answer
+    /// in the format of the request
+    #[getset(get = "pub", set = "pub")]
+    pub wire_format: WireFormat,
+    /// Accept authenticated peers only if their identity, gathered from
+    /// `/proc`, satisfies the policy
+    #[getset(get = "pub", set = "pub")]
+    pub peer_policy: Option<PeerPolicy>,
+    /// Group and mode of the created FIFOs, and uids and gids of the
+    /// peers accepted by the handshake
+    #[getset(get = "pub", set = "pub")]
+    pub channel_acl: Option<ChannelAcl>,
+    /// Throttle senders opened from this configuration, see
+    /// `set_rate_limit`
+    #[getset(get = "pub")]
+    pub rate_limit: Option<RateLimit>,
+    /// How opens waiting for a peer are retried
+    #[getset(get = "pub", set = "pub")]
+    pub retry_policy: RetryPolicy,
+    /// Log a `StallReport` from `/proc` when an open waits longer than
+    /// this, and return it in the error if the open then times out
+    #[getset(get = "pub", set = "pub")]
+    pub stall_diagnostics: Option<Duration>,
+    /// Largest frame payload of framed FIFOs and channels, see
+    /// `set_max_frame_size`
+    #[getset(get = "pub")]
+    pub max_frame_size: Option<usize>,
+    /// Bytes of frames a channel may keep in memory, see
+    /// `set_memory_limit`
+    #[getset(get = "pub")]
+    pub memory_limit: Option<usize>,
+    /// Capacity of the buffer framed receivers and `read_bytes` read
+    /// through, `DEFAULT_READ_BUFFER_CAPACITY` if `None`
+    #[getset(get = "pub", set = "pub")]
+    pub read_buffer_capacity: Option<usize>,
+    /// How authenticated FIFOs read and write, see `set_backend`
+    #[getset(get = "pub")]
+    pub backend: Backend,
+    /// Close connections and remove created FIFOs on SIGTERM or SIGINT,
+    /// see `with_signal_shutdown`
+    #[getset(get = "pub")]
+    pub signal_shutdown: bool,
+    /// Offer or use a Unix socket next to the FIFO, see
+    /// `open_transport_as_server`
+    #[getset(get = "pub", set = "pub")]
+    pub prefer_socket: bool,
+    /// Check the PID claimed by the peer, and learn its uid and gid, with
+    /// `SO_PEERCRED` on a Unix socket at `<path>.cred` during the
+    /// handshake. Both sides must enable it
+    #[getset(get = "pub", set = "pub")]
+    pub kernel_peer_cred: bool,
+    /// Authenticate each client with its own token, looked up in the
+    /// registry, instead of the token the server is opened with
+    #[getset(get = "pub", set = "pub")]
+    pub token_registry: Option<TokenRegistry>,
+    /// Token of handshakes on both sides, replacing the one they are
+    /// opened with, which the server can rotate on a live `Channel`
+    #[getset(get = "pub", set = "pub")]
+    pub token_rotation: Option<TokenRotation>,
+    /// Where every handshake attempt is recorded, successful or not
+    #[getset(get = "pub", set = "pub")]
+    pub audit: Option<Audit>,
+    // Lifecycle of the connections opened from this configuration
+    pub(crate) state: StateWatch,
+    // Receives the handshake phases, see `open_as_client_with_progress`
+    pub(crate) progress: Option<tokio::sync::mpsc::UnboundedSender<HandshakePhase>>,
 }
 
 impl Sfifo {
@@ -305,90 +1095,203 @@ impl Sfifo {
         }
     }
 
+    /// State a connection attempt starts in
+    fn initial_state(&self) -> ChannelState {
+        if self.create {
+            ChannelState::Creating
+        } else {
+            ChannelState::WaitingPeer
+        }
+    }
+
+    /// State of the last connection opened from this configuration or
+    /// its clones
+    pub fn state(&self) -> ChannelState {
+        self.state.get()
+    }
+
+    /// Watch the state of the connections opened from this configuration
+    /// or its clones, from creation through handshake to close
+    pub fn watch_state(&self) -> watch::Receiver<ChannelState> {
+        self.state.subscribe()
+    }
+
+    /// Creates the FIFO file and returns a guard that removes it on drop.
+    pub async fn create_owned(&self) -> Result<OwnedFifo, std::io::Error> {
+        OwnedFifo::create(&self.file_path).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(path = ?self.file_path))
+    )]
     pub async fn open_sender(&self) -> Result<Sender, std::io::Error> {
+        self.create_on_open().await?;
         let file_path = self.file_path.clone();
-        let file_op = move |tokio_cancel: tokio_util::sync::CancellationToken| async move {
-            loop {
-                if tokio_cancel.is_cancelled() {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "File deleted",
-                    ));
+        let retry = self.retry_policy.clone();
+        // Attempts made and errno of the last one, kept for the error
+        // returned when the policy aborts the loop
+        let progress = std::sync::Arc::new(std::sync::Mutex::new((0u32, None)));
+        let started = Instant::now();
+        let file_op = {
+            let progress = progress.clone();
+            move |tokio_cancel: tokio_util::sync::CancellationToken| async move {
+                let mut attempt = 0;
+                loop {
+                    if tokio_cancel.is_cancelled() {
+                        return Err(std::io::Error::other("File deleted"));
+                    }
+                    match tokio::net::unix::pipe::OpenOptions::new().open_sender(&file_path) {
+                        Ok(sender) => return Ok(sender),
+                        Err(e) => {
+                            attempt += 1;
+                            *progress.lock().unwrap_or_else(|e| e.into_inner()) =
+                                (attempt, e.raw_os_error());
+                            retry.retry(attempt, e).await?;
+                        }
+                    }
                 }
-                let r = tokio::net::unix::pipe::OpenOptions::new().open_sender(&file_path);
-                if let Ok(r) = r {
-                    return Ok(r);
+            }
+        };
+        self.open_with_policy(file_op).await.map_err(|e| {
+            // Errors of the open itself carry their errno already, stall
+            // reports tell more than the last errno
+            if e.raw_os_error().is_some() || SfifoError::from_io(&e).is_some() {
+                return e;
+            }
+            let (attempts, last_os_error) = *progress.lock().unwrap_or_else(|e| e.into_inner());
+            SfifoError::OpenAborted {
+                attempts,
+                elapsed: started.elapsed(),
+                last_os_error,
+                deleted: e.kind() != std::io::ErrorKind::TimedOut,
+            }
+            .into()
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(path = ?self.file_path))
+    )]
+    pub async fn open_receiver(&self) -> Result<Receiver, std::io::Error> {
+        self.create_on_open().await?;
+        let file_op = |_| async {
+            let receiver =
+                tokio::net::unix::pipe::OpenOptions::new().open_receiver(&self.file_path)?;
+            if self.drain_on_open {
+                let discarded = backpressure::discard_queued(&receiver)?;
+                if discarded > 0 {
+                    debug!(
+                        "Discarded {} stale bytes from {:?}",
+                        discarded, self.file_path
+                    );
                 }
-                tokio::time::sleep(Duration::from_millis(100)).await;
             }
+            if self.wait_for_writer {
+                self.writer_attached(&receiver).await;
+            }
+            Ok(receiver)
         };
-        if self.notify {
-            handle_file_with_notify_sender(file_op, &self.file_path).await
+        self.open_with_policy(file_op).await
+    }
+
+    /// Create the FIFO as asked by `create` and `create_exclusive`
+    async fn create_on_open(&self) -> std::io::Result<()> {
+        if self.create_exclusive {
+            create_fifo_exclusive(&self.file_path)?;
+        } else if self.create {
+            create_fifo(&self.file_path).await?;
         } else {
-            let tokio_cancel = tokio_util::sync::CancellationToken::new();
-            let cancel_clone = tokio_cancel.clone();
-            let timeout = self.timeout;
-            let t = tokio::task::spawn(async move {
-                tokio::time::sleep(timeout).await;
-                cancel_clone.cancel();
-            });
-            let res = tokio::select! {
-                res = file_op(tokio_cancel) => {
-                    res
-                },
-                _ = t => {
-                    Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "File deleted"))
-                }
-            };
-            res
+            return Ok(());
+        }
+        match &self.channel_acl {
+            Some(acl) => acl.apply(&self.file_path),
+            None => Ok(()),
         }
     }
 
-    pub async fn open_receiver(&self) -> Result<Receiver, std::io::Error> {
-        if self.create {
-            create_fifo(&self.file_path).await?;
+    /// Copy of the configuration for opening the other end, or opening
+    /// again, a FIFO this configuration already created
+    pub(crate) fn reopening(&self) -> Sfifo {
+        let mut config = self.clone();
+        config.create_exclusive = false;
+        config
+    }
+
+    /// Copy of the configuration for another FIFO at `file_path`, with a
+    /// state of its own
+    pub(crate) fn at_path(&self, file_path: impl AsRef<Path>) -> Sfifo {
+        let mut config = self.clone();
+        config.file_path = file_path.as_ref().to_path_buf();
+        config.state = StateWatch::default();
+        config
+    }
+
+    /// Configuration for a handshake side channel at `file_path`
+    pub(crate) fn side_channel(&self, file_path: impl AsRef<Path>) -> Sfifo {
+        Sfifo::new(file_path)
+            .set_retry_policy(self.retry_policy.clone())
+            .set_channel_acl(self.channel_acl.clone())
+            .set_stall_diagnostics(self.stall_diagnostics)
+            .clone()
+    }
+
+    /// Run `file_op` until the FIFO is deleted if `notify` is set, or until
+    /// `timeout` otherwise
+    pub(crate) async fn open_with_policy<T, F, Fut>(&self, file_op: F) -> std::io::Result<T>
+    where
+        F: FnOnce(tokio_util::sync::CancellationToken) -> Fut,
+        Fut: std::future::Future<Output = std::io::Result<T>>,
+    {
+        let started = Instant::now();
+        let _stall = self.stall_diagnostics.map(|threshold| {
+            let file_path = self.file_path.clone();
+            task::TaskGuard::spawn("sfifo::stall_diagnostics", async move {
+                tokio::time::sleep(threshold).await;
+                warn!("{}", StallReport::inspect(&file_path, threshold));
+            })
+        });
+        if self.notify {
+            return with_notify(file_op, &self.file_path).await;
         }
-        let file_path = self.file_path.clone();
-        tokio::net::unix::pipe::OpenOptions::new().open_receiver(&file_path)
+        with_timeout(file_op, self.timeout).await.map_err(|e| {
+            if e.kind() != std::io::ErrorKind::TimedOut {
+                return e;
+            }
+            metrics::emit(self.metrics.as_ref(), |m| m.timeout());
+            match self.stall_diagnostics {
+                Some(_) => SfifoError::Stalled {
+                    report: StallReport::inspect(&self.file_path, started.elapsed()),
+                }
+                .into(),
+                None => e,
+            }
+        })
     }
-    /// Opens a FIFO file with the specified options.
-    ///
-    /// # Returns
-    ///
-    /// Returns a `Result` containing the opened file or an error.
     /// Open FIFO as tokio::fs::File (legacy method)
-    /// Deprecated: Use open_sender() or open_receiver() instead
+    ///
+    /// Opens in the mode given by the `read`, `write` and `blocking`
+    /// flags, setting both `read` and `write` needs `read_write_unchecked`.
+    #[deprecated(note = "use `open_with`, or `open_sender()`/`open_receiver()`")]
     pub async fn open(&self) -> Result<tokio::fs::File, std::io::Error> {
-        if self.create {
-            create_fifo(&self.file_path).await?;
-        }
-
-        if self.read && self.write {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "For safety, read and write cannot be true at the same time",
-            ));
-        }
+        self.open_with(self.legacy_open_mode()?).await?.into_file()
+    }
 
-        let read = self.read;
-        if self.blocking {
-            if read {
-                let owned_fd = self.open_receiver().await?.into_blocking_fd()?;
-                let std_file = std::fs::File::from(owned_fd);
-                Ok(tokio::fs::File::from_std(std_file))
-            } else {
-                let owned_fd = self.open_sender().await?.into_blocking_fd()?;
-                let std_file = std::fs::File::from(owned_fd);
-                Ok(tokio::fs::File::from_std(std_file))
-            }
-        } else {
-            tokio::fs::OpenOptions::new()
-                .custom_flags(libc::O_NONBLOCK)
-                .read(self.read)
-                .write(self.write)
-                .open(&self.file_path)
-                .await
-        }
+    /// Opens the FIFO as both ends (`O_RDWR`, non-blocking).
+    ///
+    /// This is Linux-specific, POSIX leaves `O_RDWR` on a FIFO undefined.
+    /// The open never waits for a peer and the FIFO stays open even when
+    /// every other reader and writer goes away, so readers of this file
+    /// never see end-of-file.
+    pub async fn open_rdwr(&self) -> Result<tokio::fs::File, std::io::Error> {
+        self.create_on_open().await?;
+        tokio::fs::OpenOptions::new()
+            .custom_flags(libc::O_NONBLOCK)
+            .read(true)
+            .write(true)
+            .open(&self.file_path)
+            .await
     }
 
     /// Create a new authenticated sender FIFO
@@ -424,28 +1327,109 @@ impl Sfifo {
     ///
     /// Returns an `AuthenticatedFifo` after successful handshake
     pub async fn open_as_server(&self, token: &str) -> Result<AuthenticatedFifo, std::io::Error> {
-        let tokio_cancel = tokio_util::sync::CancellationToken::new();
-        let cancel_clone = tokio_cancel.clone();
+        self.open_as_server_mutual(token, token).await
+    }
 
-        // Spawn a task that will cancel the operation after HANDSHAKE_TIMEOUT
-        let cancel_handle = tokio::spawn(async move {
-            tokio::time::sleep(HANDSHAKE_TIMEOUT).await;
-            cancel_clone.cancel();
-        });
+    /// Opens a FIFO as server side with distinct credentials per direction
+    ///
+    /// # Parameters
+    ///
+    /// * `server_secret`: Token the server proves it holds to the client
+    /// * `expected_client_token`: Token the client must present
+    ///
+    /// The server never sends `server_secret`, it answers with a MAC of it
+    /// over the nonces of the handshake. A leaked client token does not
+    /// reveal it, so its holder cannot impersonate the server.
+    pub async fn open_as_server_mutual(
+        &self,
+        server_secret: &str,
+        expected_client_token: &str,
+    ) -> Result<AuthenticatedFifo, std::io::Error> {
+        let credentials =
+            Credentials::mutual(server_secret, expected_client_token).keep_peer_token(true);
+        self.open_as_server_with_credentials(&credentials).await
+    }
+
+    /// Opens a FIFO as server side with the given credentials
+    ///
+    /// The client token is checked in constant time and scrubbed from
+    /// `peer_info()` unless the credentials keep it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "info",
+            skip_all,
+            fields(path = ?self.file_path, peer_pid = tracing::field::Empty, elapsed_ms = tracing::field::Empty)
+        )
+    )]
+    pub async fn open_as_server_with_credentials(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<AuthenticatedFifo, std::io::Error> {
+        let started = Instant::now();
+        let tokio_cancel = self.handshake_cancel_token();
+        // Cancels the operation after HANDSHAKE_TIMEOUT, stopped on return
+        let timer = task::cancel_after(HANDSHAKE_TIMEOUT, &tokio_cancel);
+        let _tracked = self.track_created();
 
-        let peer_info = self.perform_server_handshake(token, &tokio_cancel).await;
+        let connecting = self.state.connecting(self.initial_state());
+        if self.create {
+            self.report(HandshakePhase::CreatingFifo);
+        }
+        let credentials = &self.rotated_credentials(credentials);
+        let secret = LockedSecret::new(credentials.secret().expose(), self.mlock_secrets)?;
+        let expected = LockedSecret::new(credentials.expected_peer().expose(), self.mlock_secrets)?;
+        let seen = PeerSeen::default();
+        let handshake = seen
+            .watch(async {
+                if self.inband_handshake {
+                    self.perform_inband_server_handshake(
+                        secret.expose(),
+                        expected.expose(),
+                        &tokio_cancel,
+                    )
+                    .await
+                    .map(|(peer_info, file)| (peer_info, Some(file)))
+                } else {
+                    self.perform_server_handshake(secret.expose(), expected.expose(), &tokio_cancel)
+                        .await
+                        .map(|peer_info| (peer_info, None))
+                }
+            })
+            .await;
+        let peer = handshake.and_then(|(peer_info, file)| Ok((self.verify_peer(peer_info)?, file)));
         // Cancel the timeout task since handshake completed
-        cancel_handle.abort();
+        drop(timer);
+        self.record_handshake(&peer);
+        self.audit_handshake(
+            true,
+            peer.as_ref().map(|((peer_info, _), _)| peer_info),
+            &seen,
+        );
 
-        match peer_info {
-            Ok(peer_info) => {
+        match peer {
+            Ok(((mut peer_info, identity), file)) => {
+                self.scrub_credentials(credentials, &mut peer_info);
+                record_span!("peer_pid", peer_info.process_id);
+                record_span!("elapsed_ms", started.elapsed().as_millis() as u64);
                 info!(
-                    "Handshake completed with client PID {}",
-                    peer_info.process_id
+                    "Handshake completed with client PID {} in {:?}",
+                    peer_info.process_id,
+                    started.elapsed()
                 );
-                // reopen
-                let file = self.open_receiver().await?;
-                Ok(AuthenticatedFifo::new_receiver(file, peer_info, true))
+                // reopen, unless the handshake went through the FIFO itself
+                let file = match file {
+                    Some(file) => file,
+                    None => self.open_receiver().await?,
+                };
+                if self.single_reader {
+                    ensure_single_reader(&file, &self.file_path)?;
+                }
+                connecting.connected();
+                Ok(AuthenticatedFifo::new_receiver(file, peer_info, true)
+                    .with_config(self)
+                    .with_peer_identity(identity)
+                    .with_credentials(credentials))
             }
             Err(e) => {
                 error!("Server: Handshake error: {:?}", e);
@@ -465,164 +1449,204 @@ impl Sfifo {
     ///
     /// Returns an `AuthenticatedFifo` after successful handshake
     pub async fn open_as_client(&self, token: &str) -> Result<AuthenticatedFifo, std::io::Error> {
-        let tokio_cancel = tokio_util::sync::CancellationToken::new();
-        let cancel_clone = tokio_cancel.clone();
+        self.open_as_client_mutual(token, token).await
+    }
 
-        // Spawn a task that will cancel the operation after HANDSHAKE_TIMEOUT
-        let cancel_handle = tokio::spawn(async move {
-            tokio::time::sleep(HANDSHAKE_TIMEOUT).await;
-            cancel_clone.cancel();
-        });
+    /// Opens a FIFO as client side with distinct credentials per direction
+    ///
+    /// # Parameters
+    ///
+    /// * `client_secret`: Token sent to the server to prove the client's identity
+    /// * `expected_server_token`: Token the server must present
+    pub async fn open_as_client_mutual(
+        &self,
+        client_secret: &str,
+        expected_server_token: &str,
+    ) -> Result<AuthenticatedFifo, std::io::Error> {
+        let credentials =
+            Credentials::mutual(client_secret, expected_server_token).keep_peer_token(true);
+        self.open_as_client_with_credentials(&credentials).await
+    }
 
-        tokio::select! {
-            peer_info = self.perform_client_handshake(token,&tokio_cancel) => {
-                // Cancel the timeout task since handshake completed
-                cancel_handle.abort();
-                match peer_info {
-                    Ok(peer_info) => {
-                        // reopen
-                        let file = self.open_sender().await?;
-                        Ok(AuthenticatedFifo::new_sender(file, peer_info, false))
-                    }
+    /// Opens a FIFO as client side with the given credentials
+    ///
+    /// The server token is checked in constant time and scrubbed from
+    /// `peer_info()` unless the credentials keep it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "info",
+            skip_all,
+            fields(path = ?self.file_path, peer_pid = tracing::field::Empty, elapsed_ms = tracing::field::Empty)
+        )
+    )]
+    pub async fn open_as_client_with_credentials(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<AuthenticatedFifo, std::io::Error> {
+        let started = Instant::now();
+        let tokio_cancel = self.handshake_cancel_token();
+        // Cancels the operation after HANDSHAKE_TIMEOUT, stopped on return
+        let timer = task::cancel_after(HANDSHAKE_TIMEOUT, &tokio_cancel);
+        let _tracked = self.track_created();
+
+        let connecting = self.state.connecting(self.initial_state());
+        if self.create {
+            self.report(HandshakePhase::CreatingFifo);
+        }
+        let credentials = &self.rotated_credentials(credentials);
+        let secret = LockedSecret::new(credentials.secret().expose(), self.mlock_secrets)?;
+        let expected = LockedSecret::new(credentials.expected_peer().expose(), self.mlock_secrets)?;
+        let seen = PeerSeen::default();
+        let handshake = seen.watch(async {
+            if self.inband_handshake {
+                self.perform_inband_client_handshake(
+                    secret.expose(),
+                    expected.expose(),
+                    &tokio_cancel,
+                )
+                .await
+                .map(|(peer_info, file)| (peer_info, Some(file)))
+            } else {
+                self.perform_client_handshake(secret.expose(), expected.expose(), &tokio_cancel)
+                    .await
+                    .map(|peer_info| (peer_info, None))
+            }
+        });
+        tokio::select! {
+            handshake = handshake => {
+                // Cancel the timeout task since handshake completed
+                drop(timer);
+                let peer = handshake
+                    .and_then(|(peer_info, file)| Ok((self.verify_peer(peer_info)?, file)));
+                self.record_handshake(&peer);
+                self.audit_handshake(false, peer.as_ref().map(|((peer_info, _), _)| peer_info), &seen);
+                match peer {
+                    Ok(((mut peer_info, identity), file)) => {
+                        self.scrub_credentials(credentials, &mut peer_info);
+                        record_span!("peer_pid", peer_info.process_id);
+                        record_span!("elapsed_ms", started.elapsed().as_millis() as u64);
+                        info!(
+                            "Handshake completed with server PID {} in {:?}",
+                            peer_info.process_id,
+                            started.elapsed()
+                        );
+                        // reopen, unless the handshake went through the FIFO itself
+                        let file = match file {
+                            Some(file) => file,
+                            None => self.open_sender().await?,
+                        };
+                        connecting.connected();
+                        Ok(AuthenticatedFifo::new_sender(file, peer_info, false)
+                            .with_config(self)
+                            .with_peer_identity(identity)
+                            .with_credentials(credentials))
+                    }
                     Err(e) => {
                         Err(e)
                     }
                 }
             }
             _ = tokio_cancel.cancelled() => {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::TimedOut,
-                    "Handshake timeout",
-                ))
+                metrics::emit(self.metrics.as_ref(), |m| {
+                    m.handshake_failed();
+                    m.timeout();
+                });
+                let error = std::io::Error::new(std::io::ErrorKind::TimedOut, "Handshake timeout");
+                self.audit_handshake(false, Err(&error), &seen);
+                Err(error)
+            }
+        }
+    }
+
+    /// Report the outcome of a handshake to the metrics hooks
+    fn record_handshake<T>(&self, result: &std::io::Result<T>) {
+        metrics::emit(self.metrics.as_ref(), |m| match result {
+            Ok(_) => m.handshake_succeeded(),
+            Err(e) => {
+                m.handshake_failed();
+                if e.kind() == std::io::ErrorKind::TimedOut {
+                    m.timeout();
+                }
+            }
+        });
+    }
+
+    /// Drop the peer's copy of the token when secrets are locked
+    fn scrub_peer_token(&self, peer_info: &mut HandshakeMessage) {
+        if self.mlock_secrets != MlockMode::Off {
+            peer_info.token.zeroize();
+        }
+    }
+
+    /// Drop the peer's copy of the token when the credentials or locked
+    /// secrets ask for it
+    fn scrub_credentials(&self, credentials: &Credentials, peer_info: &mut HandshakeMessage) {
+        if credentials.scrubs_peer_token() {
+            peer_info.token.zeroize();
+        } else {
+            self.scrub_peer_token(peer_info);
+        }
+    }
+
+    /// Add the checkpoint and compression extensions to a server response
+    pub(crate) fn with_server_extensions(
+        &self,
+        mut response: HandshakeMessage,
+    ) -> std::io::Result<HandshakeMessage> {
+        if let Some(checkpoint) = &self.checkpoint {
+            if let Some(extension) = checkpoint.resume_extension()? {
+                response = response.with_extension(extension);
             }
         }
+        if let Some(extension) = Compression::offer(self.compression) {
+            response = response.with_extension(extension);
+        }
+        Ok(response)
     }
 
     /// Perform handshake as server (waits for client to initiate)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     async fn perform_server_handshake(
         &self,
         token: &str,
+        expected_token: &str,
         cancel_token: &tokio_util::sync::CancellationToken,
     ) -> Result<HandshakeMessage, std::io::Error> {
-        // Step 1: Wait for client handshake request (client->server FIFO)
-        let mut client_to_server_path = self.file_path.clone();
-        client_to_server_path.set_extension("c2s");
-
-        let mut read_sfifo = Sfifo::new(&client_to_server_path);
-        read_sfifo.set_create(true);
-        let mut read_file = read_sfifo.open_receiver().await?;
-        debug!(
-            "Server: Waiting for client handshake request on {:?}",
-            client_to_server_path
-        );
-        let client_request = read_handshake_message(&mut read_file, cancel_token).await?;
-        debug!(
-            "Server: Received client handshake request {:?}",
-            client_request
-        );
-        drop(read_file);
-        // Validate client request
-        if client_request.message_type != HandshakeType::Request {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Expected handshake request",
-            ));
-        }
-
-        client_request.validate(token, 30)?;
-
-        // Step 2: Send handshake response (server->client FIFO)
-        debug!("Server: Sending handshake response");
-        let mut server_to_client_path = self.file_path.clone();
-        server_to_client_path.set_extension("s2c");
-
-        let mut write_sfifo = Sfifo::new(&server_to_client_path);
-        write_sfifo.set_create(true);
-        let mut write_file = write_sfifo.open_sender().await?;
-        let server_response = HandshakeMessage::new(token.to_string(), HandshakeType::Response)?;
-        write_handshake_message(&mut write_file, &server_response).await?;
-        drop(write_file);
-
-        // Step 3: Wait for client acknowledgment (client->server FIFO)
-        debug!("Server: Waiting for client acknowledgment");
-        let read_sfifo = Sfifo::new(&client_to_server_path);
-        let mut read_file = read_sfifo.open_receiver().await?;
-        let client_ack = read_handshake_message(&mut read_file, cancel_token).await?;
-        drop(read_file);
-
-        debug!("Server: Received client acknowledgment {:?}", client_ack);
-        if client_ack.message_type != HandshakeType::Ack {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Expected handshake acknowledgment",
-            ));
-        }
-
-        client_ack.validate(token, 30)?;
-
-        debug!(
-            "Server: Handshake completed with client PID {}",
-            client_request.process_id
-        );
-        Ok(client_request)
+        handshake::server_exchange(
+            &mut SideChannels::new(self, true),
+            &mut SfifoHooks::side_channels(self),
+            token,
+            expected_token,
+            cancel_token,
+        )
+        .await
     }
 
     /// Perform handshake as client (initiates handshake)
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     async fn perform_client_handshake(
         &self,
         token: &str,
+        expected_token: &str,
         cancel_token: &tokio_util::sync::CancellationToken,
     ) -> Result<HandshakeMessage, std::io::Error> {
-        // Step 1: Send handshake request (client->server FIFO)
-        debug!("client: Sending handshake request");
-        let mut client_to_server_path = self.file_path.clone();
-        client_to_server_path.set_extension("c2s");
-
-        let mut write_sfifo = Sfifo::new(&client_to_server_path);
-        write_sfifo.set_create(true);
-        let mut write_file = write_sfifo.open_sender().await?;
-        let client_request = HandshakeMessage::new(token.to_string(), HandshakeType::Request)?;
-        write_handshake_message(&mut write_file, &client_request).await?;
-        drop(write_file);
-
-        // Step 2: Wait for server response (server->client FIFO)
-        debug!("client: Waiting for server response");
-        let mut server_to_client_path = self.file_path.clone();
-        server_to_client_path.set_extension("s2c");
-
-        let mut read_sfifo = Sfifo::new(&server_to_client_path);
-        read_sfifo.set_create(true);
-        let mut read_file = read_sfifo.open_receiver().await?;
-        let server_response = read_handshake_message(&mut read_file, cancel_token).await?;
-        drop(read_file);
-        debug!("client: Received server response {:?}", server_response);
-        if server_response.message_type != HandshakeType::Response {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Expected handshake response",
-            ));
-        }
-
-        server_response.validate(token, 30)?;
-
-        // Step 3: Send acknowledgment (client->server FIFO)
-        debug!("client: Sending acknowledgment");
-        let write_sfifo = Sfifo::new(&client_to_server_path);
-        let mut write_file = write_sfifo.open_sender().await?;
-        let client_ack = HandshakeMessage::new(token.to_string(), HandshakeType::Ack)?;
-        write_handshake_message(&mut write_file, &client_ack).await?;
-        drop(write_file);
-
-        debug!(
-            "Client: Handshake completed with server PID {}",
-            server_response.process_id
-        );
-        Ok(server_response)
+        handshake::client_exchange(
+            &mut SideChannels::new(self, false),
+            &mut SfifoHooks::side_channels(self),
+            token,
+            expected_token,
+            cancel_token,
+        )
+        .await
     }
 }
 
 /// Creates a FIFO file at the specified path.
 ///
+/// An existing FIFO is kept. Anything else already at the path, a
+/// symbolic link included, makes it fail with `AlreadyExists`.
+///
 /// # Parameters
 ///
 /// * `file_path`: The path where the FIFO file should be created.
@@ -631,11 +1655,103 @@ impl Sfifo {
 ///
 /// Returns a `Result` indicating success or an I/O error.
 pub async fn create_fifo(file_path: impl AsRef<Path>) -> Result<(), std::io::Error> {
-    if !Path::new(file_path.as_ref()).exists() {
-        mkfifo(file_path.as_ref(), Mode::S_IRWXU)?;
+    dir::make_fifo(None, file_path.as_ref(), false)
+}
+
+/// Creates a FIFO at `file_path`, failing with `AlreadyExists` if anything,
+/// FIFO or not, is already there
+fn create_fifo_exclusive(file_path: &Path) -> std::io::Result<()> {
+    dir::make_fifo(None, file_path, true)
+}
+
+/// Error returned when committing a checkpoint without a store
+pub(crate) fn no_checkpoint_store() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "No checkpoint store configured",
+    )
+}
+
+/// Returns the path of a handshake side-channel FIFO (`c2s` or `s2c`)
+/// belonging to `file_path`.
+pub(crate) fn handshake_path(file_path: impl AsRef<Path>, extension: &str) -> PathBuf {
+    let mut path = file_path.as_ref().to_path_buf();
+    path.set_extension(extension);
+    path
+}
+
+/// Fixed part of a binary handshake message, with the message type as
+/// the variant index bincode encodes it as
+#[derive(Deserialize)]
+struct FixedFields {
+    process_id: u32,
+    process_name: String,
+    token: String,
+    timestamp: u64,
+    message_type: u32,
+}
+
+impl TryFrom<u32> for HandshakeType {
+    type Error = std::io::Error;
+
+    fn try_from(value: u32) -> std::io::Result<Self> {
+        match value {
+            0 => Ok(HandshakeType::Request),
+            1 => Ok(HandshakeType::Response),
+            2 => Ok(HandshakeType::Ack),
+            _ => Err(SfifoError::InvalidDiscriminant {
+                what: "handshake message type",
+                value: value.into(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Error for a handshake message bincode could not decode
+fn malformed_handshake(error: bincode::ErrorKind, available: usize) -> std::io::Error {
+    const WHAT: &str = "handshake message";
+    match error {
+        bincode::ErrorKind::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            SfifoError::Truncated { what: WHAT }.into()
+        }
+        bincode::ErrorKind::SizeLimit => SfifoError::LengthOverflow {
+            what: WHAT,
+            available,
+        }
+        .into(),
+        error => std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+    }
+}
+
+/// Returns the inode of the file at `file_path`, if it exists.
+pub(crate) fn fifo_inode(file_path: impl AsRef<Path>) -> Option<u64> {
+    std::fs::metadata(file_path).ok().map(|m| m.ino())
+}
+
+/// Makes sure `receiver` is the only reader of the FIFO at `file_path`.
+///
+/// An exclusive advisory lock is taken on the receiver, which catches other
+/// sfifo servers, and `/proc` is scanned for readers that do not lock.
+fn ensure_single_reader(receiver: &Receiver, file_path: &Path) -> std::io::Result<()> {
+    let fd = receiver.as_raw_fd();
+    // The lock lives as long as the receiver's file descriptor
+    let locked = unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } == 0;
+    let own_pid = std::process::id();
+    let mut pids: Vec<u32> = procfs::fifo_openers(file_path)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|o| o.read && !(o.pid == own_pid && o.fd == fd))
+        .map(|o| o.pid)
+        .collect();
+    pids.dedup();
+    if !locked || !pids.is_empty() {
+        error!("FIFO {:?} has other readers: {:?}", file_path, pids);
+        return Err(SfifoError::MultipleReaders { pids }.into());
     }
     Ok(())
 }
+
 /// Deletes a FIFO file at the specified path.
 ///
 /// # Parameters
@@ -649,25 +1765,14 @@ pub async fn delete_fifo(file_path: impl AsRef<Path>) -> Result<(), std::io::Err
     tokio::fs::remove_file(file_path).await?;
     Ok(())
 }
-/// Deprecated, There's a tread leak
-/// Handles a file operation with a timeout.
-///
-/// # Parameters
+/// Runs a file operation, cancelling it after `timeout`.
 ///
-/// * `file_op`: A closure that performs the file operation.
-/// * `timeout`: The maximum duration to wait for the file operation to complete.
-///
-/// # Returns
-///
-/// Returns a `Result` containing the file or an error if the operation times out.
-///
-pub async fn handle_file_with_timeout<F, Fut>(
-    file_op: F,
-    timeout: Duration,
-) -> Result<tokio::fs::File, std::io::Error>
+/// The operation receives a token that is cancelled when the timeout
+/// fires, the result is then a `TimedOut` error.
+pub async fn with_timeout<T, F, Fut>(file_op: F, timeout: Duration) -> std::io::Result<T>
 where
     F: FnOnce(tokio_util::sync::CancellationToken) -> Fut,
-    Fut: std::future::Future<Output = Result<tokio::fs::File, std::io::Error>> + Send,
+    Fut: std::future::Future<Output = std::io::Result<T>>,
 {
     let tokio_cancel = tokio_util::sync::CancellationToken::new();
     let cancel_clone = tokio_cancel.clone();
@@ -682,55 +1787,66 @@ where
         }
     }
 }
-/// Deprecated, There's a tread leak
-/// Handles a file operation with notification on file deletion.
-///
-/// # Parameters
-///
-/// * `file_op`: A closure that performs the file operation.
-/// * `file_path`: The path of the file to monitor for deletion.
-///
-/// # Returns
+
+/// Runs a file operation, cancelling it when the file at `file_path` is
+/// deleted.
 ///
-/// Returns a `Result` containing the file or an error if the file is deleted.
-pub async fn handle_file_with_notify<F, Fut>(
-    file_op: F,
-    file_path: impl AsRef<Path>,
-) -> Result<tokio::fs::File, std::io::Error>
+/// The operation receives a token that is cancelled on deletion, the
+/// result is then a "File deleted" error. A file already missing aborts
+/// the operation unless it completes right away.
+pub async fn with_notify<T, F, Fut>(file_op: F, file_path: impl AsRef<Path>) -> std::io::Result<T>
 where
     F: FnOnce(tokio_util::sync::CancellationToken) -> Fut,
-    Fut: std::future::Future<Output = Result<tokio::fs::File, std::io::Error>> + Send,
+    Fut: std::future::Future<Output = std::io::Result<T>>,
 {
     let tokio_cancel = tokio_util::sync::CancellationToken::new();
     let cancel_clone = tokio_cancel.clone();
-    let filepath_clone = file_path.as_ref().to_path_buf();
-    let t = tokio::task::spawn(async move {
-        loop {
-            if tokio::fs::metadata(&filepath_clone).await.is_err() {
-                break;
-            }
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    let mut watcher = FifoWatcher::existence(&file_path, Duration::from_millis(500));
+    let deleted = async {
+        if tokio::fs::metadata(&file_path).await.is_ok() {
+            watcher.wait_for(FifoEvent::Deleted).await;
         }
-    });
-
+    };
     tokio::select! {
+        biased;
         res = file_op(tokio_cancel) => {
             res
         },
-        _ = t => {
+        _ = deleted => {
             cancel_clone.cancel();
-            Err(std::io::Error::new(std::io::ErrorKind::Other, "File deleted"))
+            Err(std::io::Error::other("File deleted"))
         }
     }
 }
-/// There's no thread leak
+
+/// Handles a file operation with a timeout.
+#[deprecated(note = "use `with_timeout`")]
+pub async fn handle_file_with_timeout<F, Fut>(
+    file_op: F,
+    timeout: Duration,
+) -> Result<tokio::fs::File, std::io::Error>
+where
+    F: FnOnce(tokio_util::sync::CancellationToken) -> Fut,
+    Fut: std::future::Future<Output = Result<tokio::fs::File, std::io::Error>> + Send,
+{
+    with_timeout(file_op, timeout).await
+}
+
 /// Handles a file operation with notification on file deletion.
-///
-/// # Parameters
-///
-/// * `file_op`: A closure that performs the file operation.
-/// * `file_path`: The path of the file to monitor for deletion.
-///
+#[deprecated(note = "use `with_notify`")]
+pub async fn handle_file_with_notify<F, Fut>(
+    file_op: F,
+    file_path: impl AsRef<Path>,
+) -> Result<tokio::fs::File, std::io::Error>
+where
+    F: FnOnce(tokio_util::sync::CancellationToken) -> Fut,
+    Fut: std::future::Future<Output = Result<tokio::fs::File, std::io::Error>> + Send,
+{
+    with_notify(file_op, file_path).await
+}
+
+/// Handles a sender open with notification on file deletion.
+#[deprecated(note = "use `with_notify`")]
 pub async fn handle_file_with_notify_sender<F, Fut>(
     file_op: F,
     file_path: impl AsRef<Path>,
@@ -740,177 +1856,161 @@ where
     Fut:
         std::future::Future<Output = Result<tokio::net::unix::pipe::Sender, std::io::Error>> + Send,
 {
-    let tokio_cancel = tokio_util::sync::CancellationToken::new();
-    let cancel_clone = tokio_cancel.clone();
-    let filepath_clone = file_path.as_ref().to_path_buf();
-    let t = tokio::task::spawn(async move {
-        loop {
-            if tokio::fs::metadata(&filepath_clone).await.is_err() {
-                break;
-            }
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        }
-    });
-    tokio::select! {
-        res = file_op(tokio_cancel) => {
-            res
-        },
-        _ = t => {
-            cancel_clone.cancel();
-            Err(std::io::Error::new(std::io::ErrorKind::Other, "File deleted"))
-        }
-    }
+    with_notify(file_op, file_path).await
 }
 
-/// Read a handshake message from the file
-async fn read_handshake_message(
-    file: &mut tokio::net::unix::pipe::Receiver,
+/// Read a handshake message from the file, in either wire format
+pub(crate) async fn read_handshake_message<R: AsyncRead + Unpin>(
+    file: &mut R,
     cancel_token: &tokio_util::sync::CancellationToken,
 ) -> Result<HandshakeMessage, std::io::Error> {
     // Read message length first (4 bytes)
     let mut len_buf = [0u8; 4];
-    let mut bytes_read = 0;
-
-    // Retry reading until we get all 4 bytes or timeout/cancel
-    while bytes_read < 4 {
-        tokio::select! {
-            res = file.readable() => {
-                match res {
-                    Ok(_) => {
-                        match file.try_read(&mut len_buf[bytes_read..]) {
-                            Ok(n) => {
-                                bytes_read += n;
-                            }
-                            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                                continue;
-                            }
-                            Err(e) => return Err(e),
-                        }
-                    }
-                    Err(e) => return Err(e),
-                }
-            }
-            _ = cancel_token.cancelled() => {
-                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Handshake timeout"));
-            }
-        }
+    read_handshake_bytes(file, &mut len_buf, cancel_token).await?;
+    if WireFormat::detect(&len_buf) == WireFormat::Json {
+        return read_json_handshake_message(file, len_buf, cancel_token).await;
     }
     let message_len = u32::from_le_bytes(len_buf) as usize;
     // Validate message length to prevent DoS
-    if message_len > 4096 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Handshake message too large",
-        ));
+    if message_len > MAX_HANDSHAKE_SIZE {
+        return Err(SfifoError::FrameTooLarge {
+            len: message_len,
+            limit: MAX_HANDSHAKE_SIZE,
+        }
+        .into());
     }
 
     // Read the actual message
     let mut message_buf = vec![0u8; message_len];
-    let mut bytes_read = 0;
+    let result = read_handshake_bytes(file, &mut message_buf, cancel_token).await;
+    let message = result.and_then(|_| HandshakeMessage::from_bytes(&message_buf));
+    message_buf.zeroize();
+    if let Ok(message) = &message {
+        audit::note_peer(message);
+    }
+    message
+}
 
-    // Retry reading until we get all message bytes or timeout/cancel
-    while bytes_read < message_len {
-        tokio::select! {
-            res = file.readable() => {
-                match res {
-                    Ok(_) => {
-                        match file.try_read(&mut message_buf[bytes_read..]) {
-                            Ok(n) => {
-                                bytes_read += n;
-                            }
-                            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                                continue;
-                            }
-                            Err(e) => return Err(e),
-                        }
-                    }
-                    Err(e) => return Err(e),
-                }
-            }
-            _ = cancel_token.cancelled() => {
-                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Handshake timeout"));
-            }
+/// Read the rest of a JSON handshake line starting with `prefix`
+async fn read_json_handshake_message<R: AsyncRead + Unpin>(
+    file: &mut R,
+    prefix: [u8; 4],
+    cancel_token: &tokio_util::sync::CancellationToken,
+) -> Result<HandshakeMessage, std::io::Error> {
+    let mut line = prefix.to_vec();
+    // One byte at a time, to leave anything after the newline in the FIFO
+    let mut byte = [0u8; 1];
+    let result = loop {
+        if line.ends_with(b"\n") {
+            break std::str::from_utf8(&line)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+                .and_then(|json| HandshakeMessage::from_json(json.trim_end()));
+        }
+        if line.len() >= wire::MAX_JSON_LEN {
+            break Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Handshake message too large",
+            ));
         }
+        if let Err(e) = read_handshake_bytes(file, &mut byte, cancel_token).await {
+            break Err(e);
+        }
+        line.push(byte[0]);
+    };
+    line.zeroize();
+    if let Ok(message) = &result {
+        audit::note_peer(message);
     }
+    result
+}
 
-    HandshakeMessage::from_bytes(&message_buf)
+/// Fill `buf` from the file, failing once the handshake is cancelled
+async fn read_handshake_bytes<R: AsyncRead + Unpin>(
+    file: &mut R,
+    buf: &mut [u8],
+    cancel_token: &tokio_util::sync::CancellationToken,
+) -> Result<(), std::io::Error> {
+    tokio::select! {
+        res = file.read_exact(buf) => res.map(|_| ()),
+        _ = cancel_token.cancelled() => {
+            Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Handshake timeout"))
+        }
+    }
 }
 
 /// Write a handshake message to the file
-async fn write_handshake_message(
-    file: &mut tokio::net::unix::pipe::Sender,
+pub(crate) async fn write_handshake_message<W: AsyncWrite + Unpin>(
+    file: &mut W,
     message: &HandshakeMessage,
 ) -> Result<(), std::io::Error> {
-    let message_bytes = message.to_bytes()?;
-    let message_len = message_bytes.len() as u32;
-    // Write message length first (4 bytes)
-    let len_bytes = message_len.to_le_bytes();
-    loop {
-        file.writable().await?;
-        match file.try_write(&len_bytes) {
-            Ok(n) if n == len_bytes.len() => break,
-            Ok(_) => continue,
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
-            Err(e) => return Err(e),
-        }
+    if message.wire_format == WireFormat::Json {
+        return write_json_handshake_message(file, message).await;
     }
-
-    // Write the actual message
-    loop {
-        file.writable().await?;
-        match file.try_write(&message_bytes) {
-            Ok(n) if n == message_bytes.len() => break,
-            Ok(_) => continue,
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
-            Err(e) => return Err(e),
-        }
+    let mut message_bytes = message.to_bytes()?;
+    // Message length first (4 bytes)
+    let result = async {
+        file.write_all(&(message_bytes.len() as u32).to_le_bytes())
+            .await?;
+        file.write_all(&message_bytes).await?;
+        file.flush().await
     }
+    .await;
+    message_bytes.zeroize();
+    result
+}
 
-    Ok(())
+/// Write a handshake message to the file as a JSON line
+async fn write_json_handshake_message<W: AsyncWrite + Unpin>(
+    file: &mut W,
+    message: &HandshakeMessage,
+) -> Result<(), std::io::Error> {
+    let mut line = message.to_json().into_bytes();
+    line.push(b'\n');
+    let result = async {
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+    .await;
+    line.zeroize();
+    result
 }
 
 /// Get the current process name
 fn get_process_name() -> std::io::Result<String> {
-    let pid = std::process::id();
-    let comm_path = format!("/proc/{}/comm", pid);
-
-    match std::fs::read_to_string(&comm_path) {
-        Ok(name) => Ok(name.trim().to_string()),
-        Err(_) => {
-            // Fallback: try to get from command line args
-            std::env::args()
-                .next()
-                .map(|arg| {
-                    std::path::Path::new(&arg)
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_string()
-                })
-                .ok_or_else(|| {
-                    std::io::Error::new(std::io::ErrorKind::Other, "Cannot determine process name")
-                })
-        }
+    if let Some(name) = platform::process_name() {
+        return Ok(name);
     }
+    // Fallback: try to get from command line args
+    std::env::args()
+        .next()
+        .map(|arg| {
+            std::path::Path::new(&arg)
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+        .ok_or_else(|| std::io::Error::other("Cannot determine process name"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     #[tokio::test]
-    async fn test_handle_file_with_timeout() {
+    async fn test_with_timeout() {
         let file_path = "test.txt";
         let file_op = |_| async { tokio::fs::File::create(file_path).await };
-        let _ = handle_file_with_timeout(file_op, Duration::from_secs(2)).await;
+        let _ = with_timeout(file_op, Duration::from_secs(2)).await;
         tokio::fs::remove_file(file_path).await.unwrap();
     }
 
     #[tokio::test]
-    async fn test_handle_file_with_notify() {
+    async fn test_with_notify() {
         let file_path = "test.txt";
         let file_op = |_| async { tokio::fs::File::create(file_path).await };
-        let _ = handle_file_with_notify(file_op, file_path).await;
+        let _ = with_notify(file_op, file_path).await;
         tokio::fs::remove_file(file_path).await.unwrap();
     }
 
@@ -942,6 +2042,129 @@ mod tests {
         assert_eq!(deserialized.process_name, msg.process_name);
     }
 
+    #[test]
+    fn test_handshake_message_malformed() {
+        let msg = HandshakeMessage::new("token".to_string(), HandshakeType::Ack).unwrap();
+        let bytes = msg.to_bytes().unwrap();
+        let error = |bytes: &[u8]| {
+            let error = HandshakeMessage::from_bytes(bytes).unwrap_err();
+            SfifoError::from_io(&error).cloned()
+        };
+
+        assert_eq!(
+            error(&bytes[..bytes.len() - 2]),
+            Some(SfifoError::Truncated {
+                what: "handshake message"
+            })
+        );
+        // A process name claiming to be huge is refused without allocating it
+        let mut huge = bytes.clone();
+        huge[4..12].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            error(&huge),
+            Some(SfifoError::LengthOverflow {
+                what: "handshake message",
+                available: bytes.len()
+            })
+        );
+        // The message type is the last fixed field
+        let mut invalid = bytes.clone();
+        let len = invalid.len();
+        invalid[len - 4..].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(
+            error(&invalid),
+            Some(SfifoError::InvalidDiscriminant {
+                what: "handshake message type",
+                value: 7
+            })
+        );
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_handshake_message_round_trip(
+            process_id: u32,
+            process_name in ".{0,32}",
+            token in ".{0,64}",
+            timestamp: u64,
+            message_type in proptest::sample::select(vec![
+                HandshakeType::Request,
+                HandshakeType::Response,
+                HandshakeType::Ack,
+            ]),
+            extensions in proptest::collection::vec(
+                (proptest::num::u16::ANY, proptest::collection::vec(proptest::num::u8::ANY, 0..16)),
+                0..4,
+            ),
+        ) {
+            let msg = HandshakeMessage {
+                process_id,
+                process_name,
+                token,
+                timestamp,
+                message_type,
+                extensions: extensions
+                    .into_iter()
+                    .map(|(kind, value)| Extension::new(kind, value))
+                    .collect(),
+                wire_format: WireFormat::default(),
+                kernel_cred: None,
+                tenant: None,
+            };
+            let decoded = HandshakeMessage::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+            proptest::prop_assert_eq!(decoded.process_id, msg.process_id);
+            proptest::prop_assert_eq!(&decoded.process_name, &msg.process_name);
+            proptest::prop_assert_eq!(&decoded.token, &msg.token);
+            proptest::prop_assert_eq!(decoded.timestamp, msg.timestamp);
+            proptest::prop_assert_eq!(&decoded.message_type, &msg.message_type);
+            proptest::prop_assert_eq!(&decoded.extensions, &msg.extensions);
+        }
+
+        #[test]
+        fn prop_handshake_parsers_reject_garbage(
+            bytes in proptest::collection::vec(proptest::num::u8::ANY, 0..512),
+            json in ".{0,256}",
+        ) {
+            if let Err(e) = HandshakeMessage::from_bytes(&bytes) {
+                proptest::prop_assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+            }
+            let _ = HandshakeMessage::from_json(&json);
+        }
+    }
+
+    #[test]
+    fn test_handshake_message_extensions_compatibility() {
+        // Layout of the handshake message before extensions were added
+        #[derive(Deserialize)]
+        struct LegacyLayout {
+            process_id: u32,
+            _process_name: String,
+            token: String,
+            _timestamp: u64,
+            _message_type: HandshakeType,
+        }
+
+        let msg = HandshakeMessage::new("ext_token".to_string(), HandshakeType::Request)
+            .unwrap()
+            .with_extension(Extension::new(0x7fff, b"future".to_vec()));
+        let bytes = msg.to_bytes().unwrap();
+
+        // New peers see the extension and report it as unknown
+        let decoded = HandshakeMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.extension(0x7fff), Some(&b"future"[..]));
+        assert_eq!(decoded.unknown_extensions().len(), 1);
+
+        // Older peers ignore the extension section
+        let legacy: LegacyLayout = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(legacy.process_id, msg.process_id);
+        assert_eq!(legacy.token, "ext_token");
+
+        // Messages from older peers carry no extensions
+        let plain = HandshakeMessage::new("t".to_string(), HandshakeType::Ack).unwrap();
+        let decoded = HandshakeMessage::from_bytes(&bincode::serialize(&plain).unwrap()).unwrap();
+        assert!(decoded.extensions.is_empty());
+    }
+
     #[tokio::test]
     async fn test_handshake_message_validation() {
         let token = "valid_token".to_string();
@@ -1065,6 +2288,65 @@ mod tests {
     }
 
     #[tokio::test]
+    async fn test_mutual_authentication() {
+        let fifo_path = "/tmp/test_mutual_auth";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let client_config = Sfifo::new(fifo_path);
+        let server_handle = tokio::spawn(async move {
+            server_config
+                .open_as_server_mutual("server_secret", "client_secret")
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_handle = tokio::spawn(async move {
+            client_config
+                .open_as_client_mutual("client_secret", "server_secret")
+                .await
+        });
+
+        let (server_result, client_result) = tokio::join!(server_handle, client_handle);
+        let server_fifo = server_result.unwrap().unwrap();
+        let client_fifo = client_result.unwrap().unwrap();
+        assert_eq!(server_fifo.peer_info().token, "client_secret");
+        assert_eq!(client_fifo.peer_info().token, "server_secret");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_credentials_scrub_peer_token() {
+        let fifo_path = "/tmp/test_credentials";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let client_config = Sfifo::new(fifo_path);
+        let server_handle = tokio::spawn(async move {
+            let credentials = Credentials::shared("credentials_token");
+            server_config
+                .open_as_server_with_credentials(&credentials)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_handle = tokio::spawn(async move {
+            let credentials = Credentials::shared("credentials_token").keep_peer_token(true);
+            client_config
+                .open_as_client_with_credentials(&credentials)
+                .await
+        });
+
+        let (server_result, client_result) = tokio::join!(server_handle, client_handle);
+        let server_fifo = server_result.unwrap().unwrap();
+        let client_fifo = client_result.unwrap().unwrap();
+        assert!(server_fifo.peer_info().token.is_empty());
+        assert_eq!(client_fifo.peer_info().token, "credentials_token");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
     async fn test_fifo_read_write() {
         let fifo_path = "/tmp/test_fifo";
         let server_config = Sfifo::new(fifo_path)
@@ -1171,4 +2453,412 @@ mod tests {
         let _ = tokio::fs::remove_file(format!("{}.c2s", fifo_path)).await;
         let _ = tokio::fs::remove_file(format!("{}.s2c", fifo_path)).await;
     }
+
+    #[tokio::test]
+    async fn test_keepalive_across_fifo_recreation() {
+        let fifo_path = "/tmp/test_keepalive_fifo";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_keepalive(true)
+            .clone();
+        let server_config = config.clone();
+        let server = tokio::spawn(async move { server_config.open_as_server("token").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut client = Sfifo::new(fifo_path).open_as_client("token").await.unwrap();
+        let mut server_fifo = server.await.unwrap().unwrap();
+
+        let writer = tokio::spawn(async move {
+            client.write_all(b"first").await.unwrap();
+
+            // Replace the FIFO under the same path while the session is idle
+            let new_path = format!("{}.new", fifo_path);
+            create_fifo(&new_path).await.unwrap();
+            tokio::fs::rename(&new_path, fifo_path).await.unwrap();
+            drop(client);
+
+            // The restarted client authenticates again
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let mut client = Sfifo::new(fifo_path).open_as_client("token").await.unwrap();
+            client.write_all(b"second").await.unwrap();
+
+            // An impostor on a recreated FIFO is refused
+            let new_path = format!("{}.new", fifo_path);
+            create_fifo(&new_path).await.unwrap();
+            tokio::fs::rename(&new_path, fifo_path).await.unwrap();
+            drop(client);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Sfifo::new(fifo_path).open_as_client("wrong_token").await
+        });
+
+        let mut buf = [0u8; 5];
+        server_fifo.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"first");
+        let mut buf = [0u8; 6];
+        server_fifo.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"second");
+        assert_eq!(server_fifo.peer_info().process_id, std::process::id());
+
+        let error = server_fifo.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        writer.abort();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(format!("{}.c2s", fifo_path)).await;
+        let _ = tokio::fs::remove_file(format!("{}.s2c", fifo_path)).await;
+    }
+
+    #[tokio::test]
+    async fn test_reopen_requires_credentials() {
+        let fifo_path = "/tmp/test_reopen_requires_credentials";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_keepalive(true)
+            .clone();
+        let receiver = config.open_receiver().await.unwrap();
+        let peer_info = HandshakeMessage::new("token".to_string(), HandshakeType::Request).unwrap();
+        let mut server_fifo =
+            AuthenticatedFifo::new_receiver(receiver, peer_info, true).with_config(&config);
+
+        // A writer on a recreated FIFO is not trusted without a handshake
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let new_path = format!("{}.new", fifo_path);
+        create_fifo(&new_path).await.unwrap();
+        tokio::fs::rename(&new_path, fifo_path).await.unwrap();
+        drop(sender);
+
+        let error = server_fifo.read(&mut [0u8; 8]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_stale_channel_after_fifo_recreation() {
+        let fifo_path = "/tmp/test_stale_channel_fifo";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+        let receiver = config.open_receiver().await.unwrap();
+        let peer_info = HandshakeMessage::new("token".to_string(), HandshakeType::Request).unwrap();
+        let mut server_fifo =
+            AuthenticatedFifo::new_receiver(receiver, peer_info, true).with_config(&config);
+        server_fifo.verify_same_instance().unwrap();
+
+        // A restarted peer replaces the FIFO and closes the old one
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let new_path = format!("{}.new", fifo_path);
+        create_fifo(&new_path).await.unwrap();
+        tokio::fs::rename(&new_path, fifo_path).await.unwrap();
+        drop(sender);
+
+        let error = server_fifo.verify_same_instance().unwrap_err();
+        assert!(matches!(
+            SfifoError::from_io(&error),
+            Some(SfifoError::StaleChannel {
+                current: Some(_),
+                ..
+            })
+        ));
+        let error = server_fifo.read(&mut [0u8; 8]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotConnected);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_single_reader_enforcement() {
+        let fifo_path = "/tmp/test_single_reader";
+        let token = "single_reader_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        // A stray reader left over by a deployment mistake
+        let stray = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+
+        let server_config = Sfifo::new(fifo_path).set_single_reader(true).clone();
+        let client_config = Sfifo::new(fifo_path);
+        let server_handle = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_handle = tokio::spawn(async move { client_config.open_as_client(token).await });
+
+        let (server_result, _) = tokio::join!(server_handle, client_handle);
+        let error = server_result.unwrap().unwrap_err();
+        assert_eq!(
+            SfifoError::from_io(&error),
+            Some(&SfifoError::MultipleReaders {
+                pids: vec![std::process::id()]
+            })
+        );
+
+        drop(stray);
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_mlock_secrets_scrubs_peer_token() {
+        let fifo_path = "/tmp/test_mlock_secrets";
+        let token = "mlock_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_mlock_secrets(MlockMode::BestEffort)
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+        let server_handle = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_handle = tokio::spawn(async move { client_config.open_as_client(token).await });
+
+        let (server_result, client_result) = tokio::join!(server_handle, client_handle);
+        let server_fifo = server_result.unwrap().unwrap();
+        let client_fifo = client_result.unwrap().unwrap();
+        assert!(server_fifo.peer_info().token.is_empty());
+        assert_eq!(client_fifo.peer_info().token, token);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_resume_point() {
+        let fifo_path = "/tmp/test_checkpoint_resume";
+        let checkpoint_path = "/tmp/test_checkpoint_resume.seq";
+        let token = "checkpoint_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(checkpoint_path).await;
+
+        // A consumer that processed up to sequence 9 before restarting
+        Checkpoint::file(checkpoint_path).save(9).unwrap();
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_checkpoint(Some(Checkpoint::file(checkpoint_path)))
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+        let server_handle = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_handle = tokio::spawn(async move { client_config.open_as_client(token).await });
+
+        let (server_result, client_result) = tokio::join!(server_handle, client_handle);
+        let server_fifo = server_result.unwrap().unwrap();
+        let client_fifo = client_result.unwrap().unwrap();
+        assert_eq!(client_fifo.resume_from(), Some(10));
+        assert!(client_fifo.peer_unknown_extensions().is_empty());
+        assert!(client_fifo.checkpoint(1).is_err());
+
+        server_fifo.checkpoint(12).unwrap();
+        assert_eq!(Checkpoint::file(checkpoint_path).load().unwrap(), Some(12));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(checkpoint_path).await;
+    }
+
+    #[cfg(feature = "lz4")]
+    #[tokio::test]
+    async fn test_negotiated_compression() {
+        let fifo_path = "/tmp/test_negotiated_compression";
+        let token = "compression_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let compression = Compression::lz4().with_threshold(64);
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_compression(Some(compression))
+            .clone();
+        let client_config = Sfifo::new(fifo_path)
+            .set_compression(Some(compression))
+            .clone();
+        let server_handle = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_handle = tokio::spawn(async move { client_config.open_as_client(token).await });
+
+        let (server_result, client_result) = tokio::join!(server_handle, client_handle);
+        let server_fifo = server_result.unwrap().unwrap();
+        let client_fifo = client_result.unwrap().unwrap();
+        assert_eq!(server_fifo.compression(), Some(compression));
+        assert_eq!(client_fifo.compression(), Some(compression));
+
+        let mut receiver = server_fifo.into_framed_receiver().unwrap();
+        let mut sender = client_fifo.into_framed_sender().unwrap();
+        let payload = "{\"level\":\"info\",\"msg\":\"compressed\"}\n".repeat(256);
+        sender.send(payload.as_bytes()).await.unwrap();
+        sender.send(b"raw").await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), payload.as_bytes());
+        assert_eq!(receiver.recv().await.unwrap().unwrap(), "raw");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_metrics_hooks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Counters {
+            written: AtomicUsize,
+            read: AtomicUsize,
+            handshakes: AtomicUsize,
+        }
+
+        impl SfifoMetrics for Counters {
+            fn bytes_written(&self, n: usize) {
+                self.written.fetch_add(n, Ordering::SeqCst);
+            }
+            fn bytes_read(&self, n: usize) {
+                self.read.fetch_add(n, Ordering::SeqCst);
+            }
+            fn handshake_succeeded(&self) {
+                self.handshakes.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let fifo_path = "/tmp/test_metrics_hooks";
+        let token = "metrics_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let counters = std::sync::Arc::new(Counters::default());
+        let metrics = Metrics::new(counters.clone());
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_metrics(Some(metrics.clone()))
+            .clone();
+        let client_config = Sfifo::new(fifo_path).set_metrics(Some(metrics)).clone();
+        let server_handle = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_handle = tokio::spawn(async move { client_config.open_as_client(token).await });
+
+        let (server_result, client_result) = tokio::join!(server_handle, client_handle);
+        let mut server_fifo = server_result.unwrap().unwrap();
+        let mut client_fifo = client_result.unwrap().unwrap();
+        assert_eq!(counters.handshakes.load(Ordering::SeqCst), 2);
+
+        client_fifo.write_all(b"counted").await.unwrap();
+        let mut buf = [0u8; 7];
+        server_fifo.read_exact(&mut buf).await.unwrap();
+        assert_eq!(counters.written.load(Ordering::SeqCst), 7);
+        assert_eq!(counters.read.load(Ordering::SeqCst), 7);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn test_open_read_write_unchecked() {
+        let fifo_path = "/tmp/test_open_rdwr";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let mut config = Sfifo::new(fifo_path);
+        config.set_create(true).set_read(true).set_write(true);
+        assert!(config.open().await.is_err());
+
+        // Both ends in one file, no peer needed
+        let mut file = config.set_read_write_unchecked(true).open().await.unwrap();
+        file.write_all(b"loopback").await.unwrap();
+        let mut buf = [0u8; 8];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"loopback");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_drain_on_open() {
+        let fifo_path = "/tmp/test_drain_on_open";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut config = Sfifo::new(fifo_path);
+        let previous = config.open_receiver().await.unwrap();
+        let mut sender = config.open_sender().await.unwrap();
+        sender.write_all(b"stale session data").await.unwrap();
+        // The bytes stay queued while the sender keeps the FIFO open
+        drop(previous);
+
+        let mut receiver = config
+            .set_drain_on_open(true)
+            .open_receiver()
+            .await
+            .unwrap();
+        sender.write_all(b"fresh").await.unwrap();
+        let mut buf = [0u8; 5];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"fresh");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_create_on_open_sender() {
+        let fifo_path = "/tmp/test_create_on_open_sender";
+        let file_path = "/tmp/test_create_exclusive_file";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        // The writer may come first and create the FIFO
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+        let sender = tokio::spawn({
+            let config = config.clone();
+            async move { config.open_sender().await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        sender
+            .await
+            .unwrap()
+            .unwrap()
+            .write_all(b"hi")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 2];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+
+        // Exclusive creation refuses an existing FIFO or regular file
+        let exclusive = config.clone().set_create_exclusive(true).clone();
+        let err = exclusive.open_sender().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        tokio::fs::write(file_path, b"").await.unwrap();
+        let err = Sfifo::new(file_path)
+            .set_create_exclusive(true)
+            .open_receiver()
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().contains("not a FIFO"));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(file_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_open_sender_timeout_cause() {
+        let fifo_path = "/tmp/test_open_sender_timeout_cause";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        // Missing FIFO, then a FIFO without reader
+        let mut config = Sfifo::new(fifo_path);
+        config.set_timeout(Duration::from_millis(250));
+        for errno in [libc::ENOENT, libc::ENXIO] {
+            let err = config.open_sender().await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+            let Some(SfifoError::OpenAborted {
+                attempts,
+                elapsed,
+                last_os_error,
+                deleted,
+            }) = SfifoError::from_io(&err)
+            else {
+                panic!("unexpected error {:?}", err);
+            };
+            assert!(*attempts > 1);
+            assert!(*elapsed >= Duration::from_millis(250));
+            assert_eq!(*last_os_error, Some(errno));
+            assert!(!deleted);
+            create_fifo(fifo_path).await.unwrap();
+        }
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
 }
@@ -0,0 +1,42 @@
+use crate::{Sfifo, SfifoError, DEFAULT_MAX_FRAME_SIZE};
+
+impl Sfifo {
+    /// Refuse frames with a payload larger than `bytes` on the framed
+    /// senders, receivers and channels opened from this configuration
+    ///
+    /// Sending a larger frame fails with `SfifoError::FrameTooLarge`, and
+    /// so does receiving one, before its payload is buffered.
+    pub fn set_max_frame_size(&mut self, bytes: usize) -> &mut Self {
+        self.max_frame_size = Some(bytes);
+        self
+    }
+
+    /// Bound the bytes of frames a channel opened from this configuration
+    /// keeps in memory: reliable messages waiting for their ack and data
+    /// received while waiting for acks or pongs
+    ///
+    /// Keeping more fails with `SfifoError::MemoryLimitExceeded`.
+    pub fn set_memory_limit(&mut self, bytes: usize) -> &mut Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Largest frame payload accepted, `DEFAULT_MAX_FRAME_SIZE` unless set
+    pub(crate) fn frame_size_limit(&self) -> usize {
+        self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+/// Check that `requested` more bytes fit in the memory `limit` with `used`
+/// bytes already kept
+pub(crate) fn reserve(used: usize, requested: usize, limit: Option<usize>) -> std::io::Result<()> {
+    match limit {
+        Some(limit) if used + requested > limit => Err(SfifoError::MemoryLimitExceeded {
+            requested,
+            used,
+            limit,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,97 @@
+use crate::AuthenticatedFifo;
+use bytes::Buf;
+use futures_util::Stream;
+
+// Bytes requested from the pipe per read while looking for a newline
+const LINE_READ_CHUNK: usize = 4096;
+
+impl AuthenticatedFifo {
+    /// Read a line without its `\n` or `\r\n` terminator - only works for
+    /// Receiver
+    ///
+    /// Returns `None` at end-of-file, or the unterminated remainder if the
+    /// writer closed mid-line. Bytes read past the newline are kept for
+    /// the next `read_line` or `read`.
+    pub async fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut scanned = 0;
+        loop {
+            if let Some(pos) = self.line_buf[scanned..].iter().position(|&b| b == b'\n') {
+                let mut line = self.line_buf.split_to(scanned + pos + 1);
+                line.truncate(line.len() - 1);
+                if line.ends_with(b"\r") {
+                    line.truncate(line.len() - 1);
+                }
+                return into_string(line.to_vec()).map(Some);
+            }
+            scanned = self.line_buf.len();
+
+            let mut chunk = [0u8; LINE_READ_CHUNK];
+            let n = self.read_pipe(&mut chunk).await?;
+            if n == 0 {
+                if self.line_buf.is_empty() {
+                    return Ok(None);
+                }
+                let rest = self.line_buf.split().to_vec();
+                return into_string(rest).map(Some);
+            }
+            self.line_buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Stream of the lines read with `read_line` until end-of-file
+    pub fn lines(&mut self) -> impl Stream<Item = std::io::Result<String>> + '_ {
+        futures_util::stream::unfold(self, |fifo| async move {
+            fifo.read_line().await.transpose().map(|line| (line, fifo))
+        })
+    }
+
+    /// Move bytes left over by `read_line` into `buf`
+    pub(crate) fn take_buffered(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.line_buf.len());
+        buf[..n].copy_from_slice(&self.line_buf[..n]);
+        self.line_buf.advance(n);
+        n
+    }
+}
+
+fn into_string(line: Vec<u8>) -> std::io::Result<String> {
+    String::from_utf8(line).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_fifo, AuthenticatedFifo, HandshakeMessage, HandshakeType, Sfifo};
+    use futures_util::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_read_lines_across_reads() {
+        let fifo_path = "/tmp/test_read_lines";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        let peer_info = HandshakeMessage::new(String::new(), HandshakeType::Request).unwrap();
+        let mut fifo = AuthenticatedFifo::new_receiver(receiver, peer_info, true);
+
+        let mut sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        sender.write_all(b"first\nraw").await.unwrap();
+        assert_eq!(fifo.read_line().await.unwrap().unwrap(), "first");
+        let mut buf = [0u8; 3];
+        fifo.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"raw");
+
+        let writer = tokio::spawn(async move {
+            for chunk in [&b"sec"[..], b"ond\r\nthi", b"rd\nunterminated"] {
+                sender.write_all(chunk).await?;
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            Ok::<_, std::io::Error>(())
+        });
+        let lines: Vec<String> = fifo.lines().map(|line| line.unwrap()).collect().await;
+        assert_eq!(lines, ["second", "third", "unterminated"]);
+        writer.await.unwrap().unwrap();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
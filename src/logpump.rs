@@ -0,0 +1,410 @@
+use crate::{pipe::open_held_receiver, task::spawn_named, Sfifo};
+use bytes::Bytes;
+use std::{
+    ffi::OsString,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::unix::pipe::{Receiver, Sender},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+// Largest chunk read from the FIFO at once
+const READ_CHUNK: usize = 64 * 1024;
+// Chunks read but not written yet
+const QUEUED_CHUNKS: usize = 64;
+
+/// When a `FifoLogPump` syncs the log file to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// Leave it to the kernel
+    #[default]
+    Never,
+    /// After every chunk written
+    Always,
+    /// At most this often while data was written
+    Interval(Duration),
+}
+
+/// Counters of a running `FifoLogPump`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogPumpStats {
+    /// Bytes appended to the log files
+    pub bytes_written: u64,
+    /// Bytes read from the FIFO and discarded while the queue was full
+    pub bytes_dropped: u64,
+    /// Times the log file was rotated
+    pub rotations: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    bytes_written: AtomicU64,
+    bytes_dropped: AtomicU64,
+    rotations: AtomicU64,
+}
+
+/// Copies everything written to a FIFO into a log file, rotating it.
+///
+/// The FIFO is opened for reading and writing, so writers may come and go
+/// without the pump seeing end-of-file. Once the log file would grow past
+/// `max_size`, or was opened `max_age` ago, it is renamed to `<log>.1`,
+/// older files shift to `<log>.2` and so on up to `keep`, and a new file is
+/// started. A slow disk stops the pump from reading, so writers block on
+/// the full pipe, unless `drop_when_full` is set.
+#[derive(Debug)]
+pub struct FifoLogPump {
+    config: Sfifo,
+    log_path: PathBuf,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+    fsync: FsyncPolicy,
+    drop_when_full: bool,
+}
+
+impl FifoLogPump {
+    /// Pump the FIFO at `config.file_path`, created if `config.create` is
+    /// set, into the file at `log_path`
+    pub fn new(config: &Sfifo, log_path: impl AsRef<Path>) -> Self {
+        FifoLogPump {
+            config: config.clone(),
+            log_path: log_path.as_ref().to_path_buf(),
+            max_size: None,
+            max_age: None,
+            keep: 1,
+            fsync: FsyncPolicy::default(),
+            drop_when_full: false,
+        }
+    }
+
+    /// Rotate before the log file grows past `bytes`
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate on the first write once the log file is `age` old
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Number of rotated files kept, 0 discards the log on rotation
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    pub fn fsync(mut self, policy: FsyncPolicy) -> Self {
+        self.fsync = policy;
+        self
+    }
+
+    /// Keep reading and discard the data instead of blocking writers when
+    /// the log file cannot keep up
+    pub fn drop_when_full(mut self, drop: bool) -> Self {
+        self.drop_when_full = drop;
+        self
+    }
+
+    /// Open the FIFO and the log file, then start pumping in the background
+    pub async fn spawn(self) -> std::io::Result<LogPumpHandle> {
+        self.config.create_on_open().await?;
+        let (receiver, hold) = open_held_receiver(&self.config.file_path)?;
+        let log = LogFile::open(&self).await?;
+        let counters = Arc::new(Counters::default());
+        let cancel = CancellationToken::new();
+        let (tx, rx) = mpsc::channel(QUEUED_CHUNKS);
+        let reader = spawn_named(
+            "sfifo::log_pump_read",
+            read_fifo(
+                receiver,
+                hold,
+                tx,
+                self.drop_when_full,
+                counters.clone(),
+                cancel.clone(),
+            ),
+        );
+        let task = spawn_named(
+            "sfifo::log_pump_write",
+            write_log(log, rx, reader, counters.clone()),
+        );
+        Ok(LogPumpHandle {
+            counters,
+            cancel,
+            task: Some(task),
+        })
+    }
+}
+
+/// Controls a running `FifoLogPump`, which stops once the handle is dropped
+#[derive(Debug)]
+pub struct LogPumpHandle {
+    counters: Arc<Counters>,
+    cancel: CancellationToken,
+    task: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl LogPumpHandle {
+    pub fn stats(&self) -> LogPumpStats {
+        LogPumpStats {
+            bytes_written: self.counters.bytes_written.load(Ordering::Relaxed),
+            bytes_dropped: self.counters.bytes_dropped.load(Ordering::Relaxed),
+            rotations: self.counters.rotations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stop reading, write out the data already in the pipe and sync the
+    /// log file, returning the first error the pump ran into
+    pub async fn shutdown(mut self) -> std::io::Result<LogPumpStats> {
+        self.cancel.cancel();
+        if let Some(task) = self.task.take() {
+            task.await.map_err(std::io::Error::other)??;
+        }
+        Ok(self.stats())
+    }
+}
+
+impl Drop for LogPumpHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// Forward chunks read from the FIFO until cancelled, then whatever is
+/// left in the pipe
+async fn read_fifo(
+    mut receiver: Receiver,
+    // Write end keeping the FIFO open where it cannot be opened read-write
+    _hold: Option<Sender>,
+    tx: mpsc::Sender<Bytes>,
+    drop_when_full: bool,
+    counters: Arc<Counters>,
+    cancel: CancellationToken,
+) -> std::io::Result<()> {
+    let mut buf = vec![0u8; READ_CHUNK];
+    loop {
+        let n = tokio::select! {
+            _ = cancel.cancelled() => break,
+            n = receiver.read(&mut buf) => n?,
+        };
+        let chunk = Bytes::copy_from_slice(&buf[..n]);
+        if !drop_when_full {
+            if tx.send(chunk).await.is_err() {
+                return Ok(());
+            }
+            continue;
+        }
+        match tx.try_send(chunk) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                counters
+                    .bytes_dropped
+                    .fetch_add(n as u64, Ordering::Relaxed);
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+        }
+    }
+    // Read the fd directly, the receiver may not be registered as readable
+    loop {
+        match nix::unistd::read(receiver.as_raw_fd(), &mut buf) {
+            Ok(0) | Err(nix::errno::Errno::EAGAIN) => return Ok(()),
+            Ok(n) => {
+                if tx.send(Bytes::copy_from_slice(&buf[..n])).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Append the chunks to the log until the reader is done
+async fn write_log(
+    mut log: LogFile,
+    mut rx: mpsc::Receiver<Bytes>,
+    reader: JoinHandle<std::io::Result<()>>,
+    counters: Arc<Counters>,
+) -> std::io::Result<()> {
+    let mut ticks = match log.fsync {
+        FsyncPolicy::Interval(interval) => Some(tokio::time::interval(interval)),
+        _ => None,
+    };
+    loop {
+        tokio::select! {
+            chunk = rx.recv() => match chunk {
+                Some(chunk) => log.write(&chunk, &counters).await?,
+                None => break,
+            },
+            _ = next_tick(&mut ticks) => log.sync().await?,
+        }
+    }
+    log.sync().await?;
+    reader.await.map_err(std::io::Error::other)?
+}
+
+async fn next_tick(ticks: &mut Option<tokio::time::Interval>) {
+    match ticks {
+        Some(ticks) => {
+            ticks.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+    fsync: FsyncPolicy,
+    // Written since the last sync
+    dirty: bool,
+}
+
+impl LogFile {
+    async fn open(pump: &FifoLogPump) -> std::io::Result<Self> {
+        let file = append(&pump.log_path).await?;
+        Ok(LogFile {
+            path: pump.log_path.clone(),
+            size: file.metadata().await?.len(),
+            file,
+            opened: Instant::now(),
+            max_size: pump.max_size,
+            max_age: pump.max_age,
+            keep: pump.keep,
+            fsync: pump.fsync,
+            dirty: false,
+        })
+    }
+
+    async fn write(&mut self, chunk: &[u8], counters: &Counters) -> std::io::Result<()> {
+        let len = chunk.len() as u64;
+        let full = self.max_size.is_some_and(|max| self.size + len > max);
+        let old = self.max_age.is_some_and(|age| self.opened.elapsed() >= age);
+        if self.size > 0 && (full || old) {
+            self.rotate().await?;
+            counters.rotations.fetch_add(1, Ordering::Relaxed);
+        }
+        self.file.write_all(chunk).await?;
+        self.size += len;
+        self.dirty = true;
+        counters.bytes_written.fetch_add(len, Ordering::Relaxed);
+        if self.fsync == FsyncPolicy::Always {
+            self.sync().await?;
+        }
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> std::io::Result<()> {
+        self.file.flush().await?;
+        if self.dirty && self.fsync != FsyncPolicy::Never {
+            self.file.sync_data().await?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Shift the rotated files and start a new log file
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        self.sync().await?;
+        if self.keep == 0 {
+            tokio::fs::remove_file(&self.path).await?;
+        } else {
+            for i in (1..self.keep).rev() {
+                match tokio::fs::rename(self.rotated(i), self.rotated(i + 1)).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            tokio::fs::rename(&self.path, self.rotated(1)).await?;
+        }
+        debug!("Rotated log file {:?}", self.path);
+        self.file = append(&self.path).await?;
+        self.size = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    fn rotated(&self, i: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{}", i));
+        path.into()
+    }
+}
+
+async fn append(path: &Path) -> std::io::Result<File> {
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_log_pump_rotation() {
+        let fifo_path = "/tmp/test_log_pump";
+        let log_path = "/tmp/test_log_pump.log";
+        for path in [fifo_path, log_path] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        for i in 1..=3 {
+            let _ = tokio::fs::remove_file(format!("{}.{}", log_path, i)).await;
+        }
+
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+        let pump = FifoLogPump::new(&config, log_path)
+            .max_size(10)
+            .keep(2)
+            .fsync(FsyncPolicy::Always)
+            .spawn()
+            .await
+            .unwrap();
+
+        // Writers come and go without stopping the pump
+        for line in ["line one\n", "line two\n", "line 3\n", "line 4\n"] {
+            let mut sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+            sender.write_all(line.as_bytes()).await.unwrap();
+            drop(sender);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let mut sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        sender.write_all(b"last\n").await.unwrap();
+
+        // Data still in the pipe is written out on shutdown
+        let stats = pump.shutdown().await.unwrap();
+        assert_eq!(stats.bytes_written, 37);
+        assert_eq!(stats.rotations, 4);
+        let read = |path: String| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(log_path.to_string()), "last\n");
+        assert_eq!(read(format!("{}.1", log_path)), "line 4\n");
+        assert_eq!(read(format!("{}.2", log_path)), "line 3\n");
+        assert!(!Path::new(&format!("{}.3", log_path)).exists());
+
+        for path in [fifo_path, log_path] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        for i in 1..=2 {
+            let _ = tokio::fs::remove_file(format!("{}.{}", log_path, i)).await;
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{pipe::wrong_direction, AuthenticatedFifo, HandshakeMessage, HandshakeType, PipeEnd};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+/// Byte stream sfifo code can run over: FIFO ends, authenticated FIFOs or
+/// in-memory pipes
+///
+/// Code written against `FifoTransport` can be unit tested with
+/// `MemoryFifo`, without FIFO files or descriptors.
+pub trait FifoTransport: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug {
+    /// Peer authenticated by the handshake, if any
+    fn peer_info(&self) -> Option<&HandshakeMessage> {
+        None
+    }
+}
+
+impl FifoTransport for PipeEnd {}
+
+impl FifoTransport for AuthenticatedFifo {
+    fn peer_info(&self) -> Option<&HandshakeMessage> {
+        Some(AuthenticatedFifo::peer_info(self))
+    }
+}
+
+/// One end of an in-memory pipe, behaving like a FIFO end
+///
+/// Only the sender writes and only the receiver reads, the other operation
+/// fails with `InvalidInput`. Writes wait while the pipe is full, the
+/// receiver reads end-of-file once the sender is dropped and writes fail
+/// with `BrokenPipe` once the receiver is dropped.
+#[derive(Debug)]
+pub struct MemoryFifo {
+    stream: DuplexStream,
+    is_sender: bool,
+    peer_info: Option<HandshakeMessage>,
+}
+
+impl MemoryFifo {
+    /// Connected sender and receiver holding up to `capacity` bytes
+    pub fn pair(capacity: usize) -> (MemoryFifo, MemoryFifo) {
+        let (sender, receiver) = tokio::io::duplex(capacity);
+        (
+            MemoryFifo {
+                stream: sender,
+                is_sender: true,
+                peer_info: None,
+            },
+            MemoryFifo {
+                stream: receiver,
+                is_sender: false,
+                peer_info: None,
+            },
+        )
+    }
+
+    /// Like `pair`, with each end reporting this process and `token` as
+    /// its peer, as if a handshake had taken place
+    pub fn authenticated_pair(
+        capacity: usize,
+        token: &str,
+    ) -> std::io::Result<(MemoryFifo, MemoryFifo)> {
+        let (mut sender, mut receiver) = Self::pair(capacity);
+        sender.peer_info = Some(HandshakeMessage::new(
+            token.to_string(),
+            HandshakeType::Response,
+        )?);
+        receiver.peer_info = Some(HandshakeMessage::new(
+            token.to_string(),
+            HandshakeType::Request,
+        )?);
+        Ok((sender, receiver))
+    }
+
+    pub fn is_sender(&self) -> bool {
+        self.is_sender
+    }
+
+    pub fn is_receiver(&self) -> bool {
+        !self.is_sender
+    }
+}
+
+impl FifoTransport for MemoryFifo {
+    fn peer_info(&self) -> Option<&HandshakeMessage> {
+        self.peer_info.as_ref()
+    }
+}
+
+impl AsyncRead for MemoryFifo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.is_sender {
+            return Poll::Ready(Err(wrong_direction("Cannot read from sender FIFO")));
+        }
+        Pin::new(&mut this.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemoryFifo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.is_sender {
+            return Poll::Ready(Err(wrong_direction("Cannot write to receiver FIFO")));
+        }
+        Pin::new(&mut this.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if !this.is_sender {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Frame, FrameCodec};
+    use bytes::Bytes;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    // Code under test only knows about the transport
+    async fn forward_upper(
+        from: &mut impl FifoTransport,
+        to: &mut impl FifoTransport,
+    ) -> std::io::Result<usize> {
+        let mut data = Vec::new();
+        from.read_to_end(&mut data).await?;
+        data.make_ascii_uppercase();
+        to.write_all(&data).await?;
+        Ok(data.len())
+    }
+
+    #[tokio::test]
+    async fn test_memory_fifo_as_transport() {
+        let (mut input, mut upstream) = MemoryFifo::authenticated_pair(64, "memory").unwrap();
+        let (mut downstream, mut output) = MemoryFifo::pair(64);
+        assert_eq!(upstream.peer_info().unwrap().token, "memory");
+        assert!(output.peer_info().is_none());
+
+        input.write_all(b"hello").await.unwrap();
+        drop(input);
+        assert_eq!(
+            forward_upper(&mut upstream, &mut downstream).await.unwrap(),
+            5
+        );
+        let mut buf = [0u8; 5];
+        output.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"HELLO");
+        assert_eq!(
+            output.write_all(b"x").await.unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        drop(output);
+        assert_eq!(
+            downstream.write_all(b"x").await.unwrap_err().kind(),
+            std::io::ErrorKind::BrokenPipe
+        );
+
+        // Frames go through unchanged
+        let (sender, receiver) = MemoryFifo::pair(64);
+        let mut sender = FramedWrite::new(sender, FrameCodec::new());
+        let mut receiver = FramedRead::new(receiver, FrameCodec::new());
+        sender
+            .send(Frame::data(Bytes::from_static(b"framed")))
+            .await
+            .unwrap();
+        let frame = receiver.next().await.unwrap().unwrap();
+        assert_eq!(&frame.payload[..], b"framed");
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+
+/// Receives counter updates from FIFOs, framed channels and handshakes.
+///
+/// Every method has an empty default, implement only the counters you
+/// care about. Hooks are called on the I/O path and should be cheap.
+pub trait SfifoMetrics: Send + Sync {
+    /// Bytes read from a FIFO, including frame headers
+    fn bytes_read(&self, _n: usize) {}
+    /// Bytes written to a FIFO, including frame headers
+    fn bytes_written(&self, _n: usize) {}
+    /// A frame was encoded and sent
+    fn frame_sent(&self) {}
+    /// A frame was received and decoded
+    fn frame_received(&self) {}
+    /// An authenticated open completed its handshake
+    fn handshake_succeeded(&self) {}
+    /// An authenticated open failed its handshake
+    fn handshake_failed(&self) {}
+    /// An open or handshake timed out
+    fn timeout(&self) {}
+    /// A FIFO was reopened after being recreated
+    fn reconnect(&self) {}
+}
+
+impl<T: SfifoMetrics + ?Sized> SfifoMetrics for Arc<T> {
+    fn bytes_read(&self, n: usize) {
+        (**self).bytes_read(n)
+    }
+    fn bytes_written(&self, n: usize) {
+        (**self).bytes_written(n)
+    }
+    fn frame_sent(&self) {
+        (**self).frame_sent()
+    }
+    fn frame_received(&self) {
+        (**self).frame_received()
+    }
+    fn handshake_succeeded(&self) {
+        (**self).handshake_succeeded()
+    }
+    fn handshake_failed(&self) {
+        (**self).handshake_failed()
+    }
+    fn timeout(&self) {
+        (**self).timeout()
+    }
+    fn reconnect(&self) {
+        (**self).reconnect()
+    }
+}
+
+/// Owned copy of the counters of an `AtomicMetrics`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub handshakes_succeeded: u64,
+    pub handshakes_failed: u64,
+    pub timeouts: u64,
+    pub reconnects: u64,
+}
+
+/// Lock-free counters implementing `SfifoMetrics`
+#[derive(Debug, Default)]
+pub struct AtomicMetrics {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    frames_sent: AtomicU64,
+    frames_received: AtomicU64,
+    handshakes_succeeded: AtomicU64,
+    handshakes_failed: AtomicU64,
+    timeouts: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+impl AtomicMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the current counters
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.collect(|counter| counter.load(Ordering::Relaxed))
+    }
+
+    /// Read the current counters and zero them
+    ///
+    /// Each counter is swapped atomically, so no update is lost or counted
+    /// twice between two snapshots, which makes the result suitable for
+    /// computing rates.
+    pub fn snapshot_and_reset(&self) -> MetricsSnapshot {
+        self.collect(|counter| counter.swap(0, Ordering::Relaxed))
+    }
+
+    fn collect(&self, read: impl Fn(&AtomicU64) -> u64) -> MetricsSnapshot {
+        MetricsSnapshot {
+            bytes_read: read(&self.bytes_read),
+            bytes_written: read(&self.bytes_written),
+            frames_sent: read(&self.frames_sent),
+            frames_received: read(&self.frames_received),
+            handshakes_succeeded: read(&self.handshakes_succeeded),
+            handshakes_failed: read(&self.handshakes_failed),
+            timeouts: read(&self.timeouts),
+            reconnects: read(&self.reconnects),
+        }
+    }
+}
+
+impl SfifoMetrics for AtomicMetrics {
+    fn bytes_read(&self, n: usize) {
+        self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    fn bytes_written(&self, n: usize) {
+        self.bytes_written.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    fn frame_sent(&self) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+    }
+    fn frame_received(&self) {
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+    }
+    fn handshake_succeeded(&self) {
+        self.handshakes_succeeded.fetch_add(1, Ordering::Relaxed);
+    }
+    fn handshake_failed(&self) {
+        self.handshakes_failed.fetch_add(1, Ordering::Relaxed);
+    }
+    fn timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+    fn reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+static GLOBAL: RwLock<Option<Arc<dyn SfifoMetrics>>> = RwLock::new(None);
+
+/// Install metrics hooks receiving updates from every FIFO in the process,
+/// in addition to the hooks set on individual `Sfifo` configurations
+pub fn set_global_metrics(metrics: impl SfifoMetrics + 'static) {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(metrics));
+}
+
+/// Remove the process-wide metrics hooks
+pub fn clear_global_metrics() {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Shared handle to metrics hooks, set on an `Sfifo`
+#[derive(Clone)]
+pub struct Metrics(Arc<dyn SfifoMetrics>);
+
+impl Metrics {
+    pub fn new(metrics: impl SfifoMetrics + 'static) -> Self {
+        Metrics(Arc::new(metrics))
+    }
+}
+
+/// Reports to both hooks
+struct Chain(Arc<dyn SfifoMetrics>, Arc<dyn SfifoMetrics>);
+
+impl SfifoMetrics for Chain {
+    fn bytes_read(&self, n: usize) {
+        self.0.bytes_read(n);
+        self.1.bytes_read(n);
+    }
+    fn bytes_written(&self, n: usize) {
+        self.0.bytes_written(n);
+        self.1.bytes_written(n);
+    }
+    fn frame_sent(&self) {
+        self.0.frame_sent();
+        self.1.frame_sent();
+    }
+    fn frame_received(&self) {
+        self.0.frame_received();
+        self.1.frame_received();
+    }
+    fn handshake_succeeded(&self) {
+        self.0.handshake_succeeded();
+        self.1.handshake_succeeded();
+    }
+    fn handshake_failed(&self) {
+        self.0.handshake_failed();
+        self.1.handshake_failed();
+    }
+    fn timeout(&self) {
+        self.0.timeout();
+        self.1.timeout();
+    }
+    fn reconnect(&self) {
+        self.0.reconnect();
+        self.1.reconnect();
+    }
+}
+
+impl Metrics {
+    /// Hooks reporting to `extra` as well as to `base`, if any
+    pub(crate) fn chain(base: Option<Metrics>, extra: Metrics) -> Metrics {
+        match base {
+            Some(base) => Metrics::new(Chain(base.0, extra.0)),
+            None => extra,
+        }
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Metrics").finish_non_exhaustive()
+    }
+}
+
+/// Report to the per-configuration hooks, if any, and to the global ones
+pub(crate) fn emit(local: Option<&Metrics>, event: impl Fn(&dyn SfifoMetrics)) {
+    if let Some(metrics) = local {
+        event(metrics.0.as_ref());
+    }
+    if let Some(metrics) = GLOBAL.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        event(metrics.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl SfifoMetrics for Counter {
+        fn bytes_written(&self, n: usize) {
+            self.0.fetch_add(n, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_emit_to_local_hooks() {
+        let counter = Arc::new(Counter::default());
+        let metrics = Metrics::new(counter.clone());
+        emit(Some(&metrics), |m| m.bytes_written(3));
+        emit(Some(&metrics), |m| m.bytes_read(5));
+        emit(None, |m| m.bytes_written(7));
+        assert_eq!(counter.0.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_snapshot_and_reset() {
+        let stats = Arc::new(AtomicMetrics::new());
+        let metrics = Metrics::chain(None, Metrics::new(stats.clone()));
+        emit(Some(&metrics), |m| {
+            m.bytes_written(10);
+            m.frame_sent();
+        });
+        let snapshot = stats.snapshot_and_reset();
+        assert_eq!(snapshot.bytes_written, 10);
+        assert_eq!(snapshot.frames_sent, 1);
+        assert_eq!(stats.snapshot(), MetricsSnapshot::default());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::{task::spawn_named, Channel, Frame, FrameKind, FramedReceiver, FramedSender};
+use bytes::Bytes;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+
+// Messages buffered per lane and direction
+const LANE_CAPACITY: usize = 64;
+
+/// Several logical channels ("lanes") over one FIFO pair.
+///
+/// Each lane is identified by the tag of its data frames, lane 0 being the
+/// plain data of a non-multiplexed peer. Outgoing messages are written by
+/// a background task: lanes with a higher priority are always served
+/// first, lanes with the same priority share the FIFO round robin.
+/// Incoming messages are dispatched to their lane, a lane that is not read
+/// eventually stalls the others. Messages still queued when the
+/// multiplexer is dropped are discarded.
+pub struct Multiplexer {
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+}
+
+struct Shared {
+    lanes: Mutex<Lanes>,
+    // Signalled when a message is queued on any lane
+    queued: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lanes> {
+        self.lanes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Default)]
+struct Lanes {
+    outgoing: BTreeMap<u16, Outgoing>,
+    last_served: Option<u16>,
+    incoming: HashMap<u16, mpsc::Sender<Bytes>>,
+    // Incoming lanes that received data before being opened
+    unopened: HashMap<u16, mpsc::Receiver<Bytes>>,
+}
+
+struct Outgoing {
+    priority: u8,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl Lanes {
+    /// Next message to write: from the highest priority lane with pending
+    /// messages, round robin among lanes of equal priority
+    fn next(&mut self) -> Option<(u16, Bytes)> {
+        self.outgoing
+            .retain(|_, lane| !(lane.rx.is_closed() && lane.rx.is_empty()));
+        let top = self
+            .outgoing
+            .values()
+            .filter(|lane| !lane.rx.is_empty())
+            .map(|lane| lane.priority)
+            .max()?;
+        let ready: Vec<u16> = self
+            .outgoing
+            .iter()
+            .filter(|(_, lane)| lane.priority == top && !lane.rx.is_empty())
+            .map(|(id, _)| *id)
+            .collect();
+        let id = ready
+            .iter()
+            .find(|id| Some(**id) > self.last_served)
+            .or(ready.first())
+            .copied()?;
+        self.last_served = Some(id);
+        let data = self.outgoing.get_mut(&id)?.rx.try_recv().ok()?;
+        Some((id, data))
+    }
+
+    /// Sender for incoming messages of lane `id`, creating the lane if needed
+    fn incoming(&mut self, id: u16) -> mpsc::Sender<Bytes> {
+        if let Some(tx) = self.incoming.get(&id) {
+            return tx.clone();
+        }
+        let (tx, rx) = mpsc::channel(LANE_CAPACITY);
+        self.incoming.insert(id, tx.clone());
+        self.unopened.insert(id, rx);
+        tx
+    }
+}
+
+impl Multiplexer {
+    /// Multiplex lanes over a framed sender and receiver
+    pub fn new(sender: FramedSender, receiver: FramedReceiver) -> Self {
+        let shared = Arc::new(Shared {
+            lanes: Mutex::new(Lanes::default()),
+            queued: Notify::new(),
+        });
+        let cancel = CancellationToken::new();
+        spawn_named(
+            "sfifo::mux_write",
+            write_lanes(sender, shared.clone(), cancel.clone()),
+        );
+        spawn_named(
+            "sfifo::mux_read",
+            read_lanes(receiver, shared.clone(), cancel.clone()),
+        );
+        Multiplexer { shared, cancel }
+    }
+
+    /// Open lane `id` with the default priority 0
+    pub fn open_channel(&self, id: u16) -> std::io::Result<(LaneSender, LaneReceiver)> {
+        self.open_channel_with_priority(id, 0)
+    }
+
+    /// Open lane `id`, served before lanes with a lower `priority`
+    pub fn open_channel_with_priority(
+        &self,
+        id: u16,
+        priority: u8,
+    ) -> std::io::Result<(LaneSender, LaneReceiver)> {
+        let mut lanes = self.shared.lock();
+        lanes.incoming(id);
+        let rx_incoming = lanes.unopened.remove(&id).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Lane {} is already open", id),
+            )
+        })?;
+        let (tx, rx) = mpsc::channel(LANE_CAPACITY);
+        lanes.outgoing.insert(id, Outgoing { priority, rx });
+        Ok((
+            LaneSender {
+                id,
+                tx,
+                shared: self.shared.clone(),
+            },
+            LaneReceiver {
+                id,
+                rx: rx_incoming,
+            },
+        ))
+    }
+}
+
+impl Drop for Multiplexer {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+impl std::fmt::Debug for Multiplexer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Multiplexer")
+            .field("lanes", &self.shared.lock().outgoing.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Channel {
+    /// Turn the channel into a multiplexer of several logical lanes
+    pub fn multiplex(self) -> Multiplexer {
+        let (sender, receiver) = self.split();
+        Multiplexer::new(sender, receiver)
+    }
+}
+
+/// Sending half of a multiplexed lane
+#[derive(Clone)]
+pub struct LaneSender {
+    id: u16,
+    tx: mpsc::Sender<Bytes>,
+    shared: Arc<Shared>,
+}
+
+impl LaneSender {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Queue a message on the lane, waiting while the lane is full
+    pub async fn send(&self, data: impl Into<Bytes>) -> std::io::Result<()> {
+        self.tx.send(data.into()).await.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Multiplexer closed")
+        })?;
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for LaneSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LaneSender")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Receiving half of a multiplexed lane
+#[derive(Debug)]
+pub struct LaneReceiver {
+    id: u16,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl LaneReceiver {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Receive the next message of the lane, or `None` once the peer closed
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        self.rx.recv().await
+    }
+}
+
+/// Write queued lane messages as data frames tagged with the lane id
+async fn write_lanes(mut sender: FramedSender, shared: Arc<Shared>, cancel: CancellationToken) {
+    loop {
+        let next = shared.lock().next();
+        let Some((id, data)) = next else {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = shared.queued.notified() => continue,
+            }
+        };
+        let frame = Frame {
+            tag: id,
+            ..Frame::data(data)
+        };
+        let sent = tokio::select! {
+            _ = cancel.cancelled() => return,
+            sent = sender.send_frame(frame) => sent,
+        };
+        if let Err(e) = sent {
+            warn!("Multiplexer: Failed to write to lane {}: {}", id, e);
+            return;
+        }
+    }
+}
+
+/// Dispatch incoming data frames to their lane by tag
+async fn read_lanes(mut receiver: FramedReceiver, shared: Arc<Shared>, cancel: CancellationToken) {
+    loop {
+        let frame = tokio::select! {
+            _ = cancel.cancelled() => return,
+            frame = receiver.recv_frame() => frame,
+        };
+        let frame = match frame {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Multiplexer: Failed to read lanes: {}", e);
+                break;
+            }
+        };
+        match frame.kind {
+            FrameKind::Data => {
+                let tx = shared.lock().incoming(frame.tag);
+                // A closed lane discards its messages
+                let _ = tx.send(frame.payload).await;
+            }
+            FrameKind::UserControl => receiver.handle_user_control(frame),
+            kind => debug!("Multiplexer: Ignoring {:?} frame", kind),
+        }
+    }
+    // Let the lane receivers see the end of the stream
+    shared.lock().incoming.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sfifo;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lane_scheduling() {
+        let mut lanes = Lanes::default();
+        let mut senders = Vec::new();
+        for (id, priority) in [(1, 0), (2, 0), (3, 5)] {
+            let (tx, rx) = mpsc::channel(LANE_CAPACITY);
+            lanes.outgoing.insert(id, Outgoing { priority, rx });
+            for n in 0..2 {
+                tx.try_send(Bytes::from(format!("{}-{}", id, n))).unwrap();
+            }
+            senders.push(tx);
+        }
+        let order: Vec<_> = std::iter::from_fn(|| lanes.next())
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(order, ["3-0", "3-1", "1-0", "2-0", "1-1", "2-1"]);
+
+        // Lanes whose sender is gone are dropped once drained
+        drop(senders);
+        assert!(lanes.next().is_none());
+        assert!(lanes.outgoing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_lanes() {
+        let fifo_path = "/tmp/test_mux_lanes";
+        let token = "mux_token";
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_cleanup_on_drop(true)
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+
+        // Echo each lane back on the same lane
+        let server = tokio::spawn(async move {
+            let mux = Channel::accept(&server_config, token).await?.multiplex();
+            let mut echoes = Vec::new();
+            for id in [1, 2] {
+                let (tx, mut rx) = mux.open_channel(id)?;
+                echoes.push(tokio::spawn(async move {
+                    while let Some(data) = rx.recv().await {
+                        tx.send(data).await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                }));
+            }
+            for echo in echoes {
+                echo.await.unwrap()?;
+            }
+            Ok::<_, std::io::Error>(())
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mux = Channel::connect(&client_config, token)
+            .await
+            .unwrap()
+            .multiplex();
+        let (control_tx, mut control_rx) = mux.open_channel_with_priority(1, 10).unwrap();
+        let (data_tx, mut data_rx) = mux.open_channel(2).unwrap();
+        assert!(mux.open_channel(2).is_err());
+
+        for n in 0..3 {
+            data_tx.send(format!("data {}", n)).await.unwrap();
+        }
+        control_tx.send("stop").await.unwrap();
+        assert_eq!(control_rx.recv().await.unwrap(), "stop");
+        for n in 0..3 {
+            assert_eq!(data_rx.recv().await.unwrap(), format!("data {}", n));
+        }
+        drop(mux);
+
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{
+    extension::{EXT_NONCE, EXT_NONCE_ECHO},
+    platform::random_bytes,
+    Extension, HandshakeMessage,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Size of handshake nonces
+pub const NONCE_LEN: usize = 16;
+
+// Longer than the 30 second handshake validity window, with room for
+// clock skew between the peers
+const NONCE_TTL: Duration = Duration::from_secs(60);
+
+pub(crate) type Nonce = [u8; NONCE_LEN];
+
+/// Fresh random nonce, failing rather than returning a predictable one
+pub(crate) fn new_nonce() -> std::io::Result<Nonce> {
+    let mut nonce = [0u8; NONCE_LEN];
+    random_bytes(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// Nonces seen in recent handshakes, shared by every open using the same
+/// configuration. A handshake reusing a nonce is rejected as a replay.
+#[derive(Clone, Default)]
+pub struct NonceCache(Arc<Mutex<HashMap<Nonce, Instant>>>);
+
+impl NonceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `nonce`, returning false if it was already seen recently
+    pub fn insert(&self, nonce: &[u8; NONCE_LEN]) -> bool {
+        let mut seen = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        seen.retain(|_, at| now.duration_since(*at) < NONCE_TTL);
+        seen.insert(*nonce, now).is_none()
+    }
+}
+
+impl std::fmt::Debug for NonceCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NonceCache").finish_non_exhaustive()
+    }
+}
+
+impl HandshakeMessage {
+    /// Nonce chosen by the sender of the message
+    pub fn nonce(&self) -> Option<[u8; NONCE_LEN]> {
+        self.extension(EXT_NONCE)?.try_into().ok()
+    }
+
+    /// Nonce of the peer's previous message, echoed back
+    pub fn nonce_echo(&self) -> Option<[u8; NONCE_LEN]> {
+        self.extension(EXT_NONCE_ECHO)?.try_into().ok()
+    }
+
+    /// Add the nonce chosen for this message
+    pub(crate) fn with_nonce(self, nonce: &Nonce) -> Self {
+        self.with_extension(Extension::new(EXT_NONCE, nonce.to_vec()))
+    }
+
+    /// Echo the nonce of the peer's previous message
+    pub(crate) fn with_nonce_echo(self, echo: &Nonce) -> Self {
+        self.with_extension(Extension::new(EXT_NONCE_ECHO, echo.to_vec()))
+    }
+
+    /// Check that the message echoes `nonce`
+    pub(crate) fn check_nonce_echo(&self, nonce: &Nonce) -> std::io::Result<()> {
+        if self.nonce_echo().as_ref() != Some(nonce) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Handshake nonce not echoed, possible replay",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the peer's nonce, rejecting it if the cache has seen it before
+    pub(crate) fn fresh_nonce(&self, cache: Option<&NonceCache>) -> std::io::Result<Nonce> {
+        let nonce = self.nonce().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Handshake message without nonce",
+            )
+        })?;
+        if cache.is_some_and(|cache| !cache.insert(&nonce)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Replayed handshake nonce",
+            ));
+        }
+        Ok(nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HandshakeType;
+
+    #[test]
+    fn test_nonce_echo_and_cache() {
+        let client_nonce = new_nonce().unwrap();
+        let request = HandshakeMessage::new(String::new(), HandshakeType::Request)
+            .unwrap()
+            .with_nonce(&client_nonce);
+
+        let cache = NonceCache::new();
+        assert_eq!(request.fresh_nonce(Some(&cache)).unwrap(), client_nonce);
+        // The same request replayed within the window
+        assert!(request.fresh_nonce(Some(&cache)).is_err());
+        assert!(request.fresh_nonce(None).is_ok());
+
+        let response = HandshakeMessage::new(String::new(), HandshakeType::Response)
+            .unwrap()
+            .with_nonce(&new_nonce().unwrap())
+            .with_nonce_echo(&client_nonce);
+        assert!(response.check_nonce_echo(&client_nonce).is_ok());
+        assert!(response.check_nonce_echo(&new_nonce().unwrap()).is_err());
+        assert!(request.check_nonce_echo(&client_nonce).is_err());
+    }
+}
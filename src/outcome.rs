@@ -0,0 +1,148 @@
+use crate::{health::pipe_connected, metrics, AuthenticatedFifo, PipeEnd, Sfifo, DEFAULT_TIMEOUT};
+use std::{os::fd::AsRawFd, time::Duration};
+use tokio::{net::unix::pipe::Receiver, time::Instant};
+
+// How often a FIFO without writers is checked for one, the reactor does
+// not report a writer attaching to an empty FIFO
+const NO_WRITER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a `read_result()` call found in the FIFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadOutcome {
+    /// This many bytes were read
+    Data(usize),
+    /// A writer had the FIFO open, and every writer has closed it since
+    WriterClosed,
+    /// No writer has opened the FIFO since the receiver did, until the
+    /// timeout
+    NoWriterYet,
+    /// A writer has the FIFO open but wrote nothing until the timeout
+    TimedOut,
+}
+
+/// Read once from `receiver`, telling apart why nothing was read
+///
+/// A plain read returns 0 both when the writers closed the FIFO and when
+/// none ever opened it, and never wakes up in the latter case. Linux only
+/// reports a hang-up once a writer has been attached, which separates the
+/// two.
+pub(crate) async fn read_outcome(
+    receiver: &Receiver,
+    buf: &mut [u8],
+    timeout: Duration,
+) -> std::io::Result<ReadOutcome> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        // Read the fd directly, the reactor never reports a FIFO without
+        // writers as readable
+        let writer_attached = match nix::unistd::read(receiver.as_raw_fd(), buf) {
+            Ok(0) if !buf.is_empty() => {
+                if !pipe_connected(receiver.as_raw_fd(), false) {
+                    return Ok(ReadOutcome::WriterClosed);
+                }
+                false
+            }
+            Ok(n) => return Ok(ReadOutcome::Data(n)),
+            Err(nix::errno::Errno::EAGAIN) => true,
+            Err(e) => return Err(e.into()),
+        };
+        if Instant::now() >= deadline {
+            return Ok(if writer_attached {
+                ReadOutcome::TimedOut
+            } else {
+                ReadOutcome::NoWriterYet
+            });
+        }
+        if !writer_attached {
+            tokio::time::sleep_until(deadline.min(Instant::now() + NO_WRITER_POLL_INTERVAL)).await;
+            continue;
+        }
+        // Reading through the receiver clears a stale readiness
+        if let Ok(ready) = tokio::time::timeout_at(deadline, receiver.readable()).await {
+            ready?;
+            match receiver.try_read(buf) {
+                Ok(n) if n > 0 => return Ok(ReadOutcome::Data(n)),
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Sfifo {
+    /// Read once from `receiver`, waiting up to the configured `timeout`
+    /// for data, and report whether nothing was read because the writers
+    /// closed the FIFO, none opened it yet, or none wrote in time
+    pub async fn read_result(
+        &self,
+        receiver: &Receiver,
+        buf: &mut [u8],
+    ) -> std::io::Result<ReadOutcome> {
+        read_outcome(receiver, buf, self.timeout).await
+    }
+}
+
+impl AuthenticatedFifo {
+    /// Read once, like `read()`, but report why nothing was read instead
+    /// of returning 0 or waiting forever - only works for Receiver
+    ///
+    /// Waits up to the `timeout` of the configuration the FIFO was opened
+    /// with. Unlike `read()`, does not reopen a recreated FIFO with
+    /// keepalive.
+    pub async fn read_result(&mut self, buf: &mut [u8]) -> std::io::Result<ReadOutcome> {
+        if !self.line_buf.is_empty() {
+            return Ok(ReadOutcome::Data(self.take_buffered(buf)));
+        }
+        let PipeEnd::Receiver(receiver) = &self.end else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot read from sender FIFO",
+            ));
+        };
+        let timeout = self.config.as_ref().map_or(DEFAULT_TIMEOUT, |c| c.timeout);
+        let outcome = read_outcome(receiver, buf, timeout).await?;
+        if let ReadOutcome::Data(n) = outcome {
+            self.touch_idle();
+            metrics::emit(self.metrics(), |m| m.bytes_read(n));
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_result() {
+        let fifo_path = "/tmp/test_read_result";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_timeout(Duration::from_millis(100))
+            .clone();
+        let receiver = config.open_receiver().await.unwrap();
+        let mut buf = [0u8; 16];
+
+        let started = Instant::now();
+        let outcome = config.read_result(&receiver, &mut buf).await.unwrap();
+        assert_eq!(outcome, ReadOutcome::NoWriterYet);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        let sender = config.open_sender().await.unwrap();
+        let outcome = config.read_result(&receiver, &mut buf).await.unwrap();
+        assert_eq!(outcome, ReadOutcome::TimedOut);
+
+        sender.try_write(b"hi").unwrap();
+        let outcome = config.read_result(&receiver, &mut buf).await.unwrap();
+        assert_eq!(outcome, ReadOutcome::Data(2));
+        assert_eq!(&buf[..2], b"hi");
+
+        drop(sender);
+        let outcome = config.read_result(&receiver, &mut buf).await.unwrap();
+        assert_eq!(outcome, ReadOutcome::WriterClosed);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{create_fifo, handshake_path, signal::Tracked};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+};
+
+// Paths owned by live guards and how many guards own each, removed by the
+// panic hook as a last resort
+static REGISTRY: Mutex<Option<HashMap<PathBuf, usize>>> = Mutex::new(None);
+static PANIC_HOOK: Once = Once::new();
+
+/// RAII guard for a FIFO created by this process.
+///
+/// When the last guard of a path is dropped, the FIFO file is unlinked
+/// together with its `.c2s`/`.s2c` handshake pair and the `.rev` FIFO of a
+/// duplex channel. See `OwnedFifo::remove_on_panic` to also remove the
+/// files when the process panics before the guards are dropped.
+#[derive(Debug)]
+pub struct OwnedFifo {
+    file_path: PathBuf,
+    // Also removed by a shutdown signal, see `Sfifo::with_signal_shutdown`
+    signal: Option<Tracked>,
+}
+
+impl OwnedFifo {
+    /// Creates the FIFO at `file_path` (if it does not exist yet) and takes
+    /// ownership of it.
+    pub async fn create(file_path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        create_fifo(file_path.as_ref()).await?;
+        Ok(Self::adopt(file_path))
+    }
+
+    /// Takes ownership of an existing FIFO path without creating it.
+    pub fn adopt(file_path: impl AsRef<Path>) -> Self {
+        let file_path = file_path.as_ref().to_path_buf();
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        *registry
+            .get_or_insert_with(HashMap::new)
+            .entry(file_path.clone())
+            .or_default() += 1;
+        OwnedFifo {
+            file_path,
+            signal: None,
+        }
+    }
+
+    /// Have a shutdown signal remove the files while the guard lives
+    pub(crate) fn removed_on_signal(mut self, tracked: Option<Tracked>) -> Self {
+        self.signal = tracked;
+        self
+    }
+
+    /// Install a panic hook removing the files of every live guard, on a
+    /// best-effort basis, before the previous hook runs
+    ///
+    /// Opt-in because the hook runs for every panic of the process, caught
+    /// ones and panics of tokio tasks included, which do not end the
+    /// process. Installed once, later calls do nothing.
+    pub fn remove_on_panic() {
+        PANIC_HOOK.call_once(|| {
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                remove_registered();
+                previous(info);
+            }));
+        });
+    }
+
+    /// Returns the path of the owned FIFO.
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    /// Gives up ownership, leaving the FIFO files in place.
+    pub fn release(mut self) -> PathBuf {
+        unregister(&self.file_path);
+        self.signal = None;
+        let file_path = std::mem::take(&mut self.file_path);
+        std::mem::forget(self);
+        file_path
+    }
+}
+
+impl Drop for OwnedFifo {
+    fn drop(&mut self) {
+        if unregister(&self.file_path) {
+            remove_fifo_files(&self.file_path);
+        }
+    }
+}
+
+/// Drop one ownership of `file_path`, returning whether it was the last
+fn unregister(file_path: &Path) -> bool {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(paths) = registry.as_mut() else {
+        return true;
+    };
+    match paths.get_mut(file_path) {
+        Some(count) if *count > 1 => {
+            *count -= 1;
+            false
+        }
+        _ => {
+            paths.remove(file_path);
+            true
+        }
+    }
+}
+
+/// Removes a FIFO and its companion FIFOs, ignoring files that do not exist.
+pub(crate) fn remove_fifo_files(file_path: &Path) {
+    for path in [
+        file_path.to_path_buf(),
+        handshake_path(file_path, "c2s"),
+        handshake_path(file_path, "s2c"),
+        handshake_path(file_path, "rev"),
+        handshake_path(file_path, "hs"),
+    ] {
+        if std::fs::remove_file(&path).is_ok() {
+            debug!("Removed FIFO {:?}", path);
+        }
+    }
+}
+
+/// Remove the files of every live guard, as a last resort before the
+/// process goes away
+fn remove_registered() {
+    // Never block, this runs inside the panic hook: skip cleanup if the
+    // lock is busy
+    if let Ok(registry) = REGISTRY.try_lock() {
+        if let Some(paths) = registry.as_ref() {
+            paths.keys().for_each(|path| remove_fifo_files(path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_owned_fifo_removes_files_on_drop() {
+        let fifo_path = "/tmp/test_owned_fifo";
+        let guard = OwnedFifo::create(fifo_path).await.unwrap();
+        create_fifo(handshake_path(fifo_path, "c2s")).await.unwrap();
+        create_fifo(handshake_path(fifo_path, "s2c")).await.unwrap();
+        assert!(Path::new(fifo_path).exists());
+
+        drop(guard);
+        assert!(!Path::new(fifo_path).exists());
+        assert!(!handshake_path(fifo_path, "c2s").exists());
+        assert!(!handshake_path(fifo_path, "s2c").exists());
+    }
+
+    #[tokio::test]
+    async fn test_owned_fifo_shared_path() {
+        let fifo_path = "/tmp/test_owned_fifo_shared";
+        let first = OwnedFifo::create(fifo_path).await.unwrap();
+        let second = OwnedFifo::adopt(fifo_path);
+
+        // The files stay until the last guard goes
+        drop(first);
+        assert!(Path::new(fifo_path).exists());
+        drop(second);
+        assert!(!Path::new(fifo_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_owned_fifo_release_keeps_files() {
+        let fifo_path = "/tmp/test_owned_fifo_release";
+        let guard = OwnedFifo::create(fifo_path).await.unwrap();
+        let path = guard.release();
+        assert!(path.exists());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{handshake_path, Extension, HandshakeMessage, Sfifo, EXT_KERNEL_CRED};
+use std::{
+    os::unix::{ffi::OsStrExt, fs::FileTypeExt},
+    path::PathBuf,
+};
+use tokio::net::{unix::UCred, UnixListener, UnixStream};
+use tokio_util::sync::CancellationToken;
+
+/// Credentials of the peer process as reported by the kernel
+/// (`SO_PEERCRED`), set in `peer_info()` once checked against the PID the
+/// peer claimed in its handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelPeerCred {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl KernelPeerCred {
+    /// Credentials of a socket peer, which must be the process `claimed_pid`
+    pub(crate) fn verify(cred: UCred, claimed_pid: u32) -> std::io::Result<Self> {
+        match cred.pid() {
+            Some(pid) if pid as u32 == claimed_pid => Ok(KernelPeerCred {
+                pid: claimed_pid,
+                uid: cred.uid(),
+                gid: cred.gid(),
+            }),
+            pid => Err(denied(format!(
+                "Peer claims PID {} but the kernel reports PID {:?}",
+                claimed_pid, pid
+            ))),
+        }
+    }
+}
+
+fn denied(reason: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::PermissionDenied, reason)
+}
+
+/// Socket the server listens on for one handshake, so both sides can read
+/// the other's credentials from the kernel. Removed when dropped
+pub(crate) struct CredSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl CredSocket {
+    /// Extension telling the client where to connect
+    pub(crate) fn extension(&self) -> Extension {
+        Extension::new(EXT_KERNEL_CRED, self.path.as_os_str().as_bytes())
+    }
+
+    /// Accept the client and check it is the process `claimed_pid`
+    pub(crate) async fn accept(
+        self,
+        claimed_pid: u32,
+        cancel_token: &CancellationToken,
+    ) -> std::io::Result<KernelPeerCred> {
+        let (stream, _) = tokio::select! {
+            accepted = self.listener.accept() => accepted?,
+            _ = cancel_token.cancelled() => {
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Handshake timeout"));
+            }
+        };
+        KernelPeerCred::verify(stream.peer_cred()?, claimed_pid)
+    }
+}
+
+impl Drop for CredSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Sfifo {
+    /// Add the offer to exchange kernel credentials to a client request,
+    /// if enabled
+    pub(crate) fn offer_kernel_cred(&self, request: HandshakeMessage) -> HandshakeMessage {
+        if !self.kernel_peer_cred {
+            return request;
+        }
+        request.with_extension(Extension::new(EXT_KERNEL_CRED, Vec::new()))
+    }
+
+    /// Listen for the client of `request` to exchange kernel credentials,
+    /// if enabled
+    pub(crate) fn bind_cred_socket(
+        &self,
+        request: &HandshakeMessage,
+    ) -> std::io::Result<Option<CredSocket>> {
+        if !self.kernel_peer_cred {
+            return Ok(None);
+        }
+        if request.extension(EXT_KERNEL_CRED).is_none() {
+            return Err(denied(
+                "Client does not support the kernel credential exchange".to_string(),
+            ));
+        }
+        let path = handshake_path(&self.file_path, "cred");
+        // Left behind by a server that died during a handshake
+        if std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        Ok(Some(CredSocket { listener, path }))
+    }
+
+    /// Connect to the socket named in the server `response` and check the
+    /// server is the process it claims to be, if enabled
+    pub(crate) async fn verify_server_cred(
+        &self,
+        response: &mut HandshakeMessage,
+    ) -> std::io::Result<()> {
+        if !self.kernel_peer_cred {
+            return Ok(());
+        }
+        let path = response
+            .extension(EXT_KERNEL_CRED)
+            .map(|path| PathBuf::from(std::ffi::OsStr::from_bytes(path)))
+            .ok_or_else(|| {
+                denied("Server does not support the kernel credential exchange".to_string())
+            })?;
+        if path != handshake_path(&self.file_path, "cred") {
+            return Err(denied(format!(
+                "Server named an unexpected credential socket {:?}",
+                path
+            )));
+        }
+        let stream = UnixStream::connect(&path).await?;
+        response.kernel_cred = Some(KernelPeerCred::verify(
+            stream.peer_cred()?,
+            response.process_id,
+        )?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_kernel_peer_cred_exchange() {
+        let fifo_path = "/tmp/test_kernel_peer_cred";
+        let token = "cred_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_kernel_peer_cred(true)
+            .clone();
+        let expected = Some(KernelPeerCred {
+            pid: std::process::id(),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        });
+
+        for inband in [false, true] {
+            let config = config.clone().set_inband_handshake(inband).clone();
+            let server_config = config.clone();
+            let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let client = config.open_as_client(token).await.unwrap();
+            let server = server.await.unwrap().unwrap();
+            assert_eq!(client.peer_info().kernel_cred, expected);
+            assert_eq!(server.peer_info().kernel_cred, expected);
+            assert!(!handshake_path(fifo_path, "cred").exists());
+        }
+
+        // A client without the exchange is refused
+        let server_config = config.clone();
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client = tokio::spawn(async move { Sfifo::new(fifo_path).open_as_client(token).await });
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        client.abort();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{metrics, AuthenticatedFifo, Sfifo};
+use std::{
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::unix::pipe::{Receiver, Sender},
+};
+use tokio_util::codec::Framed;
+
+/// Either end of a FIFO.
+///
+/// Implements `AsyncRead` and `AsyncWrite` so it can be wrapped with any
+/// tokio-util codec; the operation not supported by the end fails with
+/// `InvalidInput`.
+#[derive(Debug)]
+pub enum PipeEnd {
+    Sender(Sender),
+    Receiver(Receiver),
+}
+
+pub(crate) fn wrong_direction(message: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+/// Open the FIFO at `path` for reading without waiting for a writer, such
+/// that the receiver never sees end-of-file while writers come and go
+///
+/// Linux opens the receiver `O_RDWR`. Elsewhere, where that is undefined,
+/// a write end is opened as well, which the caller has to keep open as
+/// long as the receiver.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn open_held_receiver(path: &Path) -> std::io::Result<(Receiver, Option<Sender>)> {
+    let receiver = tokio::net::unix::pipe::OpenOptions::new()
+        .read_write(true)
+        .open_receiver(path)?;
+    Ok((receiver, None))
+}
+
+/// Open the FIFO at `path` for reading without waiting for a writer, such
+/// that the receiver never sees end-of-file while writers come and go
+///
+/// Linux opens the receiver `O_RDWR`. Elsewhere, where that is undefined,
+/// a write end is opened as well, which the caller has to keep open as
+/// long as the receiver.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn open_held_receiver(path: &Path) -> std::io::Result<(Receiver, Option<Sender>)> {
+    let receiver = tokio::net::unix::pipe::OpenOptions::new().open_receiver(path)?;
+    // Cannot fail with ENXIO, the FIFO has a reader now
+    let sender = tokio::net::unix::pipe::OpenOptions::new().open_sender(path)?;
+    Ok((receiver, Some(sender)))
+}
+
+impl AsFd for PipeEnd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            PipeEnd::Sender(inner) => inner.as_fd(),
+            PipeEnd::Receiver(inner) => inner.as_fd(),
+        }
+    }
+}
+
+impl AsRawFd for PipeEnd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+impl PipeEnd {
+    /// Wrap a FIFO descriptor, as a sender if it was opened write-only and
+    /// as a receiver if it was opened read-only
+    ///
+    /// The descriptor is switched to non-blocking mode. Descriptors opened
+    /// read-write are ambiguous, wrap them with `Sender::from_owned_fd` or
+    /// `Receiver::from_owned_fd` instead.
+    pub fn from_owned_fd(fd: OwnedFd) -> std::io::Result<PipeEnd> {
+        let flags = nix::fcntl::OFlag::from_bits_truncate(nix::fcntl::fcntl(
+            fd.as_raw_fd(),
+            nix::fcntl::FcntlArg::F_GETFL,
+        )?);
+        match flags & nix::fcntl::OFlag::O_ACCMODE {
+            nix::fcntl::OFlag::O_WRONLY => Ok(PipeEnd::Sender(Sender::from_owned_fd(fd)?)),
+            nix::fcntl::OFlag::O_RDONLY => Ok(PipeEnd::Receiver(Receiver::from_owned_fd(fd)?)),
+            _ => Err(wrong_direction(
+                "Cannot tell the direction of a read-write FIFO descriptor",
+            )),
+        }
+    }
+
+    /// Deregister the pipe from the runtime and return its descriptor,
+    /// left in non-blocking mode
+    pub fn into_owned_fd(self) -> std::io::Result<OwnedFd> {
+        match self {
+            PipeEnd::Sender(inner) => inner.into_nonblocking_fd(),
+            PipeEnd::Receiver(inner) => inner.into_nonblocking_fd(),
+        }
+    }
+}
+
+impl AsyncRead for PipeEnd {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            PipeEnd::Receiver(inner) => Pin::new(inner).poll_read(cx, buf),
+            PipeEnd::Sender(_) => Poll::Ready(Err(wrong_direction("Cannot read from sender FIFO"))),
+        }
+    }
+}
+
+impl AsyncWrite for PipeEnd {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            PipeEnd::Sender(inner) => Pin::new(inner).poll_write(cx, buf),
+            PipeEnd::Receiver(_) => {
+                Poll::Ready(Err(wrong_direction("Cannot write to receiver FIFO")))
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            PipeEnd::Sender(inner) => Pin::new(inner).poll_flush(cx),
+            PipeEnd::Receiver(_) => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            PipeEnd::Sender(inner) => Pin::new(inner).poll_shutdown(cx),
+            PipeEnd::Receiver(_) => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/// Reads and writes go straight to the pipe, after any bytes buffered by
+/// `read_line` and within the rate limit; keepalive reopening only
+/// applies to the async `read`/`write` methods.
+impl AsyncRead for AuthenticatedFifo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if !this.line_buf.is_empty() {
+            let n = this.take_buffered(buf.initialize_unfilled());
+            buf.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+        // An expired idle timeout wins over data arriving late
+        if let Poll::Ready(error) = this.poll_idle(cx) {
+            return Poll::Ready(Err(error));
+        }
+        let filled = buf.filled().len();
+        let res = ready!(Pin::new(&mut this.end).poll_read(cx, buf));
+        let n = buf.filled().len() - filled;
+        this.touch_idle();
+        metrics::emit(this.metrics(), |m| m.bytes_read(n));
+        Poll::Ready(res)
+    }
+}
+
+impl AsyncWrite for AuthenticatedFifo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let buf = match this.rate_limit.as_mut() {
+            Some(bucket) => {
+                let buf = &buf[..buf.len().min(bucket.burst())];
+                ready!(bucket.poll_acquire(cx, buf.len()));
+                buf
+            }
+            None => buf,
+        };
+        if let Poll::Ready(error) = this.poll_idle(cx) {
+            return Poll::Ready(Err(error));
+        }
+        let res = ready!(Pin::new(&mut this.end).poll_write(cx, buf));
+        if let Ok(n) = res {
+            this.touch_idle();
+            if let Some(bucket) = this.rate_limit.as_mut() {
+                bucket.consume(n);
+            }
+            metrics::emit(this.metrics(), |m| m.bytes_written(n));
+        }
+        Poll::Ready(res)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().end).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().end).poll_shutdown(cx)
+    }
+}
+
+impl AuthenticatedFifo {
+    /// Wrap the authenticated FIFO with a tokio-util codec
+    pub fn framed<C>(self, codec: C) -> Framed<AuthenticatedFifo, C> {
+        Framed::new(self, codec)
+    }
+
+    /// Get the underlying pipe end
+    pub fn into_pipe_end(self) -> PipeEnd {
+        self.end
+    }
+
+    /// Deregister the pipe from the runtime and return its descriptor,
+    /// left in non-blocking mode
+    pub fn into_owned_fd(self) -> std::io::Result<OwnedFd> {
+        self.end.into_owned_fd()
+    }
+}
+
+impl AsFd for AuthenticatedFifo {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.end.as_fd()
+    }
+}
+
+impl AsRawFd for AuthenticatedFifo {
+    fn as_raw_fd(&self) -> RawFd {
+        self.end.as_raw_fd()
+    }
+}
+
+impl Sfifo {
+    /// Opens the receiver if `read` is set, the sender otherwise
+    pub async fn open_end(&self) -> Result<PipeEnd, std::io::Error> {
+        if self.read {
+            Ok(PipeEnd::Receiver(self.open_receiver().await?))
+        } else {
+            Ok(PipeEnd::Sender(self.open_sender().await?))
+        }
+    }
+
+    /// Opens the FIFO (see `open_end`) wrapped with a tokio-util codec such
+    /// as `LinesCodec` or `LengthDelimitedCodec`
+    pub async fn open_framed<C>(&self, codec: C) -> Result<Framed<PipeEnd, C>, std::io::Error> {
+        Ok(Framed::new(self.open_end().await?, codec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_fifo;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::{LengthDelimitedCodec, LinesCodec};
+
+    #[tokio::test]
+    async fn test_open_framed_lines() {
+        let fifo_path = "/tmp/test_open_framed_lines";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let mut reader = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_read(true)
+            .open_framed(LinesCodec::new())
+            .await
+            .unwrap();
+        let mut writer = Sfifo::new(fifo_path)
+            .set_write(true)
+            .open_framed(LinesCodec::new())
+            .await
+            .unwrap();
+
+        writer.send("first line").await.unwrap();
+        writer.send("second line").await.unwrap();
+        drop(writer);
+
+        assert_eq!(reader.next().await.unwrap().unwrap(), "first line");
+        assert_eq!(reader.next().await.unwrap().unwrap(), "second line");
+        assert!(reader.next().await.is_none());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_framed_wrong_direction() {
+        let fifo_path = "/tmp/test_framed_wrong_direction";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut reader = Sfifo::new(fifo_path)
+            .set_read(true)
+            .open_framed(LengthDelimitedCodec::new())
+            .await
+            .unwrap();
+        let error = reader.send(bytes::Bytes::from("x")).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_owned_fd_round_trip() {
+        let fifo_path = "/tmp/test_owned_fd_round_trip";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let reader = Sfifo::new(fifo_path)
+            .set_read(true)
+            .open_end()
+            .await
+            .unwrap();
+        let writer = Sfifo::new(fifo_path).open_end().await.unwrap();
+        let raw = writer.as_raw_fd();
+        let fd = writer.into_owned_fd().unwrap();
+        assert_eq!(fd.as_raw_fd(), raw);
+
+        let mut writer = PipeEnd::from_owned_fd(fd).unwrap();
+        assert!(matches!(writer, PipeEnd::Sender(_)));
+        let fd = reader.into_owned_fd().unwrap();
+        let mut reader = PipeEnd::from_owned_fd(fd).unwrap();
+        assert!(matches!(reader, PipeEnd::Receiver(_)));
+
+        writer.write_all(b"rewrapped").await.unwrap();
+        let mut buf = [0u8; 9];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"rewrapped");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
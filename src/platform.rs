@@ -0,0 +1,155 @@
+//! Operating system specific lookups, with a Linux implementation based
+//! on `/proc` and `fcntl`, and macOS/BSD ones based on libproc and libc.
+//!
+//! Deletion notifications need nothing platform specific: `FifoWatcher`
+//! polls the path, because kqueue would have to open the FIFO and would
+//! then count as one of its readers.
+
+use std::os::fd::AsRawFd;
+
+/// Pipe buffer size assumed where it cannot be queried (BSD `PIPE_SIZE`)
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const DEFAULT_PIPE_CAPACITY: usize = 16 * 1024;
+
+/// Name of the current process
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn process_name() -> Option<String> {
+    std::fs::read_to_string("/proc/self/comm")
+        .ok()
+        .map(|name| name.trim().to_string())
+}
+
+/// Name of the current process
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(crate) fn process_name() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let pid = std::process::id() as libc::c_int;
+    let len = unsafe { libc::proc_name(pid, buf.as_mut_ptr().cast(), buf.len() as u32) };
+    if len <= 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf[..len as usize]).into_owned())
+}
+
+/// Name of the current process
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn process_name() -> Option<String> {
+    let name = unsafe { libc::getprogname() };
+    if name.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Name of the current process
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn process_name() -> Option<String> {
+    None
+}
+
+/// Capacity of the pipe buffer behind `fd`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn pipe_capacity(fd: &impl AsRawFd) -> std::io::Result<usize> {
+    let capacity = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETPIPE_SZ) };
+    if capacity < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(capacity as usize)
+}
+
+/// Capacity of the pipe buffer behind `fd`, the platform default size
+/// since it cannot be queried
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn pipe_capacity(_fd: &impl AsRawFd) -> std::io::Result<usize> {
+    Ok(DEFAULT_PIPE_CAPACITY)
+}
+
+/// Fill `buf` from the system random source, retrying interrupted and
+/// short reads
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub(crate) fn random_bytes(buf: &mut [u8]) -> std::io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let rest = &mut buf[filled..];
+        let n = unsafe { libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        if n < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        filled += n as usize;
+    }
+    Ok(())
+}
+
+/// Fill `buf` from the system random source
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn random_bytes(buf: &mut [u8]) -> std::io::Result<()> {
+    unsafe { libc::arc4random_buf(buf.as_mut_ptr().cast(), buf.len()) };
+    Ok(())
+}
+
+/// Fill `buf` from the system random source, which this platform lacks
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn random_bytes(_buf: &mut [u8]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "No system random source",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_name() {
+        let name = process_name().unwrap();
+        assert!(!name.is_empty());
+        let exe = std::env::current_exe().unwrap();
+        let exe = exe.file_name().unwrap().to_string_lossy();
+        // Linux truncates the name to 15 bytes
+        assert!(exe.starts_with(&name));
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        random_bytes(&mut a).unwrap();
+        random_bytes(&mut b).unwrap();
+        assert_ne!(a, b);
+    }
+}
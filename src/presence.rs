@@ -0,0 +1,227 @@
+use crate::{
+    procfs::{fifo_openers, scan_openers, FifoOpener},
+    task::spawn_named,
+    FifoWatcher, Sfifo,
+};
+use std::{os::unix::fs::FileTypeExt, time::Duration};
+use tokio::{net::unix::pipe::Receiver, sync::mpsc};
+
+// How often the writer count is sampled while waiting for a writer
+const WRITER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether a process has a FIFO open on some end, see `Sfifo::has_reader`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    /// Some process has it open
+    Present,
+    /// No process has it open, or the FIFO does not exist
+    Absent,
+    /// None was found, but some processes could not be inspected
+    Unknown,
+}
+
+/// Transition of the number of writers attached to a FIFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriterEvent {
+    /// At least one writer has the FIFO open (estimated count)
+    WritersAttached(usize),
+    /// No writer has the FIFO open
+    NoWriters,
+}
+
+impl Sfifo {
+    /// Estimates how many file descriptors have the FIFO open for writing
+    ///
+    /// The estimate comes from scanning `/proc`, so writers belonging to
+    /// processes this one cannot inspect are not counted. Descriptors
+    /// opened read-write count as writers.
+    pub fn writer_count_estimate(&self) -> std::io::Result<usize> {
+        Ok(fifo_openers(&self.file_path)?
+            .iter()
+            .filter(|o| o.write)
+            .count())
+    }
+
+    /// Checks whether some process has the FIFO open for reading, so that
+    /// `open_sender()` would not wait
+    ///
+    /// Looks in `/proc` only and never opens the FIFO, which peers could
+    /// notice. `Unknown` if no reader was found but some processes cannot
+    /// be inspected, or `/proc` is not available. `Absent` if the FIFO does
+    /// not exist.
+    pub fn has_reader(&self) -> std::io::Result<Presence> {
+        self.presence(|o| o.read)
+    }
+
+    /// Checks whether some process has the FIFO open for writing, from
+    /// `/proc` like `has_reader()`
+    pub fn has_writer(&self) -> std::io::Result<Presence> {
+        self.presence(|o| o.write)
+    }
+
+    fn presence(&self, end: impl Fn(&FifoOpener) -> bool) -> std::io::Result<Presence> {
+        if !self.fifo_exists()? {
+            return Ok(Presence::Absent);
+        }
+        Ok(match scan_openers(&self.file_path) {
+            Ok((openers, _)) if openers.iter().any(end) => Presence::Present,
+            Ok((_, true)) => Presence::Absent,
+            Ok((_, false)) => Presence::Unknown,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Presence::Unknown,
+            Err(e) => return Err(e),
+        })
+    }
+
+    /// Whether the path exists, failing if it is not a FIFO
+    fn fifo_exists(&self) -> std::io::Result<bool> {
+        match std::fs::metadata(&self.file_path) {
+            Ok(m) if m.file_type().is_fifo() => Ok(true),
+            Ok(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{:?} is not a FIFO", self.file_path),
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Waits until a writer has the FIFO of `receiver` open
+    ///
+    /// A writer is detected when data or end-of-file becomes readable, or
+    /// by a `FifoWatcher`. Like `open_sender()` waiting for a reader, this
+    /// fails with `TimedOut` after `timeout`, or when `notify` is set,
+    /// waits until the FIFO file is deleted.
+    pub async fn wait_for_peer(&self, receiver: &Receiver) -> std::io::Result<()> {
+        self.open_with_policy(|_| async {
+            self.writer_attached(receiver).await;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Resolves once a writer has the FIFO of `receiver` open
+    pub(crate) async fn writer_attached(&self, receiver: &Receiver) {
+        let mut watcher = FifoWatcher::new(&self.file_path, WRITER_POLL_INTERVAL);
+        tokio::select! {
+            _ = receiver.readable() => {}
+            _ = watcher.writer_attached() => {}
+        }
+    }
+
+    /// Watches the writer count, emitting an event for the initial state
+    /// and every time it transitions between zero and non-zero
+    ///
+    /// The count is sampled every `interval`; the background task stops as
+    /// soon as the returned receiver is dropped.
+    pub fn watch_writers(&self, interval: Duration) -> mpsc::Receiver<WriterEvent> {
+        let (tx, rx) = mpsc::channel(16);
+        let config = self.clone();
+        spawn_named("sfifo::watch_writers", async move {
+            let mut previous: Option<bool> = None;
+            loop {
+                let count = config.writer_count_estimate().unwrap_or(0);
+                if previous != Some(count > 0) {
+                    previous = Some(count > 0);
+                    let event = if count > 0 {
+                        WriterEvent::WritersAttached(count)
+                    } else {
+                        WriterEvent::NoWriters
+                    };
+                    if tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                tokio::select! {
+                    _ = tx.closed() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+            }
+        });
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_fifo;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_writer_count_transitions() {
+        let fifo_path = "/tmp/test_writer_count";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let config = Sfifo::new(fifo_path);
+        let _receiver = config.open_receiver().await.unwrap();
+        assert_eq!(config.writer_count_estimate().unwrap(), 0);
+
+        let mut events = config.watch_writers(Duration::from_millis(20));
+        assert_eq!(events.recv().await, Some(WriterEvent::NoWriters));
+
+        let sender = config.open_sender().await.unwrap();
+        assert_eq!(config.writer_count_estimate().unwrap(), 1);
+        assert_eq!(events.recv().await, Some(WriterEvent::WritersAttached(1)));
+
+        drop(sender);
+        assert_eq!(events.recv().await, Some(WriterEvent::NoWriters));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_peer_presence() {
+        let fifo_path = "/tmp/test_peer_presence";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+        assert_eq!(config.has_reader().unwrap(), Presence::Absent);
+        assert_eq!(config.has_writer().unwrap(), Presence::Absent);
+
+        // The tests can inspect every process, there is no unknown answer
+        let mut receiver = config.open_receiver().await.unwrap();
+        assert_eq!(config.has_reader().unwrap(), Presence::Present);
+        assert_eq!(config.has_writer().unwrap(), Presence::Absent);
+        let mut sender = config.open_sender().await.unwrap();
+        assert_eq!(config.has_writer().unwrap(), Presence::Present);
+
+        // Querying does not disturb the peers
+        sender.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        drop(receiver);
+        assert_eq!(config.has_reader().unwrap(), Presence::Absent);
+        drop(sender);
+        assert_eq!(config.has_writer().unwrap(), Presence::Absent);
+
+        let error = Sfifo::new("/tmp").has_reader().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_writer() {
+        let fifo_path = "/tmp/test_wait_for_writer";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let mut config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_wait_for_writer(true)
+            .set_timeout(Duration::from_millis(200))
+            .clone();
+        let error = config.open_receiver().await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+
+        let writer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Sfifo::new(fifo_path).open_sender().await
+        });
+        config.set_timeout(Duration::from_secs(2));
+        let _receiver = config.open_receiver().await.unwrap();
+        let _sender = writer.await.unwrap().unwrap();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,104 @@
+use std::{os::unix::fs::MetadataExt, path::Path};
+
+/// A process file descriptor referring to a FIFO, found through `/proc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FifoOpener {
+    pub pid: u32,
+    pub fd: i32,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// Lists the file descriptors of all visible processes that refer to the
+/// FIFO at `file_path`.
+///
+/// This is best effort: processes of other users are only visible with
+/// sufficient privileges.
+pub fn fifo_openers(file_path: impl AsRef<Path>) -> std::io::Result<Vec<FifoOpener>> {
+    Ok(scan_openers(file_path.as_ref())?.0)
+}
+
+/// Like `fifo_openers`, also telling whether every process could be
+/// inspected, so that no opener can have been missed
+pub(crate) fn scan_openers(file_path: &Path) -> std::io::Result<(Vec<FifoOpener>, bool)> {
+    let metadata = std::fs::metadata(file_path)?;
+    let (dev, ino) = (metadata.dev(), metadata.ino());
+
+    let mut openers = Vec::new();
+    let mut complete = true;
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let fds = match std::fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            // Exited meanwhile
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(_) => {
+                complete = false;
+                continue;
+            }
+        };
+        for fd_entry in fds.flatten() {
+            let Some(fd) = fd_entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            match std::fs::metadata(fd_entry.path()) {
+                Ok(m) if m.dev() == dev && m.ino() == ino => {}
+                _ => continue,
+            }
+            let (read, write) = fd_access_mode(pid, fd).unwrap_or((true, true));
+            openers.push(FifoOpener {
+                pid,
+                fd,
+                read,
+                write,
+            });
+        }
+    }
+    Ok((openers, complete))
+}
+
+/// Reads the access mode of a file descriptor from `/proc/<pid>/fdinfo`.
+fn fd_access_mode(pid: u32, fd: i32) -> Option<(bool, bool)> {
+    let fdinfo = std::fs::read_to_string(format!("/proc/{}/fdinfo/{}", pid, fd)).ok()?;
+    let flags = fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("flags:"))
+        .and_then(|flags| i32::from_str_radix(flags.trim(), 8).ok())?;
+    match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => Some((true, false)),
+        libc::O_WRONLY => Some((false, true)),
+        _ => Some((true, true)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, Sfifo};
+
+    #[tokio::test]
+    async fn test_fifo_openers_finds_own_fds() {
+        let fifo_path = "/tmp/test_procfs_openers";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        let openers = fifo_openers(fifo_path).unwrap();
+        let own = std::process::id();
+        assert!(openers.iter().any(|o| o.pid == own && o.read && !o.write));
+        assert!(!openers.iter().any(|o| o.pid == own && o.write));
+        drop(receiver);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{task::TaskGuard, Frame, FramedReceiver, FramedSender, Sfifo, SfifoError};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::Stream;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+// Kind of writer id at the start of a record
+const WRITER_PID: u8 = 0;
+const WRITER_LABEL: u8 = 1;
+// Records buffered per writer stream
+const STREAM_CAPACITY: usize = 64;
+
+/// Identifies the producer of a record
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WriterId {
+    /// Process id of the producer
+    Pid(u32),
+    /// Label chosen by the producer, at most 255 bytes
+    Label(String),
+}
+
+impl WriterId {
+    fn encode(&self) -> Bytes {
+        let mut prefix = BytesMut::new();
+        match self {
+            WriterId::Pid(pid) => {
+                prefix.put_u8(WRITER_PID);
+                prefix.put_u32_le(*pid);
+            }
+            WriterId::Label(label) => {
+                prefix.put_u8(WRITER_LABEL);
+                prefix.put_u8(label.len() as u8);
+                prefix.put_slice(label.as_bytes());
+            }
+        }
+        prefix.freeze()
+    }
+
+    /// Split the writer id off the start of a record
+    fn decode(record: &mut Bytes) -> std::io::Result<Self> {
+        let malformed = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed record");
+        if record.is_empty() {
+            return Err(malformed());
+        }
+        match record.get_u8() {
+            WRITER_PID if record.len() >= 4 => Ok(WriterId::Pid(record.get_u32_le())),
+            WRITER_LABEL if !record.is_empty() => {
+                let len = record.get_u8() as usize;
+                if record.len() < len {
+                    return Err(malformed());
+                }
+                let label = record.split_to(len);
+                String::from_utf8(label.to_vec())
+                    .map(WriterId::Label)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            }
+            _ => Err(malformed()),
+        }
+    }
+}
+
+impl std::fmt::Display for WriterId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriterId::Pid(pid) => write!(f, "pid {}", pid),
+            WriterId::Label(label) => f.write_str(label),
+        }
+    }
+}
+
+/// One of many writers sharing a FIFO, sending records tagged with its
+/// `WriterId`
+///
+/// Records are written as atomic frames, so they never interleave with
+/// the records of other producers. A record that does not fit in
+/// `PIPE_BUF` together with its headers fails with
+/// `SfifoError::MessageTooLarge`.
+pub struct SfifoProducer {
+    sender: FramedSender,
+    id: WriterId,
+    prefix: Bytes,
+}
+
+impl SfifoProducer {
+    /// Open the FIFO for writing, tagging records with the process id
+    pub async fn open(config: &Sfifo) -> std::io::Result<Self> {
+        let sender = FramedSender::new(config.open_sender().await?)
+            .with_metrics(config.metrics.clone())
+            .frame_diagnostics(config.frame_diagnostics)
+            .checksum(config.frame_checksum);
+        Ok(Self::new(sender))
+    }
+
+    /// Produce records on `sender`, tagged with the process id
+    pub fn new(sender: FramedSender) -> Self {
+        let id = WriterId::Pid(std::process::id());
+        SfifoProducer {
+            sender: sender.atomic_frames(true),
+            prefix: id.encode(),
+            id,
+        }
+    }
+
+    /// Tag records with `label` instead of the process id
+    pub fn with_label(mut self, label: impl Into<String>) -> std::io::Result<Self> {
+        let label = label.into();
+        if label.len() > u8::MAX as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Writer label longer than 255 bytes",
+            ));
+        }
+        self.id = WriterId::Label(label);
+        self.prefix = self.id.encode();
+        Ok(self)
+    }
+
+    pub fn writer_id(&self) -> &WriterId {
+        &self.id
+    }
+
+    /// Send one record
+    pub async fn send(&mut self, record: &[u8]) -> std::io::Result<()> {
+        let mut payload = BytesMut::with_capacity(self.prefix.len() + record.len());
+        payload.put_slice(&self.prefix);
+        payload.put_slice(record);
+        self.sender.send_frame(Frame::data(payload.freeze())).await
+    }
+}
+
+impl std::fmt::Debug for SfifoProducer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SfifoProducer")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reader of a FIFO shared by several `SfifoProducer`s, yielding a
+/// `WriterStream` for every writer seen
+///
+/// Records are dispatched to the stream of their writer; the records of a
+/// writer whose stream was dropped are discarded. A writer stream that is
+/// not read holds up the others once it has buffered 64 records. All
+/// streams end when every producer has closed the FIFO, or when the
+/// consumer is dropped.
+pub struct SfifoConsumer {
+    writers: mpsc::Receiver<std::io::Result<WriterStream>>,
+    // Stops demultiplexing once the consumer is dropped
+    _task: TaskGuard,
+}
+
+impl SfifoConsumer {
+    /// Open the FIFO for reading records
+    pub async fn open(config: &Sfifo) -> std::io::Result<Self> {
+        let receiver = FramedReceiver::new(config.open_receiver().await?)
+            .with_metrics(config.metrics.clone())
+            .frame_diagnostics(config.frame_diagnostics)
+            .resync_on_corruption(config.frame_resync);
+        Ok(Self::new(receiver))
+    }
+
+    /// Demultiplex the records read from `receiver`
+    pub fn new(receiver: FramedReceiver) -> Self {
+        let (tx, writers) = mpsc::channel(STREAM_CAPACITY);
+        let task = TaskGuard::spawn("sfifo::consumer_demux", demux(receiver, tx));
+        SfifoConsumer {
+            writers,
+            _task: task,
+        }
+    }
+
+    /// Wait for the first record of a new writer and return its stream,
+    /// `None` once the FIFO is closed
+    ///
+    /// Decoding errors are returned here; reading stops after them unless
+    /// frame resynchronization is enabled.
+    pub async fn next_writer(&mut self) -> std::io::Result<Option<WriterStream>> {
+        self.writers.recv().await.transpose()
+    }
+}
+
+impl Stream for SfifoConsumer {
+    type Item = std::io::Result<WriterStream>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.writers.poll_recv(cx)
+    }
+}
+
+impl std::fmt::Debug for SfifoConsumer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SfifoConsumer").finish_non_exhaustive()
+    }
+}
+
+/// Records of a single writer, in the order they were written
+#[derive(Debug)]
+pub struct WriterStream {
+    id: WriterId,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl WriterStream {
+    pub fn writer_id(&self) -> &WriterId {
+        &self.id
+    }
+
+    /// Receive the next record, `None` once the consumer stopped
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for WriterStream {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Read records and dispatch them to the stream of their writer
+async fn demux(mut receiver: FramedReceiver, writers: mpsc::Sender<std::io::Result<WriterStream>>) {
+    let mut streams: HashMap<WriterId, mpsc::Sender<Bytes>> = HashMap::new();
+    loop {
+        let mut record = match receiver.recv().await {
+            Ok(Some(record)) => record,
+            Ok(None) => return,
+            Err(e) => {
+                let resynchronized = matches!(
+                    SfifoError::from_io(&e),
+                    Some(SfifoError::Resynchronized { .. })
+                );
+                if writers.send(Err(e)).await.is_err() || !resynchronized {
+                    return;
+                }
+                continue;
+            }
+        };
+        let id = match WriterId::decode(&mut record) {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Discarding record without writer id: {}", e);
+                continue;
+            }
+        };
+        let stream = match streams.entry(id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let (tx, rx) = mpsc::channel(STREAM_CAPACITY);
+                let id = entry.key().clone();
+                debug!("New writer {} on shared FIFO", id);
+                if writers.send(Ok(WriterStream { id, rx })).await.is_err() {
+                    return;
+                }
+                entry.insert(tx)
+            }
+        };
+        // The writer's stream may have been dropped
+        let _ = stream.send(record).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_fifo;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_records_split_per_writer() {
+        let fifo_path = "/tmp/test_producer_consumer";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let config = Sfifo::new(fifo_path);
+        let mut consumer = SfifoConsumer::open(&config).await.unwrap();
+        let mut by_pid = SfifoProducer::open(&config).await.unwrap();
+        let mut by_label = SfifoProducer::open(&config)
+            .await
+            .unwrap()
+            .with_label("worker-2")
+            .unwrap();
+        assert_eq!(by_pid.writer_id(), &WriterId::Pid(std::process::id()));
+
+        for n in 0..3 {
+            by_pid.send(format!("pid {}", n).as_bytes()).await.unwrap();
+            by_label
+                .send(format!("label {}", n).as_bytes())
+                .await
+                .unwrap();
+        }
+        drop((by_pid, by_label));
+
+        let first = consumer.next_writer().await.unwrap().unwrap();
+        let second = consumer.next_writer().await.unwrap().unwrap();
+        assert!(consumer.next_writer().await.unwrap().is_none());
+        assert_eq!(first.writer_id(), &WriterId::Pid(std::process::id()));
+        assert_eq!(second.writer_id(), &WriterId::Label("worker-2".into()));
+
+        let first: Vec<Bytes> = first.collect().await;
+        let second: Vec<Bytes> = second.collect().await;
+        assert_eq!(first, ["pid 0", "pid 1", "pid 2"]);
+        assert_eq!(second, ["label 0", "label 1", "label 2"]);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{AuthenticatedFifo, Sfifo};
+use tokio::sync::mpsc;
+
+/// Step of an authenticated open, reported by
+/// `Sfifo::open_as_client_with_progress` and
+/// `Sfifo::open_as_server_with_progress`
+///
+/// Each side reports the steps it goes through, in order: a client
+/// `SentRequest` then `AwaitingResponse`, a server `ReceivedRequest` then
+/// `SentResponse` while it waits for the acknowledgment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakePhase {
+    /// Creating the FIFO, with `create` set
+    CreatingFifo,
+    /// Waiting for the peer to open its end
+    WaitingForPeer,
+    /// The client sent its request
+    SentRequest,
+    /// The client waits for the response of the server
+    AwaitingResponse,
+    /// The server received a request and checks it
+    ReceivedRequest,
+    /// The server answered and waits for the acknowledgment
+    SentResponse,
+    /// The peer is authenticated and accepted by the peer policy
+    Validated,
+}
+
+impl Sfifo {
+    /// `open_as_client`, reporting each handshake phase to `progress`
+    ///
+    /// Reporting never blocks: phases sent after `progress` is closed are
+    /// dropped.
+    pub async fn open_as_client_with_progress(
+        &self,
+        token: &str,
+        progress: mpsc::UnboundedSender<HandshakePhase>,
+    ) -> std::io::Result<AuthenticatedFifo> {
+        self.with_progress(progress).open_as_client(token).await
+    }
+
+    /// `open_as_server`, reporting each handshake phase to `progress`
+    pub async fn open_as_server_with_progress(
+        &self,
+        token: &str,
+        progress: mpsc::UnboundedSender<HandshakePhase>,
+    ) -> std::io::Result<AuthenticatedFifo> {
+        self.with_progress(progress).open_as_server(token).await
+    }
+
+    fn with_progress(&self, progress: mpsc::UnboundedSender<HandshakePhase>) -> Sfifo {
+        let mut config = self.clone();
+        config.progress = Some(progress);
+        config
+    }
+
+    pub(crate) fn report(&self, phase: HandshakePhase) {
+        if let Some(progress) = &self.progress {
+            let _ = progress.send(phase);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_handshake_progress() {
+        let fifo_path = "/tmp/test_handshake_progress";
+        let token = "progress_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let server = tokio::spawn(async move {
+            server_config
+                .open_as_server_with_progress(token, server_tx)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server_rx.recv().await, Some(HandshakePhase::CreatingFifo));
+        assert_eq!(server_rx.recv().await, Some(HandshakePhase::WaitingForPeer));
+
+        let (client_tx, mut client_rx) = mpsc::unbounded_channel();
+        Sfifo::new(fifo_path)
+            .open_as_client_with_progress(token, client_tx)
+            .await
+            .unwrap();
+        server.await.unwrap().unwrap();
+
+        let mut client_phases = Vec::new();
+        while let Some(phase) = client_rx.recv().await {
+            client_phases.push(phase);
+        }
+        assert_eq!(
+            client_phases,
+            [
+                HandshakePhase::WaitingForPeer,
+                HandshakePhase::SentRequest,
+                HandshakePhase::AwaitingResponse,
+                HandshakePhase::Validated,
+            ]
+        );
+        let mut server_phases = Vec::new();
+        while let Some(phase) = server_rx.recv().await {
+            server_phases.push(phase);
+        }
+        assert_eq!(
+            server_phases,
+            [
+                HandshakePhase::ReceivedRequest,
+                HandshakePhase::SentResponse,
+                HandshakePhase::Validated,
+            ]
+        );
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{extension::EXT_PROOF, nonce::Nonce, Extension, HandshakeMessage};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Who computes a proof, so that a proof of one side is never valid for
+/// the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Prover {
+    /// Server answering a request
+    Server,
+    /// Client acknowledging a response
+    Client,
+    /// Server accepting a session resumption, keyed by the session token
+    Session,
+}
+
+impl Prover {
+    fn label(self) -> &'static [u8] {
+        match self {
+            Prover::Server => b"sfifo server proof",
+            Prover::Client => b"sfifo client proof",
+            Prover::Session => b"sfifo session proof",
+        }
+    }
+}
+
+/// MAC keyed by `token` over the `challenge` nonce of the peer, then the
+/// `nonce` of the prover
+fn mac(token: &str, prover: Prover, challenge: &Nonce, nonce: &Nonce) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(token.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(prover.label());
+    mac.update(challenge);
+    mac.update(nonce);
+    mac
+}
+
+impl HandshakeMessage {
+    /// Prove knowledge of `token` to the peer that sent `challenge`
+    pub(crate) fn with_proof(
+        self,
+        token: &str,
+        prover: Prover,
+        challenge: &Nonce,
+        nonce: &Nonce,
+    ) -> Self {
+        let proof = mac(token, prover, challenge, nonce).finalize().into_bytes();
+        self.with_extension(Extension::new(EXT_PROOF, proof.to_vec()))
+    }
+
+    /// Check, in constant time, that the sender holds `token`
+    pub(crate) fn check_proof(
+        &self,
+        token: &str,
+        prover: Prover,
+        challenge: &Nonce,
+        nonce: &Nonce,
+    ) -> std::io::Result<()> {
+        let valid = self.extension(EXT_PROOF).is_some_and(|proof| {
+            mac(token, prover, challenge, nonce)
+                .verify_slice(proof)
+                .is_ok()
+        });
+        if !valid {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Invalid authentication token",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{nonce::new_nonce, HandshakeType};
+
+    #[test]
+    fn test_handshake_proof() {
+        let (challenge, nonce) = (new_nonce().unwrap(), new_nonce().unwrap());
+        let message = HandshakeMessage::new(String::new(), HandshakeType::Response)
+            .unwrap()
+            .with_proof("secret", Prover::Server, &challenge, &nonce);
+        assert!(message.token.is_empty());
+        message
+            .check_proof("secret", Prover::Server, &challenge, &nonce)
+            .unwrap();
+
+        // Bound to the token, the side and both nonces
+        let other = new_nonce().unwrap();
+        for (token, prover, challenge, nonce) in [
+            ("secreT", Prover::Server, &challenge, &nonce),
+            ("secret", Prover::Client, &challenge, &nonce),
+            ("secret", Prover::Server, &other, &nonce),
+            ("secret", Prover::Server, &challenge, &other),
+        ] {
+            let error = message
+                .check_proof(token, prover, challenge, nonce)
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::Sfifo;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::time::Sleep;
+
+/// Throughput allowed to the sending side of a FIFO, see
+/// `Sfifo::set_rate_limit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Sustained rate
+    pub bytes_per_sec: u64,
+    /// Bytes that may be written at once after a quiet period
+    pub burst: u64,
+}
+
+impl Sfifo {
+    /// Throttle senders opened from this configuration to `bytes_per_sec`,
+    /// allowing bursts of up to `burst` bytes
+    ///
+    /// Writes beyond the budget wait for the token bucket to refill.
+    /// Writes and frames larger than `burst` are sent whole once the
+    /// bucket is full and the next ones wait longer, only `AsyncWrite`
+    /// writes are cut to `burst` bytes.
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64, burst: u64) -> &mut Self {
+        self.rate_limit = Some(RateLimit {
+            bytes_per_sec: bytes_per_sec.max(1),
+            burst: burst.max(1),
+        });
+        self
+    }
+}
+
+/// Token bucket enforcing a `RateLimit`, one token per byte
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    // Negative after a write larger than the available tokens
+    tokens: f64,
+    last: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            last: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    /// Largest write that is worth waiting for
+    pub(crate) fn burst(&self) -> usize {
+        usize::try_from(self.limit.burst).unwrap_or(usize::MAX)
+    }
+
+    /// Wait until `len` bytes may be written, or the bucket is full for
+    /// larger writes. Tokens are only taken by `consume`
+    pub(crate) fn poll_acquire(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<()> {
+        loop {
+            if let Some(sleep) = self.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            self.refill();
+            let wanted = len.min(self.burst()) as f64;
+            if self.tokens >= wanted {
+                return Poll::Ready(());
+            }
+            let wait = (wanted - self.tokens) / self.limit.bytes_per_sec as f64;
+            self.sleep = Some(Box::pin(tokio::time::sleep(Duration::from_secs_f64(wait))));
+        }
+    }
+
+    pub(crate) async fn acquire(&mut self, len: usize) {
+        std::future::poll_fn(|cx| self.poll_acquire(cx, len)).await
+    }
+
+    /// Take the tokens of `len` written bytes
+    pub(crate) fn consume(&mut self, len: usize) {
+        self.tokens -= len as f64;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.limit.bytes_per_sec as f64).min(self.limit.burst as f64);
+        self.last = now;
+    }
+}
+
+impl std::fmt::Debug for TokenBucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenBucket")
+            .field("limit", &self.limit)
+            .field("tokens", &self.tokens)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, FramedReceiver, FramedSender};
+
+    #[tokio::test]
+    async fn test_rate_limited_sender() {
+        let fifo_path = "/tmp/test_rate_limit";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap())
+            .rate_limit(Some(RateLimit {
+                bytes_per_sec: 10_000,
+                burst: 1_000,
+            }));
+        let reader = tokio::spawn(async move {
+            let mut received = 0;
+            while let Some(data) = receiver.recv().await.unwrap() {
+                received += data.len();
+            }
+            received
+        });
+
+        // The burst goes through at once, the next 2000 bytes take 200ms
+        let started = Instant::now();
+        for _ in 0..3 {
+            sender.send(&[0u8; 990]).await.unwrap();
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(180), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+        drop(sender);
+        assert_eq!(reader.await.unwrap(), 2970);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
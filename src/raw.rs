@@ -0,0 +1,306 @@
+use crate::{frame::FRAME_MAGIC, handshake_path, PipeEnd, Sfifo};
+use bytes::{Buf, BytesMut};
+use std::{
+    os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+// Largest length prefix of a handshake message
+const MAX_HANDSHAKE_LEN: u32 = 4096;
+// Bytes looked at by `detect_protocol`
+const DETECT_LEN: usize = 16;
+
+/// Protocol spoken by the peer of a FIFO, as guessed from its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerProtocol {
+    /// A length-prefixed sfifo handshake message
+    Handshake,
+    /// sfifo frames
+    Framed,
+    /// Anything else: plain bytes
+    Raw,
+    /// Not enough bytes to tell
+    Unknown,
+}
+
+/// Guess the protocol of a peer from the first bytes it wrote
+pub fn detect_protocol(prefix: &[u8]) -> PeerProtocol {
+    if prefix.len() < 4 {
+        return PeerProtocol::Unknown;
+    }
+    if u16::from_le_bytes([prefix[0], prefix[1]]) == FRAME_MAGIC {
+        return PeerProtocol::Framed;
+    }
+    let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+    if len == 0 || len > MAX_HANDSHAKE_LEN {
+        return PeerProtocol::Raw;
+    }
+    // The message starts with the process id and the bincode length of
+    // the process name
+    if prefix.len() >= DETECT_LEN {
+        let name_len = u64::from_le_bytes(prefix[8..16].try_into().unwrap_or_default());
+        if name_len > len as u64 {
+            return PeerProtocol::Raw;
+        }
+    }
+    PeerProtocol::Handshake
+}
+
+/// Whether an sfifo peer is handshaking on the side channels of the FIFO
+/// at `path`
+pub fn handshake_pending(path: impl AsRef<Path>) -> bool {
+    ["c2s", "s2c", "hs"]
+        .iter()
+        .any(|extension| handshake_path(path.as_ref(), extension).exists())
+}
+
+/// A FIFO without handshake or framing, byte-for-byte compatible with
+/// `cat`, shell redirections or C programs on the other end.
+///
+/// Opening honors the `timeout` and `notify` settings of the `Sfifo`, a
+/// reader waits for a writer to attach. With `reconnect`, a reader that
+/// reaches end-of-file waits for the next writer, and a writer whose
+/// reader went away waits for the next reader and retries.
+#[derive(Debug)]
+pub struct RawFifo {
+    config: Sfifo,
+    end: PipeEnd,
+    reconnect: bool,
+    // Bytes read by `detect_peer` and not returned yet
+    pending: BytesMut,
+}
+
+impl RawFifo {
+    /// Open the FIFO for reading, once a writer has it open
+    pub async fn open_reader(config: &Sfifo) -> std::io::Result<Self> {
+        let config = config.clone().set_wait_for_writer(true).clone();
+        let end = PipeEnd::Receiver(config.open_receiver().await?);
+        Ok(Self::new(config, end))
+    }
+
+    /// Open the FIFO for writing, once a reader has it open
+    pub async fn open_writer(config: &Sfifo) -> std::io::Result<Self> {
+        let end = PipeEnd::Sender(config.open_sender().await?);
+        Ok(Self::new(config.clone(), end))
+    }
+
+    fn new(config: Sfifo, end: PipeEnd) -> Self {
+        RawFifo {
+            config,
+            end,
+            reconnect: false,
+            pending: BytesMut::new(),
+        }
+    }
+
+    /// Wait for the next peer instead of ending when the current one
+    /// closes the FIFO
+    pub fn reconnect(mut self, enabled: bool) -> Self {
+        self.reconnect = enabled;
+        self
+    }
+
+    pub fn is_reader(&self) -> bool {
+        matches!(self.end, PipeEnd::Receiver(_))
+    }
+
+    /// Read some bytes, 0 at end-of-file
+    pub async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.pending.is_empty() {
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.advance(n);
+            return Ok(n);
+        }
+        loop {
+            let n = self.end.read(buf).await?;
+            if n > 0 || buf.is_empty() || !self.reconnect {
+                return Ok(n);
+            }
+            debug!(
+                "Writer closed {:?}, waiting for the next one",
+                self.config.file_path
+            );
+            self.end = PipeEnd::Receiver(self.config.open_receiver().await?);
+        }
+    }
+
+    /// Read exact number of bytes
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        let mut bytes_read = 0;
+        while bytes_read < buf.len() {
+            match self.read(&mut buf[bytes_read..]).await? {
+                0 => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Failed to read exact number of bytes",
+                    ))
+                }
+                n => bytes_read += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Write some bytes
+    pub async fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        loop {
+            match self.end.write(buf).await {
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe && self.reconnect => {
+                    debug!(
+                        "Reader closed {:?}, waiting for the next one",
+                        self.config.file_path
+                    );
+                    self.end = PipeEnd::Sender(self.config.open_sender().await?);
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Write all bytes
+    pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        let mut bytes_written = 0;
+        while bytes_written < buf.len() {
+            bytes_written += self.write(&buf[bytes_written..]).await?;
+        }
+        Ok(())
+    }
+
+    /// Read the first bytes of the writer to guess its protocol - only
+    /// works for readers
+    ///
+    /// The bytes are kept and returned by the following reads.
+    pub async fn detect_peer(&mut self) -> std::io::Result<PeerProtocol> {
+        while self.pending.len() < DETECT_LEN {
+            let mut chunk = [0u8; DETECT_LEN];
+            let n = self.end.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            self.pending.extend_from_slice(&chunk[..n]);
+        }
+        Ok(detect_protocol(&self.pending))
+    }
+
+    /// Get the underlying pipe end, dropping bytes read by `detect_peer`
+    pub fn into_pipe_end(self) -> PipeEnd {
+        self.end
+    }
+}
+
+/// Reads and writes go straight to the pipe, after any bytes read by
+/// `detect_peer`; reconnecting only applies to the async methods.
+impl AsyncRead for RawFifo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if !this.pending.is_empty() {
+            let n = buf.remaining().min(this.pending.len());
+            buf.put_slice(&this.pending[..n]);
+            this.pending.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.end).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RawFifo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().end).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().end).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().end).poll_shutdown(cx)
+    }
+}
+
+impl AsFd for RawFifo {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.end.as_fd()
+    }
+}
+
+impl AsRawFd for RawFifo {
+    fn as_raw_fd(&self) -> RawFd {
+        self.end.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, HandshakeMessage, HandshakeType};
+    use std::time::Duration;
+
+    #[test]
+    fn test_detect_protocol() {
+        let message = HandshakeMessage::new("token".into(), HandshakeType::Request)
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        let mut handshake = (message.len() as u32).to_le_bytes().to_vec();
+        handshake.extend_from_slice(&message);
+        assert_eq!(detect_protocol(&handshake), PeerProtocol::Handshake);
+        assert_eq!(detect_protocol(&[0x46, 0x53, 0, 0]), PeerProtocol::Framed);
+        assert_eq!(detect_protocol(b"hello from cat\n"), PeerProtocol::Raw);
+        assert_eq!(detect_protocol(b"hi"), PeerProtocol::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_raw_fifo_with_shell_peers() {
+        let fifo_path = "/tmp/test_raw_fifo";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let config = Sfifo::new(fifo_path)
+            .set_timeout(Duration::from_secs(2))
+            .clone();
+        let shell = |script: String| {
+            tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(script)
+                .spawn()
+                .unwrap()
+        };
+
+        // Two writers in turn, the reader reconnects to the second one
+        let mut writers = shell(format!(
+            "printf 'first\\n' > {0}; sleep 0.2; printf 'second\\n' > {0}",
+            fifo_path
+        ));
+        let mut reader = RawFifo::open_reader(&config).await.unwrap().reconnect(true);
+        assert_eq!(reader.detect_peer().await.unwrap(), PeerProtocol::Raw);
+        let mut buf = [0u8; 13];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"first\nsecond\n");
+        writers.wait().await.unwrap();
+        drop(reader);
+
+        let head = tokio::process::Command::new("head")
+            .args(["-c", "7", fifo_path])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut writer = RawFifo::open_writer(&config).await.unwrap();
+        writer.write_all(b"to head").await.unwrap();
+        drop(writer);
+        assert_eq!(head.wait_with_output().await.unwrap().stdout, b"to head");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,78 @@
+use crate::AuthenticatedFifo;
+use bytes::Bytes;
+
+/// Capacity of the read buffer of framed receivers and of
+/// `AuthenticatedFifo::read_bytes`, unless set with
+/// `Sfifo::set_read_buffer_capacity`
+pub const DEFAULT_READ_BUFFER_CAPACITY: usize = 8 * 1024;
+
+impl AuthenticatedFifo {
+    /// Read the bytes available, up to the read buffer capacity, or `None`
+    /// at end-of-file - only works for Receiver
+    ///
+    /// Reads go through one buffer kept across calls: the returned `Bytes`
+    /// is a cheap to clone view into it, and its memory is reused by a
+    /// later read once every view on it is dropped. Bytes left over by
+    /// `read_line` are returned first.
+    pub async fn read_bytes(&mut self) -> std::io::Result<Option<Bytes>> {
+        if self.line_buf.is_empty() {
+            let capacity = self
+                .config
+                .as_ref()
+                .and_then(|c| c.read_buffer_capacity)
+                .unwrap_or(DEFAULT_READ_BUFFER_CAPACITY);
+            let mut buf = std::mem::take(&mut self.line_buf);
+            buf.resize(capacity, 0);
+            let read = self.read_pipe(&mut buf).await;
+            buf.truncate(*read.as_ref().unwrap_or(&0));
+            self.line_buf = buf;
+            if read? == 0 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.line_buf.split().freeze()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_fifo, AuthenticatedFifo, HandshakeMessage, HandshakeType, Sfifo};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_read_bytes_reuses_buffer() {
+        let fifo_path = "/tmp/test_read_bytes";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let config = Sfifo::new(fifo_path)
+            .set_read_buffer_capacity(Some(16))
+            .clone();
+        let receiver = config.open_receiver().await.unwrap();
+        let peer_info = HandshakeMessage::new(String::new(), HandshakeType::Request).unwrap();
+        let mut fifo =
+            AuthenticatedFifo::new_receiver(receiver, peer_info, true).with_config(&config);
+
+        let mut sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        sender.write_all(b"0123456789abcdefXYZ").await.unwrap();
+        let first = fifo.read_bytes().await.unwrap().unwrap();
+        assert_eq!(&first[..], b"0123456789abcdef");
+        let ptr = first.as_ptr();
+        drop(first);
+        let second = fifo.read_bytes().await.unwrap().unwrap();
+        assert_eq!(&second[..], b"XYZ");
+        // The memory of the first read was reclaimed
+        assert_eq!(second.as_ptr(), ptr);
+        let copy = second.clone();
+        drop(second);
+        assert_eq!(&copy[..], b"XYZ");
+
+        sender.write_all(b"line\nrest").await.unwrap();
+        drop(sender);
+        assert_eq!(fifo.read_line().await.unwrap().unwrap(), "line");
+        assert_eq!(&fifo.read_bytes().await.unwrap().unwrap()[..], b"rest");
+        assert!(fifo.read_bytes().await.unwrap().is_none());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
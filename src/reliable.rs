@@ -0,0 +1,322 @@
+use crate::{trace_context::fill_random, Frame, FrameKind};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// Size of the sequence number in front of reliable payloads and in acks
+pub const RELIABLE_SEQUENCE_LEN: usize = 8;
+
+// Size of the sender incarnation in front of the sequence number
+const INCARNATION_LEN: usize = 8;
+
+// Senders whose delivery state a receiver remembers, the least recently
+// heard from is forgotten first
+const MAX_SENDERS: usize = 16;
+
+/// Delivery state of reliable channel messages, set on an `Sfifo` so it
+/// outlives the channel and survives reconnects.
+///
+/// The sender keeps every message sent with `Channel::send_reliable()`
+/// until the peer acknowledges it, and retransmits the unacknowledged ones
+/// when a channel is established again with the same `Reliable`. The
+/// receiver drops retransmitted messages it already delivered. With
+/// `auto_ack` off, messages are only acknowledged by `Channel::ack()`, so a
+/// receiver that exits before acking gets them again.
+///
+/// Each `Reliable` numbers its messages under a random incarnation id, and
+/// receivers track the sequences of each incarnation apart. A restarted
+/// sender, or another client of the same server, starts over at sequence 1
+/// without its messages being taken for duplicates.
+#[derive(Clone)]
+pub struct Reliable {
+    state: Arc<Mutex<ReliableState>>,
+    auto_ack: bool,
+}
+
+struct ReliableState {
+    // Sending side
+    incarnation: u64,
+    last_sent: u64,
+    unacked: VecDeque<(u64, Bytes)>,
+    // Receiving side, most recently heard from sender first
+    senders: VecDeque<Delivery>,
+}
+
+// What was delivered and acknowledged of one sender incarnation
+struct Delivery {
+    incarnation: u64,
+    delivered: u64,
+    acked: u64,
+}
+
+impl Default for Reliable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reliable {
+    /// Delivery state acknowledging received messages automatically
+    pub fn new() -> Self {
+        let mut incarnation = [0u8; INCARNATION_LEN];
+        fill_random(&mut incarnation);
+        Reliable {
+            state: Arc::new(Mutex::new(ReliableState {
+                incarnation: u64::from_le_bytes(incarnation),
+                last_sent: 0,
+                unacked: VecDeque::new(),
+                senders: VecDeque::new(),
+            })),
+            auto_ack: true,
+        }
+    }
+
+    /// Acknowledge messages as they are received, or only on `Channel::ack()`
+    pub fn with_auto_ack(mut self, auto_ack: bool) -> Self {
+        self.auto_ack = auto_ack;
+        self
+    }
+
+    pub fn auto_ack(&self) -> bool {
+        self.auto_ack
+    }
+
+    /// Number of sent messages the peer has not acknowledged yet
+    pub fn unacked(&self) -> usize {
+        self.lock().unacked.len()
+    }
+
+    /// Bytes of the sent messages the peer has not acknowledged yet
+    pub fn unacked_bytes(&self) -> usize {
+        self.lock().unacked.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    /// Sequence of the last message delivered to the application, from
+    /// the sender heard from most recently
+    pub fn delivered(&self) -> u64 {
+        self.lock().senders.front().map_or(0, |d| d.delivered)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ReliableState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Assign the next sequence to `data` and keep it until acknowledged
+    pub(crate) fn enqueue(&self, data: &[u8]) -> Frame {
+        let mut state = self.lock();
+        state.last_sent += 1;
+        let sequence = state.last_sent;
+        state
+            .unacked
+            .push_back((sequence, Bytes::copy_from_slice(data)));
+        reliable_frame(state.incarnation, sequence, data)
+    }
+
+    /// Skip to `sequence`, announced by a server that has processed every
+    /// message before it: earlier messages count as acknowledged, and a
+    /// sender that restarted without them numbers new messages from there
+    pub(crate) fn resume_from(&self, sequence: u64) {
+        let mut state = self.lock();
+        while state.unacked.front().is_some_and(|(s, _)| *s < sequence) {
+            state.unacked.pop_front();
+        }
+        state.last_sent = state.last_sent.max(sequence.saturating_sub(1));
+    }
+
+    /// Frames of all unacknowledged messages, oldest first
+    pub(crate) fn unacked_frames(&self) -> Vec<Frame> {
+        let state = self.lock();
+        state
+            .unacked
+            .iter()
+            .map(|(sequence, data)| reliable_frame(state.incarnation, *sequence, data))
+            .collect()
+    }
+
+    /// Handle a cumulative ack from the peer, ignoring acks of messages
+    /// sent by a previous incarnation
+    pub(crate) fn acknowledge(&self, payload: Bytes) -> std::io::Result<()> {
+        let (incarnation, sequence, _) = decode_reliable(payload)?;
+        let mut state = self.lock();
+        if incarnation != state.incarnation {
+            return Ok(());
+        }
+        while state.unacked.front().is_some_and(|(s, _)| *s <= sequence) {
+            state.unacked.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Handle a reliable frame, returning the incarnation of its sender
+    /// and its data unless it is a duplicate
+    pub(crate) fn receive(&self, payload: Bytes) -> std::io::Result<(u64, Option<Bytes>)> {
+        let (incarnation, sequence, data) = decode_reliable(payload)?;
+        let mut state = self.lock();
+        let delivery = state.sender(incarnation);
+        if sequence <= delivery.delivered {
+            return Ok((incarnation, None));
+        }
+        delivery.delivered = sequence;
+        Ok((incarnation, Some(data)))
+    }
+
+    /// Ack frame covering every message delivered from `incarnation`,
+    /// `None` if already sent
+    pub(crate) fn ack_frame(&self, incarnation: u64) -> Option<Frame> {
+        let mut state = self.lock();
+        let delivery = state.sender(incarnation);
+        if delivery.delivered == delivery.acked {
+            return None;
+        }
+        delivery.acked = delivery.delivered;
+        Some(ack_frame(incarnation, delivery.acked))
+    }
+
+    /// Ack frame repeating the last ack sent to `incarnation`, for
+    /// duplicates whose ack was lost
+    pub(crate) fn repeat_ack_frame(&self, incarnation: u64) -> Option<Frame> {
+        let acked = self.lock().sender(incarnation).acked;
+        (acked > 0).then(|| ack_frame(incarnation, acked))
+    }
+}
+
+impl ReliableState {
+    /// Delivery state of `incarnation`, moved to the front
+    fn sender(&mut self, incarnation: u64) -> &mut Delivery {
+        match self
+            .senders
+            .iter()
+            .position(|d| d.incarnation == incarnation)
+        {
+            Some(0) => {}
+            Some(index) => {
+                let delivery = self.senders.remove(index).expect("index in bounds");
+                self.senders.push_front(delivery);
+            }
+            None => {
+                self.senders.truncate(MAX_SENDERS - 1);
+                self.senders.push_front(Delivery {
+                    incarnation,
+                    delivered: 0,
+                    acked: 0,
+                });
+            }
+        }
+        &mut self.senders[0]
+    }
+}
+
+impl std::fmt::Debug for Reliable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reliable")
+            .field("auto_ack", &self.auto_ack)
+            .field("unacked", &self.unacked())
+            .finish_non_exhaustive()
+    }
+}
+
+fn reliable_frame(incarnation: u64, sequence: u64, data: &[u8]) -> Frame {
+    let mut payload = BytesMut::with_capacity(INCARNATION_LEN + RELIABLE_SEQUENCE_LEN + data.len());
+    payload.put_u64_le(incarnation);
+    payload.put_u64_le(sequence);
+    payload.put_slice(data);
+    Frame::new(FrameKind::Reliable, payload.freeze())
+}
+
+fn ack_frame(incarnation: u64, sequence: u64) -> Frame {
+    let mut payload = BytesMut::with_capacity(INCARNATION_LEN + RELIABLE_SEQUENCE_LEN);
+    payload.put_u64_le(incarnation);
+    payload.put_u64_le(sequence);
+    Frame::new(FrameKind::Ack, payload.freeze())
+}
+
+/// Split the sender incarnation and the sequence number off a reliable
+/// or ack payload
+pub(crate) fn decode_reliable(mut payload: Bytes) -> std::io::Result<(u64, u64, Bytes)> {
+    if payload.len() < INCARNATION_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Reliable frame too short for its incarnation",
+        ));
+    }
+    let incarnation = payload.get_u64_le();
+    let (sequence, data) = decode_sequence(payload)?;
+    Ok((incarnation, sequence, data))
+}
+
+/// Split the sequence number off the front of a payload
+pub(crate) fn decode_sequence(mut payload: Bytes) -> std::io::Result<(u64, Bytes)> {
+    if payload.len() < RELIABLE_SEQUENCE_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Reliable frame too short for its sequence number",
+        ));
+    }
+    let sequence = payload.get_u64_le();
+    Ok((sequence, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(receiver: &Reliable, frame: &Frame) -> (u64, Option<Bytes>) {
+        receiver.receive(frame.payload.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_reliable_ack_and_duplicates() {
+        let sender = Reliable::new();
+        let receiver = Reliable::new().with_auto_ack(false);
+        let frames: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|m| sender.enqueue(m.as_bytes()))
+            .collect();
+        assert_eq!(sender.unacked(), 3);
+
+        let (incarnation, data) = deliver(&receiver, &frames[0]);
+        assert_eq!(data.unwrap(), "a");
+        assert_eq!(deliver(&receiver, &frames[1]).1.unwrap(), "b");
+        // Retransmitted after a reconnect
+        assert!(deliver(&receiver, &frames[0]).1.is_none());
+        assert!(receiver.repeat_ack_frame(incarnation).is_none());
+
+        let ack = receiver.ack_frame(incarnation).unwrap();
+        assert!(receiver.ack_frame(incarnation).is_none());
+        sender.acknowledge(ack.payload).unwrap();
+        assert_eq!(sender.unacked(), 1);
+        assert_eq!(sender.unacked_frames(), vec![frames[2].clone()]);
+    }
+
+    #[test]
+    fn test_reliable_sender_restart() {
+        let receiver = Reliable::new();
+        let sender = Reliable::new();
+        let (first, _) = deliver(&receiver, &sender.enqueue(b"a"));
+        assert_eq!(deliver(&receiver, &sender.enqueue(b"b")).1.unwrap(), "b");
+        let stale_ack = receiver.ack_frame(first).unwrap();
+
+        // The restarted sender starts over at sequence 1
+        let restarted = Reliable::new();
+        let frame = restarted.enqueue(b"c");
+        let (second, data) = deliver(&receiver, &frame);
+        assert_ne!(first, second);
+        assert_eq!(data.unwrap(), "c");
+        assert_eq!(receiver.delivered(), 1);
+        assert!(deliver(&receiver, &frame).1.is_none());
+
+        // Acks meant for the previous incarnation acknowledge nothing
+        restarted.acknowledge(stale_ack.payload).unwrap();
+        assert_eq!(restarted.unacked(), 1);
+        restarted
+            .acknowledge(receiver.ack_frame(second).unwrap().payload)
+            .unwrap();
+        assert_eq!(restarted.unacked(), 0);
+
+        // The first sender is still told apart from the second
+        assert_eq!(deliver(&receiver, &sender.enqueue(b"d")).1.unwrap(), "d");
+        assert!(deliver(&receiver, &sender.unacked_frames()[0]).1.is_none());
+    }
+}
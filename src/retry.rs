@@ -0,0 +1,184 @@
+use crate::trace_context::fill_random;
+use std::{sync::Arc, time::Duration};
+
+/// Delay between attempts of the default policy
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Callback told about each retry, see `RetryPolicy::on_retry`
+pub type RetryHook = Arc<dyn Fn(&RetryAttempt) + Send + Sync>;
+
+/// Failed attempt about to be retried
+#[derive(Debug)]
+pub struct RetryAttempt<'a> {
+    /// Number of the failed attempt, starting at 1
+    pub attempt: u32,
+    /// Wait before the next attempt
+    pub delay: Duration,
+    /// Why the attempt failed
+    pub error: &'a std::io::Error,
+}
+
+/// How the delay grows between attempts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Always wait the same time
+    Fixed(Duration),
+    /// Start at `initial` and double up to `max`
+    Exponential { initial: Duration, max: Duration },
+}
+
+/// How opens waiting for a peer are retried: by `open_sender()`, the
+/// handshake side channels and keepalive reopening
+///
+/// Retrying stops after `max_attempts` or when the timeout of the
+/// configuration expires, whichever comes first. The default retries every
+/// 100ms without attempt limit.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    backoff: Backoff,
+    jitter: bool,
+    max_attempts: Option<u32>,
+    on_retry: Option<RetryHook>,
+}
+
+impl RetryPolicy {
+    /// Retry every `delay`
+    pub fn fixed(delay: Duration) -> Self {
+        RetryPolicy {
+            backoff: Backoff::Fixed(delay),
+            jitter: false,
+            max_attempts: None,
+            on_retry: None,
+        }
+    }
+
+    /// Retry after `initial`, doubling the delay up to `max`
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        RetryPolicy {
+            backoff: Backoff::Exponential { initial, max },
+            ..Self::fixed(initial)
+        }
+    }
+
+    /// Wait a random time between half and all of each delay, so peers
+    /// started together do not retry in lockstep
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Give up after `attempts` attempts, with the error of the last one
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts.max(1));
+        self
+    }
+
+    /// Call `hook` before waiting for each retry
+    pub fn on_retry(mut self, hook: impl Fn(&RetryAttempt) + Send + Sync + 'static) -> Self {
+        self.on_retry = Some(Arc::new(hook));
+        self
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+
+    /// Delay after failed attempt `attempt`, `None` once attempts are
+    /// exhausted
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+        let delay = match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => initial
+                .saturating_mul(1u32 << attempt.saturating_sub(1).min(31))
+                .min(max),
+        };
+        if !self.jitter {
+            return Some(delay);
+        }
+        let mut random = [0u8; 4];
+        fill_random(&mut random);
+        let fraction = u32::from_le_bytes(random) as f64 / u32::MAX as f64;
+        Some(delay.mul_f64(0.5 + fraction / 2.0))
+    }
+
+    /// Wait before retrying after failed attempt `attempt`, or return
+    /// `error` once attempts are exhausted
+    pub(crate) async fn retry(&self, attempt: u32, error: std::io::Error) -> std::io::Result<()> {
+        let Some(delay) = self.delay(attempt) else {
+            debug!("Giving up after {} attempts: {}", attempt, error);
+            return Err(error);
+        };
+        if let Some(hook) = &self.on_retry {
+            hook(&RetryAttempt {
+                attempt,
+                delay,
+                error: &error,
+            });
+        }
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::fixed(DEFAULT_RETRY_DELAY)
+    }
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("backoff", &self.backoff)
+            .field("jitter", &self.jitter)
+            .field("max_attempts", &self.max_attempts)
+            .field("on_retry", &self.on_retry.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sfifo;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn test_retry_policy_backoff_and_limit() {
+        let policy = RetryPolicy::exponential(Duration::from_millis(10), Duration::from_millis(50));
+        let delays: Vec<_> = (1..=5).map(|n| policy.delay(n).unwrap()).collect();
+        assert_eq!(
+            delays,
+            [10, 20, 40, 50, 50].map(Duration::from_millis).to_vec()
+        );
+        let jittered = policy.clone().with_jitter(true).delay(3).unwrap();
+        assert!(jittered >= Duration::from_millis(20) && jittered <= Duration::from_millis(40));
+
+        // No reader: the open gives up after the last attempt
+        let fifo_path = "/tmp/test_retry_policy";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let retries = Arc::new(AtomicU32::new(0));
+        let policy = RetryPolicy::fixed(Duration::from_millis(10))
+            .max_attempts(3)
+            .on_retry({
+                let retries = retries.clone();
+                move |attempt| {
+                    assert_eq!(attempt.error.raw_os_error(), Some(libc::ENXIO));
+                    retries.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        let err = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_retry_policy(policy)
+            .open_sender()
+            .await
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENXIO));
+        assert_eq!(retries.load(Ordering::SeqCst), 2);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{
+    constant_time_eq,
+    control::{CONTROL_ROTATE, CONTROL_ROTATE_ACK},
+    Credentials, Frame, FrameKind, SecretString, Sfifo,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Shared token of both sides of a channel, which the server can replace
+/// on a live channel with `Channel::rotate_token`
+///
+/// Set on an `Sfifo`, it replaces the token handshakes are opened with.
+/// Each rotation starts a new epoch; servers keep accepting the token of
+/// the previous epoch for the grace window, so clients that missed the
+/// rotation can still reconnect.
+#[derive(Clone)]
+pub struct TokenRotation {
+    state: Arc<Mutex<RotationState>>,
+    grace: Duration,
+}
+
+struct RotationState {
+    epoch: u64,
+    current: SecretString,
+    // Token of the previous epoch, and until when it is accepted
+    previous: Option<(SecretString, Instant)>,
+}
+
+impl TokenRotation {
+    /// Start at epoch 0 with `token`, accepting the previous token for
+    /// `grace` after each rotation
+    pub fn new(token: impl Into<SecretString>, grace: Duration) -> Self {
+        TokenRotation {
+            state: Arc::new(Mutex::new(RotationState {
+                epoch: 0,
+                current: token.into(),
+                previous: None,
+            })),
+            grace,
+        }
+    }
+
+    /// Number of rotations applied so far
+    pub fn epoch(&self) -> u64 {
+        self.lock().epoch
+    }
+
+    /// Token of the current epoch
+    pub fn current(&self) -> SecretString {
+        self.lock().current.clone()
+    }
+
+    /// Switch to `token` for `epoch`, unless that epoch was already reached.
+    /// Returns whether the token changed
+    pub(crate) fn rotate(&self, epoch: u64, token: SecretString) -> bool {
+        let mut state = self.lock();
+        if epoch <= state.epoch {
+            return false;
+        }
+        let previous = std::mem::replace(&mut state.current, token);
+        state.previous = Some((previous, Instant::now() + self.grace));
+        state.epoch = epoch;
+        true
+    }
+
+    /// Whether `token` is the current one, or the previous one within the
+    /// grace window
+    pub(crate) fn accepts(&self, token: &str) -> bool {
+        let state = self.lock();
+        let current = constant_time_eq(state.current.expose().as_bytes(), token.as_bytes());
+        let previous = state.previous.as_ref().is_some_and(|(previous, until)| {
+            constant_time_eq(previous.expose().as_bytes(), token.as_bytes())
+                && Instant::now() < *until
+        });
+        current || previous
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RotationState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl std::fmt::Debug for TokenRotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenRotation")
+            .field("epoch", &self.epoch())
+            .field("grace", &self.grace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sfifo {
+    /// Credentials of a handshake: the current token of the rotation if
+    /// set, `credentials` otherwise
+    pub(crate) fn rotated_credentials(&self, credentials: &Credentials) -> Credentials {
+        match &self.token_rotation {
+            Some(rotation) => Credentials::shared(rotation.current())
+                .keep_peer_token(!credentials.scrubs_peer_token()),
+            None => credentials.clone(),
+        }
+    }
+}
+
+/// Control frame announcing `token` for `epoch`
+pub(crate) fn rotate_frame(epoch: u64, token: &str) -> Frame {
+    let mut payload = BytesMut::with_capacity(8 + token.len());
+    payload.put_u64_le(epoch);
+    payload.put_slice(token.as_bytes());
+    Frame {
+        tag: CONTROL_ROTATE,
+        ..Frame::new(FrameKind::Control, payload.freeze())
+    }
+}
+
+/// Epoch and token announced by a rotate frame
+pub(crate) fn decode_rotate(payload: &[u8]) -> std::io::Result<(u64, SecretString)> {
+    let invalid = |message| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    if payload.len() < 8 {
+        return Err(invalid("Token rotation frame too short"));
+    }
+    let (epoch, token) = payload.split_at(8);
+    let epoch = u64::from_le_bytes(epoch.try_into().expect("8 bytes"));
+    let token = std::str::from_utf8(token).map_err(|_| invalid("Rotated token is not UTF-8"))?;
+    Ok((epoch, SecretString::from(token)))
+}
+
+/// Acknowledgment of the rotation to `epoch`
+pub(crate) fn rotate_ack_frame(epoch: u64) -> Frame {
+    Frame {
+        tag: CONTROL_ROTATE_ACK,
+        ..Frame::new(
+            FrameKind::Control,
+            Bytes::copy_from_slice(&epoch.to_le_bytes()),
+        )
+    }
+}
+
+/// Whether `frame` acknowledges the rotation to `epoch`
+pub(crate) fn is_rotate_ack(frame: &Frame, epoch: u64) -> bool {
+    frame.kind == FrameKind::Control
+        && frame.tag == CONTROL_ROTATE_ACK
+        && frame.payload[..] == epoch.to_le_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Channel;
+
+    #[tokio::test]
+    async fn test_token_rotation() {
+        let fifo_path = "/tmp/test_token_rotation";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let grace = Duration::from_millis(500);
+        let server_rotation = TokenRotation::new("old_token", grace);
+        let client_rotation = TokenRotation::new("old_token", grace);
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_token_rotation(Some(server_rotation.clone()))
+            .clone();
+        let client_config = Sfifo::new(fifo_path)
+            .set_token_rotation(Some(client_rotation.clone()))
+            .clone();
+
+        // The live channel switches without dropping messages
+        let accept_config = server_config.clone();
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&accept_config, "old_token").await?;
+            channel.send(b"before").await?;
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let epoch = channel.rotate_token("new_token", deadline).await?;
+            channel.send(b"after").await?;
+            let reply = channel.recv().await?;
+            Ok::<_, std::io::Error>((epoch, reply))
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut client = Channel::connect(&client_config, "old_token").await.unwrap();
+        assert_eq!(&client.recv().await.unwrap().unwrap()[..], b"before");
+        assert_eq!(&client.recv().await.unwrap().unwrap()[..], b"after");
+        client.send(b"reply").await.unwrap();
+        let (epoch, reply) = server.await.unwrap().unwrap();
+        assert_eq!(epoch, 1);
+        assert_eq!(reply.as_deref(), Some(&b"reply"[..]));
+        assert_eq!(client_rotation.epoch(), 1);
+        assert_eq!(client_rotation.current(), SecretString::from("new_token"));
+        assert_eq!(server_rotation.current(), SecretString::from("new_token"));
+        drop(client);
+
+        // A client reconnecting with either token is accepted during the
+        // grace window, and only with the new one after it
+        let old_client = Sfifo::new(fifo_path);
+        for (client_config, token) in [(&client_config, ""), (&old_client, "old_token")] {
+            let accept_config = server_config.clone();
+            let server = tokio::spawn(async move { accept_config.open_as_server("").await });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            client_config.open_as_client(token).await.unwrap();
+            server.await.unwrap().unwrap();
+        }
+        tokio::time::sleep(grace).await;
+        assert!(!server_rotation.accepts("old_token"));
+        assert!(server_rotation.accepts("new_token"));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(crate::handshake_path(fifo_path, "rev")).await;
+    }
+
+    #[tokio::test]
+    async fn test_client_cannot_rotate() {
+        let fifo_path = "/tmp/test_client_cannot_rotate";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let server_rotation = TokenRotation::new("token", Duration::from_secs(1));
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_token_rotation(Some(server_rotation.clone()))
+            .clone();
+        let client_config = Sfifo::new(fifo_path)
+            .set_token_rotation(Some(TokenRotation::new("token", Duration::from_secs(1))))
+            .clone();
+
+        let server = tokio::spawn(async move {
+            let mut channel = Channel::accept(&server_config, "token").await?;
+            channel.recv().await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client = Channel::connect(&client_config, "token").await.unwrap();
+        let (mut sender, _receiver) = client.split();
+        sender
+            .send_frame(rotate_frame(1, "attacker_token"))
+            .await
+            .unwrap();
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(server_rotation.epoch(), 0);
+        assert!(server_rotation.accepts("token"));
+        assert!(!server_rotation.accepts("attacker_token"));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let _ = tokio::fs::remove_file(crate::handshake_path(fifo_path, "rev")).await;
+    }
+}
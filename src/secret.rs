@@ -0,0 +1,210 @@
+use zeroize::Zeroize;
+
+/// How pages holding secrets (tokens, session keys) are pinned in memory
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MlockMode {
+    /// Secrets are zeroized on drop but not locked
+    #[default]
+    Off,
+    /// Try to `mlock` secrets, logging a warning if the kernel refuses
+    BestEffort,
+    /// Fail when secrets cannot be locked in memory
+    Required,
+}
+
+/// A secret kept in its own heap allocation, optionally locked in memory
+/// so it is never swapped to disk, and zeroized when dropped.
+pub struct LockedSecret {
+    bytes: Box<[u8]>,
+    locked: bool,
+}
+
+impl LockedSecret {
+    /// Copy `secret` into a new allocation, locking it according to `mode`
+    pub fn new(secret: &str, mode: MlockMode) -> std::io::Result<Self> {
+        let bytes: Box<[u8]> = secret.as_bytes().into();
+        let mut locked = false;
+        if mode != MlockMode::Off && !bytes.is_empty() {
+            // The allocation stays at the same address until it is dropped
+            locked = unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } == 0;
+            if !locked {
+                let error = std::io::Error::last_os_error();
+                if mode == MlockMode::Required {
+                    return Err(error);
+                }
+                warn!("Failed to lock secret in memory: {}", error);
+            }
+        }
+        Ok(LockedSecret { bytes, locked })
+    }
+
+    /// Get the secret as a string slice
+    pub fn expose(&self) -> &str {
+        // Only ever built from a `&str`
+        std::str::from_utf8(&self.bytes).unwrap_or_default()
+    }
+
+    /// Check whether the secret is locked in memory
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl Drop for LockedSecret {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+        if self.locked {
+            unsafe { libc::munlock(self.bytes.as_ptr().cast(), self.bytes.len()) };
+        }
+    }
+}
+
+impl std::fmt::Debug for LockedSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LockedSecret")
+            .field("bytes", &"<redacted>")
+            .field("locked", &self.locked)
+            .finish()
+    }
+}
+
+/// Compare two byte strings in time that depends only on their lengths,
+/// so a failed token check does not tell how many leading bytes matched
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+/// A string secret zeroized when dropped, redacted in `Debug` output and
+/// compared in constant time
+#[derive(Clone, Default)]
+pub struct SecretString(String);
+
+impl SecretString {
+    /// Wrap `secret`, taking ownership of its allocation
+    pub fn new(secret: impl Into<String>) -> Self {
+        SecretString(secret.into())
+    }
+
+    /// Get the secret as a string slice
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        SecretString(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        SecretString(secret.to_string())
+    }
+}
+
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
+impl Eq for SecretString {}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretString(<redacted>)")
+    }
+}
+
+/// Tokens presented and expected during a handshake, see
+/// `Sfifo::open_as_server_with_credentials`
+///
+/// Both tokens are zeroized on drop, and the peer's copy of the token is
+/// scrubbed from `AuthenticatedFifo::peer_info()` once it was validated.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    secret: SecretString,
+    expected_peer: SecretString,
+    scrub_peer_token: bool,
+}
+
+impl Credentials {
+    /// Credentials where both sides share `token`
+    pub fn shared(token: impl Into<SecretString>) -> Self {
+        let secret = token.into();
+        Credentials::mutual(secret.clone(), secret)
+    }
+
+    /// Credentials presenting `secret` and expecting the peer to present
+    /// `expected_peer`
+    pub fn mutual(secret: impl Into<SecretString>, expected_peer: impl Into<SecretString>) -> Self {
+        Credentials {
+            secret: secret.into(),
+            expected_peer: expected_peer.into(),
+            scrub_peer_token: true,
+        }
+    }
+
+    /// Keep the peer's token in `AuthenticatedFifo::peer_info()` instead
+    /// of scrubbing it, unless secrets are locked in memory
+    pub fn keep_peer_token(mut self, keep: bool) -> Self {
+        self.scrub_peer_token = !keep;
+        self
+    }
+
+    /// Token presented to the peer
+    pub fn secret(&self) -> &SecretString {
+        &self.secret
+    }
+
+    /// Token the peer must present
+    pub fn expected_peer(&self) -> &SecretString {
+        &self.expected_peer
+    }
+
+    /// Check whether the peer's token is scrubbed after the handshake
+    pub fn scrubs_peer_token(&self) -> bool {
+        self.scrub_peer_token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locked_secret() {
+        let secret = LockedSecret::new("top_secret", MlockMode::BestEffort).unwrap();
+        assert_eq!(secret.expose(), "top_secret");
+        assert!(!format!("{:?}", secret).contains("top_secret"));
+
+        let unlocked = LockedSecret::new("top_secret", MlockMode::Off).unwrap();
+        assert!(!unlocked.is_locked());
+    }
+
+    #[test]
+    fn test_secret_string() {
+        let secret = SecretString::new("top_secret");
+        assert_eq!(secret.expose(), "top_secret");
+        assert!(!format!("{:?}", secret).contains("top_secret"));
+        assert_eq!(secret, SecretString::from("top_secret"));
+        assert_ne!(secret, SecretString::from("top_secreT"));
+        assert_ne!(secret, SecretString::from("top"));
+
+        let credentials = Credentials::shared("token");
+        assert_eq!(credentials.secret(), credentials.expected_peer());
+        assert!(credentials.scrubs_peer_token());
+        assert!(!format!("{:?}", credentials).contains("token\""));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{
+    audit::PeerSeen,
+    extension::EXT_SESSION,
+    handshake::{expect_type, HandshakeIo, SideChannels, MAX_MESSAGE_AGE_SECS},
+    nonce,
+    platform::random_bytes,
+    proof::Prover,
+    task::cancel_after,
+    trace_context::hex,
+    AuthenticatedFifo, ChannelState, Extension, HandshakeMessage, HandshakeType, LockedSecret,
+    Sfifo, HANDSHAKE_TIMEOUT,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long an issued session token can be redeemed by default
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(60);
+
+/// Session tokens issued by a server after full handshakes.
+///
+/// A client presenting one with `Sfifo::resume_session` skips the 3-way
+/// handshake. Tokens are single use, each resumption issues a new one.
+#[derive(Clone)]
+pub struct SessionStore {
+    sessions: Arc<Mutex<HashMap<String, Instant>>>,
+    ttl: Duration,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        SessionStore {
+            sessions: Arc::default(),
+            ttl: DEFAULT_SESSION_TTL,
+        }
+    }
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how long issued tokens stay valid
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Generate a token, valid once it is `insert`ed
+    pub(crate) fn issue() -> std::io::Result<String> {
+        let mut token = [0u8; 16];
+        random_bytes(&mut token)?;
+        Ok(hex(&token))
+    }
+
+    pub(crate) fn insert(&self, token: String) {
+        self.lock().insert(token, Instant::now() + self.ttl);
+    }
+
+    /// Consume `token`, returning whether it was valid
+    pub(crate) fn redeem(&self, token: &str) -> bool {
+        let mut sessions = self.lock();
+        let now = Instant::now();
+        sessions.retain(|_, expires| *expires > now);
+        sessions.remove(token).is_some()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Instant>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl std::fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionStore")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HandshakeMessage {
+    /// Session token issued by the server, or presented by a resuming client
+    pub fn session_token(&self) -> Option<&str> {
+        std::str::from_utf8(self.extension(EXT_SESSION)?).ok()
+    }
+
+    pub(crate) fn with_session_token(self, token: &str) -> Self {
+        self.with_extension(Extension::new(EXT_SESSION, token.as_bytes()))
+    }
+}
+
+impl AuthenticatedFifo {
+    /// Token to pass to `Sfifo::resume_session` when reconnecting, if the
+    /// server keeps a `SessionStore`
+    pub fn session_token(&self) -> Option<&str> {
+        self.peer_info().session_token()
+    }
+}
+
+impl Sfifo {
+    /// Reconnect as client with a session token from an earlier handshake,
+    /// in one round trip instead of the full 3-way handshake.
+    ///
+    /// The server must have been configured with a `SessionStore` and run
+    /// `open_as_server` as usual. The returned FIFO carries the token for
+    /// the next resumption.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "info",
+            skip_all,
+            fields(path = ?self.file_path, peer_pid = tracing::field::Empty, elapsed_ms = tracing::field::Empty)
+        )
+    )]
+    pub async fn resume_session(&self, session: &str) -> std::io::Result<AuthenticatedFifo> {
+        let started = Instant::now();
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let timer = cancel_after(HANDSHAKE_TIMEOUT, &cancel);
+        let connecting = self.state.connecting(ChannelState::Handshaking);
+        let secret = LockedSecret::new(session, self.mlock_secrets)?;
+        let seen = PeerSeen::default();
+        let peer_info = tokio::select! {
+            peer_info = seen.watch(self.perform_resume_handshake(secret.expose(), &cancel)) => peer_info,
+            _ = cancel.cancelled() => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Handshake timeout",
+            )),
+        };
+        drop(timer);
+        let peer = peer_info.and_then(|peer_info| self.verify_peer(peer_info));
+        self.record_handshake(&peer);
+        self.audit_handshake(false, peer.as_ref().map(|(peer_info, _)| peer_info), &seen);
+        let (peer_info, identity) = peer?;
+        record_span!("peer_pid", peer_info.process_id);
+        record_span!("elapsed_ms", started.elapsed().as_millis() as u64);
+        info!(
+            "Session resumed with server PID {} in {:?}",
+            peer_info.process_id,
+            started.elapsed()
+        );
+        let file = self.open_sender().await?;
+        connecting.connected();
+        Ok(AuthenticatedFifo::new_sender(file, peer_info, false)
+            .with_config(self)
+            .with_peer_identity(identity))
+    }
+
+    async fn perform_resume_handshake(
+        &self,
+        session: &str,
+        cancel: &tokio_util::sync::CancellationToken,
+    ) -> std::io::Result<HandshakeMessage> {
+        debug!("client: Sending session resumption request");
+        let mut side_channels = SideChannels::new(self, false);
+        let client_nonce = nonce::new_nonce()?;
+        let request = HandshakeMessage::new(String::new(), HandshakeType::Request)?
+            .with_nonce(&client_nonce)
+            .with_session_token(session)
+            .with_wire_format(self.wire_format);
+        side_channels.send(&request).await?;
+
+        let response = side_channels.recv(cancel).await?;
+        expect_type(&response, HandshakeType::Response)?;
+        response.check_timestamp(MAX_MESSAGE_AGE_SECS)?;
+        response.check_nonce_echo(&client_nonce)?;
+        if response.session_token().is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Session resumption rejected",
+            ));
+        }
+        // Only the server that issued the session knows its token
+        let server_nonce = response.fresh_nonce(self.nonce_cache.as_ref())?;
+        response.check_proof(session, Prover::Session, &client_nonce, &server_nonce)?;
+        Ok(response)
+    }
+
+    /// Response to a resumption request read by the server handshake, and
+    /// whether the session was redeemed. A response without session token
+    /// tells the client it was refused
+    pub(crate) fn session_response(
+        &self,
+        request: &HandshakeMessage,
+        session: &str,
+    ) -> std::io::Result<(HandshakeMessage, bool)> {
+        request.check_timestamp(MAX_MESSAGE_AGE_SECS)?;
+        let client_nonce = request.fresh_nonce(self.nonce_cache.as_ref())?;
+        let accepted = self.sessions.as_ref().is_some_and(|s| s.redeem(session));
+        let server_nonce = nonce::new_nonce()?;
+        let mut response = HandshakeMessage::new(String::new(), HandshakeType::Response)?
+            .with_nonce(&server_nonce)
+            .with_nonce_echo(&client_nonce)
+            .with_wire_format(request.wire_format);
+        if accepted {
+            let next = SessionStore::issue()?;
+            response = response.with_session_token(&next).with_proof(
+                session,
+                Prover::Session,
+                &client_nonce,
+                &server_nonce,
+            );
+            response = self.with_server_extensions(response)?;
+            if let Some(sessions) = &self.sessions {
+                sessions.insert(next);
+            }
+        }
+        Ok((response, accepted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resume_session() {
+        let fifo_path = "/tmp/test_resume_session";
+        let token = "session_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let server_config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_sessions(Some(SessionStore::new()))
+            .clone();
+        let client_config = Sfifo::new(fifo_path);
+
+        let server = server_config.clone();
+        let server_handle = tokio::spawn(async move { server.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client_fifo = client_config.open_as_client(token).await.unwrap();
+        server_handle.await.unwrap().unwrap();
+        let session = client_fifo.session_token().unwrap().to_string();
+        drop(client_fifo);
+
+        let server = server_config.clone();
+        let server_handle = tokio::spawn(async move { server.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let resumed = client_config.resume_session(&session).await.unwrap();
+        let server_fifo = server_handle.await.unwrap().unwrap();
+        assert_eq!(server_fifo.peer_info().process_id, std::process::id());
+        let next = resumed.session_token().unwrap().to_string();
+        assert_ne!(next, session);
+        drop(resumed);
+
+        // Tokens are single use
+        let server_handle = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let error = client_config.resume_session(&session).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(server_handle.await.unwrap().is_err());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_resume_session_rejects_forged_response() {
+        let fifo_path = "/tmp/test_resume_forged";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        // Echoes the nonce and hands out a session, without knowing the
+        // session token the client presents
+        let impostor_config = config.clone();
+        let impostor = tokio::spawn(async move {
+            let cancel = tokio_util::sync::CancellationToken::new();
+            let mut side_channels = SideChannels::new(&impostor_config, true);
+            let request = side_channels.recv(&cancel).await?;
+            let response = HandshakeMessage::new(String::new(), HandshakeType::Response)?
+                .with_nonce(&nonce::new_nonce()?)
+                .with_nonce_echo(&request.nonce().unwrap())
+                .with_session_token("forged");
+            side_channels.send(&response).await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let error = config.resume_session("real_session").await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        impostor.await.unwrap().unwrap();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{
+    backpressure::pipe_fill_level,
+    control::{control_frame, CONTROL_CLOSE},
+    health::pipe_connected,
+    FramedSender, SfifoError,
+};
+use std::{
+    os::fd::AsRawFd,
+    time::{Duration, Instant},
+};
+use tokio::net::unix::pipe::Sender;
+
+// How often the pipe is checked while the reader drains it
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+impl FramedSender {
+    /// Close the stream without cutting the reader off mid-message
+    ///
+    /// Flushes the frames sent so far, sends a close frame and waits until
+    /// the reader has read everything from the pipe. The reader's `recv()`
+    /// returns `None` at the close frame. Fails with
+    /// `SfifoError::DrainTimeout` if bytes are still unread at `deadline`,
+    /// or `BrokenPipe` if the reader goes away before reading them.
+    pub async fn shutdown_gracefully(mut self, deadline: Instant) -> std::io::Result<()> {
+        let drained = tokio::time::timeout_at(deadline.into(), async {
+            self.send_frame(control_frame(CONTROL_CLOSE)).await?;
+            wait_drained(self.get_ref()).await
+        })
+        .await;
+        match drained {
+            Ok(drained) => drained,
+            Err(_) => {
+                let (unread, _) = pipe_fill_level(self.get_ref())?;
+                Err(SfifoError::DrainTimeout {
+                    unread: Some(unread),
+                }
+                .into())
+            }
+        }
+    }
+}
+
+/// Wait until the pipe of `sender` is empty
+async fn wait_drained(sender: &Sender) -> std::io::Result<()> {
+    loop {
+        let (queued, _) = pipe_fill_level(sender)?;
+        if queued == 0 {
+            return Ok(());
+        }
+        if !pipe_connected(sender.as_raw_fd(), true) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                format!("Reader closed the FIFO with {} bytes unread", queued),
+            ));
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FramedReceiver, Sfifo};
+
+    #[tokio::test]
+    async fn test_framed_sender_shutdown_gracefully() {
+        let fifo_path = "/tmp/test_shutdown_gracefully";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+        let mut receiver = FramedReceiver::new(config.open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(config.open_sender().await.unwrap());
+
+        // Nobody reads: the deadline passes with the frames in the pipe
+        sender.send(b"first").await.unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let error = sender.shutdown_gracefully(deadline).await.unwrap_err();
+        let Some(SfifoError::DrainTimeout {
+            unread: Some(unread),
+        }) = SfifoError::from_io(&error)
+        else {
+            panic!("unexpected error {:?}", error);
+        };
+        assert!(*unread > 0);
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"first");
+        // The close frame ends the stream although the FIFO is still open
+        assert!(receiver.recv().await.unwrap().is_none());
+
+        // A reader draining the pipe lets the shutdown complete
+        let mut receiver = FramedReceiver::new(config.open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(config.open_sender().await.unwrap());
+        let _writer = config.open_sender().await.unwrap();
+        sender.send(b"second").await.unwrap();
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(message) = receiver.recv().await.unwrap() {
+                received.push(message);
+            }
+            received
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        sender.shutdown_gracefully(deadline).await.unwrap();
+        assert_eq!(reader.await.unwrap(), vec![&b"second"[..]]);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
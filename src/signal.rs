@@ -0,0 +1,164 @@
+use crate::{owned, task::spawn_named, Sfifo};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+// Cancelled on the first SIGTERM or SIGINT once the listener runs, and
+// replaced for the connections opened after it
+static SHUTDOWN: Mutex<Option<CancellationToken>> = Mutex::new(None);
+// Listener task, installed again if the runtime it ran on went away
+static LISTENER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+// FIFOs created from configurations with signal shutdown, by handshakes in
+// progress or owned by open connections, with the number of users of each
+static PENDING: Mutex<Option<HashMap<PathBuf, usize>>> = Mutex::new(None);
+
+impl Sfifo {
+    /// Close the connections opened from this configuration cleanly on
+    /// SIGTERM or SIGINT, and remove the FIFO files they created
+    ///
+    /// The first connection opened installs a process-wide listener for
+    /// both signals, which replaces their default action. On the first
+    /// signal, handshakes in progress fail, `Channel::recv()` sends the
+    /// peer a close frame and returns `None`, and the FIFOs created from
+    /// configurations with signal shutdown are removed with their
+    /// `.c2s`/`.s2c` handshake pair, so the application can leave its
+    /// loops and exit. Other FIFOs are left alone. Connections opened
+    /// after the signal are not affected by it. A second signal exits the
+    /// process right away. Also sets `cleanup_on_drop`.
+    pub fn with_signal_shutdown(&mut self) -> &mut Self {
+        self.signal_shutdown = true;
+        self.cleanup_on_drop = true;
+        self
+    }
+
+    /// Cancellation token of a handshake, cancelled by a shutdown signal
+    /// if enabled
+    pub(crate) fn handshake_cancel_token(&self) -> CancellationToken {
+        if !self.signal_shutdown {
+            return CancellationToken::new();
+        }
+        install();
+        shutdown_token().child_token()
+    }
+
+    /// Token cancelled by a shutdown signal, if enabled
+    pub(crate) fn signal_shutdown_token(&self) -> Option<CancellationToken> {
+        self.signal_shutdown.then(|| {
+            install();
+            shutdown_token()
+        })
+    }
+
+    /// Have a shutdown signal remove the FIFO files of this configuration
+    /// until the returned guard is dropped
+    pub(crate) fn track_created(&self) -> Option<Tracked> {
+        (self.signal_shutdown && self.create).then(|| Tracked::new(&self.file_path))
+    }
+}
+
+/// Token cancelled by the next SIGTERM or SIGINT received by a process
+/// using `Sfifo::with_signal_shutdown`, for the application to stop its own
+/// work. Tokens taken after a signal are cancelled by the next one only
+pub fn shutdown_signal() -> CancellationToken {
+    shutdown_token()
+}
+
+fn shutdown_token() -> CancellationToken {
+    SHUTDOWN
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(CancellationToken::new)
+        .clone()
+}
+
+/// Cancel the token of the current connections, later ones get a new one
+fn cancel_shutdown_token() {
+    let token = SHUTDOWN.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(token) = token {
+        token.cancel();
+    }
+}
+
+/// Start listening for the shutdown signals, unless already listening
+fn install() {
+    let mut listener = LISTENER.lock().unwrap_or_else(|e| e.into_inner());
+    if listener.as_ref().is_some_and(|task| !task.is_finished()) {
+        return;
+    }
+    let signals = signal(SignalKind::terminate())
+        .and_then(|terminate| Ok((terminate, signal(SignalKind::interrupt())?)));
+    match signals {
+        Ok((terminate, interrupt)) => {
+            *listener = Some(spawn_named(
+                "sfifo::signal_shutdown",
+                listen(terminate, interrupt),
+            ));
+        }
+        Err(e) => warn!("Failed to listen for shutdown signals: {}", e),
+    }
+}
+
+async fn listen(mut terminate: Signal, mut interrupt: Signal) {
+    let signal = next_signal(&mut terminate, &mut interrupt).await;
+    info!("Received signal {}, closing FIFO connections", signal);
+    cancel_shutdown_token();
+    remove_files();
+
+    let signal = next_signal(&mut terminate, &mut interrupt).await;
+    warn!("Received signal {} again, exiting", signal);
+    remove_files();
+    std::process::exit(128 + signal);
+}
+
+async fn next_signal(terminate: &mut Signal, interrupt: &mut Signal) -> i32 {
+    tokio::select! {
+        _ = terminate.recv() => libc::SIGTERM,
+        _ = interrupt.recv() => libc::SIGINT,
+    }
+}
+
+/// Remove the FIFOs created from configurations with signal shutdown and
+/// their companions
+fn remove_files() {
+    let pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    for path in pending.iter().flat_map(|paths| paths.keys()) {
+        owned::remove_fifo_files(path);
+    }
+}
+
+/// FIFO removed by a shutdown signal while the guard lives, held by the
+/// handshake creating it and then by the `OwnedFifo` of the connection
+#[derive(Debug)]
+pub(crate) struct Tracked(PathBuf);
+
+impl Tracked {
+    fn new(path: &Path) -> Self {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        *pending
+            .get_or_insert_with(HashMap::new)
+            .entry(path.to_path_buf())
+            .or_default() += 1;
+        Tracked(path.to_path_buf())
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(paths) = pending.as_mut() {
+            if let Some(count) = paths.get_mut(&self.0) {
+                *count -= 1;
+                if *count == 0 {
+                    paths.remove(&self.0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{
+    control::CONTROL_SNAPSHOT, reliable::decode_sequence, Channel, Frame, FrameKind,
+    FramedReceiver, FramedSender, Sfifo, SfifoError, RELIABLE_SEQUENCE_LEN,
+};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::FutureExt;
+
+/// Callback producing the current state for a `SnapshotPublisher`
+pub type SnapshotSource = Box<dyn FnMut() -> Bytes + Send>;
+
+/// What a `SnapshotSubscriber` received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEvent {
+    /// Full state, replacing the current one
+    Snapshot(Bytes),
+    /// Update to apply on top of the state
+    Update(Bytes),
+}
+
+/// Sending side of the snapshot and catch-up pattern
+///
+/// Each subscriber first gets a snapshot of the state, produced by the
+/// snapshot callback, then every update published after it. Updates are
+/// numbered and snapshots carry the number of the last update they
+/// include, so a subscriber sees no gap and no duplicate between the
+/// snapshot and the stream.
+pub struct SnapshotPublisher {
+    sender: FramedSender,
+    receiver: FramedReceiver,
+    snapshot: SnapshotSource,
+    // Sequence of the last published update
+    sequence: u64,
+}
+
+impl SnapshotPublisher {
+    /// Accept a subscriber and send it the snapshot it requests
+    pub async fn accept(
+        config: &Sfifo,
+        token: &str,
+        snapshot: impl FnMut() -> Bytes + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let (sender, mut receiver) = Channel::accept(config, token).await?.split();
+        loop {
+            let frame = receiver.recv_frame().await?.ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Subscriber closed before requesting a snapshot",
+                )
+            })?;
+            if is_snapshot(&frame) {
+                break;
+            }
+        }
+        let mut publisher = SnapshotPublisher {
+            sender,
+            receiver,
+            snapshot: Box::new(snapshot),
+            sequence: 0,
+        };
+        publisher.send_snapshot().await?;
+        Ok(publisher)
+    }
+
+    /// Sequence of the last published update
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Publish an update, then answer pending snapshot requests
+    ///
+    /// The update must already be applied to the state returned by the
+    /// snapshot callback.
+    pub async fn publish(&mut self, update: &[u8]) -> std::io::Result<()> {
+        self.sequence += 1;
+        let mut payload = BytesMut::with_capacity(RELIABLE_SEQUENCE_LEN + update.len());
+        payload.put_u64_le(self.sequence);
+        payload.put_slice(update);
+        self.sender
+            .send_frame(Frame::data(payload.freeze()))
+            .await?;
+        self.serve_requests().await
+    }
+
+    /// Answer the snapshot requests received so far, without waiting
+    async fn serve_requests(&mut self) -> std::io::Result<()> {
+        while let Some(frame) = self.receiver.recv_frame().now_or_never() {
+            match frame? {
+                Some(frame) if is_snapshot(&frame) => self.send_snapshot().await?,
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_snapshot(&mut self) -> std::io::Result<()> {
+        let state = (self.snapshot)();
+        debug!(
+            "Sending a snapshot of {} bytes at update {}",
+            state.len(),
+            self.sequence
+        );
+        let mut payload = BytesMut::with_capacity(RELIABLE_SEQUENCE_LEN + state.len());
+        payload.put_u64_le(self.sequence);
+        payload.put_slice(&state);
+        self.sender
+            .send_frame(snapshot_frame(payload.freeze()))
+            .await
+    }
+}
+
+impl std::fmt::Debug for SnapshotPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotPublisher")
+            .field("sequence", &self.sequence)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Receiving side of the snapshot and catch-up pattern, see
+/// `SnapshotPublisher`
+#[derive(Debug)]
+pub struct SnapshotSubscriber {
+    sender: FramedSender,
+    receiver: FramedReceiver,
+    // Sequence of the last update applied
+    sequence: u64,
+    // Set while a requested snapshot has not arrived
+    awaiting_snapshot: bool,
+}
+
+impl SnapshotSubscriber {
+    /// Connect to a publisher, returning the subscriber and the snapshot
+    /// of the state
+    pub async fn connect(config: &Sfifo, token: &str) -> std::io::Result<(Self, Bytes)> {
+        let (sender, receiver) = Channel::connect(config, token).await?.split();
+        let mut subscriber = SnapshotSubscriber {
+            sender,
+            receiver,
+            sequence: 0,
+            awaiting_snapshot: false,
+        };
+        subscriber.request_snapshot().await?;
+        match subscriber.recv().await? {
+            Some(SyncEvent::Snapshot(state)) => Ok((subscriber, state)),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Publisher closed before sending a snapshot",
+            )),
+        }
+    }
+
+    /// Sequence of the last update included in the state
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Ask for a new snapshot, e.g. after a `SfifoError::SequenceGap`
+    ///
+    /// Updates are skipped until the snapshot arrives, which the publisher
+    /// sends after its next update.
+    pub async fn request_snapshot(&mut self) -> std::io::Result<()> {
+        self.awaiting_snapshot = true;
+        self.sender.send_frame(snapshot_frame(Bytes::new())).await
+    }
+
+    /// Receive the next snapshot or update, `None` once the publisher
+    /// has closed
+    ///
+    /// Updates already included in the last snapshot are skipped, a
+    /// missing update fails with `SfifoError::SequenceGap`.
+    pub async fn recv(&mut self) -> std::io::Result<Option<SyncEvent>> {
+        while let Some(frame) = self.receiver.recv_frame().await? {
+            if is_snapshot(&frame) {
+                let (sequence, state) = decode_sequence(frame.payload)?;
+                self.sequence = sequence;
+                self.awaiting_snapshot = false;
+                return Ok(Some(SyncEvent::Snapshot(state)));
+            }
+            if frame.kind != FrameKind::Data || self.awaiting_snapshot {
+                continue;
+            }
+            let (sequence, update) = decode_sequence(frame.payload)?;
+            if sequence <= self.sequence {
+                continue;
+            }
+            if sequence != self.sequence + 1 {
+                return Err(SfifoError::SequenceGap {
+                    expected: self.sequence + 1,
+                    received: sequence,
+                }
+                .into());
+            }
+            self.sequence = sequence;
+            return Ok(Some(SyncEvent::Update(update)));
+        }
+        Ok(None)
+    }
+}
+
+fn snapshot_frame(payload: Bytes) -> Frame {
+    Frame {
+        tag: CONTROL_SNAPSHOT,
+        ..Frame::new(FrameKind::Control, payload)
+    }
+}
+
+fn is_snapshot(frame: &Frame) -> bool {
+    frame.kind == FrameKind::Control && frame.tag == CONTROL_SNAPSHOT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_snapshot_then_updates_without_gap() {
+        let fifo_path = "/tmp/test_snapshot";
+        let token = "snapshot_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        let state = Arc::new(Mutex::new(vec![b'a']));
+        let publisher = tokio::spawn({
+            let config = config.clone();
+            let state = state.clone();
+            async move {
+                let source = state.clone();
+                let mut publisher = SnapshotPublisher::accept(&config, token, move || {
+                    Bytes::from(source.lock().unwrap().clone())
+                })
+                .await?;
+                for update in [b'b', b'c', b'd'] {
+                    state.lock().unwrap().push(update);
+                    publisher.publish(&[update]).await?;
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Ok::<_, std::io::Error>(publisher.sequence())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let (mut subscriber, snapshot) = SnapshotSubscriber::connect(&config, token).await.unwrap();
+        assert_eq!(&snapshot[..], b"a");
+        assert_eq!(subscriber.sequence(), 0);
+        let mut replica = snapshot.to_vec();
+        assert_eq!(
+            subscriber.recv().await.unwrap(),
+            Some(SyncEvent::Update(Bytes::from_static(b"b")))
+        );
+        replica.push(b'b');
+
+        // A snapshot requested mid-stream replaces the state, later
+        // updates continue right after it
+        subscriber.request_snapshot().await.unwrap();
+        while let Some(event) = subscriber.recv().await.unwrap() {
+            match event {
+                SyncEvent::Snapshot(state) => replica = state.to_vec(),
+                SyncEvent::Update(update) => replica.extend_from_slice(&update),
+            }
+        }
+        assert_eq!(replica, b"abcd");
+        assert_eq!(subscriber.sequence(), 3);
+        assert_eq!(publisher.await.unwrap().unwrap(), 3);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
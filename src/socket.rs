@@ -0,0 +1,444 @@
+use crate::{
+    audit::PeerSeen,
+    handshake::{self, SfifoHooks},
+    handshake_path, task, AuthenticatedFifo, Credentials, FifoTransport, FrameCodec,
+    HandshakeMessage, KernelPeerCred, LockedSecret, PeerIdentity, Sfifo, HANDSHAKE_TIMEOUT,
+};
+use std::{
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{unix::UCred, UnixListener, UnixStream},
+};
+use tokio_util::codec::Framed;
+
+// Content of the marker file of a server accepting Unix socket clients
+const SOCKET_MARKER: &str = "unix-socket\n";
+
+/// What a `Connection` runs over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// The FIFO, one direction per open
+    #[default]
+    Fifo,
+    /// A Unix stream socket next to the FIFO, both directions at once
+    UnixSocket,
+}
+
+/// Listening socket and marker file offered by a server, removed when
+/// dropped
+struct SocketOffer {
+    listener: UnixListener,
+    socket_path: PathBuf,
+    marker_path: PathBuf,
+}
+
+impl SocketOffer {
+    fn bind(file_path: &Path) -> std::io::Result<Self> {
+        let socket_path = handshake_path(file_path, "sock");
+        // A server that died left its socket behind
+        if std::fs::symlink_metadata(&socket_path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&socket_path)?;
+        }
+        let listener = UnixListener::bind(&socket_path)?;
+        let marker_path = handshake_path(file_path, "transport");
+        let offer = SocketOffer {
+            listener,
+            socket_path,
+            marker_path,
+        };
+        std::fs::write(&offer.marker_path, SOCKET_MARKER)?;
+        Ok(offer)
+    }
+}
+
+impl Drop for SocketOffer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.marker_path);
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Whether the server of the FIFO at `file_path` accepts socket clients
+fn socket_offered(file_path: &Path) -> bool {
+    std::fs::read_to_string(handshake_path(file_path, "transport"))
+        .is_ok_and(|marker| marker == SOCKET_MARKER)
+}
+
+impl Sfifo {
+    /// Open as server over the transport the client picks
+    ///
+    /// Without `prefer_socket` this is `open_as_server_with_credentials`.
+    /// With it, the server also listens on a Unix socket at `<path>.sock`
+    /// and announces it in the marker file `<path>.transport` until a
+    /// client connects, over the socket or the FIFO.
+    pub async fn open_transport_as_server(
+        &self,
+        credentials: &Credentials,
+    ) -> std::io::Result<Connection> {
+        if !self.prefer_socket {
+            return self
+                .open_as_server_with_credentials(credentials)
+                .await
+                .map(Connection::Fifo);
+        }
+        let offer = SocketOffer::bind(&self.file_path)?;
+        tokio::select! {
+            fifo = self.open_as_server_with_credentials(credentials) => fifo.map(Connection::Fifo),
+            socket = self.accept_socket(&offer.listener, credentials) => socket.map(Connection::Socket),
+        }
+    }
+
+    /// Open as client over the Unix socket if both sides prefer it, the
+    /// FIFO otherwise
+    ///
+    /// The socket is used when `prefer_socket` is set and the marker file
+    /// of the server announces it. A marker left by a server that died
+    /// falls back to the FIFO.
+    pub async fn open_transport_as_client(
+        &self,
+        credentials: &Credentials,
+    ) -> std::io::Result<Connection> {
+        if self.prefer_socket && socket_offered(&self.file_path) {
+            match UnixStream::connect(handshake_path(&self.file_path, "sock")).await {
+                Ok(stream) => {
+                    let seen = PeerSeen::default();
+                    let socket = seen
+                        .watch(self.socket_client_handshake(stream, credentials))
+                        .await;
+                    self.audit_handshake(false, socket.as_ref().map(|s| &s.peer_info), &seen);
+                    return socket.map(Connection::Socket);
+                }
+                Err(e) => debug!("Socket offered but unreachable, using the FIFO: {}", e),
+            }
+        }
+        self.open_as_client_with_credentials(credentials)
+            .await
+            .map(Connection::Fifo)
+    }
+
+    async fn accept_socket(
+        &self,
+        listener: &UnixListener,
+        credentials: &Credentials,
+    ) -> std::io::Result<AuthenticatedSocket> {
+        let (stream, _) = listener.accept().await?;
+        let seen = PeerSeen::default();
+        let socket = seen
+            .watch(self.socket_server_handshake(stream, credentials))
+            .await;
+        self.audit_handshake(true, socket.as_ref().map(|s| &s.peer_info), &seen);
+        socket
+    }
+
+    async fn socket_server_handshake(
+        &self,
+        mut stream: UnixStream,
+        credentials: &Credentials,
+    ) -> std::io::Result<AuthenticatedSocket> {
+        let cancel = self.handshake_cancel_token();
+        let _timer = task::cancel_after(HANDSHAKE_TIMEOUT, &cancel);
+        let credentials = &self.rotated_credentials(credentials);
+        let secret = LockedSecret::new(credentials.secret().expose(), self.mlock_secrets)?;
+        let expected = LockedSecret::new(credentials.expected_peer().expose(), self.mlock_secrets)?;
+        let client_request = handshake::server_exchange(
+            &mut stream.split(),
+            &mut SfifoHooks::socket(self),
+            secret.expose(),
+            expected.expose(),
+            &cancel,
+        )
+        .await?;
+        self.authenticated_socket(stream, client_request, true, credentials)
+    }
+
+    async fn socket_client_handshake(
+        &self,
+        mut stream: UnixStream,
+        credentials: &Credentials,
+    ) -> std::io::Result<AuthenticatedSocket> {
+        let cancel = self.handshake_cancel_token();
+        let _timer = task::cancel_after(HANDSHAKE_TIMEOUT, &cancel);
+        let credentials = &self.rotated_credentials(credentials);
+        let secret = LockedSecret::new(credentials.secret().expose(), self.mlock_secrets)?;
+        let expected = LockedSecret::new(credentials.expected_peer().expose(), self.mlock_secrets)?;
+        let server_response = handshake::client_exchange(
+            &mut stream.split(),
+            &mut SfifoHooks::socket(self),
+            secret.expose(),
+            expected.expose(),
+            &cancel,
+        )
+        .await?;
+        self.authenticated_socket(stream, server_response, false, credentials)
+    }
+
+    /// Check the peer of an authenticated socket against the kernel's
+    /// view of it and the peer policy
+    fn authenticated_socket(
+        &self,
+        stream: UnixStream,
+        peer_info: HandshakeMessage,
+        is_server: bool,
+        credentials: &Credentials,
+    ) -> std::io::Result<AuthenticatedSocket> {
+        let kernel_cred = KernelPeerCred::verify(stream.peer_cred()?, peer_info.process_id)?;
+        let peer = self.verify_peer(HandshakeMessage {
+            kernel_cred: Some(kernel_cred),
+            ..peer_info
+        });
+        self.record_handshake(&peer);
+        let (mut peer_info, identity) = peer?;
+        self.scrub_credentials(credentials, &mut peer_info);
+        info!(
+            "Handshake completed over the socket with PID {}",
+            peer_info.process_id
+        );
+        Ok(AuthenticatedSocket {
+            stream,
+            peer_info,
+            is_server,
+            peer_identity: identity,
+            max_frame_size: self.frame_size_limit(),
+        })
+    }
+}
+
+/// Authenticated Unix socket connection, readable and writable at once
+#[derive(Debug)]
+pub struct AuthenticatedSocket {
+    stream: UnixStream,
+    peer_info: HandshakeMessage,
+    is_server: bool,
+    peer_identity: Option<PeerIdentity>,
+    max_frame_size: usize,
+}
+
+impl AuthenticatedSocket {
+    pub fn peer_info(&self) -> &HandshakeMessage {
+        &self.peer_info
+    }
+
+    pub fn is_server(&self) -> bool {
+        self.is_server
+    }
+
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.peer_identity.as_ref()
+    }
+
+    /// Credentials of the peer process as seen by the kernel
+    /// (`SO_PEERCRED`)
+    pub fn peer_cred(&self) -> std::io::Result<UCred> {
+        self.stream.peer_cred()
+    }
+
+    /// Send and receive frames in the sfifo frame format, limited to the
+    /// maximum frame size of the configuration
+    pub fn into_framed(self) -> Framed<UnixStream, FrameCodec> {
+        Framed::new(
+            self.stream,
+            FrameCodec::new().max_frame_size(self.max_frame_size),
+        )
+    }
+
+    pub fn into_inner(self) -> UnixStream {
+        self.stream
+    }
+}
+
+impl AsyncRead for AuthenticatedSocket {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for AuthenticatedSocket {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl FifoTransport for AuthenticatedSocket {
+    fn peer_info(&self) -> Option<&HandshakeMessage> {
+        Some(AuthenticatedSocket::peer_info(self))
+    }
+}
+
+/// Authenticated connection opened with `open_transport_as_server` or
+/// `open_transport_as_client`
+// Opened once per connection, boxing the FIFO would only add indirection
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Connection {
+    Fifo(AuthenticatedFifo),
+    Socket(AuthenticatedSocket),
+}
+
+impl Connection {
+    pub fn transport(&self) -> Transport {
+        match self {
+            Connection::Fifo(_) => Transport::Fifo,
+            Connection::Socket(_) => Transport::UnixSocket,
+        }
+    }
+
+    pub fn peer_info(&self) -> &HandshakeMessage {
+        match self {
+            Connection::Fifo(fifo) => fifo.peer_info(),
+            Connection::Socket(socket) => socket.peer_info(),
+        }
+    }
+
+    pub fn is_server(&self) -> bool {
+        match self {
+            Connection::Fifo(fifo) => fifo.is_server(),
+            Connection::Socket(socket) => socket.is_server(),
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Connection::Fifo(fifo) => Pin::new(fifo).poll_read(cx, buf),
+            Connection::Socket(socket) => Pin::new(socket).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Fifo(fifo) => Pin::new(fifo).poll_write(cx, buf),
+            Connection::Socket(socket) => Pin::new(socket).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Connection::Fifo(fifo) => Pin::new(fifo).poll_flush(cx),
+            Connection::Socket(socket) => Pin::new(socket).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Connection::Fifo(fifo) => Pin::new(fifo).poll_shutdown(cx),
+            Connection::Socket(socket) => Pin::new(socket).poll_shutdown(cx),
+        }
+    }
+}
+
+impl FifoTransport for Connection {
+    fn peer_info(&self) -> Option<&HandshakeMessage> {
+        Some(Connection::peer_info(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures_util::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_socket_transport() {
+        let fifo_path = "/tmp/test_socket_transport";
+        let credentials = Credentials::shared("socket_token");
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_prefer_socket(true)
+            .clone();
+
+        // Both sides prefer the socket: frames flow both ways
+        let server_config = config.clone();
+        let server_credentials = credentials.clone();
+        let server = tokio::spawn(async move {
+            server_config
+                .open_transport_as_server(&server_credentials)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(socket_offered(Path::new(fifo_path)));
+        let client = config.open_transport_as_client(&credentials).await.unwrap();
+        let server = server.await.unwrap().unwrap();
+        assert_eq!(client.transport(), Transport::UnixSocket);
+        assert_eq!(server.transport(), Transport::UnixSocket);
+        assert!(!socket_offered(Path::new(fifo_path)));
+        let (Connection::Socket(client), Connection::Socket(server)) = (client, server) else {
+            unreachable!();
+        };
+        assert_eq!(
+            client.peer_cred().unwrap().pid(),
+            Some(std::process::id() as i32)
+        );
+        let mut client = client.into_framed();
+        let mut server = server.into_framed();
+        client
+            .send(crate::Frame::data(Bytes::from_static(b"ping")))
+            .await
+            .unwrap();
+        let frame = server.next().await.unwrap().unwrap();
+        assert_eq!(&frame.payload[..], b"ping");
+        server
+            .send(crate::Frame::data(Bytes::from_static(b"pong")))
+            .await
+            .unwrap();
+        assert_eq!(&client.next().await.unwrap().unwrap().payload[..], b"pong");
+
+        // A client without the preference uses the FIFO
+        let server_config = config.clone();
+        let server_credentials = credentials.clone();
+        let server = tokio::spawn(async move {
+            server_config
+                .open_transport_as_server(&server_credentials)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut client = Sfifo::new(fifo_path)
+            .open_transport_as_client(&credentials)
+            .await
+            .unwrap();
+        assert_eq!(client.transport(), Transport::Fifo);
+        client.write_all(b"fifo").await.unwrap();
+        let mut server = server.await.unwrap().unwrap();
+        assert_eq!(server.transport(), Transport::Fifo);
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"fifo");
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,131 @@
+use crate::fifo_openers;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// What `/proc` tells about a FIFO an open is stuck on, see
+/// `Sfifo::set_stall_diagnostics`
+///
+/// This is best effort: processes of other users are only visible with
+/// sufficient privileges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StallReport {
+    pub path: PathBuf,
+    /// How long the open had been waiting
+    pub waited: Duration,
+    /// Whether the path exists
+    pub exists: bool,
+    /// Processes with the FIFO open for reading
+    pub readers: Vec<u32>,
+    /// Processes with the FIFO open for writing
+    pub writers: Vec<u32>,
+    /// Processes blocked opening a FIFO, waiting for its other end.
+    /// `/proc` does not tell which FIFO, nor is it always readable
+    pub blocked_opening: Vec<u32>,
+}
+
+impl StallReport {
+    /// Inspect the openers of the FIFO at `path`
+    pub fn inspect(path: impl AsRef<Path>, waited: Duration) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let openers = fifo_openers(&path);
+        let pids = |write: bool| {
+            let mut pids: Vec<u32> = openers
+                .iter()
+                .flatten()
+                .filter(|o| if write { o.write } else { o.read })
+                .map(|o| o.pid)
+                .collect();
+            pids.dedup();
+            pids
+        };
+        StallReport {
+            exists: path.exists(),
+            readers: pids(false),
+            writers: pids(true),
+            blocked_opening: blocked_opening(),
+            path,
+            waited,
+        }
+    }
+}
+
+/// Processes whose wait channel shows them in the open of a FIFO
+fn blocked_opening() -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let wchan = std::fs::read_to_string(entry.path().join("wchan")).ok()?;
+            matches!(wchan.trim(), "fifo_open" | "wait_for_partner").then_some(pid)
+        })
+        .collect()
+}
+
+impl fmt::Display for StallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Open of {:?} stalled for {:?}: ", self.path, self.waited)?;
+        if !self.exists {
+            return write!(f, "the FIFO does not exist");
+        }
+        let describe = |f: &mut fmt::Formatter<'_>, role: &str, pids: &[u32]| match pids {
+            [] => write!(f, "no {} has the FIFO open", role),
+            pids => write!(f, "{} pids {:?} have the FIFO open", role, pids),
+        };
+        describe(f, "reader", &self.readers)?;
+        write!(f, "; ")?;
+        describe(f, "writer", &self.writers)?;
+        if !self.blocked_opening.is_empty() {
+            write!(
+                f,
+                "; pids {:?} are blocked opening a FIFO",
+                self.blocked_opening
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, Sfifo, SfifoError};
+
+    #[tokio::test]
+    async fn test_stall_report_on_timeout() {
+        let fifo_path = "/tmp/test_stall_report";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        // Openers are found by inode, here this process holding both ends
+        let writer = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(fifo_path)
+            .unwrap();
+        let report = StallReport::inspect(fifo_path, Duration::ZERO);
+        assert!(report.writers.contains(&std::process::id()));
+        drop(writer);
+
+        let error = Sfifo::new(fifo_path)
+            .set_timeout(Duration::from_millis(300))
+            .set_stall_diagnostics(Some(Duration::from_millis(100)))
+            .open_sender()
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        let Some(SfifoError::Stalled { report }) = SfifoError::from_io(&error) else {
+            panic!("unexpected error {:?}", error);
+        };
+        assert!(report.exists && report.readers.is_empty());
+        assert!(report.waited >= Duration::from_millis(300));
+        assert!(error.to_string().contains("no reader has the FIFO open"));
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
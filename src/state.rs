@@ -0,0 +1,154 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Lifecycle of the connections opened from an `Sfifo`, observed with
+/// `Sfifo::watch_state()` or `AuthenticatedFifo::watch_state()`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelState {
+    /// Creating the FIFO files
+    Creating,
+    /// Waiting for the peer to open its end
+    WaitingPeer,
+    /// Exchanging handshake messages with the peer
+    Handshaking,
+    /// Authenticated and ready for data
+    Connected,
+    /// The FIFO was deleted or recreated, reopening it
+    Degraded,
+    /// Not connected: never opened, closed by either side, or failed
+    #[default]
+    Closed,
+}
+
+/// State shared by the clones of an `Sfifo` and the FIFOs opened from it
+#[derive(Clone)]
+pub(crate) struct StateWatch(Arc<watch::Sender<ChannelState>>);
+
+impl StateWatch {
+    pub(crate) fn get(&self) -> ChannelState {
+        *self.0.borrow()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<ChannelState> {
+        self.0.subscribe()
+    }
+
+    /// Move to `state`, waking the watchers only if it changed
+    pub(crate) fn set(&self, state: ChannelState) {
+        self.0.send_if_modified(|current| {
+            if *current == state {
+                return false;
+            }
+            debug!("Channel state {:?} -> {:?}", current, state);
+            *current = state;
+            true
+        });
+    }
+
+    /// Start connecting from `state`, falling back to `Closed` unless
+    /// the returned guard is marked connected
+    pub(crate) fn connecting(&self, state: ChannelState) -> Connecting<'_> {
+        self.set(state);
+        Connecting {
+            state: self,
+            connected: false,
+        }
+    }
+}
+
+impl Default for StateWatch {
+    fn default() -> Self {
+        StateWatch(Arc::new(watch::Sender::new(ChannelState::default())))
+    }
+}
+
+impl std::fmt::Debug for StateWatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("StateWatch").field(&self.get()).finish()
+    }
+}
+
+/// Connection attempt, see `StateWatch::connecting`
+pub(crate) struct Connecting<'a> {
+    state: &'a StateWatch,
+    connected: bool,
+}
+
+impl Connecting<'_> {
+    pub(crate) fn connected(mut self) {
+        self.state.set(ChannelState::Connected);
+        self.connected = true;
+    }
+}
+
+impl Drop for Connecting<'_> {
+    fn drop(&mut self) {
+        if !self.connected {
+            self.state.set(ChannelState::Closed);
+        }
+    }
+}
+
+/// Held by an open connection, which is `Closed` once it is dropped
+#[derive(Debug, Default)]
+pub(crate) struct StateGuard(StateWatch);
+
+impl StateGuard {
+    pub(crate) fn new(state: StateWatch) -> Self {
+        state.set(ChannelState::Connected);
+        StateGuard(state)
+    }
+}
+
+impl std::ops::Deref for StateGuard {
+    type Target = StateWatch;
+
+    fn deref(&self) -> &StateWatch {
+        &self.0
+    }
+}
+
+impl Drop for StateGuard {
+    fn drop(&mut self) {
+        self.0.set(ChannelState::Closed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sfifo;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_state_through_lifecycle() {
+        let fifo_path = "/tmp/test_channel_state";
+        let token = "state_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let client_config = Sfifo::new(fifo_path);
+        assert_eq!(server_config.state(), ChannelState::Closed);
+        let mut server_states = server_config.watch_state();
+
+        let server = tokio::spawn({
+            let config = server_config.clone();
+            async move { config.open_as_server(token).await }
+        });
+        server_states.changed().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server_config.state(), ChannelState::WaitingPeer);
+
+        let mut client = client_config.open_as_client(token).await.unwrap();
+        let server = server.await.unwrap().unwrap();
+        assert_eq!(server_config.state(), ChannelState::Connected);
+        assert_eq!(client.state(), ChannelState::Connected);
+
+        // The reader going away closes the client on its next write
+        drop(server);
+        assert_eq!(*server_states.borrow_and_update(), ChannelState::Closed);
+        assert!(client.write(b"lost").await.is_err());
+        assert_eq!(client_config.state(), ChannelState::Closed);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{task::spawn_named, AuthenticatedFifo, Credentials, RetryPolicy, Sfifo};
+use bytes::Bytes;
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+/// What a supervised channel is doing, see `SupervisorHandle::status()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SupervisorStatus {
+    /// Waiting for a client and running the handshake
+    #[default]
+    Connecting,
+    /// Connected, passing messages to the handler
+    Running,
+    /// The last session failed, waiting before failed attempt `attempt`
+    /// is retried
+    Restarting { attempt: u32 },
+    /// Shut down by the handle
+    Stopped,
+    /// Gave up once the restart policy ran out of attempts
+    Failed,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    status: watch::Sender<SupervisorStatus>,
+    restarts: AtomicU64,
+}
+
+/// Runs the server side of an authenticated FIFO in the background,
+/// calling a handler for each frame received and reconnecting whenever
+/// the session ends.
+///
+/// A session ends when the client closes the FIFO, reading fails or the
+/// handler returns an error. A closed FIFO is reopened right away; failures
+/// are retried according to the restart policy, whose attempt count starts
+/// over with every successful handshake. Once the policy gives up the
+/// supervisor stops with status `Failed`.
+#[derive(Debug)]
+pub struct SfifoSupervisor {
+    config: Sfifo,
+    credentials: Credentials,
+    restart_policy: RetryPolicy,
+}
+
+impl SfifoSupervisor {
+    /// Supervise the FIFO at `config.file_path`, authenticating clients
+    /// with `credentials`
+    pub fn new(config: &Sfifo, credentials: Credentials) -> Self {
+        SfifoSupervisor {
+            config: config.clone(),
+            credentials,
+            restart_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Delay and maximum number of restarts after failed sessions,
+    /// retrying every 100ms without limit by default
+    pub fn restart_policy(mut self, policy: RetryPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Start serving in the background, awaiting `handler` for each frame
+    /// before reading the next one
+    pub fn spawn<F, Fut>(self, handler: F) -> SupervisorHandle
+    where
+        F: Fn(Bytes) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::io::Result<()>> + Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        let cancel = CancellationToken::new();
+        let task = spawn_named(
+            "sfifo::supervisor",
+            supervise(self, handler, shared.clone(), cancel.clone()),
+        );
+        SupervisorHandle {
+            shared,
+            cancel,
+            task: Some(task),
+        }
+    }
+}
+
+/// Controls a running `SfifoSupervisor`, which stops once the handle is
+/// dropped
+#[derive(Debug)]
+pub struct SupervisorHandle {
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+    task: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl SupervisorHandle {
+    pub fn status(&self) -> SupervisorStatus {
+        *self.shared.status.borrow()
+    }
+
+    pub fn watch_status(&self) -> watch::Receiver<SupervisorStatus> {
+        self.shared.status.subscribe()
+    }
+
+    /// Sessions started again after the first one, whether they ended
+    /// cleanly or failed
+    pub fn restarts(&self) -> u64 {
+        self.shared.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Stop serving, letting a running handler call finish, and return the
+    /// error the supervisor gave up on, if it did
+    pub async fn shutdown(mut self) -> std::io::Result<()> {
+        self.cancel.cancel();
+        match self.task.take() {
+            Some(task) => task.await.map_err(std::io::Error::other)?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SupervisorHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// How a session ended without error
+enum SessionEnd {
+    Cancelled,
+    Closed,
+}
+
+async fn supervise<F, Fut>(
+    supervisor: SfifoSupervisor,
+    handler: F,
+    shared: Arc<Shared>,
+    cancel: CancellationToken,
+) -> std::io::Result<()>
+where
+    F: Fn(Bytes) -> Fut,
+    Fut: Future<Output = std::io::Result<()>>,
+{
+    let mut attempt = 0;
+    loop {
+        shared.status.send_replace(SupervisorStatus::Connecting);
+        let opened = tokio::select! {
+            _ = cancel.cancelled() => break,
+            opened = supervisor.config.open_as_server_with_credentials(&supervisor.credentials) => opened,
+        };
+        let ended = match opened {
+            Ok(fifo) => {
+                attempt = 0;
+                shared.status.send_replace(SupervisorStatus::Running);
+                serve(fifo, &handler, &cancel).await
+            }
+            Err(e) => Err(e),
+        };
+        match ended {
+            Ok(SessionEnd::Cancelled) => break,
+            Ok(SessionEnd::Closed) => debug!("Supervised FIFO closed by the client, reopening"),
+            Err(e) => {
+                attempt += 1;
+                warn!("Supervised FIFO session failed: {}", e);
+                shared
+                    .status
+                    .send_replace(SupervisorStatus::Restarting { attempt });
+                let restart = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    restart = supervisor.restart_policy.retry(attempt, e) => restart,
+                };
+                if let Err(e) = restart {
+                    shared.status.send_replace(SupervisorStatus::Failed);
+                    return Err(e);
+                }
+            }
+        }
+        shared.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+    shared.status.send_replace(SupervisorStatus::Stopped);
+    Ok(())
+}
+
+/// Pass the frames read from `fifo` to `handler` until the client closes
+/// it or `cancel` fires
+async fn serve<F, Fut>(
+    fifo: AuthenticatedFifo,
+    handler: &F,
+    cancel: &CancellationToken,
+) -> std::io::Result<SessionEnd>
+where
+    F: Fn(Bytes) -> Fut,
+    Fut: Future<Output = std::io::Result<()>>,
+{
+    let mut receiver = fifo.into_framed_receiver()?;
+    loop {
+        let message = tokio::select! {
+            _ = cancel.cancelled() => return Ok(SessionEnd::Cancelled),
+            message = receiver.recv() => message?,
+        };
+        match message {
+            Some(message) => handler(message).await?,
+            None => return Ok(SessionEnd::Closed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    async fn send_frames(config: &Sfifo, token: &str, frames: &[&[u8]]) {
+        let mut sender = config
+            .open_as_client(token)
+            .await
+            .unwrap()
+            .into_framed_sender()
+            .unwrap();
+        for frame in frames {
+            sender.send(frame).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_restarts_sessions() {
+        let fifo_path = "/tmp/test_supervisor_restarts";
+        let token = "supervisor_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let policy = RetryPolicy::fixed(Duration::from_millis(200)).max_attempts(2);
+        let handle = SfifoSupervisor::new(&config, Credentials::shared(token))
+            .restart_policy(policy)
+            .spawn(move |message| {
+                let tx = tx.clone();
+                async move {
+                    if &message[..] == b"fail" {
+                        return Err(std::io::Error::other("handler failed"));
+                    }
+                    tx.send(message).unwrap();
+                    Ok(())
+                }
+            });
+
+        // The supervisor keeps serving clients one after the other
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        send_frames(&config, token, &[b"one", b"two"]).await;
+        assert_eq!(&rx.recv().await.unwrap()[..], b"one");
+        assert_eq!(&rx.recv().await.unwrap()[..], b"two");
+        send_frames(&config, token, &[b"three"]).await;
+        assert_eq!(&rx.recv().await.unwrap()[..], b"three");
+        assert!(handle.restarts() >= 1);
+
+        // A failing handler restarts the session
+        send_frames(&config, token, &[b"fail"]).await;
+        let mut status = handle.watch_status();
+        status
+            .wait_for(|status| *status == SupervisorStatus::Restarting { attempt: 1 })
+            .await
+            .unwrap();
+        send_frames(&config, token, &[b"four"]).await;
+        assert_eq!(&rx.recv().await.unwrap()[..], b"four");
+        assert!(handle.restarts() >= 3);
+
+        handle.shutdown().await.unwrap();
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_gives_up() {
+        let fifo_path = "/tmp/test_supervisor_gives_up";
+        let token = "supervisor_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+
+        let policy = RetryPolicy::fixed(Duration::from_millis(10)).max_attempts(1);
+        let handle = SfifoSupervisor::new(&config, Credentials::shared(token))
+            .restart_policy(policy)
+            .spawn(|_| async { Err(std::io::Error::other("handler failed")) });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        send_frames(&config, token, &[b"fail"]).await;
+
+        let mut status = handle.watch_status();
+        status
+            .wait_for(|status| *status == SupervisorStatus::Failed)
+            .await
+            .unwrap();
+        let error = handle.shutdown().await.unwrap_err();
+        assert_eq!(error.to_string(), "handler failed");
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,158 @@
+use crate::Sfifo;
+use futures_util::Stream;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::unix::pipe::Receiver,
+};
+
+/// Follows the lines written to a FIFO across writers, like `tail -f`
+///
+/// When the last writer closes the FIFO, it is opened again and the next
+/// writer is waited for, as configured by `wait_for_writer`, `timeout`
+/// and `notify`; a wait that times out is simply started over. Following
+/// ends once the FIFO is deleted. A line left unterminated by a writer is
+/// returned as is. Invalid UTF-8 is replaced rather than failing.
+#[derive(Debug)]
+pub struct FifoTailer {
+    config: Sfifo,
+    timestamps: bool,
+    reader: Option<BufReader<Receiver>>,
+}
+
+impl FifoTailer {
+    pub fn new(config: &Sfifo) -> Self {
+        FifoTailer {
+            config: config.clone().set_wait_for_writer(true).clone(),
+            timestamps: false,
+            reader: None,
+        }
+    }
+
+    /// Prefix each line with the UTC time it was read at, as in
+    /// `2024-05-01T12:00:00.000Z line`
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Wait for the next line, `None` once the FIFO was deleted
+    pub async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut line = Vec::new();
+        loop {
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None => match self.open().await? {
+                    Some(reader) => self.reader.insert(reader),
+                    None => return Ok(None),
+                },
+            };
+            reader.read_until(b'\n', &mut line).await?;
+            if !line.ends_with(b"\n") {
+                debug!("Writers closed {:?}, reopening", self.config.file_path);
+                self.reader = None;
+                if line.is_empty() {
+                    continue;
+                }
+            }
+            return Ok(Some(self.format(&line)));
+        }
+    }
+
+    /// Stream of the lines returned by `next_line`
+    pub fn into_stream(self) -> impl Stream<Item = std::io::Result<String>> {
+        futures_util::stream::unfold(self, |mut tailer| async move {
+            tailer
+                .next_line()
+                .await
+                .transpose()
+                .map(|line| (line, tailer))
+        })
+    }
+
+    async fn open(&self) -> std::io::Result<Option<BufReader<Receiver>>> {
+        loop {
+            match self.config.open_receiver().await {
+                Ok(receiver) => return Ok(Some(BufReader::new(receiver))),
+                Err(_) if !self.config.file_path.exists() => return Ok(None),
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn format(&self, line: &[u8]) -> String {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+        if self.timestamps {
+            format!("{} {}", rfc3339(SystemTime::now()), line)
+        } else {
+            line.into_owned()
+        }
+    }
+}
+
+/// Format `time` as an RFC 3339 UTC timestamp with milliseconds
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // Days to civil date, from Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_fifo;
+    use futures_util::StreamExt;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_tail_across_writers() {
+        let fifo_path = "/tmp/test_fifo_tailer";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let config = Sfifo::new(fifo_path).set_notify(true).clone();
+        let lines = tokio::spawn(FifoTailer::new(&config).into_stream().collect::<Vec<_>>());
+        for chunk in [&b"first\nunterminated"[..], b"second\r\n"] {
+            let mut sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+            sender.write_all(chunk).await.unwrap();
+            drop(sender);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        tokio::fs::remove_file(fifo_path).await.unwrap();
+
+        let lines: Vec<String> = lines
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|line| line.unwrap())
+            .collect();
+        assert_eq!(lines, ["first", "unterminated", "second"]);
+
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(rfc3339(time), "2023-11-14T22:13:20.123Z");
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+}
@@ -0,0 +1,68 @@
+use std::{future::Future, time::Duration};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Spawn a background task, named `name` in tokio-console when built with
+/// `--cfg tokio_unstable` and the `tracing` feature
+pub(crate) fn spawn_named<F>(name: &'static str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("Failed to spawn task");
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Aborts its task when dropped, so the task never outlives its owner
+#[derive(Debug)]
+pub(crate) struct TaskGuard(JoinHandle<()>);
+
+impl TaskGuard {
+    /// Spawn a task named `name`, aborted with the guard
+    pub(crate) fn spawn(
+        name: &'static str,
+        future: impl Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        TaskGuard(spawn_named(name, future))
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Cancel `cancel` after `timeout`, unless the guard is dropped first
+pub(crate) fn cancel_after(timeout: Duration, cancel: &CancellationToken) -> TaskGuard {
+    let cancel = cancel.clone();
+    TaskGuard::spawn("sfifo::handshake_timeout", async move {
+        tokio::time::sleep(timeout).await;
+        cancel.cancel();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_task_guard_aborts_on_drop() {
+        let cancel = CancellationToken::new();
+        let timer = cancel_after(Duration::from_millis(50), &cancel);
+        drop(timer);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!cancel.is_cancelled());
+
+        let _timer = cancel_after(Duration::from_millis(10), &cancel);
+        cancel.cancelled().await;
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{FifoTransport, HandshakeMessage};
+use std::{
+    fs::File,
+    io::Write,
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// How long to wait before opening a tee FIFO without reader again
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Copies every byte read from or written to a transport into a tee, a
+/// FIFO or a file, for debugging and auditing
+///
+/// The tee never slows down or fails the wrapped transport. Bytes are
+/// appended to a file, or written to a FIFO without waiting: while the
+/// tee FIFO has no reader or is full the bytes are dropped and counted in
+/// `dropped_bytes()`.
+#[derive(Debug)]
+pub struct TeeFifo<T> {
+    inner: T,
+    sink: TeeSink,
+}
+
+impl<T> TeeFifo<T> {
+    /// Mirror the traffic of `inner` to `path`
+    ///
+    /// A FIFO at `path` is opened once it has a reader, anything else is
+    /// opened for appending, created if missing.
+    pub fn new(inner: T, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let is_fifo = std::fs::metadata(&path)
+            .map(|m| m.file_type().is_fifo())
+            .unwrap_or(false);
+        let mut sink = TeeSink {
+            path,
+            is_fifo,
+            file: None,
+            last_open: None,
+            dropped: 0,
+        };
+        if !is_fifo {
+            sink.file = Some(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&sink.path)?,
+            );
+        }
+        Ok(TeeFifo { inner, sink })
+    }
+
+    /// Path of the tee
+    pub fn tee_path(&self) -> &Path {
+        &self.sink.path
+    }
+
+    /// Bytes that could not be copied to the tee
+    pub fn dropped_bytes(&self) -> u64 {
+        self.sink.dropped
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Stop mirroring and get the wrapped transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+#[derive(Debug)]
+struct TeeSink {
+    path: PathBuf,
+    is_fifo: bool,
+    file: Option<File>,
+    // Last attempt to open the tee FIFO
+    last_open: Option<Instant>,
+    dropped: u64,
+}
+
+impl TeeSink {
+    fn copy(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let is_fifo = self.is_fifo;
+        let Some(file) = self.file() else {
+            self.dropped += bytes.len() as u64;
+            return;
+        };
+        // Files take everything, a non-blocking FIFO what fits
+        let written = if is_fifo {
+            file.write(bytes)
+        } else {
+            file.write_all(bytes).map(|_| bytes.len())
+        };
+        let written = match written {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+            Err(e) => {
+                warn!("Failed to write to tee {:?}: {}", self.path, e);
+                self.file = None;
+                0
+            }
+        };
+        self.dropped += (bytes.len() - written) as u64;
+    }
+
+    fn file(&mut self) -> Option<&mut File> {
+        if self.file.is_none() && self.is_fifo {
+            let due = self
+                .last_open
+                .is_none_or(|last| last.elapsed() >= REOPEN_INTERVAL);
+            if due {
+                self.last_open = Some(Instant::now());
+                // Fails with ENXIO while the tee FIFO has no reader
+                self.file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&self.path)
+                    .ok();
+            }
+        }
+        self.file.as_mut()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for TeeFifo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.sink.copy(&buf.filled()[filled..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for TeeFifo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.sink.copy(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: FifoTransport> FifoTransport for TeeFifo<T> {
+    fn peer_info(&self) -> Option<&HandshakeMessage> {
+        self.inner.peer_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, MemoryFifo, Sfifo};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_tee_to_file_and_fifo() {
+        let log_path = "/tmp/test_tee_log";
+        let fifo_path = "/tmp/test_tee_fifo";
+        let _ = tokio::fs::remove_file(log_path).await;
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let (sender, receiver) = MemoryFifo::pair(64);
+        let mut sender = TeeFifo::new(sender, log_path).unwrap();
+        let mut receiver = TeeFifo::new(receiver, fifo_path).unwrap();
+
+        // Nobody reads the tee FIFO yet, the primary path is unaffected
+        sender.write_all(b"lost ").await.unwrap();
+        let mut buf = [0u8; 5];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(receiver.dropped_bytes(), 5);
+
+        let mut observer = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        tokio::time::sleep(REOPEN_INTERVAL).await;
+        sender.write_all(b"seen").await.unwrap();
+        let mut buf = [0u8; 4];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"seen");
+        observer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"seen");
+
+        assert_eq!(
+            tokio::fs::read(log_path).await.unwrap(),
+            b"lost seen".to_vec()
+        );
+        assert_eq!(sender.dropped_bytes(), 0);
+
+        let _ = tokio::fs::remove_file(log_path).await;
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
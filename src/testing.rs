@@ -0,0 +1,99 @@
+use crate::{AuthenticatedFifo, Sfifo};
+use std::{
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Tells apart the directories created by one process
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+
+/// FIFO in a private temporary directory, for tests
+///
+/// Each `ScopedFifo` gets its own directory, so tests running in parallel
+/// never share a path. The directory, with the FIFO, its handshake pair
+/// and any other FIFO created in it, is removed on drop.
+#[derive(Debug)]
+pub struct ScopedFifo {
+    dir: PathBuf,
+    config: Sfifo,
+}
+
+impl ScopedFifo {
+    /// Create the directory, the FIFO is created by the first end opened
+    pub fn new() -> std::io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let dir = std::env::temp_dir().join(format!(
+            "sfifo-{}-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed),
+            nanos
+        ));
+        std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        let config = Sfifo::new(dir.join("fifo")).set_create(true).clone();
+        Ok(ScopedFifo { dir, config })
+    }
+
+    /// Path of the FIFO
+    pub fn path(&self) -> &Path {
+        &self.config.file_path
+    }
+
+    /// Configuration opening the FIFO, with `create` set
+    pub fn config(&self) -> &Sfifo {
+        &self.config
+    }
+
+    /// Configuration for another FIFO named `name` in the same directory
+    pub fn fifo(&self, name: &str) -> Sfifo {
+        Sfifo::new(self.dir.join(name)).set_create(true).clone()
+    }
+
+    /// Authenticate a server and a client over the FIFO, returning the
+    /// receiving server and the sending client
+    pub async fn connected_pair(
+        &self,
+        token: &str,
+    ) -> std::io::Result<(AuthenticatedFifo, AuthenticatedFifo)> {
+        tokio::try_join!(
+            self.config.open_as_server(token),
+            self.config.open_as_client(token)
+        )
+    }
+}
+
+impl Drop for ScopedFifo {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            warn!("Failed to remove {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scoped_fifo_pair_and_cleanup() {
+        let scoped = ScopedFifo::new().unwrap();
+        let other = ScopedFifo::new().unwrap();
+        assert_ne!(scoped.path(), other.path());
+
+        let (mut server, mut client) = scoped.connected_pair("scoped_token").await.unwrap();
+        assert!(server.is_server() && server.is_receiver());
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        let dir = scoped.path().parent().unwrap().to_path_buf();
+        drop((server, client));
+        drop(scoped);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{
+    constant_time_eq, handshake::MAX_MESSAGE_AGE_SECS, HandshakeMessage, SecretString, Sfifo,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use zeroize::Zeroize;
+
+/// Resolves the token presented by a client to the tenant it belongs to
+pub trait TokenStore: Send + Sync {
+    /// Tenant whose token is `token`, `None` if no tenant has it
+    fn lookup(&self, token: &str) -> std::io::Result<Option<String>>;
+}
+
+/// Fixed set of tenants and their tokens
+#[derive(Debug, Clone, Default)]
+pub struct StaticTokens {
+    tokens: Vec<(String, SecretString)>,
+}
+
+impl StaticTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let `tenant` authenticate with `token`
+    pub fn insert(mut self, tenant: impl Into<String>, token: impl Into<SecretString>) -> Self {
+        self.tokens.push((tenant.into(), token.into()));
+        self
+    }
+}
+
+impl<T: Into<String>, S: Into<SecretString>> FromIterator<(T, S)> for StaticTokens {
+    fn from_iter<I: IntoIterator<Item = (T, S)>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(StaticTokens::new(), |tokens, (tenant, token)| {
+                tokens.insert(tenant, token)
+            })
+    }
+}
+
+impl TokenStore for StaticTokens {
+    fn lookup(&self, token: &str) -> std::io::Result<Option<String>> {
+        // Compare with every token, so the time taken does not tell which
+        // one matched
+        let mut found = None;
+        for (tenant, expected) in &self.tokens {
+            if constant_time_eq(expected.expose().as_bytes(), token.as_bytes()) && found.is_none() {
+                found = Some(tenant.clone());
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Reads `tenant:token` lines from a file at every lookup, so edits apply
+/// to the next handshake. Blank lines and lines starting with `#` are
+/// ignored
+#[derive(Debug, Clone)]
+pub struct FileTokens {
+    path: PathBuf,
+}
+
+impl FileTokens {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileTokens {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Parse the file into a fixed set of tokens
+    pub fn load(&self) -> std::io::Result<StaticTokens> {
+        let mut content = std::fs::read_to_string(&self.path)?;
+        let tokens = parse_tokens(&content);
+        content.zeroize();
+        tokens
+    }
+}
+
+fn parse_tokens(content: &str) -> std::io::Result<StaticTokens> {
+    let mut tokens = StaticTokens::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((tenant, token)) if !tenant.is_empty() && !token.is_empty() => {
+                tokens = tokens.insert(tenant, token);
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Line {} is not a tenant:token pair", number + 1),
+                ))
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+impl TokenStore for FileTokens {
+    fn lookup(&self, token: &str) -> std::io::Result<Option<String>> {
+        self.load()?.lookup(token)
+    }
+}
+
+type LookupFn = Box<dyn Fn(&str) -> std::io::Result<Option<String>> + Send + Sync>;
+
+/// Delegates token lookups to a user callback
+pub struct CallbackTokens {
+    lookup: LookupFn,
+}
+
+impl CallbackTokens {
+    pub fn new(
+        lookup: impl Fn(&str) -> std::io::Result<Option<String>> + Send + Sync + 'static,
+    ) -> Self {
+        CallbackTokens {
+            lookup: Box::new(lookup),
+        }
+    }
+}
+
+impl TokenStore for CallbackTokens {
+    fn lookup(&self, token: &str) -> std::io::Result<Option<String>> {
+        (self.lookup)(token)
+    }
+}
+
+/// Shared handle to a token store, set on an `Sfifo` server
+///
+/// With a registry, the server accepts any client whose token the store
+/// knows instead of the single token it was opened with, answers the
+/// client with that same token and records the tenant in
+/// `peer_info().tenant`.
+#[derive(Clone)]
+pub struct TokenRegistry(Arc<dyn TokenStore>);
+
+impl TokenRegistry {
+    pub fn new(store: impl TokenStore + 'static) -> Self {
+        TokenRegistry(Arc::new(store))
+    }
+
+    /// Tokens read from a file of `tenant:token` lines
+    pub fn file(path: impl AsRef<Path>) -> Self {
+        Self::new(FileTokens::new(path))
+    }
+
+    /// Tenant whose token is `token`
+    pub fn lookup(&self, token: &str) -> std::io::Result<Option<String>> {
+        self.0.lookup(token)
+    }
+}
+
+impl std::fmt::Debug for TokenRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TokenRegistry").finish_non_exhaustive()
+    }
+}
+
+impl Sfifo {
+    /// Check the token of a client request, against the token registry or
+    /// the token rotation if set. The registry records the client's tenant
+    pub(crate) fn authenticate_client(
+        &self,
+        request: &mut HandshakeMessage,
+        expected_token: &str,
+    ) -> std::io::Result<()> {
+        if let Some(registry) = &self.token_registry {
+            request.tenant = registry.lookup(&request.token)?;
+            if request.tenant.is_none() {
+                return Err(invalid_token());
+            }
+        } else if let Some(rotation) = &self.token_rotation {
+            if !rotation.accepts(&request.token) {
+                return Err(invalid_token());
+            }
+        } else {
+            return request.validate(expected_token, MAX_MESSAGE_AGE_SECS);
+        }
+        request.check_timestamp(MAX_MESSAGE_AGE_SECS)
+    }
+
+    /// Token the client of an authenticated `request` must present again,
+    /// and is presented by the server: the client's own if it was checked
+    /// against the registry or the rotation
+    pub(crate) fn client_token<'a>(
+        &self,
+        request: &'a HandshakeMessage,
+        expected_token: &'a str,
+    ) -> &'a str {
+        if self.token_registry.is_some() || self.token_rotation.is_some() {
+            &request.token
+        } else {
+            expected_token
+        }
+    }
+}
+
+fn invalid_token() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::PermissionDenied,
+        "Invalid authentication token",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_stores() {
+        let tokens: StaticTokens = [("alpha", "alpha_token"), ("beta", "beta_token")]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            tokens.lookup("beta_token").unwrap().as_deref(),
+            Some("beta")
+        );
+        assert_eq!(tokens.lookup("beta_toke").unwrap(), None);
+        assert!(!format!("{:?}", tokens).contains("alpha_token"));
+
+        let path = "/tmp/test_file_tokens";
+        std::fs::write(path, "# tenants\nalpha:alpha_token\n\nbeta:beta_token\n").unwrap();
+        let registry = TokenRegistry::file(path);
+        assert_eq!(
+            registry.lookup("alpha_token").unwrap().as_deref(),
+            Some("alpha")
+        );
+        std::fs::write(path, "alpha\n").unwrap();
+        let error = registry.lookup("alpha_token").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(path);
+
+        let registry = TokenRegistry::new(CallbackTokens::new(|token| {
+            Ok(token.strip_prefix("key-").map(str::to_string))
+        }));
+        assert_eq!(
+            registry.lookup("key-gamma").unwrap().as_deref(),
+            Some("gamma")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_registry_handshake() {
+        let fifo_path = "/tmp/test_token_registry";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let registry = TokenRegistry::new(
+            StaticTokens::new()
+                .insert("alpha", "alpha_token")
+                .insert("beta", "beta_token"),
+        );
+        let config = Sfifo::new(fifo_path)
+            .set_create(true)
+            .set_token_registry(Some(registry))
+            .clone();
+
+        for (inband, tenant, token) in [
+            (false, "alpha", "alpha_token"),
+            (false, "beta", "beta_token"),
+            (true, "beta", "beta_token"),
+        ] {
+            let server_config = config.clone().set_inband_handshake(inband).clone();
+            let server = tokio::spawn(async move { server_config.open_as_server("").await });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let client = Sfifo::new(fifo_path)
+                .set_inband_handshake(inband)
+                .open_as_client(token)
+                .await
+                .unwrap();
+            let server = server.await.unwrap().unwrap();
+            assert_eq!(server.peer_info().tenant.as_deref(), Some(tenant));
+            assert_eq!(client.peer_info().tenant, None);
+        }
+
+        // Tokens the registry does not know are refused
+        let server_config = config.clone();
+        let server = tokio::spawn(async move { server_config.open_as_server("").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let client =
+            tokio::spawn(async move { Sfifo::new(fifo_path).open_as_client("gamma_token").await });
+        let error = server.await.unwrap().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        client.abort();
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,35 @@
+// Logging macros used throughout the crate. Events go to `tracing` when the
+// `tracing` feature is enabled and to `log` otherwise.
+
+macro_rules! sfifo_log {
+    ($level:ident, $($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::$level!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        ::log::$level!($($arg)*);
+    }};
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { sfifo_log!(debug, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { sfifo_log!(info, $($arg)*) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { sfifo_log!(warn, $($arg)*) };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { sfifo_log!(error, $($arg)*) };
+}
+
+// Records a field on the current span, a no-op without the `tracing` feature
+macro_rules! record_span {
+    ($field:literal, $value:expr) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::Span::current().record($field, $value);
+    }};
+}
@@ -0,0 +1,184 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{fmt::Write, future::Future};
+
+/// Frame flag: the payload starts with an encoded `TraceContext`
+pub const FLAG_TRACE_CONTEXT: u8 = 0x01;
+/// Encoded size of a trace context: version, trace id, parent id, flags
+pub const TRACE_CONTEXT_LEN: usize = 26;
+
+// Only version of the header, as in W3C traceparent
+const VERSION: u8 = 0;
+const SAMPLED: u8 = 0x01;
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// Compact trace context carried on frames, modeled on W3C `traceparent`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// Id of the span that sent the frame
+    pub parent_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Start a new trace with random ids
+    pub fn new_root() -> Self {
+        let mut trace_id = [0u8; 16];
+        let mut parent_id = [0u8; 8];
+        fill_random(&mut trace_id);
+        fill_random(&mut parent_id);
+        TraceContext {
+            trace_id,
+            parent_id,
+            sampled: true,
+        }
+    }
+
+    /// Same trace, new span id
+    pub fn child(&self) -> Self {
+        let mut parent_id = [0u8; 8];
+        fill_random(&mut parent_id);
+        TraceContext { parent_id, ..*self }
+    }
+
+    /// Context of the enclosing `scope`, attached to frames sent from it
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|c| *c).ok()
+    }
+
+    /// Run `f` with this context as the current one
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT.scope(self, f).await
+    }
+
+    /// Trace id as 32 lowercase hex digits
+    pub fn trace_id_hex(&self) -> String {
+        hex(&self.trace_id)
+    }
+
+    /// Format as a W3C `traceparent` header value
+    pub fn traceparent(&self) -> String {
+        format!(
+            "{:02x}-{}-{}-{:02x}",
+            VERSION,
+            hex(&self.trace_id),
+            hex(&self.parent_id),
+            if self.sampled { SAMPLED } else { 0 }
+        )
+    }
+
+    /// Parse a W3C `traceparent` header value
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let mut parts = value.split('-');
+        let version = parts.next()?;
+        let trace_id = unhex(parts.next()?)?;
+        let parent_id = unhex(parts.next()?)?;
+        let flags = u8::from_str_radix(parts.next()?, 16).ok()?;
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            parent_id,
+            sampled: flags & SAMPLED != 0,
+        })
+    }
+
+    pub(crate) fn encode(&self, dst: &mut BytesMut) {
+        dst.put_u8(VERSION);
+        dst.put_slice(&self.trace_id);
+        dst.put_slice(&self.parent_id);
+        dst.put_u8(if self.sampled { SAMPLED } else { 0 });
+    }
+
+    /// Split the context off the front of a frame payload
+    pub(crate) fn decode(src: &mut Bytes) -> std::io::Result<Self> {
+        if src.len() < TRACE_CONTEXT_LEN || src[0] != VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid frame trace context",
+            ));
+        }
+        src.advance(1);
+        let mut context = TraceContext {
+            trace_id: [0; 16],
+            parent_id: [0; 8],
+            sampled: false,
+        };
+        src.copy_to_slice(&mut context.trace_id);
+        src.copy_to_slice(&mut context.parent_id);
+        context.sampled = src.get_u8() & SAMPLED != 0;
+        Ok(context)
+    }
+}
+
+impl std::fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.traceparent())
+    }
+}
+
+/// Fill `buf` with random bytes, predictable ones if the system random
+/// source fails. Not for secrets or nonces, use `platform::random_bytes`
+pub(crate) fn fill_random(buf: &mut [u8]) {
+    if crate::platform::random_bytes(buf).is_err() {
+        // Ids only need to be unique, fall back to time and pid
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            ^ ((std::process::id() as u128) << 64);
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = (seed >> ((i % 16) * 8)) as u8;
+        }
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trace_context_round_trip() {
+        let context = TraceContext::new_root();
+        assert_eq!(
+            TraceContext::from_traceparent(&context.traceparent()),
+            Some(context)
+        );
+        assert!(TraceContext::from_traceparent("00-zz-00-01").is_none());
+
+        let mut buf = BytesMut::new();
+        context.encode(&mut buf);
+        buf.put_slice(b"payload");
+        let mut payload = buf.freeze();
+        assert_eq!(TraceContext::decode(&mut payload).unwrap(), context);
+        assert_eq!(payload, "payload");
+
+        assert_eq!(TraceContext::current(), None);
+        let child = context.child();
+        let current = child.scope(async { TraceContext::current() }).await;
+        assert_eq!(current, Some(child));
+        assert_eq!(child.trace_id, context.trace_id);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    control::{CONTROL_BEGIN, CONTROL_COMMIT},
+    Frame, FrameKind, FramedSender,
+};
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+/// Frames sent all-or-nothing, see `FramedSender::transaction()`
+///
+/// Nothing is written until `commit()`. A transaction dropped without
+/// being committed is discarded.
+///
+/// The receiver tracks a single open transaction, so the frames of other
+/// writers must not come between the markers: use transactions on FIFOs
+/// with a single writer, or enable `atomic_frames` on every writer, which
+/// writes each transaction at once and limits it to `PIPE_BUF` bytes.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    sender: &'a mut FramedSender,
+    frames: Vec<Frame>,
+}
+
+impl Transaction<'_> {
+    /// Add a data frame to the transaction
+    pub fn send(&mut self, data: &[u8]) -> &mut Self {
+        self.send_frame(Frame::data(Bytes::copy_from_slice(data)))
+    }
+
+    /// Add a raw frame to the transaction
+    pub fn send_frame(&mut self, frame: Frame) -> &mut Self {
+        self.frames.push(frame);
+        self
+    }
+
+    /// Number of frames in the transaction
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Write the frames between a begin and a commit marker
+    ///
+    /// The receiver holds the frames back until the commit marker, and
+    /// drops them if the writer goes away before it. With `atomic_frames`,
+    /// fails with `SfifoError::MessageTooLarge` if the frames and markers
+    /// take more than `PIPE_BUF` bytes.
+    pub async fn commit(self) -> std::io::Result<()> {
+        if self.frames.is_empty() {
+            return Ok(());
+        }
+        let mut frames = Vec::with_capacity(self.frames.len() + 2);
+        frames.push(marker(CONTROL_BEGIN));
+        frames.extend(self.frames);
+        frames.push(marker(CONTROL_COMMIT));
+        self.sender.send_together(frames).await
+    }
+
+    /// Discard the transaction, same as dropping it
+    pub fn abort(self) {
+        debug!("Aborting a transaction of {} frames", self.frames.len());
+    }
+}
+
+impl FramedSender {
+    /// Start a transaction: frames added to it reach the receiver all
+    /// together once committed, or not at all
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction {
+            sender: self,
+            frames: Vec::new(),
+        }
+    }
+}
+
+fn marker(tag: u16) -> Frame {
+    Frame {
+        tag,
+        ..Frame::new(FrameKind::Control, Bytes::new())
+    }
+}
+
+/// Frames of the transactions seen by a receiver
+#[derive(Debug, Default)]
+pub(crate) struct Transactions {
+    // Frames of the transaction being received
+    open: Option<Vec<Frame>>,
+    // Frames of the last committed transaction, not delivered yet
+    committed: VecDeque<Frame>,
+}
+
+impl Transactions {
+    /// Next frame of a committed transaction
+    pub(crate) fn pop(&mut self) -> Option<Frame> {
+        self.committed.pop_front()
+    }
+
+    /// Take a received frame, returning it if it can be delivered now
+    pub(crate) fn accept(&mut self, frame: Frame) -> Option<Frame> {
+        if frame.kind == FrameKind::Control {
+            match frame.tag {
+                CONTROL_BEGIN => {
+                    self.abort("Transaction started before the commit");
+                    self.open = Some(Vec::new());
+                    return None;
+                }
+                CONTROL_COMMIT => {
+                    match self.open.take() {
+                        Some(frames) => self.committed.extend(frames),
+                        None => warn!("Ignoring the commit of an unknown transaction"),
+                    }
+                    return self.pop();
+                }
+                _ => {}
+            }
+        }
+        match self.open.as_mut() {
+            Some(frames) => {
+                frames.push(frame);
+                None
+            }
+            None => Some(frame),
+        }
+    }
+
+    /// Drop the frames of the transaction being received, if any
+    pub(crate) fn abort(&mut self, reason: &str) {
+        if let Some(frames) = self.open.take() {
+            warn!("{}, discarding {} uncommitted frames", reason, frames.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, FramedReceiver, Sfifo};
+
+    #[tokio::test]
+    async fn test_transactions_are_all_or_nothing() {
+        let fifo_path = "/tmp/test_transaction";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap());
+
+        let mut transaction = sender.transaction();
+        transaction.send(b"discarded");
+        transaction.abort();
+        let mut transaction = sender.transaction();
+        transaction.send(b"debit").send(b"credit");
+        assert_eq!(transaction.len(), 2);
+        transaction.commit().await.unwrap();
+        sender.send(b"outside").await.unwrap();
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"debit");
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"credit");
+        assert_eq!(&receiver.recv().await.unwrap().unwrap()[..], b"outside");
+
+        // A writer dying before the commit leaves nothing behind
+        sender.send_frame(marker(CONTROL_BEGIN)).await.unwrap();
+        sender.send(b"half an update").await.unwrap();
+        drop(sender);
+        assert!(receiver.recv().await.unwrap().is_none());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_atomic_transactions_from_several_writers() {
+        let fifo_path = "/tmp/test_atomic_transactions";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        let limit = crate::atomic::pipe_buf(&sender).unwrap();
+        let mut sender = FramedSender::new(sender).atomic_frames(true);
+        let mut transaction = sender.transaction();
+        transaction.send(&vec![0u8; limit / 2]).send(&vec![0u8; limit / 2]);
+        let error = transaction.commit().await.unwrap_err();
+        assert!(matches!(
+            crate::SfifoError::from_io(&error),
+            Some(crate::SfifoError::MessageTooLarge { .. })
+        ));
+        drop(sender);
+
+        let mut writers = Vec::new();
+        for id in 0..4u8 {
+            let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+            let mut sender = FramedSender::new(sender).atomic_frames(true);
+            writers.push(tokio::spawn(async move {
+                for _ in 0..50 {
+                    let mut transaction = sender.transaction();
+                    transaction.send(&[id; 1000]).send(&[id; 1000]);
+                    transaction.commit().await?;
+                }
+                Ok::<_, std::io::Error>(())
+            }));
+        }
+        // Both halves of a transaction arrive together
+        for _ in 0..writers.len() * 50 {
+            let first = receiver.recv().await.unwrap().unwrap();
+            let second = receiver.recv().await.unwrap().unwrap();
+            assert_eq!(first, second);
+        }
+        for writer in writers {
+            writer.await.unwrap().unwrap();
+        }
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
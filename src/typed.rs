@@ -0,0 +1,228 @@
+use crate::{Channel, FramedReceiver, FramedSender};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+
+/// Serialization of the messages of typed channels
+///
+/// `BincodeCodec` is always available, `JsonCodec` and `PostcardCodec`
+/// come with the `json` and `postcard` features. Both ends of a channel
+/// must use the same codec.
+pub trait FifoCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> std::io::Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> std::io::Result<T>;
+}
+
+/// Compact binary encoding, the default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BincodeCodec;
+
+impl FifoCodec for BincodeCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> std::io::Result<Vec<u8>> {
+        bincode::serialize(value).map_err(invalid_data)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> std::io::Result<T> {
+        bincode::deserialize(bytes).map_err(invalid_data)
+    }
+}
+
+/// Human-readable encoding, handy to debug or talk to scripts
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl FifoCodec for JsonCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> std::io::Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(invalid_data)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> std::io::Result<T> {
+        serde_json::from_slice(bytes).map_err(invalid_data)
+    }
+}
+
+/// Varint-based encoding with a stable format, suited to embedded peers
+#[cfg(feature = "postcard")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PostcardCodec;
+
+#[cfg(feature = "postcard")]
+impl FifoCodec for PostcardCodec {
+    fn encode<T: Serialize>(&self, value: &T) -> std::io::Result<Vec<u8>> {
+        postcard::to_allocvec(value).map_err(invalid_data)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> std::io::Result<T> {
+        postcard::from_bytes(bytes).map_err(invalid_data)
+    }
+}
+
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+/// Sends values of type `T`, one data frame each
+#[derive(Debug)]
+pub struct TypedSender<T, C = BincodeCodec> {
+    inner: FramedSender,
+    codec: C,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Serialize> TypedSender<T> {
+    /// Send bincode encoded values
+    pub fn new(sender: FramedSender) -> Self {
+        Self::with_codec(sender, BincodeCodec)
+    }
+}
+
+impl<T: Serialize, C: FifoCodec> TypedSender<T, C> {
+    /// Send values encoded with `codec`
+    pub fn with_codec(sender: FramedSender, codec: C) -> Self {
+        TypedSender {
+            inner: sender,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+
+    pub async fn send(&mut self, value: &T) -> std::io::Result<()> {
+        let bytes = self.codec.encode(value)?;
+        self.inner.send(&bytes).await
+    }
+
+    /// Get the underlying framed sender
+    pub fn into_inner(self) -> FramedSender {
+        self.inner
+    }
+}
+
+/// Receives values of type `T` sent by a `TypedSender`
+#[derive(Debug)]
+pub struct TypedReceiver<T, C = BincodeCodec> {
+    inner: FramedReceiver,
+    codec: C,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> TypedReceiver<T> {
+    /// Receive bincode encoded values
+    pub fn new(receiver: FramedReceiver) -> Self {
+        Self::with_codec(receiver, BincodeCodec)
+    }
+}
+
+impl<T: DeserializeOwned, C: FifoCodec> TypedReceiver<T, C> {
+    /// Receive values encoded with `codec`
+    pub fn with_codec(receiver: FramedReceiver, codec: C) -> Self {
+        TypedReceiver {
+            inner: receiver,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Receive the next value, or `None` once the writer has closed
+    ///
+    /// A value that fails to decode is an `InvalidData` error, receiving
+    /// can go on with the next one.
+    pub async fn recv(&mut self) -> std::io::Result<Option<T>> {
+        match self.inner.recv().await? {
+            Some(bytes) => self.codec.decode(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the underlying framed receiver
+    pub fn into_inner(self) -> FramedReceiver {
+        self.inner
+    }
+}
+
+impl Channel {
+    /// Split the channel into halves sending and receiving values of type
+    /// `T`, encoded with `codec`
+    pub fn into_typed<T, C>(self, codec: C) -> (TypedSender<T, C>, TypedReceiver<T, C>)
+    where
+        T: Serialize + DeserializeOwned,
+        C: FifoCodec + Clone,
+    {
+        let (sender, receiver) = self.split();
+        (
+            TypedSender::with_codec(sender, codec.clone()),
+            TypedReceiver::with_codec(receiver, codec),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sfifo;
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: String,
+        value: f64,
+        tags: Vec<u16>,
+    }
+
+    async fn round_trip<C: FifoCodec + Clone + Send + 'static>(fifo_path: &str, codec: C) {
+        let token = "typed_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let config = Sfifo::new(fifo_path).set_create(true).clone();
+        let server = tokio::spawn({
+            let config = config.clone();
+            let codec = codec.clone();
+            async move {
+                let (mut sender, mut receiver) = Channel::accept(&config, token)
+                    .await?
+                    .into_typed::<Reading, _>(codec);
+                while let Some(mut reading) = receiver.recv().await? {
+                    reading.value *= 2.0;
+                    sender.send(&reading).await?;
+                }
+                Ok::<_, std::io::Error>(())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let (mut sender, mut receiver) = Channel::connect(&config, token)
+            .await
+            .unwrap()
+            .into_typed::<Reading, _>(codec);
+        let reading = Reading {
+            sensor: "boiler".to_string(),
+            value: 21.5,
+            tags: vec![1, 300],
+        };
+        sender.send(&reading).await.unwrap();
+        let echoed = receiver.recv().await.unwrap().unwrap();
+        assert_eq!(
+            echoed,
+            Reading {
+                value: 43.0,
+                ..reading
+            }
+        );
+
+        // Bytes that are not a `Reading` fail to decode
+        let mut raw = sender.into_inner();
+        raw.send(b"\xff").await.unwrap();
+        drop(raw);
+        server.await.unwrap().unwrap_err();
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_typed_channel_codecs() {
+        round_trip("/tmp/test_typed_bincode", BincodeCodec).await;
+        #[cfg(feature = "json")]
+        round_trip("/tmp/test_typed_json", JsonCodec).await;
+        #[cfg(feature = "postcard")]
+        round_trip("/tmp/test_typed_postcard", PostcardCodec).await;
+    }
+}
@@ -0,0 +1,198 @@
+//! io_uring read and write path of `Backend::Uring`
+
+use io_uring::{opcode, squeue, types::Fd, IoUring};
+use std::{
+    ops::Range,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+use tokio::io::{unix::AsyncFd, Interest};
+
+// A poll and the read or write linked to it, plus a cancellation
+const RING_ENTRIES: u32 = 4;
+const POLL: u64 = 1;
+const IO: u64 = 2;
+const CANCEL: u64 = 3;
+
+/// Ring running one read or write at a time, completions being signalled
+/// to the tokio reactor through an eventfd
+///
+/// The kernel reads into or writes from a buffer owned by the ring rather
+/// than the caller's, so a call dropped before its operation completes
+/// cannot leave the kernel writing to freed memory: the next call waits
+/// for it instead, a read then returning the data it got.
+pub(crate) struct UringIo {
+    ring: IoUring,
+    eventfd: AsyncFd<OwnedFd>,
+    buf: Vec<u8>,
+    // Bytes read into `buf` and not returned yet
+    ready: Range<usize>,
+    // Submitted operations whose completion was not reaped
+    in_flight: u32,
+    // Result of the read or write, once reaped
+    result: Option<i32>,
+}
+
+impl UringIo {
+    pub(crate) fn new() -> std::io::Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let eventfd = unsafe { OwnedFd::from_raw_fd(fd) };
+        ring.submitter().register_eventfd(eventfd.as_raw_fd())?;
+        Ok(UringIo {
+            ring,
+            eventfd: AsyncFd::with_interest(eventfd, Interest::READABLE)?,
+            buf: Vec::new(),
+            ready: 0..0,
+            in_flight: 0,
+            result: None,
+        })
+    }
+
+    /// Read from `fd` once it is readable, 0 at end-of-file
+    pub(crate) async fn read(&mut self, fd: RawFd, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.ready.is_empty() {
+            let n = match self.in_flight {
+                // Left by a dropped call
+                0 => {
+                    self.buf.resize(buf.len(), 0);
+                    let read =
+                        opcode::Read::new(Fd(fd), self.buf.as_mut_ptr(), self.buf.len() as u32);
+                    self.submit(fd, libc::POLLIN, read.build()).await?
+                }
+                _ => self.complete().await?,
+            };
+            self.ready = 0..n;
+        }
+        let n = buf.len().min(self.ready.len());
+        buf[..n].copy_from_slice(&self.buf[self.ready.start..self.ready.start + n]);
+        self.ready.start += n;
+        Ok(n)
+    }
+
+    /// Write to `fd` once it is writable
+    pub(crate) async fn write(&mut self, fd: RawFd, buf: &[u8]) -> std::io::Result<usize> {
+        if self.in_flight > 0 {
+            // The bytes of a dropped write went out or failed with it
+            let _ = self.complete().await;
+        }
+        self.buf.clear();
+        self.buf.extend_from_slice(buf);
+        let write = opcode::Write::new(Fd(fd), self.buf.as_ptr(), self.buf.len() as u32);
+        self.submit(fd, libc::POLLOUT, write.build()).await
+    }
+
+    async fn submit(
+        &mut self,
+        fd: RawFd,
+        events: libc::c_short,
+        io: squeue::Entry,
+    ) -> std::io::Result<usize> {
+        let poll = opcode::PollAdd::new(Fd(fd), events as u32)
+            .build()
+            .flags(squeue::Flags::IO_LINK)
+            .user_data(POLL);
+        // Both entries point to `buf` or nothing, which stays put until
+        // their completions are reaped
+        unsafe {
+            self.ring
+                .submission()
+                .push_multiple(&[poll, io.user_data(IO)])
+        }
+        .map_err(|_| std::io::Error::other("io_uring submission queue full"))?;
+        self.ring.submit()?;
+        self.in_flight = 2;
+        self.result = None;
+        self.complete().await
+    }
+
+    /// Wait for the operations in flight, returning the result of the
+    /// read or write
+    async fn complete(&mut self) -> std::io::Result<usize> {
+        loop {
+            for cqe in self.ring.completion() {
+                self.in_flight = self.in_flight.saturating_sub(1);
+                match cqe.user_data() {
+                    IO => self.result = Some(cqe.result()),
+                    // A failed poll cancels the operation linked to it
+                    POLL if cqe.result() < 0 => self.result = Some(cqe.result()),
+                    _ => {}
+                }
+            }
+            if self.in_flight == 0 {
+                return match self.result.take() {
+                    Some(res) if res < 0 => Err(std::io::Error::from_raw_os_error(-res)),
+                    Some(res) => Ok(res as usize),
+                    None => Err(std::io::Error::other("io_uring operation lost")),
+                };
+            }
+            let mut guard = self.eventfd.readable().await?;
+            let mut counter = [0u8; 8];
+            unsafe { libc::read(self.eventfd.as_raw_fd(), counter.as_mut_ptr().cast(), 8) };
+            guard.clear_ready();
+        }
+    }
+}
+
+impl Drop for UringIo {
+    fn drop(&mut self) {
+        if self.in_flight == 0 {
+            return;
+        }
+        // `buf` must outlive the operations, cancel them and wait
+        let cancel = opcode::AsyncCancel::new(POLL).build().user_data(CANCEL);
+        if unsafe { self.ring.submission().push(&cancel) }.is_ok() {
+            let _ = self.ring.submit_and_wait(self.in_flight as usize + 1);
+        }
+    }
+}
+
+impl std::fmt::Debug for UringIo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UringIo")
+            .field("in_flight", &self.in_flight)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_fifo, AuthenticatedFifo, Backend, HandshakeMessage, HandshakeType, Sfifo};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_uring_backend() {
+        let fifo_path = "/tmp/test_uring_backend";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let config = Sfifo::new(fifo_path).set_backend(Backend::Uring).clone();
+        let peer_info = HandshakeMessage::new(String::new(), HandshakeType::Request).unwrap();
+        let receiver = config.open_receiver().await.unwrap();
+        let mut receiver =
+            AuthenticatedFifo::new_receiver(receiver, peer_info.clone(), true).with_config(&config);
+        let sender = config.open_sender().await.unwrap();
+        let mut sender =
+            AuthenticatedFifo::new_sender(sender, peer_info, false).with_config(&config);
+
+        sender.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        receiver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // A read dropped while waiting hands its data to the next one
+        let mut buf = [0u8; 16];
+        let dropped =
+            tokio::time::timeout(Duration::from_millis(50), receiver.read(&mut buf)).await;
+        assert!(dropped.is_err());
+        sender.write_all(b"later").await.unwrap();
+        drop(sender);
+        let n = receiver.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"later");
+        assert_eq!(receiver.read(&mut buf).await.unwrap(), 0);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
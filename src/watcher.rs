@@ -0,0 +1,316 @@
+use crate::{procfs::fifo_openers, task::TaskGuard};
+use futures_util::Stream;
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+/// Change observed on a FIFO path by a `FifoWatcher`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoEvent {
+    /// The path appeared
+    Created,
+    /// The path was removed
+    Deleted,
+    /// A writer opened the FIFO while it had none (estimated count)
+    WriterAttached(usize),
+    /// A reader opened the FIFO while it had none (estimated count)
+    ReaderAttached(usize),
+}
+
+/// Polls a FIFO path and yields a `FifoEvent` for every change, as a
+/// `Stream` independent of any open operation.
+///
+/// Readers and writers already attached when watching starts are reported
+/// right away, the path existing at that point is not. Reader and writer
+/// counts come from `/proc`, see `fifo_openers`. On macOS and the BSDs, a
+/// kqueue on the parent directory reports the path appearing or going away
+/// without waiting for the next sample. The background task stops when
+/// the watcher is dropped.
+#[derive(Debug)]
+pub struct FifoWatcher {
+    rx: mpsc::Receiver<FifoEvent>,
+    _task: TaskGuard,
+}
+
+impl FifoWatcher {
+    /// Watch `path` for all events, sampling it every `interval`
+    pub fn new(path: impl AsRef<Path>, interval: Duration) -> Self {
+        Self::spawn(path.as_ref().to_path_buf(), interval, true)
+    }
+
+    /// Watch `path` for `Created` and `Deleted` only, without scanning
+    /// `/proc` for readers and writers
+    pub fn existence(path: impl AsRef<Path>, interval: Duration) -> Self {
+        Self::spawn(path.as_ref().to_path_buf(), interval, false)
+    }
+
+    fn spawn(path: PathBuf, interval: Duration, openers: bool) -> Self {
+        let (tx, rx) = mpsc::channel(16);
+        let mut state = WatchState {
+            exists: path.exists(),
+            readers: false,
+            writers: false,
+        };
+        let task = TaskGuard::spawn("sfifo::fifo_watcher", async move {
+            let mut changes = DirChanges::new(&path);
+            loop {
+                for event in state.update(&path, openers).await {
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                tokio::select! {
+                    _ = tx.closed() => return,
+                    _ = changes.wait(interval) => {}
+                }
+            }
+        });
+        FifoWatcher { rx, _task: task }
+    }
+
+    /// Wait until `event` is observed
+    pub async fn wait_for(&mut self, event: FifoEvent) {
+        while let Some(observed) = self.rx.recv().await {
+            if observed == event {
+                return;
+            }
+        }
+    }
+
+    /// Wait until a writer attaches, returning the estimated writer count
+    pub async fn writer_attached(&mut self) -> usize {
+        while let Some(event) = self.rx.recv().await {
+            if let FifoEvent::WriterAttached(count) = event {
+                return count;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+impl Stream for FifoWatcher {
+    type Item = FifoEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<FifoEvent>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Wakes a watcher when an entry of the directory holding its path is
+/// created, removed or renamed, through a kqueue on the directory
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+struct DirChanges {
+    // Fields drop in order: deregister, then close the queue, then the
+    // directory it watches
+    ready: Option<tokio::io::unix::AsyncFd<std::os::fd::RawFd>>,
+    queue: Option<nix::sys::event::Kqueue>,
+    _dir: Option<std::os::fd::OwnedFd>,
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+impl DirChanges {
+    fn new(path: &Path) -> Self {
+        match Self::watch(path) {
+            Ok(changes) => changes,
+            Err(e) => {
+                debug!("Polling {:?} only, cannot watch its directory: {}", path, e);
+                DirChanges {
+                    ready: None,
+                    queue: None,
+                    _dir: None,
+                }
+            }
+        }
+    }
+
+    fn watch(path: &Path) -> std::io::Result<Self> {
+        use nix::{
+            fcntl::OFlag,
+            sys::{
+                event::{EventFilter, EventFlag, FilterFlag, KEvent, Kqueue},
+                stat::Mode,
+            },
+        };
+        use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let fd = nix::fcntl::open(
+            dir,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        // Safety: the descriptor was just opened and is owned by nobody else
+        let dir = unsafe { OwnedFd::from_raw_fd(fd) };
+        let queue = Kqueue::new()?;
+        let change = KEvent::new(
+            dir.as_raw_fd() as libc::uintptr_t,
+            EventFilter::EVFILT_VNODE,
+            EventFlag::EV_ADD | EventFlag::EV_CLEAR,
+            FilterFlag::NOTE_WRITE | FilterFlag::NOTE_DELETE | FilterFlag::NOTE_RENAME,
+            0,
+            0,
+        );
+        queue.kevent(&[change], &mut [], None)?;
+        // A kqueue is readable while it holds events
+        let ready = tokio::io::unix::AsyncFd::with_interest(
+            queue.as_fd().as_raw_fd(),
+            tokio::io::Interest::READABLE,
+        )?;
+        Ok(DirChanges {
+            ready: Some(ready),
+            queue: Some(queue),
+            _dir: Some(dir),
+        })
+    }
+
+    /// Wait until the directory changes, or `interval` at most
+    async fn wait(&mut self, interval: Duration) {
+        let (Some(ready), Some(queue)) = (&self.ready, &self.queue) else {
+            return tokio::time::sleep(interval).await;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            guard = ready.readable() => {
+                if let Ok(mut guard) = guard {
+                    let mut events = [nix::sys::event::KEvent::new(
+                        0,
+                        nix::sys::event::EventFilter::EVFILT_VNODE,
+                        nix::sys::event::EventFlag::empty(),
+                        nix::sys::event::FilterFlag::empty(),
+                        0,
+                        0,
+                    )];
+                    let now = libc::timespec {
+                        tv_sec: 0,
+                        tv_nsec: 0,
+                    };
+                    // Consume the event, the path is sampled again anyway
+                    let _ = queue.kevent(&[], &mut events, Some(now));
+                    guard.clear_ready();
+                }
+            }
+        }
+    }
+}
+
+/// Waits between samples of a watched path, the platform has no kqueue
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+struct DirChanges;
+
+#[cfg(not(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+impl DirChanges {
+    fn new(_path: &Path) -> Self {
+        DirChanges
+    }
+
+    /// Wait for `interval`
+    async fn wait(&mut self, interval: Duration) {
+        tokio::time::sleep(interval).await
+    }
+}
+
+struct WatchState {
+    exists: bool,
+    readers: bool,
+    writers: bool,
+}
+
+impl WatchState {
+    /// Sample the path and return the events since the previous sample
+    async fn update(&mut self, path: &Path, openers: bool) -> Vec<FifoEvent> {
+        let mut events = Vec::new();
+        let exists = tokio::fs::metadata(path).await.is_ok();
+        if exists != self.exists {
+            self.exists = exists;
+            events.push(if exists {
+                FifoEvent::Created
+            } else {
+                FifoEvent::Deleted
+            });
+        }
+        if !openers {
+            return events;
+        }
+        let (readers, writers) = match exists.then(|| fifo_openers(path)) {
+            Some(Ok(openers)) => (
+                openers.iter().filter(|o| o.read).count(),
+                openers.iter().filter(|o| o.write).count(),
+            ),
+            _ => (0, 0),
+        };
+        if (readers > 0) != self.readers {
+            self.readers = readers > 0;
+            if self.readers {
+                events.push(FifoEvent::ReaderAttached(readers));
+            }
+        }
+        if (writers > 0) != self.writers {
+            self.writers = writers > 0;
+            if self.writers {
+                events.push(FifoEvent::WriterAttached(writers));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, Sfifo};
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_fifo_watcher_events() {
+        let fifo_path = "/tmp/test_fifo_watcher";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+
+        let mut watcher = FifoWatcher::new(fifo_path, Duration::from_millis(20));
+        create_fifo(fifo_path).await.unwrap();
+        assert_eq!(watcher.next().await, Some(FifoEvent::Created));
+
+        let receiver = Sfifo::new(fifo_path).open_receiver().await.unwrap();
+        assert_eq!(watcher.next().await, Some(FifoEvent::ReaderAttached(1)));
+        let sender = Sfifo::new(fifo_path).open_sender().await.unwrap();
+        assert_eq!(watcher.next().await, Some(FifoEvent::WriterAttached(1)));
+
+        tokio::fs::remove_file(fifo_path).await.unwrap();
+        assert_eq!(watcher.next().await, Some(FifoEvent::Deleted));
+        drop((sender, receiver));
+    }
+}
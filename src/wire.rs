@@ -0,0 +1,460 @@
+use crate::{Extension, HandshakeMessage, HandshakeType};
+use std::fmt::Write;
+
+// Longest JSON handshake line accepted
+pub(crate) const MAX_JSON_LEN: usize = 8192;
+
+/// Encoding of handshake messages on the wire
+///
+/// `Bincode` messages are prefixed with their length as a little endian
+/// `u32`. `Json` messages are single lines terminated by `\n`:
+///
+/// ```text
+/// {"process_id":42,"process_name":"worker","token":"secret","timestamp":1700000000,
+///  "message_type":"Request","extensions":[{"kind":3,"value":"<hex>"}]}
+/// ```
+///
+/// so that peers written in other languages can authenticate. Servers
+/// detect the format of each request and answer in the same format.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Bincode,
+    Json,
+}
+
+impl WireFormat {
+    /// Detect the format from the first bytes of a message: a bincode
+    /// length prefix never exceeds 4096, so its second byte is at most 16
+    pub(crate) fn detect(prefix: &[u8]) -> WireFormat {
+        match prefix {
+            [b'{', second, ..] if *second > 0x10 => WireFormat::Json,
+            _ => WireFormat::Bincode,
+        }
+    }
+}
+
+impl HandshakeMessage {
+    /// Encode the message as a JSON line, without the newline
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"process_id\":{},\"process_name\":",
+            self.process_id
+        );
+        push_string(&mut json, &self.process_name);
+        json.push_str(",\"token\":");
+        push_string(&mut json, &self.token);
+        let _ = write!(
+            json,
+            ",\"timestamp\":{},\"message_type\":\"{:?}\",\"extensions\":[",
+            self.timestamp, self.message_type
+        );
+        for (i, extension) in self.extensions.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"kind\":{},\"value\":\"", extension.kind);
+            for byte in &extension.value {
+                let _ = write!(json, "{:02x}", byte);
+            }
+            json.push_str("\"}");
+        }
+        json.push_str("]}");
+        json
+    }
+
+    /// Decode a message from a JSON line, ignoring unknown fields
+    pub fn from_json(json: &str) -> std::io::Result<Self> {
+        let Value::Object(fields) = Parser::new(json).parse()? else {
+            return Err(invalid("Handshake JSON is not an object"));
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| invalid(format!("Handshake JSON lacks {}", name)))
+        };
+        let message_type = match field("message_type")?.as_str()? {
+            "Request" => HandshakeType::Request,
+            "Response" => HandshakeType::Response,
+            "Ack" => HandshakeType::Ack,
+            other => return Err(invalid(format!("Unknown message type {}", other))),
+        };
+        let mut extensions = Vec::new();
+        if let Ok(Value::Array(items)) = field("extensions") {
+            for item in items {
+                let Value::Object(entry) = item else {
+                    return Err(invalid("Extension is not an object"));
+                };
+                let get = |name: &str| {
+                    entry
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value)
+                        .ok_or_else(|| invalid(format!("Extension lacks {}", name)))
+                };
+                let kind = u16::try_from(get("kind")?.as_u64()?)
+                    .map_err(|_| invalid("Extension kind out of range"))?;
+                extensions.push(Extension::new(kind, decode_hex(get("value")?.as_str()?)?));
+            }
+        }
+        Ok(HandshakeMessage {
+            process_id: u32::try_from(field("process_id")?.as_u64()?)
+                .map_err(|_| invalid("Process id out of range"))?,
+            process_name: field("process_name")?.as_str()?.to_string(),
+            token: field("token")?.as_str()?.to_string(),
+            timestamp: field("timestamp")?.as_u64()?,
+            message_type,
+            extensions,
+            wire_format: WireFormat::Json,
+            kernel_cred: None,
+            tenant: None,
+        })
+    }
+}
+
+fn invalid(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+pub(crate) fn push_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn decode_hex(hex: &str) -> std::io::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid("Odd length hex value"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| invalid("Invalid hex value"))
+        })
+        .collect()
+}
+
+/// The subset of JSON values a handshake needs; numbers are kept as
+/// unsigned integers
+enum Value {
+    Null,
+    Bool,
+    Number(u64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn as_str(&self) -> std::io::Result<&str> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(invalid("Expected a JSON string")),
+        }
+    }
+
+    fn as_u64(&self) -> std::io::Result<u64> {
+        match self {
+            Value::Number(n) => Ok(*n),
+            _ => Err(invalid("Expected a JSON integer")),
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(json: &'a str) -> Self {
+        Parser {
+            bytes: json.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn parse(mut self) -> std::io::Result<Value> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.pos != self.bytes.len() {
+            return Err(invalid("Trailing characters after JSON value"));
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r' | b'\n')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> std::io::Result<()> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return Err(invalid(format!("Expected '{}' in JSON", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> std::io::Result<Value> {
+        if !self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            return Err(invalid("Invalid JSON literal"));
+        }
+        self.pos += literal.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> std::io::Result<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true", Value::Bool),
+            Some(b'f') => self.literal("false", Value::Bool),
+            Some(b'n') => self.literal("null", Value::Null),
+            _ => Err(invalid("Unsupported JSON value")),
+        }
+    }
+
+    fn object(&mut self) -> std::io::Result<Value> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err(invalid("Expected ',' or '}' in JSON object")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> std::io::Result<Value> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(invalid("Expected ',' or ']' in JSON array")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> std::io::Result<Value> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .map(Value::Number)
+            .ok_or_else(|| invalid("Invalid JSON integer"))
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        self.expect(b'"')?;
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            while !matches!(self.peek(), Some(b'"' | b'\\') | None) {
+                self.pos += 1;
+            }
+            s.push_str(
+                std::str::from_utf8(&self.bytes[start..self.pos])
+                    .map_err(|_| invalid("Invalid UTF-8 in JSON string"))?,
+            );
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = self.peek().ok_or_else(|| invalid("Unterminated escape"))?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' => s.push('"'),
+                        b'\\' => s.push('\\'),
+                        b'/' => s.push('/'),
+                        b'b' => s.push('\u{8}'),
+                        b'f' => s.push('\u{c}'),
+                        b'n' => s.push('\n'),
+                        b'r' => s.push('\r'),
+                        b't' => s.push('\t'),
+                        b'u' => s.push(self.unicode_escape()?),
+                        _ => return Err(invalid("Invalid escape in JSON string")),
+                    }
+                }
+                _ => return Err(invalid("Unterminated JSON string")),
+            }
+        }
+    }
+
+    /// Decode `XXXX` after `\u`, with a following low surrogate if needed
+    fn unicode_escape(&mut self) -> std::io::Result<char> {
+        let high = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                return Err(invalid("Unpaired surrogate in JSON string"));
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| invalid("Invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> std::io::Result<u32> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| invalid("Invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handshake_path, proof::Prover, trace_context::hex, Sfifo};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    #[test]
+    fn test_json_round_trip() {
+        let message = HandshakeMessage::new("to\"ken\n".into(), HandshakeType::Ack)
+            .unwrap()
+            .with_extension(Extension::new(7, vec![0, 0xab, 0xff]));
+        let json = message.to_json();
+        assert_eq!(WireFormat::detect(json.as_bytes()), WireFormat::Json);
+        assert_eq!(
+            WireFormat::detect(&message.to_bytes().unwrap()),
+            WireFormat::Bincode
+        );
+        let decoded = HandshakeMessage::from_json(&json).unwrap();
+        assert_eq!(decoded.token, "to\"ken\n");
+        assert_eq!(decoded.message_type, HandshakeType::Ack);
+        assert_eq!(decoded.extensions, message.extensions);
+        assert!(HandshakeMessage::from_json(r#"{"process_id":1}"#).is_err());
+    }
+
+    /// A peer that only speaks JSON, as a script would
+    #[tokio::test]
+    async fn test_server_answers_json_client() {
+        let fifo_path = "/tmp/test_json_handshake";
+        let token = "json_token";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        let c2s = Sfifo::new(handshake_path(fifo_path, "c2s"))
+            .set_create(true)
+            .clone();
+        let s2c = Sfifo::new(handshake_path(fifo_path, "s2c"))
+            .set_create(true)
+            .clone();
+
+        let server_config = Sfifo::new(fifo_path).set_create(true).clone();
+        let server = tokio::spawn(async move { server_config.open_as_server(token).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let nonce = "00112233445566778899aabbccddeeff";
+        let mut sender = c2s.open_sender().await.unwrap();
+        let request = format!(
+            "{{\"process_id\": 7, \"process_name\": \"script.py\", \"token\": \"{}\", \"timestamp\": {}, \"message_type\": \"Request\", \"extensions\": [{{\"kind\": {}, \"value\": \"{}\"}}]}}\n",
+            token, now, crate::EXT_NONCE, nonce
+        );
+        sender.write_all(request.as_bytes()).await.unwrap();
+        drop(sender);
+
+        let receiver = s2c.open_receiver().await.unwrap();
+        let mut line = String::new();
+        BufReader::new(receiver).read_line(&mut line).await.unwrap();
+        let response = HandshakeMessage::from_json(line.trim_end()).unwrap();
+        assert_eq!(response.message_type, HandshakeType::Response);
+        assert!(response.token.is_empty());
+        let client_nonce: [u8; 16] = std::array::from_fn(|i| (i as u8) * 0x11);
+        let server_nonce = response.nonce().unwrap();
+        response
+            .check_proof(token, Prover::Server, &client_nonce, &server_nonce)
+            .unwrap();
+        // What the script computes with its HMAC library
+        let proof = HandshakeMessage::new(String::new(), HandshakeType::Ack)
+            .unwrap()
+            .with_proof(token, Prover::Client, &server_nonce, &client_nonce);
+
+        let mut sender = c2s.open_sender().await.unwrap();
+        let ack = format!(
+            "{{\"process_id\": 7, \"process_name\": \"script.py\", \"token\": \"\", \"timestamp\": {}, \"message_type\": \"Ack\", \"extensions\": [{{\"kind\": {}, \"value\": \"{}\"}}, {{\"kind\": {}, \"value\": \"{}\"}}]}}\n",
+            now,
+            crate::EXT_NONCE_ECHO,
+            hex(&server_nonce),
+            crate::EXT_PROOF,
+            hex(proof.extension(crate::EXT_PROOF).unwrap())
+        );
+        sender.write_all(ack.as_bytes()).await.unwrap();
+        drop(sender);
+
+        let server = server.await.unwrap().unwrap();
+        assert_eq!(server.peer_info().process_name, "script.py");
+        assert_eq!(server.peer_info().wire_format, WireFormat::Json);
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,107 @@
+use crate::FramedReceiver;
+use bytes::Bytes;
+use std::{io::IoSliceMut, marker::PhantomData, ops::Deref};
+
+/// Payload of a message returned by `FramedReceiver::recv_borrowed`
+///
+/// It borrows the receiver, so it is dropped before the next message is
+/// read and the read buffer can take its memory back instead of
+/// allocating a new one.
+#[derive(Debug)]
+pub struct BorrowedPayload<'a> {
+    payload: Bytes,
+    receiver: PhantomData<&'a mut FramedReceiver>,
+}
+
+impl Deref for BorrowedPayload<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+impl AsRef<[u8]> for BorrowedPayload<'_> {
+    fn as_ref(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+impl FramedReceiver {
+    /// Receive the next message as a view into the read buffer, or `None`
+    /// once the writer has closed the FIFO
+    ///
+    /// Unlike `recv()`, the payload cannot outlive the next call, which
+    /// lets a consumer handling one message at a time read them all
+    /// through the same buffer.
+    pub async fn recv_borrowed(&mut self) -> std::io::Result<Option<BorrowedPayload<'_>>> {
+        Ok(self.recv().await?.map(|payload| BorrowedPayload {
+            payload,
+            receiver: PhantomData,
+        }))
+    }
+
+    /// Receive the next message into `bufs`, filled in order, returning
+    /// its length or `None` once the writer has closed the FIFO
+    ///
+    /// Like a datagram read, the part of a message that does not fit is
+    /// discarded: a length greater than the size of `bufs` tells that it
+    /// was truncated.
+    pub async fn recv_into(
+        &mut self,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> std::io::Result<Option<usize>> {
+        let Some(payload) = self.recv_borrowed().await? else {
+            return Ok(None);
+        };
+        let mut rest = &payload[..];
+        for buf in bufs.iter_mut() {
+            if rest.is_empty() {
+                break;
+            }
+            let n = buf.len().min(rest.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            rest = &rest[n..];
+        }
+        Ok(Some(payload.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{create_fifo, FramedSender, Sfifo};
+
+    #[tokio::test]
+    async fn test_recv_into_and_borrowed() {
+        let fifo_path = "/tmp/test_recv_into";
+        let _ = tokio::fs::remove_file(fifo_path).await;
+        create_fifo(fifo_path).await.unwrap();
+
+        let mut receiver =
+            FramedReceiver::new(Sfifo::new(fifo_path).open_receiver().await.unwrap());
+        let mut sender = FramedSender::new(Sfifo::new(fifo_path).open_sender().await.unwrap());
+        sender.send(b"header-body").await.unwrap();
+        sender.send(b"truncated").await.unwrap();
+        sender.send(b"borrowed").await.unwrap();
+        drop(sender);
+
+        let (mut header, mut body) = ([0u8; 7], [0u8; 8]);
+        let mut bufs = [IoSliceMut::new(&mut header), IoSliceMut::new(&mut body)];
+        assert_eq!(receiver.recv_into(&mut bufs).await.unwrap(), Some(11));
+        assert_eq!(&header, b"header-");
+        assert_eq!(&body[..4], b"body");
+
+        let mut short = [0u8; 4];
+        let mut bufs = [IoSliceMut::new(&mut short)];
+        assert_eq!(receiver.recv_into(&mut bufs).await.unwrap(), Some(9));
+        assert_eq!(&short, b"trun");
+
+        let payload = receiver.recv_borrowed().await.unwrap().unwrap();
+        assert_eq!(&*payload, b"borrowed");
+        drop(payload);
+        assert!(receiver.recv_borrowed().await.unwrap().is_none());
+
+        let _ = tokio::fs::remove_file(fifo_path).await;
+    }
+}
@@ -0,0 +1,65 @@
+//! Raises a real SIGTERM, which the shutdown listener acts on for the
+//! whole process, so it runs in a test binary of its own
+
+use sfifo::{shutdown_signal, Channel, OwnedFifo, Sfifo};
+use std::{path::Path, time::Duration};
+
+#[tokio::test]
+async fn test_signal_shutdown() {
+    let fifo_path = "/tmp/test_signal_shutdown";
+    let other_path = "/tmp/test_signal_shutdown_other";
+    let token = "signal_token";
+    let _ = tokio::fs::remove_file(fifo_path).await;
+
+    // FIFOs of configurations without signal shutdown are left alone
+    let other = OwnedFifo::create(other_path).await.unwrap();
+
+    let server_config = Sfifo::new(fifo_path)
+        .set_create(true)
+        .with_signal_shutdown()
+        .clone();
+    let client_config = Sfifo::new(fifo_path);
+    let accept_config = server_config.clone();
+    let server = tokio::spawn(async move {
+        let mut channel = Channel::accept(&accept_config, token).await?;
+        let mut received = Vec::new();
+        while let Some(data) = channel.recv().await? {
+            received.push(data);
+        }
+        Ok::<_, std::io::Error>((channel, received))
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut client = Channel::connect(&client_config, token).await.unwrap();
+    client.send(b"before").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let shutdown = shutdown_signal();
+    unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+    shutdown.cancelled().await;
+    let (server_channel, received) = server.await.unwrap().unwrap();
+    assert_eq!(received, vec![&b"before"[..]]);
+    // The files are gone while the channel is still open
+    assert!(!Path::new(fifo_path).exists());
+    assert!(!Path::new(&format!("{}.c2s", fifo_path)).exists());
+    assert!(!Path::new(&format!("{}.s2c", fifo_path)).exists());
+    assert!(Path::new(other_path).exists());
+    // The peer sees the stream end on the close frame
+    assert!(client.recv().await.unwrap().is_none());
+    drop((server_channel, client));
+
+    // Connections opened after the signal work normally
+    let accept_config = server_config.clone();
+    let server = tokio::spawn(async move {
+        let mut channel = Channel::accept(&accept_config, token).await?;
+        channel.recv().await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut client = Channel::connect(&client_config, token).await.unwrap();
+    client.send(b"after").await.unwrap();
+    let received = server.await.unwrap().unwrap();
+    assert_eq!(received.as_deref(), Some(&b"after"[..]));
+    assert!(!shutdown_signal().is_cancelled());
+
+    drop(other);
+    let _ = tokio::fs::remove_file(fifo_path).await;
+}